@@ -1,8 +1,11 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use serde;
-use thor_cluster::gridsearch::cluster_grid_search;
+use thor_cluster::cellsearch::{ClusterPoint, Linking, ThorCell};
+use thor_cluster::dbscan::brute_force::BruteForceTree;
+use thor_cluster::dbscan::SearchTree;
+use thor_cluster::gridsearch::{cluster_grid_search, GridSearchConfig};
 use thor_cluster::points::{XYPoint, XYTPoint};
-use thor_cluster::{find_clusters, ClusterAlgorithm};
+use thor_cluster::{find_clusters, find_clusters_columns, ClusterAlgorithm, DistanceMetric};
 
 #[derive(Debug, serde::Deserialize)]
 struct TestDataRow {
@@ -50,6 +53,7 @@ fn criterion_benchmark(c: &mut Criterion) {
                     0.02,
                     5,
                     &ClusterAlgorithm::Hotspot2D,
+                    &DistanceMetric::Euclidean,
                 ))
             });
         });
@@ -69,6 +73,7 @@ fn criterion_benchmark(c: &mut Criterion) {
                     0.02,
                     4,
                     &ClusterAlgorithm::DBSCAN,
+                    &DistanceMetric::Euclidean,
                 ))
             });
         });
@@ -88,12 +93,123 @@ fn criterion_benchmark(c: &mut Criterion) {
                     0.02,
                     4,
                     &ClusterAlgorithm::DbscanRStar,
+                    &DistanceMetric::Euclidean,
                 ))
             });
         });
     }
     group.finish();
 
+    // The test data spans about 21 units, beyond what 16-bit fixed-point
+    // numbers hold, so the tree scales it down by 16, resolving points to
+    // about 0.001: still well under eps. Check that it finds clusters, so
+    // that a tree which has lost its precision doesn't look like a speedup.
+    let smoke = find_clusters(
+        &points,
+        0.02,
+        4,
+        &ClusterAlgorithm::DbscanFixed16,
+        &DistanceMetric::Euclidean,
+    );
+    assert!(smoke.iter().any(|&label| label > 0));
+
+    let mut group = c.benchmark_group("find_clusters_fixed16");
+    for size in [10, 100, 1000, 10000, 30000, 50000, 70000].iter() {
+        group.throughput(Throughput::Elements(*size as u64));
+        group.sample_size(10);
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
+            let mut points_n = points.clone();
+            points_n.truncate(size);
+            b.iter(|| {
+                black_box(find_clusters(
+                    black_box(&points_n.clone()),
+                    0.02,
+                    4,
+                    &ClusterAlgorithm::DbscanFixed16,
+                    &DistanceMetric::Euclidean,
+                ))
+            });
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("find_clusters_grid");
+    for size in [10, 100, 1000, 10000, 30000, 50000, 70000].iter() {
+        group.throughput(Throughput::Elements(*size as u64));
+        group.sample_size(10);
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
+            let mut points_n = points.clone();
+            points_n.truncate(size);
+            b.iter(|| {
+                black_box(find_clusters(
+                    black_box(&points_n.clone()),
+                    0.02,
+                    4,
+                    &ClusterAlgorithm::GridDbscan,
+                    &DistanceMetric::Euclidean,
+                ))
+            });
+        });
+    }
+    group.finish();
+
+    // The same clustering, from columns like find_clusters gets from Arrow:
+    // either copied into points first, or read in place.
+    let mut group = c.benchmark_group("find_clusters_columns");
+    let xs: Vec<f64> = points.iter().map(|p| p.x).collect();
+    let ys: Vec<f64> = points.iter().map(|p| p.y).collect();
+    group.throughput(Throughput::Elements(points.len() as u64));
+    group.sample_size(10);
+    group.bench_function("points", |b| {
+        b.iter(|| {
+            let points: Vec<XYPoint<f64>> = black_box(&xs)
+                .iter()
+                .zip(black_box(&ys).iter())
+                .map(|(&x, &y)| XYPoint::new(x, y))
+                .collect();
+            black_box(find_clusters(
+                &points,
+                0.02,
+                4,
+                &ClusterAlgorithm::DBSCAN,
+                &DistanceMetric::Euclidean,
+            ))
+        })
+    });
+    group.bench_function("columns", |b| {
+        b.iter(|| {
+            black_box(find_clusters_columns(
+                black_box(&xs),
+                black_box(&ys),
+                0.02,
+                4,
+                &ClusterAlgorithm::DBSCAN,
+                &DistanceMetric::Euclidean,
+            ))
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("brute_force_neighbors");
+    let tree = BruteForceTree::from_points(&points);
+    let queries: Vec<XYPoint<f64>> = points.iter().step_by(1000).copied().collect();
+    group.throughput(Throughput::Elements((queries.len() * points.len()) as u64));
+    group.bench_function("batched", |b| {
+        b.iter(|| {
+            for query in queries.iter() {
+                black_box(tree.neighbors(black_box(query), 0.02));
+            }
+        })
+    });
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            for query in queries.iter() {
+                black_box(tree.neighbors_scalar(black_box(query), 0.02));
+            }
+        })
+    });
+    group.finish();
+
     let mut group = c.benchmark_group("gridsearch");
     let points = load_testdata_dts();
     for size in [10, 25, 50, 100, 150, 200].iter() {
@@ -113,15 +229,62 @@ fn criterion_benchmark(c: &mut Criterion) {
                     black_box(&points_n.clone()),
                     vxs.clone(),
                     vys.clone(),
-                    ClusterAlgorithm::DbscanRStar,
-                    0.02,
-                    4,
-                    8,
+                    &GridSearchConfig::new(0.02, 4)
+                        .algorithm(ClusterAlgorithm::DbscanRStar)
+                        .n_threads(8),
+                    None,
+                    None,
                 ))
             });
         });
     }
     group.finish();
+
+    // Check that the cellsearch benchmark's path finds clusters in the test
+    // data, so that a broken search doesn't look like a speedup. The whole
+    // file is needed: the first few hundred points are all from the first
+    // two exposures.
+    let smoke = search_cell(&testdata_cell(&points, points.len()), &[0.0]);
+    assert!(smoke.iter().any(|(_, _, clusters)| !clusters.is_empty()));
+
+    let vs: Vec<f32> = (0..300).map(|i| ((i - 150) as f32) / 150.0).collect();
+
+    let mut group = c.benchmark_group("cellsearch");
+    for size in [10, 25, 50, 100, 150, 200].iter() {
+        group.throughput(Throughput::Elements(*size as u64));
+        group.sample_size(10);
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
+            let cell = testdata_cell(&points, size);
+            b.iter(|| black_box(search_cell(&cell, &vs)));
+        });
+    }
+    group.finish();
+}
+
+/// Build a ThorCell from the first size test points.
+fn testdata_cell(points: &[XYTPoint<f64>], size: usize) -> ThorCell {
+    let mut cell = ThorCell::new();
+    for point in points.iter().take(size) {
+        cell.add_point(point.t as f32, XYPoint::new(point.x as f32, point.y as f32))
+            .unwrap();
+    }
+    cell
+}
+
+/// Search cell at every pair of vs, with the same settings as the gridsearch
+/// benchmark.
+fn search_cell(cell: &ThorCell, vs: &[f32]) -> Vec<(f32, f32, Vec<Vec<ClusterPoint>>)> {
+    cell.grid_search(
+        0.02,
+        4,
+        vs,
+        vs,
+        f32::INFINITY,
+        &DistanceMetric::Euclidean,
+        Linking::Forward,
+        8,
+    )
+    .unwrap()
 }
 
 criterion_group!(benches, criterion_benchmark);
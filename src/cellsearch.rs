@@ -1,20 +1,98 @@
-use log::debug;
+use log::{debug, warn};
 
 use std::collections::HashMap;
+use std::fmt;
 
-use kiddo::float::distance::manhattan;
+use kiddo::float::distance::{manhattan, squared_euclidean};
 use kiddo::float::kdtree;
 use ordered_float::OrderedFloat;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 
+use crate::dbscan;
 use crate::points::{XYPoint, XYTPoint};
+use crate::DistanceMetric;
 
 type XYPoint32 = XYPoint<f32>;
 type XYTPoint32 = XYTPoint<f32>;
 
+/// A point which belongs to a cluster. idx is the order in which the point
+/// was added to the cell, counting from 0.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClusterPoint {
+    pub idx: usize,
+    pub point: XYTPoint32,
+}
+
+/// The clusters found at one velocity, as (vx, vy, clusters).
+pub type VelocityClusters = (f32, f32, Vec<Vec<ClusterPoint>>);
+
+/// Returned when adding points at a dt that isn't finite. A NaN dt can't be
+/// ordered against the others, and an infinite one would shift its points
+/// off to infinity at any velocity.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InvalidDt(pub f32);
+
+impl fmt::Display for InvalidDt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dt must be finite, but got {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidDt {}
+
+/// Returned when searching with the haversine metric, which cellsearch
+/// doesn't support: its points aren't stored on the unit sphere.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UnsupportedMetric;
+
+impl fmt::Display for UnsupportedMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cellsearch does not support the haversine metric")
+    }
+}
+
+impl std::error::Error for UnsupportedMetric {}
+
+fn check_metric(metric: &DistanceMetric) -> Result<(), UnsupportedMetric> {
+    match metric {
+        DistanceMetric::Haversine => Err(UnsupportedMetric),
+        _ => Ok(()),
+    }
+}
+
+/// How a ThorCell's points are laid out, for debugging and capacity
+/// planning.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellStats {
+    /// The number of subtrees: one per distinct dt.
+    pub n_subtrees: usize,
+    /// The number of points across every subtree.
+    pub n_points: usize,
+    /// The number of points in each subtree, in order of dt.
+    pub points_per_subtree: Vec<usize>,
+}
+
+/// How find_clusters2 links points into clusters.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Linking {
+    /// Link each point to the points near it in its own and later subtrees,
+    /// and no further: clusters aren't grown through their members'
+    /// neighbors. This is quick, but can split or trim the clusters DBSCAN
+    /// would find.
+    Forward,
+    /// DBSCAN: link each point to the points near it in every subtree, and
+    /// grow clusters through their core points. At a single velocity, with
+    /// the euclidean metric, this finds the same clusters as gridsearch's
+    /// DBSCAN.
+    Dbscan,
+}
+
 pub struct ThorCell {
     subtrees: Vec<ThorSubtree>,
     dts: HashMap<OrderedFloat<f32>, usize>,
     sorted_dts: Vec<OrderedFloat<f32>>,
+    n_points: usize,
 }
 
 fn sorted_insert(vec: &mut Vec<OrderedFloat<f32>>, val: f32) {
@@ -29,42 +107,171 @@ impl ThorCell {
             subtrees: Vec::new(),
             dts: HashMap::new(),
             sorted_dts: Vec::new(),
+            n_points: 0,
         }
     }
 
-    pub fn add_point(&mut self, dt: f32, point: XYPoint32) {
+    /// Add a point at dt. Fails, leaving the cell unchanged, if dt isn't
+    /// finite.
+    pub fn add_point(&mut self, dt: f32, point: XYPoint32) -> Result<(), InvalidDt> {
+        if !dt.is_finite() {
+            return Err(InvalidDt(dt));
+        }
+        let idx = self.n_points;
+        self.n_points += 1;
         match self.dts.get(&OrderedFloat(dt)) {
             Some(subtree_idx) => {
-                self.subtrees[*subtree_idx].add_point(point);
+                self.subtrees[*subtree_idx].add_point(point, idx);
             }
             None => {
                 self.dts.insert(OrderedFloat(dt), self.subtrees.len());
                 sorted_insert(&mut self.sorted_dts, dt);
-                self.subtrees.push(ThorSubtree::new(dt, vec![point]));
+                self.subtrees.push(ThorSubtree::new(dt, vec![point], idx));
             }
         }
+        Ok(())
     }
 
-    pub fn add_points(&mut self, dt: f32, points: Vec<XYPoint32>) {
+    /// Add several points which all share the same dt. Fails, leaving the
+    /// cell unchanged, if dt isn't finite.
+    pub fn add_points(&mut self, dt: f32, points: Vec<XYPoint32>) -> Result<(), InvalidDt> {
+        if !dt.is_finite() {
+            return Err(InvalidDt(dt));
+        }
+        let first_idx = self.n_points;
+        self.n_points += points.len();
         match self.dts.get(&OrderedFloat(dt)) {
             Some(subtree_idx) => {
-                self.subtrees[*subtree_idx].add_points(points);
+                self.subtrees[*subtree_idx].add_points(points, first_idx);
             }
             None => {
-                self.sorted_dts.push(OrderedFloat(dt));
+                self.dts.insert(OrderedFloat(dt), self.subtrees.len());
                 sorted_insert(&mut self.sorted_dts, dt);
-                self.subtrees.push(ThorSubtree::new(dt, points));
+                self.subtrees.push(ThorSubtree::new(dt, points, first_idx));
             }
         }
+        Ok(())
+    }
+
+    /// Count the cell's subtrees and points.
+    pub fn stats(&self) -> CellStats {
+        CellStats {
+            n_subtrees: self.subtrees.len(),
+            n_points: self.n_points,
+            points_per_subtree: self
+                .sorted_dts
+                .iter()
+                .map(|dt| self.subtrees[self.dts[dt]].points.len())
+                .collect(),
+        }
     }
 
+    /// Run find_clusters2 at every pair in the product of vxs and vys, spread
+    /// across n_threads threads. 0 threads means one per CPU. Results come
+    /// back in the same order as a nested loop over vxs, then vys.
+    ///
+    /// Velocities mean the same thing as in gridsearch: a cluster found at
+    /// (vx, vy) is an object at x = x0 + vx*dt, y = y0 + vy*dt. Fails if
+    /// metric is haversine.
+    pub fn grid_search(
+        &self,
+        eps: f32,
+        min_weight: usize,
+        vxs: &[f32],
+        vys: &[f32],
+        max_dt_span: f32,
+        metric: &DistanceMetric,
+        linking: Linking,
+        n_threads: usize,
+    ) -> Result<Vec<VelocityClusters>, UnsupportedMetric> {
+        check_metric(metric)?;
+        let velocities = vxs
+            .iter()
+            .flat_map(|vx| vys.iter().map(move |vy| (*vx, *vy)))
+            .collect::<Vec<_>>();
+        let search = |&(vx, vy): &(f32, f32)| {
+            (
+                vx,
+                vy,
+                self.find_linked_clusters(eps, min_weight, vx, vy, max_dt_span, metric, linking),
+            )
+        };
+        if n_threads == 1 {
+            return Ok(velocities.iter().map(search).collect());
+        }
+
+        match ThreadPoolBuilder::new()
+            .num_threads(n_threads)
+            .thread_name(|i| format!("cellsearch_{}", i))
+            .build()
+        {
+            Ok(pool) => Ok(pool.install(|| velocities.par_iter().map(search).collect())),
+            Err(err) => {
+                warn!(
+                    "can't start cellsearch threads, searching serially: {}",
+                    err
+                );
+                Ok(velocities.iter().map(search).collect())
+            }
+        }
+    }
+
+    /// Find clusters of points moving at (vx, vy), linking them as linking
+    /// says, and only to points no more than max_dt_span away in dt. Pass
+    /// f32::INFINITY for no limit.
+    ///
+    /// max_dt_span is a sliding window over the sorted dts: with
+    /// Linking::Forward, each point only scans the subtrees in
+    /// [dt, dt + max_dt_span], so on long, many-night datasets a window of a
+    /// few nights keeps the follower scan from growing with the total number
+    /// of epochs. With Linking::Dbscan, the window reaches as far back.
+    ///
+    /// Fails if metric is haversine.
     pub fn find_clusters2(
         &self,
         eps: f32,
         min_weight: usize,
         vx: f32,
         vy: f32,
-    ) -> Vec<Vec<XYTPoint32>> {
+        max_dt_span: f32,
+        metric: &DistanceMetric,
+        linking: Linking,
+    ) -> Result<Vec<Vec<ClusterPoint>>, UnsupportedMetric> {
+        check_metric(metric)?;
+        Ok(self.find_linked_clusters(eps, min_weight, vx, vy, max_dt_span, metric, linking))
+    }
+
+    /// find_clusters2, once metric has been checked.
+    fn find_linked_clusters(
+        &self,
+        eps: f32,
+        min_weight: usize,
+        vx: f32,
+        vy: f32,
+        max_dt_span: f32,
+        metric: &DistanceMetric,
+        linking: Linking,
+    ) -> Vec<Vec<ClusterPoint>> {
+        match linking {
+            Linking::Forward => {
+                self.find_clusters_forward(eps, min_weight, vx, vy, max_dt_span, metric)
+            }
+            Linking::Dbscan => {
+                self.find_clusters_dbscan(eps, min_weight, vx, vy, max_dt_span, metric)
+            }
+        }
+    }
+
+    /// find_clusters2 with Linking::Forward.
+    fn find_clusters_forward(
+        &self,
+        eps: f32,
+        min_weight: usize,
+        vx: f32,
+        vy: f32,
+        max_dt_span: f32,
+        metric: &DistanceMetric,
+    ) -> Vec<Vec<ClusterPoint>> {
         // The idea is to find all points that are within eps, but only in *later* subtrees.
 
         // Labels for each point in each subtree
@@ -87,55 +294,60 @@ impl ThorCell {
                 }
 
                 let mut possible_cluster_points = Vec::new();
+                let origin =
+                    XYTPoint32::new(point.x, point.y, subtree.dt).shift_by_velocity(vx, vy);
 
                 let followers = self.sorted_dts[i..]
                     .iter()
                     .enumerate()
                     .map(|(k, dt)| (k + i, &self.subtrees[self.dts[dt]]));
                 for (k, follower_subtree) in followers {
-                    // Modify point to adjust by velocity
-                    let point = XYPoint32 {
-                        x: point.x + vx * (follower_subtree.dt),
-                        y: point.y + vy * (follower_subtree.dt),
-                    };
+                    if follower_subtree.dt - subtree.dt > max_dt_span {
+                        // sorted_dts is in order, so the rest are later still.
+                        break;
+                    }
+                    // Where the point would be at the follower's dt.
+                    let dt = follower_subtree.dt;
+                    let point = origin + XYPoint32::new(vx * dt, vy * dt);
 
-                    if let Some(idx) = follower_subtree.nearest_within(&point, eps) {
+                    for idx in follower_subtree.neighbors_within(&point, eps, metric) {
                         possible_cluster_points.push((k, idx));
-                        labels[k][idx] = ClusterClassification::Border(cluster_idx);
                     }
                 }
 
                 if possible_cluster_points.len() < min_weight {
-                    // Too small
+                    // Too small. The other candidates are left alone, since
+                    // they may still seed a cluster of their own.
                     labels[i][j] = ClusterClassification::Noise;
-                    for (k, idx) in possible_cluster_points {
-                        labels[k][idx] = ClusterClassification::Noise;
-                    }
                 } else {
-                    // New cluster
+                    // New cluster. Points already claimed by an earlier
+                    // cluster stay where they are.
                     cluster_idx += 1;
                     labels[i][j] = ClusterClassification::Core(cluster_idx);
                     for (k, idx) in possible_cluster_points {
-                        labels[k][idx] = ClusterClassification::Core(cluster_idx);
+                        match labels[k][idx] {
+                            ClusterClassification::Undefined | ClusterClassification::Noise => {
+                                labels[k][idx] = ClusterClassification::Core(cluster_idx);
+                            }
+                            _ => {}
+                        }
                     }
                 }
             }
         }
-        // All points are labeled. Now organize the results.
-        let mut clusters: Vec<Vec<XYTPoint32>> = vec![Vec::new(); cluster_idx];
+        // All points are labeled. Now organize the results. Labels are in
+        // DT order, so look up each subtree through sorted_dts.
+        let mut clusters: Vec<Vec<ClusterPoint>> = vec![Vec::new(); cluster_idx];
 
-        for (subtree_idx, subtree_point_classifications) in labels.iter().enumerate() {
+        for (i, subtree_point_classifications) in labels.iter().enumerate() {
+            let subtree = &self.subtrees[self.dts[&self.sorted_dts[i]]];
             for (point_idx, point_classification) in
                 subtree_point_classifications.iter().enumerate()
             {
                 if let ClusterClassification::Core(cluster_idx)
                 | ClusterClassification::Border(cluster_idx) = point_classification
                 {
-                    clusters[*cluster_idx - 1].push(XYTPoint32 {
-                        x: self.subtrees[subtree_idx].points[point_idx].x,
-                        y: self.subtrees[subtree_idx].points[point_idx].y,
-                        t: self.subtrees[subtree_idx].dt,
-                    });
+                    clusters[*cluster_idx - 1].push(subtree.cluster_point(point_idx));
                 }
             }
         }
@@ -143,13 +355,67 @@ impl ThorCell {
         clusters
     }
 
+    /// find_clusters2 with Linking::Dbscan. Points are visited in dt order.
+    fn find_clusters_dbscan(
+        &self,
+        eps: f32,
+        min_weight: usize,
+        vx: f32,
+        vy: f32,
+        max_dt_span: f32,
+        metric: &DistanceMetric,
+    ) -> Vec<Vec<ClusterPoint>> {
+        // Number every point in dt order, as a (subtree, point) pair, with
+        // each subtree's first number in offsets.
+        let subtrees: Vec<&ThorSubtree> = self
+            .sorted_dts
+            .iter()
+            .map(|dt| &self.subtrees[self.dts[dt]])
+            .collect();
+        let mut offsets = Vec::with_capacity(subtrees.len());
+        let mut points = Vec::with_capacity(self.n_points);
+        for (k, subtree) in subtrees.iter().enumerate() {
+            offsets.push(points.len());
+            points.extend((0..subtree.points.len()).map(|j| (k, j)));
+        }
+
+        let labels = dbscan::label_with_neighbors(points.len(), min_weight, |i| {
+            let (k, j) = points[i];
+            let (subtree, point) = (subtrees[k], subtrees[k].points[j]);
+            let origin = XYTPoint32::new(point.x, point.y, subtree.dt).shift_by_velocity(vx, vy);
+            let mut neighbors = Vec::new();
+            for (m, other) in subtrees.iter().enumerate() {
+                if (other.dt - subtree.dt).abs() > max_dt_span {
+                    continue;
+                }
+                let point = origin + XYPoint32::new(vx * other.dt, vy * other.dt);
+                let found = other.neighbors_within(&point, eps, metric);
+                neighbors.extend(found.into_iter().map(|idx| offsets[m] + idx));
+            }
+            neighbors
+        });
+
+        let n_clusters = labels.iter().copied().max().unwrap_or(0).max(0) as usize;
+        let mut clusters: Vec<Vec<ClusterPoint>> = vec![Vec::new(); n_clusters];
+        for (&(k, j), &label) in points.iter().zip(labels.iter()) {
+            if label > 0 {
+                clusters[label as usize - 1].push(subtrees[k].cluster_point(j));
+            }
+        }
+        clusters
+    }
+
+    /// Find clusters of points moving at (vx, vy) with DBSCAN, looking for
+    /// neighbors in every subtree. Fails if metric is haversine.
     pub fn find_clusters(
         &self,
         eps: f32,
         min_weight: usize,
         vx: f32,
         vy: f32,
-    ) -> Vec<Vec<XYTPoint32>> {
+        metric: &DistanceMetric,
+    ) -> Result<Vec<Vec<ClusterPoint>>, UnsupportedMetric> {
+        check_metric(metric)?;
         // Labels for each point in each subtree
         let mut labels: Vec<Vec<ClusterClassification>> = self
             .subtrees
@@ -167,7 +433,9 @@ impl ThorCell {
                 }
 
                 // Gather neighbors from *all* subtrees
-                let mut neighbors = self.neighbors(&point, eps, vx, vy);
+                let origin =
+                    XYTPoint32::new(point.x, point.y, subtree.dt).shift_by_velocity(vx, vy);
+                let mut neighbors = self.neighbors(&origin, eps, vx, vy, metric);
                 if neighbors.len() < min_weight {
                     // Too small
                     labels[i][j] = ClusterClassification::Noise;
@@ -191,20 +459,28 @@ impl ThorCell {
                         continue;
                     }
                     // You're a new core member maybe
-                    let neighbor_point =
-                        &self.subtrees[neighbor_idx.subtree_idx].points[neighbor_idx.point_idx];
-                    let neighbors_of_neighbor = self.neighbors(&neighbor_point, eps, vx, vy);
+                    let neighbor_subtree = &self.subtrees[neighbor_idx.subtree_idx];
+                    let neighbor_point = &neighbor_subtree.points[neighbor_idx.point_idx];
+                    let neighbor_origin =
+                        XYTPoint32::new(neighbor_point.x, neighbor_point.y, neighbor_subtree.dt)
+                            .shift_by_velocity(vx, vy);
+                    let neighbors_of_neighbor =
+                        self.neighbors(&neighbor_origin, eps, vx, vy, metric);
                     if neighbors_of_neighbor.len() >= min_weight {
                         // Join our cluster
                         *label = ClusterClassification::Core(cluster_idx);
                         neighbors.extend(neighbors_of_neighbor);
+                    } else {
+                        // Not a core member, but reachable from one, so
+                        // you're on our border
+                        *label = ClusterClassification::Border(cluster_idx);
                     }
                 }
             }
         }
 
         // All points are labeled. Now organize the results.
-        let mut clusters: Vec<Vec<XYTPoint32>> = vec![Vec::new(); cluster_idx];
+        let mut clusters: Vec<Vec<ClusterPoint>> = vec![Vec::new(); cluster_idx];
 
         for (subtree_idx, subtree_point_classifications) in labels.iter().enumerate() {
             for (point_idx, point_classification) in
@@ -213,25 +489,29 @@ impl ThorCell {
                 if let ClusterClassification::Core(cluster_idx)
                 | ClusterClassification::Border(cluster_idx) = point_classification
                 {
-                    clusters[*cluster_idx - 1].push(XYTPoint32 {
-                        x: self.subtrees[subtree_idx].points[point_idx].x,
-                        y: self.subtrees[subtree_idx].points[point_idx].y,
-                        t: self.subtrees[subtree_idx].dt,
-                    });
+                    clusters[*cluster_idx - 1]
+                        .push(self.subtrees[subtree_idx].cluster_point(point_idx));
                 }
             }
         }
 
-        clusters
+        Ok(clusters)
     }
 
-    fn neighbors(&self, point: &XYPoint32, eps: f32, vx: f32, vy: f32) -> Vec<SubtreeNeighbor> {
+    /// Find the points within eps of where an object moving at (vx, vy) would
+    /// be at each subtree's dt. origin is the object's position at dt = 0.
+    fn neighbors(
+        &self,
+        origin: &XYPoint32,
+        eps: f32,
+        vx: f32,
+        vy: f32,
+        metric: &DistanceMetric,
+    ) -> Vec<SubtreeNeighbor> {
         let mut neighbors = Vec::new();
         for (subtree_idx, subtree) in self.subtrees.iter().enumerate() {
-            let mut point = point.clone();
-            point.x += vx * subtree.dt;
-            point.y += vy * subtree.dt;
-            for neighbor_idx in subtree.neighbor_indexes(&point, eps) {
+            let point = *origin + XYPoint32::new(vx * subtree.dt, vy * subtree.dt);
+            for neighbor_idx in subtree.neighbors_within(&point, eps, metric) {
                 neighbors.push(SubtreeNeighbor {
                     subtree_idx: subtree_idx,
                     point_idx: neighbor_idx,
@@ -250,48 +530,80 @@ struct SubtreeNeighbor {
 struct ThorSubtree {
     pub point_index: kdtree::KdTree<f32, usize, 2, 32, u32>,
     pub points: Vec<XYPoint32>,
+    // The cell-wide index of each point in points.
+    pub idxs: Vec<usize>,
     pub dt: f32,
 }
 
 impl ThorSubtree {
-    pub fn new(dt: f32, points: Vec<XYPoint32>) -> ThorSubtree {
+    /// Make a subtree of points, numbering them from first_idx.
+    pub fn new(dt: f32, points: Vec<XYPoint32>, first_idx: usize) -> ThorSubtree {
         let mut point_tree = kdtree::KdTree::with_capacity(points.len());
         for (i, p) in points.iter().enumerate() {
             point_tree.add(&[p.x, p.y], i);
         }
         ThorSubtree {
             point_index: point_tree,
+            idxs: (first_idx..first_idx + points.len()).collect(),
             points: points,
             dt: dt,
         }
     }
 
-    pub fn add_point(&mut self, point: XYPoint32) {
+    pub fn add_point(&mut self, point: XYPoint32, idx: usize) {
         self.point_index.add(&[point.x, point.y], self.points.len());
         self.points.push(point);
+        self.idxs.push(idx);
     }
 
-    pub fn add_points(&mut self, points: Vec<XYPoint32>) {
-        for point in points {
-            self.add_point(point);
+    pub fn add_points(&mut self, points: Vec<XYPoint32>, first_idx: usize) {
+        for (i, point) in points.into_iter().enumerate() {
+            self.add_point(point, first_idx + i);
         }
     }
 
-    pub fn nearest_within(&self, point: &XYPoint32, radius: f32) -> Option<usize> {
-        let (distance, idx) = self
-            .point_index
-            .nearest_one(&[point.x, point.y], &manhattan);
-        if distance < radius {
-            Some(idx)
-        } else {
-            None
+    fn cluster_point(&self, point_idx: usize) -> ClusterPoint {
+        let point = &self.points[point_idx];
+        ClusterPoint {
+            idx: self.idxs[point_idx],
+            point: XYTPoint32::new(point.x, point.y, self.dt),
         }
     }
 
-    pub fn neighbor_indexes(&self, point: &XYPoint32, radius: f32) -> Vec<usize> {
-        self.point_index
-            .within_unsorted(&[point.x, point.y], radius, &manhattan)
+    /// The indexes of points no more than radius from point, measured in
+    /// metric. ThorCell rejects haversine before searching.
+    pub fn neighbors_within(
+        &self,
+        point: &XYPoint32,
+        radius: f32,
+        metric: &DistanceMetric,
+    ) -> Vec<usize> {
+        let query = [point.x, point.y];
+        // kiddo only finds points strictly within the limit, so search a
+        // little further, and keep the points exactly on it too, as
+        // gridsearch's DBSCAN does.
+        let pad = |limit: f32| limit * (1.0 + f32::EPSILON) + f32::MIN_POSITIVE;
+        let (limit, neighbors) = match metric {
+            DistanceMetric::Euclidean => {
+                let limit = radius * radius;
+                let neighbors =
+                    self.point_index
+                        .within_unsorted(&query, pad(limit), &squared_euclidean);
+                (limit, neighbors)
+            }
+            DistanceMetric::Manhattan => {
+                let neighbors = self
+                    .point_index
+                    .within_unsorted(&query, pad(radius), &manhattan);
+                (radius, neighbors)
+            }
+            DistanceMetric::Haversine => {
+                unreachable!("cellsearch does not support the haversine metric")
+            }
+        };
+        neighbors
             .iter()
+            .filter(|neighbor| neighbor.distance <= limit)
             .map(|neighbor| neighbor.item)
             .collect()
     }
@@ -304,3 +616,540 @@ enum ClusterClassification {
     Border(usize),
     Core(usize),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbscan::{self, brute_force::BruteForceTree};
+
+    fn members(clusters: &[Vec<ClusterPoint>]) -> Vec<usize> {
+        clusters.iter().map(|c| c.len()).collect()
+    }
+
+    #[test]
+    fn test_empty_cell() {
+        let cell = ThorCell::new();
+        let metric = DistanceMetric::Euclidean;
+        assert!(cell
+            .find_clusters2(0.5, 4, 0.0, 0.0, f32::INFINITY, &metric, Linking::Forward)
+            .unwrap()
+            .is_empty());
+        let results = cell
+            .grid_search(
+                0.5,
+                4,
+                &[0.0, 1.0],
+                &[0.0],
+                f32::INFINITY,
+                &metric,
+                Linking::Forward,
+                2,
+            )
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, _, clusters)| clusters.is_empty()));
+    }
+
+    #[test]
+    fn test_single_epoch_matches_dbscan() {
+        // With every point at dt=0 and no velocity, ThorCell::find_clusters
+        // is DBSCAN on a single tree. The two are written separately, so
+        // check that they agree.
+        let mut points = Vec::new();
+        for (cx, cy) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)] {
+            for (dx, dy) in [
+                (0.0, 0.0),
+                (0.05, 0.0),
+                (0.0, 0.05),
+                (-0.05, 0.0),
+                (0.0, -0.05),
+            ] {
+                points.push(XYPoint::new(cx + dx, cy + dy));
+            }
+        }
+        // A chain, which only holds together through its core points, with
+        // an end at each side which is only a border point.
+        for k in 0..6 {
+            points.push(XYPoint::new(5.0 + 0.1 * k as f64, 5.0));
+        }
+        // Too few to make a cluster, and some loners.
+        points.push(XYPoint::new(2.0, 2.0));
+        points.push(XYPoint::new(2.05, 2.0));
+        points.push(XYPoint::new(3.0, -1.0));
+        points.push(XYPoint::new(-2.0, 0.5));
+        // Interleave the clusters and noise, so that neither search visits
+        // them in a convenient order.
+        let points: Vec<XYPoint<f64>> = (0..points.len())
+            .map(|i| points[(i * 7) % points.len()])
+            .collect();
+
+        let metric = DistanceMetric::Euclidean;
+        let labels = dbscan::find_clusters::<BruteForceTree>(&points, 0.15, 3, &metric);
+        let mut want = dbscan::group_labels(&labels);
+        want.sort();
+        assert!(want.iter().any(|cluster| cluster.len() == 6));
+
+        let mut cell = ThorCell::new();
+        let points32 = points
+            .iter()
+            .map(|p| XYPoint32::new(p.x as f32, p.y as f32))
+            .collect();
+        cell.add_points(0.0, points32).unwrap();
+        let mut have: Vec<Vec<usize>> = cell
+            .find_clusters(0.15, 3, 0.0, 0.0, &metric)
+            .unwrap()
+            .iter()
+            .map(|cluster| {
+                let mut idxs: Vec<usize> = cluster.iter().map(|p| p.idx).collect();
+                idxs.sort();
+                idxs
+            })
+            .collect();
+        have.sort();
+
+        assert_eq!(want.len(), 4);
+        assert_eq!(have, want);
+    }
+
+    #[test]
+    fn test_points_exactly_eps_apart() {
+        // As in gridsearch's DBSCAN, points exactly eps apart are neighbors,
+        // in either metric, and in the same subtree or across two.
+        let metrics = [DistanceMetric::Euclidean, DistanceMetric::Manhattan];
+        for metric in metrics.iter() {
+            let mut cell = ThorCell::new();
+            let pair = vec![XYPoint32::new(1.0, 1.0), XYPoint32::new(1.5, 1.0)];
+            cell.add_points(0.0, pair).unwrap();
+            cell.add_points(1.0, vec![XYPoint32::new(1.0, 1.5)])
+                .unwrap();
+            let clusters = cell.find_clusters(0.5, 3, 0.0, 0.0, metric).unwrap();
+            assert_eq!(clusters.len(), 1);
+            assert_eq!(clusters[0].len(), 3);
+            let clusters = cell
+                .find_clusters2(0.5, 3, 0.0, 0.0, f32::INFINITY, metric, Linking::Dbscan)
+                .unwrap();
+            assert_eq!(clusters.len(), 1);
+            assert_eq!(clusters[0].len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_add_point_rejects_nan_dt() {
+        let mut cell = ThorCell::new();
+        cell.add_point(0.0, XYPoint32::new(0.0, 0.0)).unwrap();
+        let err = cell
+            .add_point(f32::NAN, XYPoint32::new(0.0, 0.0))
+            .unwrap_err();
+        assert!(err.0.is_nan());
+        assert_eq!(err.to_string(), "dt must be finite, but got NaN");
+        assert_eq!(
+            cell.add_points(f32::INFINITY, vec![XYPoint32::new(0.0, 0.0)]),
+            Err(InvalidDt(f32::INFINITY))
+        );
+
+        // The cell is left as it was, and still searchable.
+        cell.add_points(1.0, vec![XYPoint32::new(0.0, 0.0); 3])
+            .unwrap();
+        let metric = DistanceMetric::Euclidean;
+        let clusters = cell
+            .find_clusters2(0.5, 4, 0.0, 0.0, f32::INFINITY, &metric, Linking::Forward)
+            .unwrap();
+        assert_eq!(clusters.len(), 1);
+        let idxs: Vec<usize> = clusters[0].iter().map(|p| p.idx).collect();
+        assert_eq!(idxs.len(), 4);
+        assert!(idxs.iter().all(|&idx| idx < 4));
+    }
+
+    #[test]
+    fn test_haversine_is_rejected() {
+        let mut cell = ThorCell::new();
+        cell.add_points(0.0, vec![XYPoint32::new(0.0, 0.0); 4])
+            .unwrap();
+        let metric = DistanceMetric::Haversine;
+        for linking in [Linking::Forward, Linking::Dbscan] {
+            assert_eq!(
+                cell.find_clusters2(0.5, 4, 0.0, 0.0, f32::INFINITY, &metric, linking),
+                Err(UnsupportedMetric)
+            );
+        }
+        assert_eq!(
+            cell.find_clusters(0.5, 4, 0.0, 0.0, &metric),
+            Err(UnsupportedMetric)
+        );
+        let err = cell
+            .grid_search(
+                0.5,
+                4,
+                &[0.0],
+                &[0.0],
+                f32::INFINITY,
+                &metric,
+                Linking::Forward,
+                2,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cellsearch does not support the haversine metric"
+        );
+    }
+
+    #[test]
+    fn test_add_points_matches_add_point() {
+        let epochs = [
+            (
+                2.0,
+                vec![XYPoint32::new(0.2, 0.0), XYPoint32::new(5.0, 5.0)],
+            ),
+            (
+                0.0,
+                vec![XYPoint32::new(0.0, 0.0), XYPoint32::new(3.0, 3.0)],
+            ),
+            (1.0, vec![XYPoint32::new(0.1, 0.0)]),
+            (3.0, vec![XYPoint32::new(0.3, 0.0)]),
+        ];
+
+        let mut by_point = ThorCell::new();
+        let mut by_batch = ThorCell::new();
+        for (dt, points) in epochs.iter() {
+            for point in points.iter() {
+                by_point.add_point(*dt, *point).unwrap();
+            }
+            by_batch.add_points(*dt, points.clone()).unwrap();
+        }
+        // Adding to an existing epoch should extend its subtree.
+        by_point.add_point(0.0, XYPoint32::new(9.0, 9.0)).unwrap();
+        by_batch
+            .add_points(0.0, vec![XYPoint32::new(9.0, 9.0)])
+            .unwrap();
+
+        assert_eq!(by_batch.sorted_dts, by_point.sorted_dts);
+        assert_eq!(by_batch.dts, by_point.dts);
+
+        let metric = DistanceMetric::Euclidean;
+        let want = by_point
+            .find_clusters2(0.5, 4, 0.1, 0.0, f32::INFINITY, &metric, Linking::Forward)
+            .unwrap();
+        let have = by_batch
+            .find_clusters2(0.5, 4, 0.1, 0.0, f32::INFINITY, &metric, Linking::Forward)
+            .unwrap();
+        assert_eq!(have, want);
+        assert_eq!(members(&have), vec![4]);
+    }
+
+    #[test]
+    fn test_grid_search_parallel_matches_serial() {
+        let mut cell = ThorCell::new();
+        for i in 0..6 {
+            let dt = i as f32;
+            cell.add_points(
+                dt,
+                vec![
+                    XYPoint32::new(0.1 * dt, 0.0),
+                    XYPoint32::new(3.0, 3.0 - 0.2 * dt),
+                    XYPoint32::new(7.0 - 0.1 * dt, 1.0 + 0.1 * dt),
+                ],
+            )
+            .unwrap();
+        }
+        let vs = [-0.2, -0.1, 0.0, 0.1, 0.2];
+        let metric = DistanceMetric::Euclidean;
+
+        let serial = cell
+            .grid_search(
+                0.05,
+                4,
+                &vs,
+                &vs,
+                f32::INFINITY,
+                &metric,
+                Linking::Forward,
+                1,
+            )
+            .unwrap();
+        let parallel = cell
+            .grid_search(
+                0.05,
+                4,
+                &vs,
+                &vs,
+                f32::INFINITY,
+                &metric,
+                Linking::Forward,
+                4,
+            )
+            .unwrap();
+        assert_eq!(serial.len(), vs.len() * vs.len());
+        assert_eq!(parallel, serial);
+        let found: usize = serial.iter().map(|(_, _, clusters)| clusters.len()).sum();
+        assert!(found >= 3);
+    }
+
+    #[test]
+    fn test_moving_object_starting_late() {
+        // Something moving at vx=0.5, first seen at dt=1. Velocities are
+        // applied relative to each point's own dt, not to dt=0.
+        let mut cell = ThorCell::new();
+        for dt in [1.0, 2.0, 3.0, 4.0] {
+            cell.add_point(dt, XYPoint32::new(0.5 * dt, 0.0)).unwrap();
+        }
+        let metric = DistanceMetric::Euclidean;
+        assert_eq!(
+            members(
+                &cell
+                    .find_clusters2(0.1, 4, 0.5, 0.0, f32::INFINITY, &metric, Linking::Forward)
+                    .unwrap()
+            ),
+            vec![4]
+        );
+        assert_eq!(
+            members(&cell.find_clusters(0.1, 4, 0.5, 0.0, &metric).unwrap()),
+            vec![4]
+        );
+        assert_eq!(
+            members(
+                &cell
+                    .find_clusters2(0.1, 4, -0.5, 0.0, f32::INFINITY, &metric, Linking::Forward)
+                    .unwrap()
+            ),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn test_find_clusters2_max_dt_span() {
+        // A stationary object seen on three nights close together, and once
+        // much later.
+        let mut cell = ThorCell::new();
+        for dt in [0.0, 1.0, 2.0, 30.0] {
+            cell.add_point(dt, XYPoint32::new(0.0, 0.0)).unwrap();
+        }
+        let metric = DistanceMetric::Euclidean;
+
+        let clusters = cell
+            .find_clusters2(0.1, 3, 0.0, 0.0, f32::INFINITY, &metric, Linking::Forward)
+            .unwrap();
+        assert_eq!(members(&clusters), vec![4]);
+
+        let clusters = cell
+            .find_clusters2(0.1, 3, 0.0, 0.0, 15.0, &metric, Linking::Forward)
+            .unwrap();
+        assert_eq!(members(&clusters), vec![3]);
+        assert!(clusters[0].iter().all(|p| p.point.t < 30.0));
+
+        // Even the near points can't be linked if the window is too short.
+        let clusters = cell
+            .find_clusters2(0.1, 3, 0.0, 0.0, 1.5, &metric, Linking::Forward)
+            .unwrap();
+        assert_eq!(members(&clusters), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_stats() {
+        let cell = ThorCell::new();
+        assert_eq!(
+            cell.stats(),
+            CellStats {
+                n_subtrees: 0,
+                n_points: 0,
+                points_per_subtree: vec![],
+            }
+        );
+
+        // Added out of dt order, and over several calls.
+        let mut cell = ThorCell::new();
+        cell.add_points(2.0, vec![XYPoint32::new(0.0, 0.0); 3])
+            .unwrap();
+        cell.add_point(0.0, XYPoint32::new(1.0, 1.0)).unwrap();
+        cell.add_points(1.0, vec![XYPoint32::new(2.0, 2.0); 4])
+            .unwrap();
+        cell.add_point(2.0, XYPoint32::new(3.0, 3.0)).unwrap();
+        assert_eq!(
+            cell.stats(),
+            CellStats {
+                n_subtrees: 3,
+                n_points: 9,
+                points_per_subtree: vec![1, 4, 4],
+            }
+        );
+    }
+
+    #[test]
+    fn test_find_clusters2_window_splits_clusters() {
+        // The same stationary source, seen on three consecutive nights, and
+        // then again on three nights a month later.
+        let mut cell = ThorCell::new();
+        for dt in [0.0, 1.0, 2.0, 30.0, 31.0, 32.0] {
+            cell.add_point(dt, XYPoint32::new(0.0, 0.0)).unwrap();
+        }
+        let metric = DistanceMetric::Euclidean;
+
+        for linking in [Linking::Forward, Linking::Dbscan] {
+            let clusters = cell
+                .find_clusters2(0.1, 3, 0.0, 0.0, f32::INFINITY, &metric, linking)
+                .unwrap();
+            assert_eq!(members(&clusters), vec![6]);
+
+            // A window of a few nights keeps the two visits apart.
+            let clusters = cell
+                .find_clusters2(0.1, 3, 0.0, 0.0, 5.0, &metric, linking)
+                .unwrap();
+            assert_eq!(members(&clusters), vec![3, 3]);
+            assert!(clusters[0].iter().all(|p| p.point.t < 3.0));
+            assert!(clusters[1].iter().all(|p| p.point.t >= 30.0));
+        }
+    }
+
+    #[test]
+    fn test_find_clusters2_dense_follower() {
+        // A later epoch has three points within eps of the first point. All
+        // of them belong in the cluster.
+        let mut cell = ThorCell::new();
+        cell.add_point(0.0, XYPoint32::new(0.0, 0.0)).unwrap();
+        cell.add_points(
+            1.0,
+            vec![
+                XYPoint32::new(0.1, 0.0),
+                XYPoint32::new(0.0, 0.1),
+                XYPoint32::new(-0.1, 0.0),
+                XYPoint32::new(5.0, 5.0),
+            ],
+        )
+        .unwrap();
+
+        let clusters = cell
+            .find_clusters2(
+                0.5,
+                4,
+                0.0,
+                0.0,
+                f32::INFINITY,
+                &DistanceMetric::Euclidean,
+                Linking::Forward,
+            )
+            .unwrap();
+        assert_eq!(members(&clusters), vec![4]);
+        assert!(!clusters[0]
+            .iter()
+            .any(|p| p.point == XYTPoint32::new(5.0, 5.0, 1.0)));
+    }
+
+    #[test]
+    fn test_cluster_point_idxs() {
+        // Points are numbered in the order they're added, whichever epoch
+        // they land in.
+        let mut cell = ThorCell::new();
+        cell.add_point(1.0, XYPoint32::new(0.1, 0.0)).unwrap();
+        cell.add_point(0.0, XYPoint32::new(5.0, 5.0)).unwrap();
+        cell.add_points(
+            0.0,
+            vec![XYPoint32::new(0.0, 0.0), XYPoint32::new(0.0, 0.1)],
+        )
+        .unwrap();
+        cell.add_point(2.0, XYPoint32::new(0.2, 0.0)).unwrap();
+
+        for clusters in [
+            cell.find_clusters2(
+                0.5,
+                4,
+                0.0,
+                0.0,
+                f32::INFINITY,
+                &DistanceMetric::Euclidean,
+                Linking::Forward,
+            )
+            .unwrap(),
+            cell.find_clusters(0.5, 4, 0.0, 0.0, &DistanceMetric::Euclidean)
+                .unwrap(),
+        ] {
+            assert_eq!(members(&clusters), vec![4]);
+            let mut idxs: Vec<usize> = clusters[0].iter().map(|p| p.idx).collect();
+            idxs.sort();
+            assert_eq!(idxs, vec![0, 2, 3, 4]);
+            for p in clusters[0].iter() {
+                if p.idx == 4 {
+                    assert_eq!(p.point, XYTPoint32::new(0.2, 0.0, 2.0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_clusters2_metric() {
+        // The second point is about 0.42 away in euclidean distance, but 0.6
+        // away in manhattan distance.
+        let mut cell = ThorCell::new();
+        cell.add_point(0.0, XYPoint32::new(0.0, 0.0)).unwrap();
+        cell.add_point(1.0, XYPoint32::new(0.3, 0.3)).unwrap();
+
+        let clusters = cell
+            .find_clusters2(
+                0.5,
+                2,
+                0.0,
+                0.0,
+                f32::INFINITY,
+                &DistanceMetric::Euclidean,
+                Linking::Forward,
+            )
+            .unwrap();
+        assert_eq!(members(&clusters), vec![2]);
+
+        let clusters = cell
+            .find_clusters2(
+                0.5,
+                2,
+                0.0,
+                0.0,
+                f32::INFINITY,
+                &DistanceMetric::Manhattan,
+                Linking::Forward,
+            )
+            .unwrap();
+        assert_eq!(members(&clusters), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_find_clusters_metric() {
+        let mut cell = ThorCell::new();
+        cell.add_point(0.0, XYPoint32::new(0.0, 0.0)).unwrap();
+        cell.add_point(1.0, XYPoint32::new(0.3, 0.3)).unwrap();
+
+        let clusters = cell
+            .find_clusters(0.5, 2, 0.0, 0.0, &DistanceMetric::Euclidean)
+            .unwrap();
+        assert_eq!(members(&clusters), vec![2]);
+
+        let clusters = cell
+            .find_clusters(0.5, 2, 0.0, 0.0, &DistanceMetric::Manhattan)
+            .unwrap();
+        assert_eq!(members(&clusters), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_grid_search_metric_boundary() {
+        // The second point is just inside eps in euclidean distance (0.4904),
+        // and just outside it in manhattan distance (0.51).
+        let mut cell = ThorCell::new();
+        cell.add_point(0.0, XYPoint32::new(0.0, 0.0)).unwrap();
+        cell.add_point(1.0, XYPoint32::new(0.49, 0.02)).unwrap();
+
+        let search = |metric| {
+            cell.grid_search(
+                0.5,
+                2,
+                &[0.0],
+                &[0.0],
+                f32::INFINITY,
+                &metric,
+                Linking::Forward,
+                1,
+            )
+            .unwrap()
+        };
+        let results = search(DistanceMetric::Euclidean);
+        assert_eq!(members(&results[0].2), vec![2]);
+        let results = search(DistanceMetric::Manhattan);
+        assert_eq!(members(&results[0].2), Vec::<usize>::new());
+    }
+}
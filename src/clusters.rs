@@ -0,0 +1,118 @@
+use crate::dbscan::group_labels;
+use crate::points::{XYPoint, XYTPoint};
+use crate::{find_clusters, ClusterAlgorithm, DistanceMetric};
+
+/// A cluster of observations, with its own copy of each one, times and all,
+/// so that nothing needs to be looked up by index afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    /// The cluster's label from find_clusters_xyt.
+    pub label: i32,
+    /// The cluster's observations, in the order they were given.
+    pub points: Vec<XYTPoint<f64>>,
+}
+
+impl Cluster {
+    /// The difference between the latest and earliest t in the cluster.
+    pub fn arc_length(&self) -> f64 {
+        let ts = self.points.iter().map(|p| p.t);
+        ts.clone().fold(f64::NEG_INFINITY, f64::max) - ts.fold(f64::INFINITY, f64::min)
+    }
+}
+
+/// Like find_clusters, but for observations with a time, which is carried
+/// along but not clustered on: points are clustered by x and y alone. The
+/// labels line up with points.
+pub fn find_clusters_xyt(
+    points: &[XYTPoint<f64>],
+    eps: f64,
+    min_cluster_size: usize,
+    alg: &ClusterAlgorithm,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let xy_points: Vec<XYPoint<f64>> = points.iter().map(|p| XYPoint::new(p.x, p.y)).collect();
+    find_clusters(&xy_points, eps, min_cluster_size, alg, metric)
+}
+
+/// Like find_clusters_xyt, but returns the clusters themselves, in order of
+/// label, each holding its observations. Noise is left out.
+pub fn find_clusters_xyt_grouped(
+    points: &[XYTPoint<f64>],
+    eps: f64,
+    min_cluster_size: usize,
+    alg: &ClusterAlgorithm,
+    metric: &DistanceMetric,
+) -> Vec<Cluster> {
+    let labels = find_clusters_xyt(points, eps, min_cluster_size, alg, metric);
+    group_clusters(points, &labels)
+}
+
+/// Gather points into clusters by their labels, which must line up with
+/// them. The clusters are in order of label, and noise (any negative label)
+/// is left out.
+pub fn group_clusters(points: &[XYTPoint<f64>], labels: &[i32]) -> Vec<Cluster> {
+    group_labels(labels)
+        .into_iter()
+        .map(|group| Cluster {
+            label: labels[group[0]],
+            points: group.iter().map(|&idx| points[idx]).collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_clusters_xyt_grouped() {
+        // Two groups, interleaved, with a stray point between them. Each
+        // observation's time tells the points apart.
+        let points = vec![
+            XYTPoint::new(0.0, 0.0, 10.0),
+            XYTPoint::new(5.0, 5.0, 20.0),
+            XYTPoint::new(0.1, 0.0, 11.0),
+            XYTPoint::new(5.1, 5.0, 21.0),
+            XYTPoint::new(2.5, 2.5, 99.0),
+            XYTPoint::new(0.0, 0.1, 12.5),
+            XYTPoint::new(5.0, 5.1, 22.5),
+        ];
+        let alg = ClusterAlgorithm::DBSCAN;
+        let metric = DistanceMetric::Euclidean;
+
+        let labels = find_clusters_xyt(&points, 0.2, 3, &alg, &metric);
+        assert_eq!(labels, [1, 2, 1, 2, -1, 1, 2]);
+
+        let clusters = find_clusters_xyt_grouped(&points, 0.2, 3, &alg, &metric);
+        assert_eq!(clusters.len(), 2);
+        let times: Vec<Vec<f64>> = clusters
+            .iter()
+            .map(|c| c.points.iter().map(|p| p.t).collect())
+            .collect();
+        assert_eq!(times, [[10.0, 11.0, 12.5], [20.0, 21.0, 22.5]]);
+        assert_eq!(clusters[1].label, 2);
+        assert_eq!(clusters[1].points[2], XYTPoint::new(5.0, 5.1, 22.5));
+        assert_eq!(clusters[0].arc_length(), 2.5);
+    }
+
+    #[test]
+    fn test_group_clusters() {
+        let points: Vec<XYTPoint<f64>> =
+            (0..4).map(|i| XYTPoint::new(0.0, 0.0, i as f64)).collect();
+        let clusters = group_clusters(&points, &[3, -1, 3, 1]);
+        assert_eq!(
+            clusters,
+            vec![
+                Cluster {
+                    label: 1,
+                    points: vec![points[3]],
+                },
+                Cluster {
+                    label: 3,
+                    points: vec![points[0], points[2]],
+                },
+            ]
+        );
+        assert!(group_clusters(&points, &[-1; 4]).is_empty());
+    }
+}
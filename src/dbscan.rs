@@ -1,7 +1,18 @@
+pub mod brute_force;
 pub mod fixed16_kdtree;
 pub mod float32_kdtree;
+pub mod grid;
+pub mod incremental;
 pub mod rstar;
-use crate::points::XYPoint;
+pub mod sphere_kdtree;
+pub mod xyz_kdtree;
+use crate::points::{XYPoint, XYZPoint};
+use crate::DistanceMetric;
+use ::rstar::primitives::GeomWithData;
+use ::rstar::RTree;
+use kiddo::distance::squared_euclidean;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, PartialEq)]
 enum DBScanClassification {
@@ -9,49 +20,810 @@ enum DBScanClassification {
     Noise,
     Border(u16),
     Core(u16),
+    // In a cluster, but not checked for being a core point yet, since the
+    // queue was full when it was reached; see dbscan_capped.
+    Deferred(u16),
 }
 
+/// The part a point plays in a DBSCAN clustering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointRole {
+    /// In no cluster.
+    Noise,
+    /// In a cluster, but without enough neighbors of its own to extend it.
+    Border,
+    /// In a cluster, with at least min_cluster_size neighbors.
+    Core,
+}
+
+impl PointRole {
+    /// The role's name: "noise", "border", or "core".
+    pub fn name(&self) -> &'static str {
+        match self {
+            PointRole::Noise => "noise",
+            PointRole::Border => "border",
+            PointRole::Core => "core",
+        }
+    }
+}
+
+/// A spatial index that DBSCAN uses to find the neighbors of each point.
+///
+/// A tree is built once per call to find_clusters, from all of the points
+/// being clustered, and is then queried once or more for each point. Points
+/// are identified by their index in the slice the tree was built from.
+///
+/// Implementing this for a new type lets it stand in for the built-in trees:
+///
+/// ```
+/// use thor_cluster::dbscan::{find_clusters, SearchTree};
+/// use thor_cluster::{DistanceMetric, XYPoint};
+///
+/// struct BruteForce(Vec<XYPoint<f64>>);
+///
+/// impl SearchTree for BruteForce {
+///     fn from_points(points: &Vec<XYPoint<f64>>) -> Self {
+///         BruteForce(points.clone())
+///     }
+///
+///     fn neighbors(&self, point: &XYPoint<f64>, radius: f64) -> Vec<usize> {
+///         (0..self.0.len())
+///             .filter(|&i| {
+///                 let (dx, dy) = (self.0[i].x - point.x, self.0[i].y - point.y);
+///                 dx * dx + dy * dy <= radius * radius
+///             })
+///             .collect()
+///     }
+/// }
+///
+/// let points = vec![
+///     XYPoint::new(0.0, 0.0),
+///     XYPoint::new(0.1, 0.0),
+///     XYPoint::new(0.0, 0.1),
+///     XYPoint::new(5.0, 5.0),
+/// ];
+/// let labels = find_clusters::<BruteForce>(&points, 0.5, 3, &DistanceMetric::Euclidean);
+/// assert_eq!(labels, vec![1, 1, 1, -1]);
+/// ```
 pub trait SearchTree {
+    /// Build a tree holding every one of points.
     fn from_points(points: &Vec<XYPoint<f64>>) -> Self;
+    /// Build a tree holding the points (xs[i], ys[i]), which must be the
+    /// same length. By default this collects them into points for
+    /// from_points; trees which can read the columns as they are should
+    /// override it, to skip that copy.
+    fn from_columns(xs: &[f64], ys: &[f64]) -> Self
+    where
+        Self: Sized,
+    {
+        let points = xs
+            .iter()
+            .zip(ys.iter())
+            .map(|(&x, &y)| XYPoint::new(x, y))
+            .collect();
+        Self::from_points(&points)
+    }
+    /// Returns the indexes of all points within `radius` of `point`,
+    /// including the point itself if it's in the tree, in any order. The
+    /// radius is a plain euclidean distance, not a squared one, in the same
+    /// units as the points' coordinates. Points exactly radius away are
+    /// included.
+    ///
+    /// For the manhattan metric, DBSCAN filters these neighbors further, so
+    /// they must include every point within radius. For the haversine
+    /// metric, a tree must store points on the unit sphere, as SphereTree
+    /// does, since radius is then a chord length.
     fn neighbors(&self, point: &XYPoint<f64>, radius: f64) -> Vec<usize>;
 }
 
+/// Find clusters with DBSCAN, using T to find neighbors. eps is in the units
+/// of the metric: the same units as the points for euclidean and manhattan
+/// distance, and radians for haversine. Noise is labeled -1 and clusters are
+/// labeled from 1.
+///
+/// Points with exactly the same coordinates are all neighbors of each
+/// other, and each counts toward min_cluster_size, so for any eps above 0,
+/// N copies of a point form a cluster whenever N >= min_cluster_size, even
+/// with nothing else near them. Use find_clusters_deduped to count them once.
 pub fn find_clusters<T: SearchTree>(
     points: &Vec<XYPoint<f64>>,
     eps: f64,
     min_cluster_size: usize,
+    metric: &DistanceMetric,
 ) -> Vec<i32> {
     let tree: T = T::from_points(points);
 
-    let labels = dbscan(points, &tree, eps, min_cluster_size);
+    let labels = dbscan(points.len(), None, min_cluster_size, |i| {
+        neighbors_within(points, &tree, &points[i], eps, metric)
+    });
+    cluster_labels(&labels)
+}
+
+/// Changes to plain DBSCAN for find_clusters_with. Any of them can be used
+/// together; the default changes nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DbscanOptions<'a> {
+    /// How much each point counts toward min_cluster_size, as in
+    /// find_clusters_weighted. Must line up with the points.
+    pub weights: Option<&'a [f64]>,
+    /// Each point's uncertainty, and how many of them to widen eps by, as
+    /// in find_clusters_uncertain. Must line up with the points.
+    pub sigmas: Option<(&'a [f64], f64)>,
+    /// The period x wraps around at, as in find_clusters_periodic.
+    pub period: Option<f64>,
+    /// The most points to queue up to be expanded at once, as in
+    /// find_clusters_capped.
+    pub max_neighbors: Option<usize>,
+}
+
+/// Like find_clusters, but with any combination of options.
+pub fn find_clusters_with<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+    options: &DbscanOptions,
+) -> Vec<i32> {
+    cluster_labels(&classify_with::<T>(
+        points,
+        eps,
+        min_cluster_size,
+        metric,
+        options,
+    ))
+}
+
+/// Like find_clusters_with, but also returns the role of each point in its
+/// cluster, as find_clusters_classified does.
+pub fn find_clusters_classified_with<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+    options: &DbscanOptions,
+) -> Vec<(i32, PointRole)> {
+    let labels = classify_with::<T>(points, eps, min_cluster_size, metric, options);
+    let roles = labels.iter().map(|label| match label {
+        DBScanClassification::Core(_) => PointRole::Core,
+        DBScanClassification::Border(_) | DBScanClassification::Deferred(_) => PointRole::Border,
+        DBScanClassification::Noise | DBScanClassification::Undefined => PointRole::Noise,
+    });
+    cluster_labels(&labels).into_iter().zip(roles).collect()
+}
+
+fn classify_with<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+    options: &DbscanOptions,
+) -> Vec<DBScanClassification> {
+    let n_sigma = options.sigmas.map_or(0.0, |(_, n_sigma)| n_sigma);
+    let radius = |sigma_a: f64, sigma_b: f64| {
+        (eps * eps + n_sigma * n_sigma * (sigma_a * sigma_a + sigma_b * sigma_b)).sqrt()
+    };
+    // With sigmas, search out to the furthest any point could be a
+    // neighbor, then check each candidate's own radius.
+    let max_sigma = options.sigmas.map_or(0.0, |(sigmas, _)| {
+        sigmas.iter().copied().fold(0.0, f64::max)
+    });
+    let reach = match options.sigmas {
+        Some(_) => radius(max_sigma, max_sigma),
+        None => eps,
+    };
+
+    // With a period, the points within reach of the seam at x = 0 are
+    // copied to the other side of it, period away, and a neighbor found
+    // among those copies counts as the point it was copied from.
+    let (searched, origins) = match options.period {
+        Some(period) => {
+            let (wrapped, origins) = wrap_points(points, period, reach);
+            (Cow::Owned(wrapped), Some(origins))
+        }
+        None => (Cow::Borrowed(points), None),
+    };
+    let origin = |idx: usize| origins.as_ref().map_or(idx, |origins| origins[idx]);
+    let tree: T = T::from_points(&searched);
+
+    let (labels, _) = dbscan_capped(
+        points.len(),
+        options.weights,
+        min_cluster_size,
+        options.max_neighbors,
+        |i| {
+            let point = &searched[i];
+            let mut neighbors: Vec<usize> = match options.sigmas {
+                Some((sigmas, _)) => neighbors_within(
+                    &searched,
+                    &tree,
+                    point,
+                    radius(sigmas[i], max_sigma),
+                    metric,
+                )
+                .into_iter()
+                .filter(|&j| {
+                    metric.distance(point, &searched[j]) <= radius(sigmas[i], sigmas[origin(j)])
+                })
+                .collect(),
+                None => neighbors_within(&searched, &tree, point, eps, metric),
+            };
+            if origins.is_some() {
+                neighbors.iter_mut().for_each(|idx| *idx = origin(*idx));
+                // A point and its copy can both be in reach when eps is
+                // close to half the period.
+                neighbors.sort_unstable();
+                neighbors.dedup();
+            }
+            neighbors
+        },
+    );
+    labels
+}
+
+/// Wrap points' x into [0, period), and copy the ones within reach of the
+/// seam to the other side of it. Returns the wrapped points, followed by
+/// the copies, and the index of the point each of those came from.
+fn wrap_points(
+    points: &[XYPoint<f64>],
+    period: f64,
+    reach: f64,
+) -> (Vec<XYPoint<f64>>, Vec<usize>) {
+    let mut wrapped: Vec<XYPoint<f64>> = points
+        .iter()
+        .map(|p| XYPoint::new(p.x.rem_euclid(period), p.y))
+        .collect();
+    let mut origins: Vec<usize> = (0..points.len()).collect();
+    for i in 0..points.len() {
+        let point = wrapped[i];
+        if point.x < reach {
+            wrapped.push(XYPoint::new(point.x + period, point.y));
+            origins.push(i);
+        }
+        if point.x >= period - reach {
+            wrapped.push(XYPoint::new(point.x - period, point.y));
+            origins.push(i);
+        }
+    }
+    (wrapped, origins)
+}
+
+/// Like find_clusters, but with a cap on how many points are queued up to be
+/// expanded at once, to bound memory in very dense regions, where a point can
+/// have tens of thousands of neighbors.
+///
+/// Neighbors found while the queue is full join the cluster straight away,
+/// but are only checked for being core points themselves once the queue
+/// runs dry, by scanning the labels for them. So the clusters are the same
+/// as find_clusters', apart from which cluster claims a border point that's
+/// in reach of two, but the cap costs a scan of the labels each time the
+/// queue is refilled.
+pub fn find_clusters_capped<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    max_neighbors: usize,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let options = DbscanOptions {
+        max_neighbors: Some(max_neighbors),
+        ..Default::default()
+    };
+    find_clusters_with::<T>(points, eps, min_cluster_size, metric, &options)
+}
+
+/// Like find_clusters, but reads the points from separate columns of x and
+/// y, which must be the same length. With a tree that overrides
+/// from_columns, the points are never copied into a Vec, which saves time
+/// and memory on large inputs, like Arrow arrays handed over from Python.
+pub fn find_clusters_columns<T: SearchTree>(
+    xs: &[f64],
+    ys: &[f64],
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let tree: T = T::from_columns(xs, ys);
+    let point_at = |i: usize| XYPoint::new(xs[i], ys[i]);
+
+    let labels = dbscan(xs.len(), None, min_cluster_size, |i| {
+        neighbors_within_by(point_at, &tree, &point_at(i), eps, metric)
+    });
+    cluster_labels(&labels)
+}
+
+/// Like find_clusters, but with x wrapping around every period, like right
+/// ascension in degrees with a period of 360: points either side of the
+/// seam at x = 0 are neighbors. eps should be well under period.
+///
+/// The points within eps of the seam are copied to the other side of it,
+/// period away, before the tree is built. A neighbor found among those
+/// copies counts as the point it was copied from.
+pub fn find_clusters_periodic<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    period: f64,
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let options = DbscanOptions {
+        period: Some(period),
+        ..Default::default()
+    };
+    find_clusters_with::<T>(points, eps, min_cluster_size, metric, &options)
+}
+
+/// Like find_clusters, but also returns the role of each point in its
+/// cluster, for callers which trust core points more than border ones.
+pub fn find_clusters_classified<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+) -> Vec<(i32, PointRole)> {
+    find_clusters_classified_with::<T>(
+        points,
+        eps,
+        min_cluster_size,
+        metric,
+        &DbscanOptions::default(),
+    )
+}
+
+/// Like find_clusters, but each point counts as its weight toward
+/// min_cluster_size, rather than as one. weights must line up with points.
+pub fn find_clusters_weighted<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    weights: &[f64],
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let options = DbscanOptions {
+        weights: Some(weights),
+        ..Default::default()
+    };
+    find_clusters_with::<T>(points, eps, min_cluster_size, metric, &options)
+}
+
+/// Like find_clusters, but with copies of a point, at exactly the same
+/// coordinates, counted only once toward min_cluster_size, as if the
+/// catalog had measured that source once. The copies are clustered as one
+/// point, and all share its label.
+pub fn find_clusters_deduped<T: SearchTree>(
+    points: &[XYPoint<f64>],
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let (unique, copies) = dedup_points(points);
+    let unique_labels = find_clusters::<T>(&unique, eps, min_cluster_size, metric);
+    copies.iter().map(|&idx| unique_labels[idx]).collect()
+}
+
+/// Collapse points with exactly the same coordinates. Returns the distinct
+/// points, in order of first appearance, and the index among them of each
+/// of the original points.
+pub fn dedup_points(points: &[XYPoint<f64>]) -> (Vec<XYPoint<f64>>, Vec<usize>) {
+    let mut unique = Vec::new();
+    let mut seen: HashMap<(u64, u64), usize> = HashMap::new();
+    let copies = points
+        .iter()
+        .map(|point| {
+            // Adding 0.0 turns -0.0 into 0.0, which is the same place.
+            let key = ((point.x + 0.0).to_bits(), (point.y + 0.0).to_bits());
+            *seen.entry(key).or_insert_with(|| {
+                unique.push(*point);
+                unique.len() - 1
+            })
+        })
+        .collect();
+    (unique, copies)
+}
+
+/// Like find_clusters, but with neighborhoods which grow with the points'
+/// uncertainties. Two points are neighbors if they're within
+/// sqrt(eps^2 + n_sigma^2 * (sigma_a^2 + sigma_b^2)) of each other: eps,
+/// widened by their sigmas added in quadrature. A precisely measured point
+/// only links to points close by, while a noisy one reaches further. sigmas
+/// must line up with points, and are in the same units as eps. With every
+/// sigma 0, this is the same as find_clusters.
+pub fn find_clusters_uncertain<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    sigmas: &[f64],
+    eps: f64,
+    n_sigma: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let options = DbscanOptions {
+        sigmas: Some((sigmas, n_sigma)),
+        ..Default::default()
+    };
+    find_clusters_with::<T>(points, eps, min_cluster_size, metric, &options)
+}
+
+/// Find clusters of points in 3D space with DBSCAN, using euclidean
+/// distance. Noise is labeled -1 and clusters are labeled from 1.
+pub fn find_clusters_3d(points: &[XYZPoint<f64>], eps: f64, min_cluster_size: usize) -> Vec<i32> {
+    let tree = xyz_kdtree::from_points(points);
+
+    let labels = dbscan(points.len(), None, min_cluster_size, |i| {
+        xyz_kdtree::neighbors(&tree, &points[i], eps)
+    });
+    cluster_labels(&labels)
+}
+
+fn cluster_labels(labels: &[DBScanClassification]) -> Vec<i32> {
     labels
         .iter()
         .map(|label| match label {
             DBScanClassification::Noise => -1,
-            DBScanClassification::Border(i) => *i as i32,
+            DBScanClassification::Border(i) | DBScanClassification::Deferred(i) => *i as i32,
             DBScanClassification::Core(i) => *i as i32,
             DBScanClassification::Undefined => -1,
         })
         .collect()
 }
 
-fn dbscan(
-    points: &Vec<XYPoint<f64>>,
+/// The distance from each point to its k-th nearest neighbor, counting the
+/// point itself, sorted from nearest to farthest. Plotted, this is the
+/// k-distance graph: a good eps for min_cluster_size = k is near the knee,
+/// where the distances start climbing steeply. Points with fewer than k
+/// neighbors in total have an infinite distance.
+pub fn estimate_eps(points: &[XYPoint<f64>], k: usize) -> Vec<f64> {
+    if k == 0 {
+        return vec![0.0; points.len()];
+    }
+    let tree = float32_kdtree::PointTree::from_points(&points.to_vec());
+    let mut distances: Vec<f64> = points
+        .iter()
+        .map(|point| {
+            let neighbors =
+                tree.nearest_n(&[point.x as f32, point.y as f32], k, &squared_euclidean);
+            match neighbors.get(k - 1) {
+                Some(neighbor) => (neighbor.distance as f64).sqrt(),
+                None => f64::INFINITY,
+            }
+        })
+        .collect();
+    distances.sort_by(|a, b| a.total_cmp(b));
+    distances
+}
+
+/// The k-nearest-neighbor graph of the points, for clustering them some
+/// other way: an edge (i, j, distance) from each point i to each of its k
+/// nearest neighbors j, not counting itself, nearest first. Points have
+/// fewer than k edges if there are no more than k points in total.
+///
+/// The edges are directed; j being one of i's nearest neighbors doesn't
+/// make i one of j's. The distances are euclidean, in full precision.
+pub fn knn_graph(points: &[XYPoint<f64>], k: usize) -> Vec<(usize, usize, f64)> {
+    if k == 0 || points.is_empty() {
+        return Vec::new();
+    }
+    // The same float32 tree as find_clusters, but with a 32-bit index, since
+    // graphs are often built over more points than a 16-bit one can number.
+    let mut tree: kiddo::float::kdtree::KdTree<f32, u32, 2, 32, u32> =
+        kiddo::float::kdtree::KdTree::with_capacity(points.len());
+    for (idx, point) in points.iter().enumerate() {
+        tree.add(&[point.x as f32, point.y as f32], idx as u32);
+    }
+    let mut edges = Vec::with_capacity(points.len() * k.min(points.len() - 1));
+    for (i, point) in points.iter().enumerate() {
+        // One extra, since the point finds itself. With duplicates, it might
+        // not be among them, so it's skipped by index rather than position.
+        let neighbors = tree.nearest_n(
+            &[point.x as f32, point.y as f32],
+            k.saturating_add(1),
+            &squared_euclidean,
+        );
+        let mut found: Vec<(usize, usize, f64)> = neighbors
+            .iter()
+            .map(|neighbor| neighbor.item as usize)
+            .filter(|&j| j != i)
+            .take(k)
+            .map(|j| (i, j, point.euclidean_distance(&points[j])))
+            .collect();
+        found.sort_by(|a, b| a.2.total_cmp(&b.2));
+        edges.extend(found);
+    }
+    edges
+}
+
+/// The points of catalog within eps of each of queries, for linking
+/// observations some other way than clustering: for each query point, the
+/// indexes into catalog of its neighbors, in increasing order. Distances are
+/// euclidean, in full precision, and a point exactly eps away is a neighbor.
+pub fn radius_query(
+    catalog: &[XYPoint<f64>],
+    queries: &[XYPoint<f64>],
+    eps: f64,
+) -> Vec<Vec<usize>> {
+    if catalog.is_empty() {
+        return vec![Vec::new(); queries.len()];
+    }
+    // Catalogs can be far bigger than a 16-bit index can number, and often
+    // repeat coordinates, which kiddo's trees can't hold more than a bucket
+    // of on one axis, so this uses an R-tree with a 32-bit index. Its search
+    // is in full precision, and includes points exactly on the radius.
+    let entries: Vec<GeomWithData<[f64; 2], u32>> = catalog
+        .iter()
+        .enumerate()
+        .map(|(idx, point)| GeomWithData::new([point.x, point.y], idx as u32))
+        .collect();
+    let tree = RTree::bulk_load(entries);
+    queries
+        .iter()
+        .map(|query| {
+            let mut neighbors: Vec<usize> = tree
+                .locate_within_distance([query.x, query.y], eps * eps)
+                .map(|entry| entry.data as usize)
+                .collect();
+            neighbors.sort_unstable();
+            neighbors
+        })
+        .collect()
+}
+
+/// The mean silhouette coefficient of the non-noise points, using euclidean
+/// distance. For each point, a is the mean distance to the rest of its
+/// cluster and b is the mean distance to the nearest other cluster; its
+/// coefficient is (b - a) / max(a, b), and is 0 for a cluster of one. Scores
+/// near 1 mean tight, well separated clusters.
+///
+/// With a single cluster there's nothing to be separated from, so the score
+/// is 0. If every point is noise, it's NaN.
+pub fn silhouette_score(points: &[XYPoint<f64>], labels: &[i32]) -> f64 {
+    let groups: Vec<Vec<usize>> = group_labels(labels);
+    match groups.len() {
+        0 => return f64::NAN,
+        1 => return 0.0,
+        _ => {}
+    }
+
+    let mean_distance = |point: &XYPoint<f64>, group: &[usize]| -> f64 {
+        let total: f64 = group
+            .iter()
+            .map(|&j| point.euclidean_distance(&points[j]))
+            .sum();
+        total / group.len() as f64
+    };
+
+    let mut total = 0.0;
+    let mut n = 0;
+    for (g, group) in groups.iter().enumerate() {
+        for &i in group.iter() {
+            n += 1;
+            if group.len() == 1 {
+                continue;
+            }
+            let point = &points[i];
+            // The point itself adds nothing to the sum, but mustn't be counted.
+            let a = mean_distance(point, group) * group.len() as f64 / (group.len() - 1) as f64;
+            let b = groups
+                .iter()
+                .enumerate()
+                .filter(|&(h, _)| h != g)
+                .map(|(_, other)| mean_distance(point, other))
+                .fold(f64::INFINITY, f64::min);
+            let max = a.max(b);
+            if max > 0.0 {
+                total += (b - a) / max;
+            }
+        }
+    }
+    total / n as f64
+}
+
+/// The adjusted Rand index of two labelings of the same points: how often
+/// they agree on whether a pair of points is in the same cluster, corrected
+/// for the agreement expected by chance. Identical clusterings score 1, no
+/// matter what their clusters are numbered; unrelated ones score about 0,
+/// and can score below it.
+///
+/// With exclude_noise, points which are noise (-1) in either labeling are
+/// left out. Otherwise, noise is treated as one more cluster.
+pub fn adjusted_rand_index(labels_a: &[i32], labels_b: &[i32], exclude_noise: bool) -> f64 {
+    let mut contingency: HashMap<(i32, i32), usize> = HashMap::new();
+    let mut sizes_a: HashMap<i32, usize> = HashMap::new();
+    let mut sizes_b: HashMap<i32, usize> = HashMap::new();
+    let mut n = 0;
+    for (&a, &b) in labels_a.iter().zip(labels_b.iter()) {
+        if exclude_noise && (a < 0 || b < 0) {
+            continue;
+        }
+        *contingency.entry((a, b)).or_default() += 1;
+        *sizes_a.entry(a).or_default() += 1;
+        *sizes_b.entry(b).or_default() += 1;
+        n += 1;
+    }
+
+    let pairs = |count: &usize| (count * count.saturating_sub(1)) as f64 / 2.0;
+    let index: f64 = contingency.values().map(pairs).sum();
+    let pairs_a: f64 = sizes_a.values().map(pairs).sum();
+    let pairs_b: f64 = sizes_b.values().map(pairs).sum();
+    let expected = pairs_a * pairs_b / pairs(&n).max(1.0);
+    let max = (pairs_a + pairs_b) / 2.0;
+    if max == expected {
+        // Either both labelings put every point in one cluster, or both put
+        // every point in a cluster of its own. Either way, they agree.
+        return 1.0;
+    }
+    (index - expected) / (max - expected)
+}
+
+/// Relabel the points of any cluster with more than max_cluster_size points
+/// as noise. Implausibly large clusters are usually dense star fields or
+/// artifacts rather than moving objects. The other clusters keep their
+/// labels.
+pub fn drop_large_clusters(labels: &mut [i32], max_cluster_size: usize) {
+    let mut sizes: HashMap<i32, usize> = HashMap::new();
+    for &label in labels.iter().filter(|label| **label >= 0) {
+        *sizes.entry(label).or_default() += 1;
+    }
+    for label in labels.iter_mut() {
+        if sizes
+            .get(label)
+            .is_some_and(|&size| size > max_cluster_size)
+        {
+            *label = -1;
+        }
+    }
+}
+
+/// Renumber the clusters 1, 2, 3, ... with no gaps, keeping them in the same
+/// order. Some algorithms skip labels, as do drop_large_clusters and the
+/// merged passes of Hotspot2D. Noise (any negative label) becomes -1.
+pub fn relabel_contiguous(labels: &mut [i32]) {
+    let mut distinct: Vec<i32> = labels.iter().copied().filter(|&l| l >= 0).collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    for label in labels.iter_mut() {
+        *label = match distinct.binary_search(label) {
+            Ok(idx) => idx as i32 + 1,
+            Err(_) => -1,
+        };
+    }
+}
+
+/// Group point indexes by cluster label. There's one group per cluster, in
+/// order of label, each listing its points' indexes in order. Noise (any
+/// negative label) is left out.
+pub fn group_labels(labels: &[i32]) -> Vec<Vec<usize>> {
+    let mut groups: BTreeMap<i32, Vec<usize>> = BTreeMap::new();
+    for (idx, &label) in labels.iter().enumerate() {
+        if label >= 0 {
+            groups.entry(label).or_default().push(idx);
+        }
+    }
+    groups.into_values().collect()
+}
+
+/// Find the indexes of all points within eps of point, under the given metric.
+pub(crate) fn neighbors_within(
+    points: &[XYPoint<f64>],
+    tree: &impl SearchTree,
+    point: &XYPoint<f64>,
+    eps: f64,
+    metric: &DistanceMetric,
+) -> Vec<usize> {
+    neighbors_within_by(|idx| points[idx], tree, point, eps, metric)
+}
+
+/// Like neighbors_within, but looking up the points the tree holds by index
+/// with point_at.
+fn neighbors_within_by(
+    point_at: impl Fn(usize) -> XYPoint<f64>,
     tree: &impl SearchTree,
+    point: &XYPoint<f64>,
     eps: f64,
+    metric: &DistanceMetric,
+) -> Vec<usize> {
+    match metric {
+        DistanceMetric::Euclidean => tree.neighbors(point, eps),
+        // Manhattan distance is never less than euclidean distance, so the
+        // euclidean neighbors are a superset that only needs filtering.
+        DistanceMetric::Manhattan => tree
+            .neighbors(point, eps)
+            .into_iter()
+            .filter(|&idx| metric.distance(point, &point_at(idx)) <= eps)
+            .collect(),
+        // The tree must be a SphereTree, which searches by chord length.
+        DistanceMetric::Haversine => tree.neighbors(point, sphere_kdtree::chord_length(eps)),
+    }
+}
+
+/// The total weight of a neighborhood. Without weights, every point counts
+/// as one.
+fn weight(neighbors: &[usize], weights: Option<&[f64]>) -> f64 {
+    match weights {
+        Some(weights) => neighbors.iter().map(|&idx| weights[idx]).sum(),
+        None => neighbors.len() as f64,
+    }
+}
+
+/// Label n_points points with DBSCAN, as find_clusters does, for points
+/// kept somewhere other than a SearchTree. neighbors finds the indexes of
+/// the points within eps of the point at an index, and must be symmetric.
+pub(crate) fn label_with_neighbors(
+    n_points: usize,
+    min_cluster_size: usize,
+    neighbors: impl Fn(usize) -> Vec<usize>,
+) -> Vec<i32> {
+    cluster_labels(&dbscan(n_points, None, min_cluster_size, neighbors))
+}
+
+/// Label n_points points with DBSCAN. neighbors finds the indexes of the
+/// points within eps of the point at an index.
+fn dbscan(
+    n_points: usize,
+    weights: Option<&[f64]>,
     min_cluster_size: usize,
+    neighbors: impl Fn(usize) -> Vec<usize>,
 ) -> Vec<DBScanClassification> {
-    let mut labels: Vec<DBScanClassification> = vec![DBScanClassification::Undefined; points.len()];
+    dbscan_capped(n_points, weights, min_cluster_size, None, neighbors).0
+}
+
+/// Like dbscan, but with at most max_neighbors points queued up to be
+/// expanded at once; see find_clusters_capped. Also returns the longest the
+/// queue got.
+fn dbscan_capped(
+    n_points: usize,
+    weights: Option<&[f64]>,
+    min_cluster_size: usize,
+    max_neighbors: Option<usize>,
+    neighbors: impl Fn(usize) -> Vec<usize>,
+) -> (Vec<DBScanClassification>, usize) {
+    let mut labels: Vec<DBScanClassification> = vec![DBScanClassification::Undefined; n_points];
     let mut cluster_idx: u16 = 0;
+    let mut peak_queue = 0;
+    let cap = max_neighbors.unwrap_or(usize::MAX).max(1);
 
-    for (i, point) in points.iter().enumerate() {
+    // Claim the neighbors of a core point which aren't in a cluster yet,
+    // and queue them up to see whether they're core points too. Once the
+    // queue is full, the rest are deferred: they're in the cluster, but are
+    // only checked once the queue has room again. Returns how many were.
+    let claim = |queue: &mut Vec<usize>,
+                 labels: &mut [DBScanClassification],
+                 neighbors: Vec<usize>,
+                 cluster_idx: u16| {
+        let mut deferred = 0;
+        for idx in neighbors {
+            match labels[idx] {
+                DBScanClassification::Noise => {
+                    // Maybe you can join our cluster? You were already found
+                    // to be too small to be a core point, so that's all.
+                    labels[idx] = DBScanClassification::Border(cluster_idx);
+                }
+                DBScanClassification::Undefined => {
+                    // Anything reachable from a core point is in the
+                    // cluster, even if it turns out not to be a core point
+                    // itself.
+                    if queue.len() < cap {
+                        labels[idx] = DBScanClassification::Border(cluster_idx);
+                        queue.push(idx);
+                    } else {
+                        labels[idx] = DBScanClassification::Deferred(cluster_idx);
+                        deferred += 1;
+                    }
+                }
+                // You're already with someone else
+                _ => {}
+            }
+        }
+        deferred
+    };
+
+    // Where to pick up looking for deferred points when the queue runs dry.
+    let mut cursor = 0;
+    for i in 0..n_points {
         if labels[i] != DBScanClassification::Undefined {
             // Already visited
             continue;
         }
-        let neighbors = tree.neighbors(point, eps);
+        let point_neighbors = neighbors(i);
 
-        if neighbors.len() < min_cluster_size {
+        if weight(&point_neighbors, weights) < min_cluster_size as f64 {
             // Too small
             labels[i] = DBScanClassification::Noise;
             continue;
@@ -59,24 +831,739 @@ fn dbscan(
         // Big enough, hooray!
         cluster_idx += 1;
         labels[i] = DBScanClassification::Core(cluster_idx);
-        let mut queue = neighbors;
-        while let Some(neighbor_idx) = queue.pop() {
-            let neighbor_label = &labels[neighbor_idx];
-            if *neighbor_label == DBScanClassification::Noise {
-                // Maybe you can join our cluster?
-                labels[neighbor_idx] = DBScanClassification::Border(cluster_idx);
-            } else if *neighbor_label != DBScanClassification::Undefined {
-                // You're already with someone else
-                continue;
+        let mut queue = Vec::new();
+        let mut deferred = claim(&mut queue, &mut labels, point_neighbors, cluster_idx);
+        loop {
+            peak_queue = peak_queue.max(queue.len());
+            while let Some(neighbor_idx) = queue.pop() {
+                let neighbors_of_neighbor = neighbors(neighbor_idx);
+                if weight(&neighbors_of_neighbor, weights) >= min_cluster_size as f64 {
+                    // You're big enough to join us
+                    labels[neighbor_idx] = DBScanClassification::Core(cluster_idx);
+                    deferred += claim(&mut queue, &mut labels, neighbors_of_neighbor, cluster_idx);
+                    peak_queue = peak_queue.max(queue.len());
+                }
             }
-            let _neighbor = &points[neighbor_idx];
-            let neighbors_of_neighbor = tree.neighbors(point, eps);
-            if neighbors_of_neighbor.len() >= min_cluster_size {
-                // You're big enough to join us
-                labels[neighbor_idx] = DBScanClassification::Core(cluster_idx);
-                queue.extend(neighbors_of_neighbor);
+            if deferred == 0 {
+                break;
+            }
+            // Refill the queue with deferred points, carrying on around the
+            // labels from wherever the last refill stopped.
+            while deferred > 0 && queue.len() < cap {
+                if labels[cursor] == DBScanClassification::Deferred(cluster_idx) {
+                    labels[cursor] = DBScanClassification::Border(cluster_idx);
+                    queue.push(cursor);
+                    deferred -= 1;
+                }
+                cursor = (cursor + 1) % n_points;
             }
         }
     }
-    labels
+    (labels, peak_queue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(separation: f64) -> Vec<XYPoint<f64>> {
+        vec![XYPoint::new(1.0, 1.0), XYPoint::new(1.0 + separation, 1.0)]
+    }
+
+    fn check_eps_units<T: SearchTree>() {
+        // Just inside eps: the two points are neighbors.
+        let labels = find_clusters::<T>(&pair(0.45), 0.5, 2, &DistanceMetric::Euclidean);
+        assert_eq!(labels, vec![1, 1]);
+
+        // Exactly eps apart: the points are neighbors, for every tree.
+        let labels = find_clusters::<T>(&pair(0.5), 0.5, 2, &DistanceMetric::Euclidean);
+        assert_eq!(labels, vec![1, 1]);
+
+        // Just outside eps, but within sqrt(eps): the points must not be
+        // neighbors.
+        let labels = find_clusters::<T>(&pair(0.6), 0.5, 2, &DistanceMetric::Euclidean);
+        assert_eq!(labels, vec![-1, -1]);
+    }
+
+    fn check_metric<T: SearchTree>() {
+        // (1.3, 1.3) is about 0.42 from (1, 1) in euclidean distance, but 0.6
+        // in manhattan distance.
+        let points = vec![XYPoint::new(1.0, 1.0), XYPoint::new(1.3, 1.3)];
+        let labels = find_clusters::<T>(&points, 0.5, 2, &DistanceMetric::Euclidean);
+        assert_eq!(labels, vec![1, 1]);
+        let labels = find_clusters::<T>(&points, 0.5, 2, &DistanceMetric::Manhattan);
+        assert_eq!(labels, vec![-1, -1]);
+    }
+
+    /// Points scattered over the unit square, from a fixed seed.
+    fn random_points(n: usize, seed: u64) -> Vec<XYPoint<f64>> {
+        let mut state = seed;
+        let mut next = || {
+            // A linear congruential generator, from Knuth's MMIX.
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        (0..n).map(|_| XYPoint::new(next(), next())).collect()
+    }
+
+    /// Renumber labels in order of first appearance, so that partitions can
+    /// be compared regardless of how their clusters are numbered.
+    fn canonical(labels: &[i32]) -> Vec<i32> {
+        let mut renumbered = std::collections::HashMap::new();
+        labels
+            .iter()
+            .map(|&label| {
+                if label == -1 {
+                    return -1;
+                }
+                let next = renumbered.len() as i32 + 1;
+                *renumbered.entry(label).or_insert(next)
+            })
+            .collect()
+    }
+
+    fn check_matches_brute_force<T: SearchTree>() {
+        let metric = DistanceMetric::Euclidean;
+        for seed in 0..20 {
+            let points = random_points(300, seed);
+            for (eps, min_cluster_size) in [(0.02, 2), (0.05, 4), (0.1, 8)] {
+                let want = find_clusters::<brute_force::BruteForceTree>(
+                    &points,
+                    eps,
+                    min_cluster_size,
+                    &metric,
+                );
+                let have = find_clusters::<T>(&points, eps, min_cluster_size, &metric);
+                assert_eq!(
+                    canonical(&have),
+                    canonical(&want),
+                    "seed={} eps={} min_cluster_size={}",
+                    seed,
+                    eps,
+                    min_cluster_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_float32_kdtree_matches_brute_force() {
+        check_matches_brute_force::<float32_kdtree::PointTree>();
+    }
+
+    #[test]
+    fn test_rstar_matches_brute_force() {
+        check_matches_brute_force::<rstar::Tree>();
+    }
+
+    fn check_columns_match_points<T: SearchTree>() {
+        for seed in 0..5 {
+            let points = random_points(300, seed);
+            let xs: Vec<f64> = points.iter().map(|p| p.x).collect();
+            let ys: Vec<f64> = points.iter().map(|p| p.y).collect();
+            for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan] {
+                let want = find_clusters::<T>(&points, 0.05, 4, &metric);
+                assert!(want.iter().any(|&label| label != -1));
+                assert_eq!(find_clusters_columns::<T>(&xs, &ys, 0.05, 4, &metric), want);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_clusters_columns() {
+        check_columns_match_points::<float32_kdtree::PointTree>();
+        check_columns_match_points::<rstar::Tree>();
+        check_columns_match_points::<fixed16_kdtree::FixedPointTree>();
+        check_columns_match_points::<brute_force::BruteForceTree>();
+    }
+
+    #[test]
+    fn test_max_neighbors() {
+        // A dense blob, where every point neighbors every other, and a short
+        // tail off one side of it, which is only reached through the blob's
+        // edge.
+        let mut points: Vec<XYPoint<f64>> = random_points(2000, 7)
+            .into_iter()
+            .map(|p| XYPoint::new(p.x * 0.05, p.y * 0.05))
+            .collect();
+        points.extend((1..=3).map(|i| XYPoint::new(0.05 + 0.08 * i as f64, 0.025)));
+        let tree = float32_kdtree::PointTree::from_points(&points);
+        let metric = DistanceMetric::Euclidean;
+        let neighbors = |i: usize| neighbors_within(&points, &tree, &points[i], 0.1, &metric);
+
+        let (uncapped, uncapped_peak) = dbscan_capped(points.len(), None, 5, None, neighbors);
+        let (capped, capped_peak) = dbscan_capped(points.len(), None, 5, Some(10), neighbors);
+        assert!(uncapped_peak >= 1999, "{}", uncapped_peak);
+        // The first point claims the whole blob, and only queues 10 of it;
+        // the rest wait their turn.
+        assert!(capped_peak <= 10, "{}", capped_peak);
+
+        // The blob and the start of the tail are one cluster either way,
+        // since the blob's edge is still expanded once its turn comes; the
+        // end of the tail is too far out.
+        let (uncapped, capped) = (cluster_labels(&uncapped), cluster_labels(&capped));
+        let mut want = vec![1; 2002];
+        want.push(-1);
+        assert_eq!(uncapped, want);
+        assert_eq!(capped, want);
+        assert_eq!(
+            find_clusters_capped::<float32_kdtree::PointTree>(&points, 0.1, 5, 10, &metric),
+            capped
+        );
+        assert_eq!(
+            find_clusters_capped::<float32_kdtree::PointTree>(&points, 0.1, 5, 5000, &metric),
+            uncapped
+        );
+    }
+
+    #[test]
+    fn test_find_clusters_periodic() {
+        // A cluster straddling the seam at 0/360, and another well away from
+        // it, given with x in [-180, 180) so that the seam's in the middle.
+        let points = vec![
+            XYPoint::new(-0.2, 10.0),
+            XYPoint::new(-0.1, 10.0),
+            XYPoint::new(0.05, 10.0),
+            XYPoint::new(0.15, 10.0),
+            XYPoint::new(359.95, 10.05),
+            XYPoint::new(90.0, -5.0),
+            XYPoint::new(90.1, -5.0),
+            XYPoint::new(90.0, -5.1),
+            XYPoint::new(180.0, 10.0),
+        ];
+        let metric = DistanceMetric::Euclidean;
+        let labels =
+            find_clusters_periodic::<float32_kdtree::PointTree>(&points, 360.0, 0.2, 3, &metric);
+        assert_eq!(labels, vec![1, 1, 1, 1, 1, 2, 2, 2, -1]);
+
+        // Without wrapping, the seam splits the first cluster, and the point
+        // at 359.95 is stranded.
+        let labels = find_clusters::<float32_kdtree::PointTree>(&points, 0.2, 3, &metric);
+        assert_eq!(labels, vec![1, 1, 1, 1, -1, 2, 2, 2, -1]);
+
+        // Well away from the seam, wrapping changes nothing.
+        let labels = find_clusters_periodic::<float32_kdtree::PointTree>(
+            &points[5..].to_vec(),
+            360.0,
+            0.2,
+            3,
+            &metric,
+        );
+        assert_eq!(labels, vec![1, 1, 1, -1]);
+    }
+
+    #[test]
+    fn test_fixed16_kdtree_matches_float32_over_wide_range() {
+        // Tight clumps of points, a few apart, spread over [-100, 100]: far
+        // outside what the fixed-point tree can store as-is.
+        let metric = DistanceMetric::Euclidean;
+        for seed in 0..5 {
+            let centers = random_points(30, seed);
+            let offsets = random_points(300, seed + 100);
+            let points: Vec<XYPoint<f64>> = offsets
+                .iter()
+                .enumerate()
+                .map(|(i, offset)| {
+                    let center = centers[i % centers.len()];
+                    XYPoint::new(
+                        200.0 * center.x - 100.0 + offset.x,
+                        200.0 * center.y - 100.0 + offset.y,
+                    )
+                })
+                .collect();
+            for (eps, min_cluster_size) in [(0.5, 3), (2.0, 5)] {
+                let want = find_clusters::<float32_kdtree::PointTree>(
+                    &points,
+                    eps,
+                    min_cluster_size,
+                    &metric,
+                );
+                let have = find_clusters::<fixed16_kdtree::FixedPointTree>(
+                    &points,
+                    eps,
+                    min_cluster_size,
+                    &metric,
+                );
+                assert!(want.iter().any(|&label| label != -1));
+                assert_eq!(
+                    canonical(&have),
+                    canonical(&want),
+                    "seed={} eps={} min_cluster_size={}",
+                    seed,
+                    eps,
+                    min_cluster_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_estimate_eps() {
+        // A tight clump with 0.01 spacing, and a sparse line with spacing 1.
+        let mut points: Vec<XYPoint<f64>> =
+            (0..5).map(|i| XYPoint::new(i as f64 * 0.01, 0.0)).collect();
+        points.extend((0..4).map(|i| XYPoint::new(10.0 + i as f64, 5.0)));
+
+        let distances = estimate_eps(&points, 2);
+        assert_eq!(distances.len(), 9);
+        assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+        for d in distances[..5].iter() {
+            assert!((d - 0.01).abs() < 1e-4);
+        }
+        for d in distances[5..].iter() {
+            assert!((d - 1.0).abs() < 1e-4);
+        }
+
+        // Not enough points to have a 10th neighbor.
+        let distances = estimate_eps(&points, 10);
+        assert!(distances.iter().all(|d| d.is_infinite()));
+    }
+
+    #[test]
+    fn test_knn_graph() {
+        // A unit square, with one corner pulled in, and a far away point.
+        let points = vec![
+            XYPoint::new(0.0, 0.0),
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(0.0, 1.0),
+            XYPoint::new(0.75, 0.75),
+            XYPoint::new(10.0, 10.0),
+        ];
+        let edges = knn_graph(&points, 2);
+        assert_eq!(edges.len(), 10);
+        for &(i, j, distance) in edges.iter() {
+            assert_ne!(i, j);
+            assert!((distance - points[i].euclidean_distance(&points[j])).abs() < 1e-12);
+        }
+        let neighbors = |i: usize| -> Vec<usize> {
+            edges
+                .iter()
+                .filter(|edge| edge.0 == i)
+                .map(|edge| edge.1)
+                .collect()
+        };
+        assert_eq!(neighbors(1), vec![3, 0]);
+        assert_eq!(neighbors(2), vec![3, 0]);
+        // The far point's edges go one way: nothing near the square has it
+        // as a neighbor.
+        assert_eq!(neighbors(4), vec![3, 1]);
+        assert!(!edges.iter().any(|edge| edge.1 == 4));
+        // Every edge within the square goes both ways.
+        let mutual = edges
+            .iter()
+            .filter(|a| edges.iter().any(|b| (b.0, b.1) == (a.1, a.0)))
+            .count();
+        assert_eq!(mutual, 8);
+
+        // Asking for more neighbors than there are points gives all of them.
+        let edges = knn_graph(&points, 10);
+        assert_eq!(edges.len(), 20);
+        assert!(knn_graph(&points, 0).is_empty());
+        assert!(knn_graph(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn test_radius_query() {
+        let catalog = vec![
+            XYPoint::new(0.0, 0.0),
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(0.0, 1.0),
+            XYPoint::new(0.5, 0.5),
+            XYPoint::new(0.5, 0.5),
+        ];
+        let queries = vec![
+            XYPoint::new(0.0, 0.0),
+            XYPoint::new(0.9, 0.1),
+            XYPoint::new(0.5, 0.6),
+            XYPoint::new(10.0, 10.0),
+        ];
+        let neighbors = radius_query(&catalog, &queries, 0.75);
+        assert_eq!(
+            neighbors,
+            vec![vec![0, 3, 4], vec![1, 3, 4], vec![2, 3, 4], vec![]]
+        );
+        assert!(radius_query(&catalog, &[], 0.75).is_empty());
+        // Exactly eps away counts.
+        assert_eq!(
+            radius_query(&catalog, &[XYPoint::new(1.5, 0.0)], 0.5),
+            [[1]]
+        );
+        assert_eq!(
+            radius_query(&[], &queries[..2], 0.75),
+            [Vec::<usize>::new(), Vec::new()]
+        );
+    }
+
+    #[test]
+    fn test_radius_query_large_catalog() {
+        // More points than a 16-bit index can number.
+        let catalog: Vec<XYPoint<f64>> = (0..70_000)
+            .map(|i| XYPoint::new((i % 300) as f64, (i / 300) as f64))
+            .collect();
+        let queries = vec![XYPoint::new(98.5, 233.0), XYPoint::new(0.0, 0.2)];
+        let neighbors = radius_query(&catalog, &queries, 0.6);
+        assert_eq!(neighbors, [vec![69_998, 69_999], vec![0]]);
+    }
+
+    #[test]
+    fn test_silhouette_score() {
+        let points = vec![
+            XYPoint::new(0.0, 0.0),
+            XYPoint::new(0.1, 0.0),
+            XYPoint::new(0.0, 0.1),
+            XYPoint::new(10.0, 10.0),
+            XYPoint::new(10.1, 10.0),
+            XYPoint::new(10.0, 10.1),
+            XYPoint::new(50.0, -50.0),
+        ];
+        let labels = vec![1, 1, 1, 2, 2, 2, -1];
+        let score = silhouette_score(&points, &labels);
+        assert!(score > 0.98 && score <= 1.0, "score = {}", score);
+
+        // Splitting a tight cluster down the middle scores worse.
+        let labels = vec![1, 1, 2, 2, 2, 2, -1];
+        assert!(silhouette_score(&points, &labels) < score);
+
+        assert_eq!(silhouette_score(&points, &[1, 1, 1, 1, 1, 1, -1]), 0.0);
+        assert!(silhouette_score(&points, &[-1; 7]).is_nan());
+    }
+
+    #[test]
+    fn test_adjusted_rand_index() {
+        let labels = vec![1, 1, 1, 2, 2, 2, 3, 3, 3];
+        assert_eq!(adjusted_rand_index(&labels, &labels, false), 1.0);
+
+        // Renumbering the clusters changes nothing.
+        let renumbered = vec![7, 7, 7, 1, 1, 1, 2, 2, 2];
+        assert_eq!(adjusted_rand_index(&labels, &renumbered, false), 1.0);
+
+        // Every pair in a cluster is split up, and every new pair is across
+        // clusters: worse than chance.
+        let shuffled = vec![1, 2, 3, 1, 2, 3, 1, 2, 3];
+        let ari = adjusted_rand_index(&labels, &shuffled, false);
+        assert!((ari + 1.0 / 3.0).abs() < 1e-12, "ari = {}", ari);
+
+        // Noise is a cluster of its own, unless it's excluded.
+        let a = vec![1, 1, 1, 2, 2, 2, -1, -1];
+        let b = vec![1, 1, 1, 2, 2, 2, 2, -1];
+        assert!(adjusted_rand_index(&a, &b, false) < 1.0);
+        assert_eq!(adjusted_rand_index(&a, &b, true), 1.0);
+
+        assert_eq!(adjusted_rand_index(&[], &[], false), 1.0);
+        assert_eq!(adjusted_rand_index(&[1, 1], &[1, 1], false), 1.0);
+    }
+
+    #[test]
+    fn test_find_clusters_reaches_border_points() {
+        // A line of points 0.1 apart. With eps 0.15, each one only reaches
+        // its neighbors on the line, so the cluster has to be grown point by
+        // point. The two ends only have one neighbor each, so they're border
+        // points, reachable from the core points next to them.
+        let points: Vec<XYPoint<f64>> = (0..6).map(|i| XYPoint::new(i as f64 * 0.1, 0.0)).collect();
+        let labels = find_clusters::<brute_force::BruteForceTree>(
+            &points,
+            0.15,
+            3,
+            &DistanceMetric::Euclidean,
+        );
+        assert_eq!(labels, vec![1; 6]);
+
+        // Visiting a border point first marks it as noise, before it's
+        // reached from the core point next to it.
+        let mut points = points;
+        points.reverse();
+        let labels = find_clusters::<brute_force::BruteForceTree>(
+            &points,
+            0.15,
+            3,
+            &DistanceMetric::Euclidean,
+        );
+        assert_eq!(labels, vec![1; 6]);
+    }
+
+    #[test]
+    fn test_group_labels() {
+        let labels = vec![2, -1, 1, 2, -1, 1, 3];
+        assert_eq!(group_labels(&labels), vec![vec![2, 5], vec![0, 3], vec![6]]);
+        assert_eq!(group_labels(&[-1, -1]), Vec::<Vec<usize>>::new());
+    }
+
+    #[test]
+    fn test_drop_large_clusters() {
+        // A dense 100-point blob, and a 4-point cluster well away from it.
+        let mut points: Vec<XYPoint<f64>> = (0..100)
+            .map(|i| XYPoint::new((i / 10) as f64 * 0.01, (i % 10) as f64 * 0.01))
+            .collect();
+        points.extend(vec![XYPoint::new(5.0, 5.0); 4]);
+        let mut labels = find_clusters::<brute_force::BruteForceTree>(
+            &points,
+            0.5,
+            4,
+            &DistanceMetric::Euclidean,
+        );
+        assert_eq!(group_labels(&labels).len(), 2);
+
+        drop_large_clusters(&mut labels, 50);
+        assert!(labels[..100].iter().all(|&label| label == -1));
+        assert_eq!(labels[100..], [2, 2, 2, 2]);
+
+        // Clusters right at the limit are kept.
+        drop_large_clusters(&mut labels, 4);
+        assert_eq!(labels[100..], [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_duplicate_points() {
+        // N copies of one point, far from anything else, are a cluster just
+        // when N reaches min_cluster_size, whatever eps is. The far point
+        // makes them span about 100, so the fixed-point tree has to scale
+        // them down, and its squared distances must still fit.
+        fn check<T: SearchTree>() {
+            for eps in [1e-6, 0.1, 10.0] {
+                for n in 1..=6 {
+                    let mut points = vec![XYPoint::new(1.5, -2.0); n];
+                    points.push(XYPoint::new(100.0, 100.0));
+                    let labels = find_clusters::<T>(&points, eps, 4, &DistanceMetric::Euclidean);
+                    let want = if n >= 4 { 1 } else { -1 };
+                    assert_eq!(labels[..n], vec![want; n], "eps {}, n {}", eps, n);
+                    assert_eq!(labels[n], -1);
+                }
+            }
+        }
+        check::<float32_kdtree::PointTree>();
+        check::<rstar::Tree>();
+        check::<fixed16_kdtree::FixedPointTree>();
+        check::<brute_force::BruteForceTree>();
+    }
+
+    #[test]
+    fn test_find_clusters_deduped() {
+        // A source measured four times, and three distinct points in a row.
+        let mut points = vec![XYPoint::new(0.0, 0.0); 4];
+        points.extend([
+            XYPoint::new(5.0, 0.0),
+            XYPoint::new(5.1, 0.0),
+            XYPoint::new(5.2, 0.0),
+            XYPoint::new(-0.0, 0.0),
+        ]);
+        let metric = DistanceMetric::Euclidean;
+
+        let (unique, copies) = dedup_points(&points);
+        assert_eq!(unique.len(), 4);
+        assert_eq!(copies, [0, 0, 0, 0, 1, 2, 3, 0]);
+
+        let labels = find_clusters::<float32_kdtree::PointTree>(&points, 0.15, 3, &metric);
+        assert_eq!(labels, [1, 1, 1, 1, 2, 2, 2, 1]);
+        let labels = find_clusters_deduped::<float32_kdtree::PointTree>(&points, 0.15, 3, &metric);
+        assert_eq!(labels, [-1, -1, -1, -1, 1, 1, 1, -1]);
+        // With a wider eps, the copies join the row as one of its points.
+        let labels = find_clusters_deduped::<float32_kdtree::PointTree>(&points, 5.05, 3, &metric);
+        assert_eq!(labels, [1; 8]);
+    }
+
+    #[test]
+    fn test_relabel_contiguous() {
+        let mut labels = vec![1, 1, 3, 3, -1];
+        relabel_contiguous(&mut labels);
+        assert_eq!(labels, [1, 1, 2, 2, -1]);
+
+        // Order is kept, and any negative label is noise.
+        let mut labels = vec![7, -2, 0, 7, 4, -1];
+        relabel_contiguous(&mut labels);
+        assert_eq!(labels, [3, -1, 1, 3, 2, -1]);
+
+        let mut labels: Vec<i32> = Vec::new();
+        relabel_contiguous(&mut labels);
+        assert!(labels.is_empty());
+    }
+
+    #[test]
+    fn test_find_clusters_3d_two_hits() {
+        let mut points = vec![XYZPoint::new(1.0, 0.0, 0.0); 4];
+        points.extend(vec![XYZPoint::new(2.0, 0.0, 0.0); 4]);
+        let labels = find_clusters_3d(&points, 0.5, 4);
+        assert_eq!(labels, vec![1, 1, 1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_find_clusters_3d_near_miss() {
+        // Three points together and one a little off along z: close in x
+        // and y, but not close enough in 3D.
+        let mut points = vec![XYZPoint::new(1.0, 0.0, 0.0); 3];
+        points.push(XYZPoint::new(1.0, 0.0, 1.0));
+        points.extend(vec![XYZPoint::new(2.0, 0.0, 0.0); 4]);
+        let labels = find_clusters_3d(&points, 0.5, 4);
+        assert_eq!(labels, vec![-1, -1, -1, -1, 1, 1, 1, 1]);
+
+        let labels = find_clusters_3d(&points, 1.5, 4);
+        assert!(labels.iter().all(|&l| l == 1));
+    }
+
+    #[test]
+    fn test_find_clusters_3d_exactly_eps_apart() {
+        // As in 2D, points exactly eps apart are neighbors, along any axis.
+        let points = vec![
+            XYZPoint::new(1.0, 1.0, 1.0),
+            XYZPoint::new(1.5, 1.0, 1.0),
+            XYZPoint::new(1.0, 1.5, 1.0),
+            XYZPoint::new(1.0, 1.0, 1.5),
+        ];
+        assert_eq!(find_clusters_3d(&points, 0.5, 4), vec![1, 1, 1, 1]);
+        assert_eq!(find_clusters_3d(&points, 0.49, 4), vec![-1; 4]);
+    }
+
+    #[test]
+    fn test_weights() {
+        let points = vec![
+            XYPoint::new(0.0, 0.0),
+            XYPoint::new(0.1, 0.0),
+            XYPoint::new(0.0, 0.1),
+            XYPoint::new(5.0, 5.0),
+            XYPoint::new(5.1, 5.0),
+            XYPoint::new(5.0, 5.1),
+        ];
+        let weights = vec![2.0, 2.0, 2.0, 1.0, 1.0, 1.0];
+        let metric = DistanceMetric::Euclidean;
+        let labels =
+            find_clusters_weighted::<float32_kdtree::PointTree>(&points, &weights, 0.5, 5, &metric);
+        assert_eq!(labels, vec![1, 1, 1, -1, -1, -1]);
+
+        // Unit weights are the same as no weights at all.
+        let labels = find_clusters_weighted::<float32_kdtree::PointTree>(
+            &points, &[1.0; 6], 0.5, 3, &metric,
+        );
+        assert_eq!(
+            labels,
+            find_clusters::<float32_kdtree::PointTree>(&points, 0.5, 3, &metric)
+        );
+    }
+
+    #[test]
+    fn test_uncertainties() {
+        // Two pairs of points 0.6 apart, just outside eps. The first pair is
+        // poorly measured, so it's within reach; the second is not.
+        let points = vec![
+            XYPoint::new(0.0, 0.0),
+            XYPoint::new(0.6, 0.0),
+            XYPoint::new(5.0, 5.0),
+            XYPoint::new(5.6, 5.0),
+        ];
+        let sigmas = vec![0.3, 0.3, 0.01, 0.01];
+        let metric = DistanceMetric::Euclidean;
+        let labels = find_clusters_uncertain::<brute_force::BruteForceTree>(
+            &points, &sigmas, 0.5, 1.0, 2, &metric,
+        );
+        assert_eq!(labels, vec![1, 1, -1, -1]);
+
+        // At 0 sigmas, the uncertainties are ignored.
+        let labels = find_clusters_uncertain::<brute_force::BruteForceTree>(
+            &points, &sigmas, 0.5, 0.0, 2, &metric,
+        );
+        assert_eq!(labels, vec![-1, -1, -1, -1]);
+
+        // With every sigma 0, this is the same as find_clusters.
+        let points = random_points(200, 7);
+        let labels = find_clusters_uncertain::<float32_kdtree::PointTree>(
+            &points,
+            &[0.0; 200],
+            0.05,
+            1.0,
+            4,
+            &metric,
+        );
+        assert_eq!(
+            labels,
+            find_clusters::<float32_kdtree::PointTree>(&points, 0.05, 4, &metric)
+        );
+    }
+
+    #[test]
+    fn test_find_clusters_with_combined_options() {
+        let metric = DistanceMetric::Euclidean;
+
+        // A heavy pair either side of the seam, and a light point far off.
+        let points = vec![
+            XYPoint::new(359.9, 0.0),
+            XYPoint::new(0.05, 0.0),
+            XYPoint::new(180.0, 0.0),
+        ];
+        let weights = [2.0, 2.0, 1.0];
+        let options = DbscanOptions {
+            weights: Some(&weights),
+            period: Some(360.0),
+            ..Default::default()
+        };
+        let labels =
+            find_clusters_with::<brute_force::BruteForceTree>(&points, 0.2, 4, &metric, &options);
+        assert_eq!(labels, vec![1, 1, -1]);
+
+        // A poorly measured pair 0.4 apart across the seam. The first is
+        // further from the seam than eps, but its sigma brings its copy in
+        // reach of the second.
+        let points = vec![XYPoint::new(359.7, 0.0), XYPoint::new(0.1, 0.0)];
+        let sigmas = [0.3, 0.3];
+        let options = DbscanOptions {
+            sigmas: Some((&sigmas, 1.0)),
+            period: Some(360.0),
+            ..Default::default()
+        };
+        let labels =
+            find_clusters_with::<float32_kdtree::PointTree>(&points, 0.2, 2, &metric, &options);
+        assert_eq!(labels, vec![1, 1]);
+
+        // A cap large enough to never bind changes nothing.
+        let points = random_points(200, 3);
+        let weights: Vec<f64> = (0..200).map(|i| (i % 3) as f64).collect();
+        let weighted = DbscanOptions {
+            weights: Some(&weights),
+            ..Default::default()
+        };
+        let capped = DbscanOptions {
+            max_neighbors: Some(1000),
+            ..weighted
+        };
+        assert_eq!(
+            find_clusters_with::<float32_kdtree::PointTree>(&points, 0.05, 4, &metric, &capped),
+            find_clusters_weighted::<float32_kdtree::PointTree>(
+                &points, &weights, 0.05, 4, &metric
+            ),
+        );
+    }
+
+    #[test]
+    fn test_metric_float32_kdtree() {
+        check_metric::<float32_kdtree::PointTree>();
+    }
+
+    #[test]
+    fn test_metric_fixed16_kdtree() {
+        check_metric::<fixed16_kdtree::FixedPointTree>();
+    }
+
+    #[test]
+    fn test_metric_rstar() {
+        check_metric::<rstar::Tree>();
+    }
+
+    #[test]
+    fn test_eps_units_float32_kdtree() {
+        check_eps_units::<float32_kdtree::PointTree>();
+    }
+
+    #[test]
+    fn test_eps_units_fixed16_kdtree() {
+        check_eps_units::<fixed16_kdtree::FixedPointTree>();
+    }
+
+    #[test]
+    fn test_eps_units_rstar() {
+        check_eps_units::<rstar::Tree>();
+    }
+
+    #[test]
+    fn test_eps_units_brute_force() {
+        check_eps_units::<brute_force::BruteForceTree>();
+    }
 }
@@ -0,0 +1,122 @@
+use crate::dbscan::SearchTree;
+use crate::points::XYPoint;
+
+// Keep every point in a flat list, and check them all on each search. This is
+// O(n) per search, so it's only useful as a reference to check the real
+// trees against.
+//
+// The coordinates are stored as separate x and y columns, and compared
+// against the search point LANES at a time, which the compiler turns into
+// SIMD instructions. Any points left over at the end are compared one by
+// one.
+pub struct BruteForceTree {
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+}
+
+const LANES: usize = 8;
+
+impl BruteForceTree {
+    /// The same as neighbors, but comparing one point at a time.
+    pub fn neighbors_scalar(&self, point: &XYPoint<f64>, radius: f64) -> Vec<usize> {
+        let radius_squared = radius * radius;
+        self.xs
+            .iter()
+            .zip(self.ys.iter())
+            .enumerate()
+            .filter(|(_, (x, y))| XYPoint::new(**x, **y).squared_distance(point) <= radius_squared)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+}
+
+/// Which of a batch of points are within radius_squared of point. Written
+/// so that the loop has no branches, and vectorizes.
+fn batch_within(
+    xs: &[f64; LANES],
+    ys: &[f64; LANES],
+    point: &XYPoint<f64>,
+    radius_squared: f64,
+) -> [bool; LANES] {
+    let mut within = [false; LANES];
+    for i in 0..LANES {
+        let dx = xs[i] - point.x;
+        let dy = ys[i] - point.y;
+        within[i] = dx * dx + dy * dy <= radius_squared;
+    }
+    within
+}
+
+impl SearchTree for BruteForceTree {
+    fn from_points(points: &Vec<XYPoint<f64>>) -> Self {
+        BruteForceTree {
+            xs: points.iter().map(|p| p.x).collect(),
+            ys: points.iter().map(|p| p.y).collect(),
+        }
+    }
+
+    fn from_columns(xs: &[f64], ys: &[f64]) -> Self {
+        BruteForceTree {
+            xs: xs.to_vec(),
+            ys: ys.to_vec(),
+        }
+    }
+
+    fn neighbors(&self, point: &XYPoint<f64>, radius: f64) -> Vec<usize> {
+        let radius_squared = radius * radius;
+        let mut found = Vec::new();
+        let xs = self.xs.chunks_exact(LANES);
+        let ys = self.ys.chunks_exact(LANES);
+        let remainder = (xs.remainder(), ys.remainder());
+        for (chunk, (xs, ys)) in xs.zip(ys).enumerate() {
+            let within = batch_within(
+                xs.try_into().unwrap(),
+                ys.try_into().unwrap(),
+                point,
+                radius_squared,
+            );
+            for (i, within) in within.iter().enumerate() {
+                if *within {
+                    found.push(chunk * LANES + i);
+                }
+            }
+        }
+        let first = self.xs.len() - remainder.0.len();
+        for (i, (x, y)) in remainder.0.iter().zip(remainder.1).enumerate() {
+            if XYPoint::new(*x, *y).squared_distance(point) <= radius_squared {
+                found.push(first + i);
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neighbors_matches_scalar() {
+        // Enough points for several full batches and a partial one, spread
+        // over a spiral so that distances vary.
+        let points: Vec<XYPoint<f64>> = (0..101)
+            .map(|i| {
+                let i = i as f64;
+                XYPoint::new(0.01 * i * (0.7 * i).cos(), 0.01 * i * (0.7 * i).sin())
+            })
+            .collect();
+        let tree = BruteForceTree::from_points(&points);
+        for point in points.iter() {
+            for radius in [0.0, 0.05, 0.2, 1.0] {
+                let want = tree.neighbors_scalar(point, radius);
+                assert_eq!(tree.neighbors(point, radius), want);
+                assert!(!want.is_empty());
+            }
+        }
+        // A radius just big enough to take in all of them.
+        assert_eq!(
+            tree.neighbors(&XYPoint::new(0.0, 0.0), 1.01).len(),
+            points.len()
+        );
+    }
+}
@@ -6,31 +6,180 @@ use kiddo::fixed::kdtree as kfixed;
 use crate::dbscan::SearchTree;
 use crate::points::XYPoint;
 
-pub type FixedPointTree = kfixed::KdTree<FixedU16<U14>, u32, 2, 32, u32>;
+type Fixed = FixedU16<U14>;
+
+// Store points in a 2-dimensional KD-tree of 16-bit fixed-point numbers, with
+// 14 fractional bits. Those can only hold values in [0, 4), and the squared
+// distances between them must fit too, or kiddo's distance overflows. So the
+// points are shifted to put the smallest x and y at 0, and then, if they span
+// 1 or more, scaled down by a power of two until they span less than that:
+// then no two points in the box are more than sqrt(2) apart, and no squared
+// distance is more than 2, even after rounding. Search points are transformed
+// the same way, and clamped into the box. A radius too big to hold once
+// squared covers the whole box anyway, so those searches check every point.
+//
+// Scaling costs precision: the points are only resolved to 2^-14 / scale,
+// and squared radii to 2^-14 / scale^2, which for points spanning hundreds
+// of degrees is coarser than most eps values. So the tree is only used to
+// find candidates, with the radius padded to cover any rounding, and the
+// candidates are then checked against the original points.
+pub struct FixedPointTree {
+    tree: kfixed::KdTree<Fixed, u32, 2, 32, u32>,
+    points: Vec<XYPoint<f64>>,
+    origin: XYPoint<f64>,
+    scale: f64,
+}
+
+/// The span of coordinates the points are scaled to fit within.
+const MAX_SPAN: f64 = 1.0;
+
+impl FixedPointTree {
+    /// Where point lands in the tree. Points outside the tree's box are
+    /// clamped to its edges, which only brings them closer to every point in
+    /// it, so no neighbor is missed.
+    fn to_fixed_point(&self, p: &XYPoint<f64>) -> [Fixed; 2] {
+        [
+            Fixed::from_num(((p.x - self.origin.x) * self.scale).clamp(0.0, MAX_SPAN)),
+            Fixed::from_num(((p.y - self.origin.y) * self.scale).clamp(0.0, MAX_SPAN)),
+        ]
+    }
+}
+
 impl SearchTree for FixedPointTree {
     fn from_points(points: &Vec<XYPoint<f64>>) -> Self {
-        let mut tree = kfixed::KdTree::with_capacity(points.len());
+        let mut origin = XYPoint::new(0.0, 0.0);
+        let mut span: f64 = 0.0;
+        if let Some(first) = points.first() {
+            let (mut min, mut max) = (*first, *first);
+            for point in points.iter() {
+                min = XYPoint::new(min.x.min(point.x), min.y.min(point.y));
+                max = XYPoint::new(max.x.max(point.x), max.y.max(point.y));
+            }
+            origin = min;
+            span = (max.x - min.x).max(max.y - min.y);
+        }
+        let mut scale = 1.0;
+        while span * scale >= MAX_SPAN {
+            scale /= 2.0;
+        }
+
+        let mut tree = FixedPointTree {
+            tree: kfixed::KdTree::with_capacity(points.len()),
+            points: points.clone(),
+            origin,
+            scale,
+        };
         for (idx, point) in points.iter().enumerate() {
-            tree.add(&to_fixed_point(point), idx as u32);
+            let fixed_point = tree.to_fixed_point(point);
+            tree.tree.add(&fixed_point, idx as u32);
         }
         tree
     }
 
     fn neighbors(&self, point: &XYPoint<f64>, radius: f64) -> Vec<usize> {
-        let eps = FixedU16::<U14>::from_num(radius);
-        let neighbors = self.within_unsorted(
-            &to_fixed_point(point),
-            eps,
+        // Rounding each coordinate can move points up to a step apart along
+        // each axis, and each squared term in the distance can be truncated
+        // by a step, so pad by enough to cover both. The distance function
+        // is squared, so the radius must be too.
+        let step = Fixed::DELTA.to_num::<f64>();
+        let padded = radius * self.scale + 2.0 * step;
+        let padded_squared = padded * padded + 3.0 * step;
+        let radius_squared = radius * radius;
+        if padded_squared >= Fixed::MAX.to_num::<f64>() {
+            return (0..self.points.len())
+                .filter(|&idx| self.points[idx].squared_distance(point) <= radius_squared)
+                .collect();
+        }
+        let candidates = self.tree.within_unsorted(
+            &self.to_fixed_point(point),
+            Fixed::from_num(padded_squared),
             &kfixed_distance::squared_euclidean,
         );
-        neighbors.iter().map(|n| n.item as usize).collect()
+        candidates
+            .iter()
+            .map(|n| n.item as usize)
+            .filter(|&idx| self.points[idx].squared_distance(point) <= radius_squared)
+            .collect()
     }
 }
 
-#[inline]
-fn to_fixed_point(p: &XYPoint<f64>) -> [FixedU16<U14>; 2] {
-    [
-        FixedU16::<U14>::from_num(p.x),
-        FixedU16::<U14>::from_num(p.y),
-    ]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_out_of_range_points() {
+        // Far outside [0, 4), and spanning more than it, in both directions.
+        let points = vec![
+            XYPoint::new(10.0, 10.0),
+            XYPoint::new(10.1, 10.0),
+            XYPoint::new(10.0, 10.1),
+            XYPoint::new(-3.0, 12.0),
+            XYPoint::new(-3.1, 12.0),
+        ];
+        let tree = FixedPointTree::from_points(&points);
+        assert_eq!(tree.scale, 0.0625);
+
+        let mut found = tree.neighbors(&points[0], 0.2);
+        found.sort();
+        assert_eq!(found, vec![0, 1, 2]);
+        let mut found = tree.neighbors(&points[3], 0.2);
+        found.sort();
+        assert_eq!(found, vec![3, 4]);
+        // 10.0 wraps around to 2.0 in plain fixed-point, so a point there
+        // mustn't match it.
+        assert!(tree.neighbors(&XYPoint::new(2.0, 10.0), 0.2).is_empty());
+    }
+
+    #[test]
+    fn test_in_range_points_are_not_scaled() {
+        let points = vec![XYPoint::new(0.5, 0.5), XYPoint::new(1.25, 1.0)];
+        let tree = FixedPointTree::from_points(&points);
+        assert_eq!(tree.scale, 1.0);
+        assert_eq!(tree.origin, XYPoint::new(0.5, 0.5));
+        assert_eq!(tree.neighbors(&points[0], 0.1), vec![0]);
+
+        assert!(FixedPointTree::from_points(&vec![])
+            .neighbors(&XYPoint::new(0.0, 0.0), 1.0)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_span_near_the_limit() {
+        // Opposite corners of a box just under the span that's stored
+        // without scaling, so that their squared distance is near 2.
+        let far = 1.0 - Fixed::DELTA.to_num::<f64>();
+        let points = vec![XYPoint::new(0.0, 0.0), XYPoint::new(far, far)];
+        let tree = FixedPointTree::from_points(&points);
+        assert_eq!(tree.scale, 1.0);
+        let diagonal = far * 2f64.sqrt();
+        for point in points.iter() {
+            let mut found = tree.neighbors(point, diagonal);
+            found.sort();
+            assert_eq!(found, vec![0, 1]);
+            assert_eq!(tree.neighbors(point, diagonal * 0.99).len(), 1);
+        }
+        // Search points far outside the box are clamped into it.
+        assert!(tree.neighbors(&XYPoint::new(50.0, -50.0), 0.5).is_empty());
+        assert_eq!(tree.neighbors(&XYPoint::new(-0.5, 0.0), 0.5), vec![0]);
+    }
+
+    #[test]
+    fn test_eps_larger_than_span() {
+        let points = vec![
+            XYPoint::new(0.0, 0.0),
+            XYPoint::new(100.0, -100.0),
+            XYPoint::new(-100.0, 100.0),
+        ];
+        let tree = FixedPointTree::from_points(&points);
+        // Too big to hold once scaled and squared, so every point is checked.
+        let mut found = tree.neighbors(&points[0], 1000.0);
+        found.sort();
+        assert_eq!(found, vec![0, 1, 2]);
+        let mut found = tree.neighbors(&points[1], 250.0);
+        found.sort();
+        assert_eq!(found, vec![0, 1]);
+        // Wider than the box, from a search point outside it.
+        assert_eq!(tree.neighbors(&XYPoint::new(500.0, 0.0), 450.0), vec![1]);
+    }
 }
@@ -16,13 +16,28 @@ impl SearchTree for PointTree {
         tree
     }
 
+    fn from_columns(xs: &[f64], ys: &[f64]) -> Self {
+        let mut tree = kfloat::KdTree::with_capacity(xs.len());
+        for (idx, (x, y)) in xs.iter().zip(ys.iter()).enumerate() {
+            tree.add(&[*x as f32, *y as f32], idx as u16);
+        }
+        tree
+    }
+
     fn neighbors(&self, point: &XYPoint<f64>, radius: f64) -> Vec<usize> {
-        let eps = (radius * radius) as f32;
+        let radius_squared = (radius * radius) as f32;
+        // kiddo only finds points strictly within the radius, so search a
+        // little further, and keep the points exactly on it too.
+        let padded = radius_squared * (1.0 + f32::EPSILON) + f32::MIN_POSITIVE;
         let neighbors = self.within_unsorted(
             &[point.x as f32, point.y as f32],
-            eps,
+            padded,
             &distance::squared_euclidean,
         );
-        neighbors.iter().map(|n| n.item as usize).collect()
+        neighbors
+            .iter()
+            .filter(|n| n.distance <= radius_squared)
+            .map(|n| n.item as usize)
+            .collect()
     }
 }
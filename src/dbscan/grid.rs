@@ -0,0 +1,125 @@
+use crate::dbscan::{cluster_labels, dbscan};
+use crate::hotspot2d::hist2d;
+use crate::points::XYPoint;
+use crate::DistanceMetric;
+
+/// Find clusters with DBSCAN, like dbscan::find_clusters, but finding
+/// neighbors with a grid of eps-sized cells instead of a tree. Any neighbor
+/// of a point must be in its cell or one of the 8 around it, so only those
+/// are checked, at full precision.
+///
+/// Building the grid is just a pass over the points, so on large fields of
+/// fairly uniform density this can beat the trees. A cell holding a dense
+/// clump is checked in full by every point near it, though, so very uneven
+/// points are better off with a tree.
+///
+/// Only the euclidean and manhattan metrics are supported, since cells of
+/// longitude and latitude don't bound great-circle distances.
+pub fn find_clusters(
+    points: &[XYPoint<f64>],
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    assert!(
+        *metric != DistanceMetric::Haversine,
+        "grid DBSCAN doesn't support the haversine metric"
+    );
+    let cells = cells(points, eps);
+    let grid = hist2d(&cells);
+    let eps_squared = eps * eps;
+    let within = |a: &XYPoint<f64>, b: &XYPoint<f64>| match metric {
+        DistanceMetric::Euclidean => a.squared_distance(b) <= eps_squared,
+        _ => metric.distance(a, b) <= eps,
+    };
+
+    let labels = dbscan(points.len(), None, min_cluster_size, |i| {
+        let (cell, point) = (cells[i], &points[i]);
+        let mut neighbors = Vec::new();
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                // Cells past the edge of an i64 can't hold any points.
+                let (x, y) = match (cell.x.checked_add(dx), cell.y.checked_add(dy)) {
+                    (Some(x), Some(y)) => (x, y),
+                    _ => continue,
+                };
+                if let Some(members) = grid.get(&XYPoint { x, y }) {
+                    neighbors.extend(members.iter().filter(|&&j| within(point, &points[j])));
+                }
+            }
+        }
+        neighbors
+    });
+    cluster_labels(&labels)
+}
+
+/// The eps-sized cell of each point. Unlike Hotspot2D's quantize, this
+/// floors rather than rounds, so that every cell is the same size: rounding
+/// half away from zero makes the cell around 0 wider than eps, and puts
+/// points eps apart across it two cells apart.
+fn cells(points: &[XYPoint<f64>], eps: f64) -> Vec<XYPoint<i64>> {
+    points
+        .iter()
+        .map(|p| XYPoint {
+            x: (p.x / eps).floor() as i64,
+            y: (p.y / eps).floor() as i64,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbscan::{brute_force, find_clusters as find_clusters_with, float32_kdtree};
+
+    #[test]
+    fn test_matches_kdtree() {
+        // Scattered points from a simple LCG, with clumps dropped in, over a
+        // field many cells wide, some of it at negative coordinates.
+        let mut state: u64 = 12345;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let mut points: Vec<XYPoint<f64>> = (0..2000)
+            .map(|_| XYPoint::new(4.0 * next() - 2.0, 3.0 * next() - 1.0))
+            .collect();
+        for k in 0..20 {
+            let (cx, cy) = (4.0 * next() - 2.0, 3.0 * next() - 1.0);
+            points.extend((0..8).map(|j| {
+                let j = j as f64 + k as f64 * 0.1;
+                XYPoint::new(cx + 0.01 * j.cos(), cy + 0.01 * j.sin())
+            }));
+        }
+
+        for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan] {
+            let want = find_clusters_with::<float32_kdtree::PointTree>(&points, 0.03, 4, &metric);
+            assert!(want.iter().filter(|&&label| label > 0).count() > 100);
+            assert!(want.contains(&-1));
+            assert_eq!(find_clusters(&points, 0.03, 4, &metric), want);
+            assert_eq!(
+                find_clusters_with::<brute_force::BruteForceTree>(&points, 0.03, 4, &metric),
+                want
+            );
+        }
+    }
+
+    #[test]
+    fn test_neighbors_across_zero() {
+        // Pairs exactly eps apart, straddling zero in x and then in y.
+        let points = vec![
+            XYPoint::new(-0.01, 5.0),
+            XYPoint::new(0.01, 5.0),
+            XYPoint::new(5.0, -0.01),
+            XYPoint::new(5.0, 0.01),
+        ];
+        let metric = DistanceMetric::Euclidean;
+        let want = find_clusters_with::<brute_force::BruteForceTree>(&points, 0.02, 2, &metric);
+        assert_eq!(want, vec![1, 1, 2, 2]);
+        assert_eq!(find_clusters(&points, 0.02, 2, &metric), want);
+        assert_eq!(
+            find_clusters(&points, 0.02, 2, &DistanceMetric::Manhattan),
+            want
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use kiddo::distance;
+use kiddo::float::kdtree as kfloat;
+
+use crate::dbscan::{neighbors_within, SearchTree};
+use crate::points::XYPoint;
+use crate::DistanceMetric;
+
+/// DBSCAN over a growing set of points, for surveys which find new
+/// detections a batch at a time. Adding a batch only searches for the
+/// neighbors of the new points; everything already known about the old
+/// ones is kept.
+///
+/// The labels are always the same as find_clusters would give for all of
+/// the points so far, in the order they were added, with a float32 kd-tree.
+/// Clusters are numbered in order of their first core point, and a border
+/// point near more than one cluster joins the lowest numbered one, so a
+/// cluster's label can change when a new batch joins it to an earlier one.
+///
+/// Every point's neighbors are held in memory, so this needs more memory
+/// than find_clusters for dense data.
+pub struct IncrementalDbscan {
+    eps: f64,
+    min_cluster_size: usize,
+    metric: DistanceMetric,
+    tree: GrowingTree,
+    points: Vec<XYPoint<f64>>,
+    /// The indexes of the points within eps of each point, including itself.
+    neighbors: Vec<Vec<usize>>,
+    core: Vec<bool>,
+    /// A union-find forest over the core points, linking each one to the
+    /// core points in its neighborhood.
+    parents: Vec<usize>,
+    labels: Vec<i32>,
+}
+
+impl IncrementalDbscan {
+    /// Start with no points. The haversine metric isn't supported, since it
+    /// needs points on the unit sphere.
+    pub fn new(eps: f64, min_cluster_size: usize, metric: DistanceMetric) -> Self {
+        assert!(
+            metric != DistanceMetric::Haversine,
+            "IncrementalDbscan doesn't support the haversine metric"
+        );
+        IncrementalDbscan {
+            eps,
+            min_cluster_size,
+            metric,
+            tree: GrowingTree(kfloat::KdTree::new()),
+            points: Vec::new(),
+            neighbors: Vec::new(),
+            core: Vec::new(),
+            parents: Vec::new(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Add a batch of points, and return the updated labels of every point
+    /// so far, old and new, in the order they were added.
+    pub fn add_points(&mut self, batch: &[XYPoint<f64>]) -> &[i32] {
+        let start = self.points.len();
+        for point in batch.iter() {
+            let idx = self.points.len();
+            self.tree
+                .0
+                .add(&[point.x as f32, point.y as f32], idx as u32);
+            self.points.push(*point);
+            self.neighbors.push(Vec::new());
+            self.core.push(false);
+            self.parents.push(idx);
+        }
+
+        // Record each new pair of neighbors once: from the new point's side,
+        // unless the other point is newer, and will find this one itself.
+        let mut touched = Vec::new();
+        for idx in start..self.points.len() {
+            let found = neighbors_within(
+                &self.points,
+                &self.tree,
+                &self.points[idx],
+                self.eps,
+                &self.metric,
+            );
+            for neighbor in found.into_iter().filter(|&n| n <= idx) {
+                self.neighbors[idx].push(neighbor);
+                if neighbor != idx {
+                    self.neighbors[neighbor].push(idx);
+                    touched.push(neighbor);
+                }
+            }
+            touched.push(idx);
+        }
+
+        // Points only gain neighbors, so they can only become core points.
+        // Link each new one up with every core point near it; links between
+        // older core points are already there.
+        let mut new_core = Vec::new();
+        for idx in touched {
+            if !self.core[idx] && self.neighbors[idx].len() >= self.min_cluster_size {
+                self.core[idx] = true;
+                new_core.push(idx);
+            }
+        }
+        for &idx in new_core.iter() {
+            for i in 0..self.neighbors[idx].len() {
+                let neighbor = self.neighbors[idx][i];
+                if self.core[neighbor] {
+                    self.union(idx, neighbor);
+                }
+            }
+        }
+
+        self.relabel();
+        &self.labels
+    }
+
+    /// The labels of every point so far. Noise is labeled -1 and clusters
+    /// are labeled from 1.
+    pub fn labels(&self) -> &[i32] {
+        &self.labels
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Number the clusters in order of their first core point, and label
+    /// each point from them.
+    fn relabel(&mut self) {
+        let roots: Vec<usize> = (0..self.points.len()).map(|idx| self.find(idx)).collect();
+        let mut cluster_ids = vec![-1; self.points.len()];
+        let mut n_clusters = 0;
+        for (idx, &root) in roots.iter().enumerate() {
+            if self.core[idx] && cluster_ids[root] == -1 {
+                n_clusters += 1;
+                cluster_ids[root] = n_clusters;
+            }
+        }
+        self.labels = (0..self.points.len())
+            .map(|idx| {
+                if self.core[idx] {
+                    return cluster_ids[roots[idx]];
+                }
+                // DBSCAN builds clusters one at a time, so the lowest
+                // numbered cluster near a border point claims it.
+                self.neighbors[idx]
+                    .iter()
+                    .filter(|&&neighbor| self.core[neighbor])
+                    .map(|&neighbor| cluster_ids[roots[neighbor]])
+                    .min()
+                    .unwrap_or(-1)
+            })
+            .collect();
+    }
+
+    fn find(&mut self, idx: usize) -> usize {
+        let mut root = idx;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        // Point everything on the way straight at the root, so that later
+        // finds are quick.
+        let mut idx = idx;
+        while self.parents[idx] != root {
+            let next = self.parents[idx];
+            self.parents[idx] = root;
+            idx = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+// The same float32 kd-tree as find_clusters uses, so that both find exactly
+// the same neighbors, but with a 32-bit index, since the points keep coming.
+struct GrowingTree(kfloat::KdTree<f32, u32, 2, 32, u32>);
+
+impl SearchTree for GrowingTree {
+    fn from_points(points: &Vec<XYPoint<f64>>) -> Self {
+        let mut tree = kfloat::KdTree::with_capacity(points.len());
+        for (idx, point) in points.iter().enumerate() {
+            tree.add(&[point.x as f32, point.y as f32], idx as u32);
+        }
+        GrowingTree(tree)
+    }
+
+    fn neighbors(&self, point: &XYPoint<f64>, radius: f64) -> Vec<usize> {
+        // Pad and filter just like float32_kdtree, so points exactly radius
+        // away are neighbors here too.
+        let radius_squared = (radius * radius) as f32;
+        let padded = radius_squared * (1.0 + f32::EPSILON) + f32::MIN_POSITIVE;
+        let neighbors = self.0.within_unsorted(
+            &[point.x as f32, point.y as f32],
+            padded,
+            &distance::squared_euclidean,
+        );
+        neighbors
+            .iter()
+            .filter(|n| n.distance <= radius_squared)
+            .map(|n| n.item as usize)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbscan::{find_clusters, float32_kdtree};
+
+    #[test]
+    fn test_batches_match_full_run() {
+        // Clumps along a spiral, close enough for some to merge as points
+        // arrive.
+        let points: Vec<XYPoint<f64>> = (0..400)
+            .map(|i| {
+                let i = i as f64;
+                let r = 0.002 * (i % 97.0);
+                let theta = 0.37 * i;
+                XYPoint::new(0.3 * (i / 400.0) + r * theta.cos(), r * theta.sin())
+            })
+            .collect();
+        for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan] {
+            let want = find_clusters::<float32_kdtree::PointTree>(&points, 0.02, 5, &metric);
+            assert!(want.iter().filter(|&&label| label > 0).count() > 10);
+            assert!(want.contains(&-1));
+
+            let mut incremental = IncrementalDbscan::new(0.02, 5, metric.clone());
+            let first = incremental.add_points(&points[..150]).to_vec();
+            assert_eq!(
+                first,
+                find_clusters::<float32_kdtree::PointTree>(
+                    &points[..150].to_vec(),
+                    0.02,
+                    5,
+                    &metric
+                )
+            );
+            assert_eq!(incremental.add_points(&points[150..]), want);
+            assert_eq!(incremental.len(), points.len());
+        }
+    }
+
+    #[test]
+    fn test_batch_merges_clusters() {
+        // Two clusters, and then a point between them which makes both of
+        // their edge points core, joining them up.
+        let mut incremental = IncrementalDbscan::new(0.12, 3, DistanceMetric::Euclidean);
+        let labels = incremental.add_points(&[
+            XYPoint::new(0.0, 0.0),
+            XYPoint::new(0.1, 0.0),
+            XYPoint::new(0.2, 0.0),
+            XYPoint::new(0.5, 0.0),
+            XYPoint::new(0.6, 0.0),
+            XYPoint::new(0.7, 0.0),
+        ]);
+        assert_eq!(labels, [1, 1, 1, 2, 2, 2]);
+        let labels = incremental.add_points(&[XYPoint::new(0.35, 0.0)]);
+        assert_eq!(labels, [1, 1, 1, 2, 2, 2, -1]);
+        let labels = incremental.add_points(&[XYPoint::new(0.3, 0.0), XYPoint::new(0.4, 0.0)]);
+        assert_eq!(labels, [1, 1, 1, 1, 1, 1, 1, 1, 1]);
+        assert!(IncrementalDbscan::new(0.1, 3, DistanceMetric::Euclidean)
+            .add_points(&[])
+            .is_empty());
+    }
+
+    #[test]
+    fn test_points_exactly_eps_apart() {
+        let mut incremental = IncrementalDbscan::new(0.5, 2, DistanceMetric::Euclidean);
+        let labels = incremental.add_points(&[XYPoint::new(0.0, 0.0)]);
+        assert_eq!(labels, [-1]);
+        let labels = incremental.add_points(&[XYPoint::new(0.5, 0.0)]);
+        assert_eq!(labels, [1, 1]);
+    }
+}
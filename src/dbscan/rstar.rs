@@ -16,8 +16,19 @@ impl SearchTree for Tree {
         Tree::bulk_load(points)
     }
 
+    fn from_columns(xs: &[f64], ys: &[f64]) -> Self {
+        let points: Vec<TreeEntry> = xs
+            .iter()
+            .zip(ys.iter())
+            .enumerate()
+            .map(|(i, (x, y))| TreeEntry::new([*x, *y], i as u16))
+            .collect();
+        Tree::bulk_load(points)
+    }
+
     fn neighbors(&self, point: &XYPoint<f64>, radius: f64) -> Vec<usize> {
-        self.locate_within_distance([point.x, point.y], radius)
+        // locate_within_distance takes a squared radius.
+        self.locate_within_distance([point.x, point.y], radius * radius)
             .map(|p| p.data as usize)
             .collect()
     }
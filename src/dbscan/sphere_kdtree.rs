@@ -0,0 +1,48 @@
+use crate::dbscan::SearchTree;
+use crate::points::XYPoint;
+use kiddo::distance;
+use kiddo::float::kdtree as kfloat;
+use std::f64::consts::PI;
+
+// Store points as unit vectors in a 3-dimensional KD-tree of 64-bit floats,
+// treating x and y as RA and Dec in degrees. The euclidean distance between
+// two unit vectors is the chord between them, which grows with great-circle
+// distance, so it doesn't suffer from distortion near the poles.
+pub type SphereTree = kfloat::KdTree<f64, u32, 3, 32, u32>;
+
+impl SearchTree for SphereTree {
+    fn from_points(points: &Vec<XYPoint<f64>>) -> Self {
+        let mut tree = kfloat::KdTree::with_capacity(points.len());
+        for (idx, point) in points.iter().enumerate() {
+            tree.add(&unit_vector(point), idx as u32);
+        }
+        tree
+    }
+
+    /// The radius is a chord length on the unit sphere; see chord_length.
+    fn neighbors(&self, point: &XYPoint<f64>, radius: f64) -> Vec<usize> {
+        // kiddo only finds points strictly within the radius; keep the ones
+        // exactly on it too.
+        let radius_squared = radius * radius;
+        let padded = radius_squared * (1.0 + f64::EPSILON) + f64::MIN_POSITIVE;
+        let neighbors =
+            self.within_unsorted(&unit_vector(point), padded, &distance::squared_euclidean);
+        neighbors
+            .iter()
+            .filter(|n| n.distance <= radius_squared)
+            .map(|n| n.item as usize)
+            .collect()
+    }
+}
+
+fn unit_vector(point: &XYPoint<f64>) -> [f64; 3] {
+    let ra = point.x.to_radians();
+    let dec = point.y.to_radians();
+    [dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin()]
+}
+
+/// The length of the chord between two points on the unit sphere which are
+/// angle radians apart.
+pub fn chord_length(angle: f64) -> f64 {
+    2.0 * (angle.min(PI) / 2.0).sin()
+}
@@ -0,0 +1,34 @@
+use crate::points::XYZPoint;
+use kiddo::distance;
+use kiddo::float::kdtree as kfloat;
+
+// Store points in a 3-dimensional KD-tree of 64-bit floats, for clustering in
+// Cartesian space rather than on the tangent plane.
+pub type XYZTree = kfloat::KdTree<f64, u32, 3, 32, u32>;
+
+pub fn from_points(points: &[XYZPoint<f64>]) -> XYZTree {
+    let mut tree = kfloat::KdTree::with_capacity(points.len());
+    for (idx, point) in points.iter().enumerate() {
+        tree.add(&[point.x, point.y, point.z], idx as u32);
+    }
+    tree
+}
+
+/// Returns the indexes of all points within radius of point, by euclidean
+/// distance, including those exactly radius away.
+pub fn neighbors(tree: &XYZTree, point: &XYZPoint<f64>, radius: f64) -> Vec<usize> {
+    // kiddo only finds points strictly within the radius, so search a little
+    // further, and keep the points exactly on it too.
+    let radius_squared = radius * radius;
+    let padded = radius_squared * (1.0 + f64::EPSILON) + f64::MIN_POSITIVE;
+    let neighbors = tree.within_unsorted(
+        &[point.x, point.y, point.z],
+        padded,
+        &distance::squared_euclidean,
+    );
+    neighbors
+        .iter()
+        .filter(|n| n.distance <= radius_squared)
+        .map(|n| n.item as usize)
+        .collect()
+}
@@ -1,148 +1,1417 @@
+use crate::dbscan::drop_large_clusters;
 use crate::points::{XYPoint, XYTPoint};
-use crate::{find_clusters, ClusterAlgorithm};
-use std::sync::mpsc::channel;
-use std::thread;
+use crate::{find_clusters, ClusterAlgorithm, DistanceMetric};
+use arrow::array::{
+    ArrayRef, BooleanBuilder, DictionaryArray, Float64Builder, LargeStringDictionaryBuilder,
+    StringBuilder, UInt32Builder,
+};
+use arrow::datatypes::{DataType, Field, Int64Type, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use log::{info, warn};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GridSearchResult {
     pub vx: f64,
     pub vy: f64,
+    /// The eps the points were clustered with.
+    pub eps: f64,
     pub cluster_labels: Vec<i32>,
 }
 
-fn apply_velocity(vx: f64, vy: f64, points: &Vec<XYTPoint<f64>>) -> Vec<XYPoint<f64>> {
-    let mut new_points = Vec::with_capacity(points.len());
+/// A cluster found at one velocity of a grid search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterSummary {
+    pub cluster_id: u32,
+    pub vx: f64,
+    pub vy: f64,
+    /// The difference between the largest and smallest dt in the cluster.
+    /// Exactly 0.0 if every observation shares one dt; see has_motion.
+    pub arc_length: f64,
+    /// The number of observations in the cluster.
+    pub n_obs: u32,
+    /// The mean position of the cluster's observations, after moving them
+    /// back to dt=0 at the cluster's velocity.
+    pub mean_x: f64,
+    pub mean_y: f64,
+    /// The number of distinct nights the cluster's observations fall on.
+    pub n_nights: u32,
+    /// The RMS residual of a linear motion fit to the cluster's
+    /// observations; see fit_linear_residual.
+    pub residual_rms: f64,
+    /// The velocity of that same fit: the cluster's own estimate of its
+    /// velocity, which can be closer to the truth than the grid's vx and vy.
+    pub vx_fit: f64,
+    pub vy_fit: f64,
+    /// The eps the cluster was found with.
+    pub eps: f64,
+}
+
+impl ClusterSummary {
+    /// Whether the cluster's observations have more than one distinct dt.
+    /// Clusters with just one come from a single exposure: they're blends of
+    /// nearby detections, not a moving object.
+    pub fn has_motion(&self) -> bool {
+        self.arc_length > 0.0
+    }
+}
+
+/// An observation which belongs to a cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterMember {
+    pub cluster_id: u32,
+    pub obs_id: String,
+}
+
+/// Give every cluster in a set of grid search results a unique ID, and
+/// summarize them. IDs count up from 1 across all of the results.
+///
+/// points and ids must line up with the points that were searched. Nights
+/// are counted by dividing dt into night_length chunks.
+pub fn summarize_clusters(
+    results: &[GridSearchResult],
+    points: &[XYTPoint<f64>],
+    ids: &[String],
+    night_length: f64,
+) -> (Vec<ClusterSummary>, Vec<ClusterMember>) {
+    number_clusters(
+        results
+            .iter()
+            .map(|result| summarize_result(result, points, ids, night_length, false)),
+    )
+}
+
+/// Summarize the clusters in one grid search result, with IDs counting up
+/// from 1. Members are in the order of points, or with sort_by_dt, grouped
+/// by cluster and in order of dt within each one.
+fn summarize_result(
+    result: &GridSearchResult,
+    points: &[XYTPoint<f64>],
+    ids: &[String],
+    night_length: f64,
+    sort_by_dt: bool,
+) -> (Vec<ClusterSummary>, Vec<ClusterMember>) {
+    let mut summaries: Vec<ClusterSummary> = Vec::new();
+    let mut members = Vec::new();
+    let mut member_dts = Vec::new();
+    let mut label_idx_map: HashMap<i32, usize> = HashMap::new();
+    let mut sum_xs = Vec::new();
+    let mut sum_ys = Vec::new();
+    let mut nights: Vec<HashSet<i64>> = Vec::new();
+    let mut cluster_points: Vec<Vec<XYTPoint<f64>>> = Vec::new();
+
+    for (i, label) in result.cluster_labels.iter().enumerate() {
+        if *label < 0 {
+            continue;
+        }
+        let point = &points[i];
+        let dt = point.t;
+        let idx = *label_idx_map.entry(*label).or_insert_with(|| {
+            summaries.push(ClusterSummary {
+                cluster_id: summaries.len() as u32 + 1,
+                vx: result.vx,
+                vy: result.vy,
+                arc_length: 0.0,
+                n_obs: 0,
+                mean_x: 0.0,
+                mean_y: 0.0,
+                n_nights: 0,
+                residual_rms: 0.0,
+                vx_fit: 0.0,
+                vy_fit: 0.0,
+                eps: result.eps,
+            });
+            sum_xs.push(0.0);
+            sum_ys.push(0.0);
+            nights.push(HashSet::new());
+            cluster_points.push(Vec::new());
+            summaries.len() - 1
+        });
+        members.push(ClusterMember {
+            cluster_id: summaries[idx].cluster_id,
+            obs_id: ids[i].clone(),
+        });
+        member_dts.push(dt);
+        summaries[idx].n_obs += 1;
+        let shifted = point.shift_by_velocity(result.vx, result.vy);
+        sum_xs[idx] += shifted.x;
+        sum_ys[idx] += shifted.y;
+        nights[idx].insert((dt / night_length).floor() as i64);
+        cluster_points[idx].push(*point);
+    }
+    // Now that we've processed all the points, we can add the arc lengths
+    // and centroids.
+    let arcs = arcs(&result.cluster_labels, points.iter().map(|p| p.t));
+    for (label, &idx) in label_idx_map.iter() {
+        let (start, end) = arcs[label];
+        summaries[idx].arc_length = end - start;
+    }
+    for (k, summary) in summaries.iter_mut().enumerate() {
+        summary.mean_x = sum_xs[k] / summary.n_obs as f64;
+        summary.mean_y = sum_ys[k] / summary.n_obs as f64;
+        summary.n_nights = nights[k].len() as u32;
+        let fit = fit_linear(&cluster_points[k]);
+        summary.residual_rms = fit.residual_rms;
+        summary.vx_fit = fit.vx;
+        summary.vy_fit = fit.vy;
+    }
+    if sort_by_dt {
+        let mut sorted: Vec<(f64, ClusterMember)> = member_dts.into_iter().zip(members).collect();
+        sorted.sort_by(|(a_dt, a), (b_dt, b)| {
+            a.cluster_id.cmp(&b.cluster_id).then(a_dt.total_cmp(b_dt))
+        });
+        members = sorted.into_iter().map(|(_, member)| member).collect();
+    }
+    (summaries, members)
+}
+
+/// Join up the summaries of several results, each numbered from 1, so that
+/// IDs count up from 1 across all of them.
+fn number_clusters(
+    summarized: impl IntoIterator<Item = (Vec<ClusterSummary>, Vec<ClusterMember>)>,
+) -> (Vec<ClusterSummary>, Vec<ClusterMember>) {
+    let mut all_summaries = Vec::new();
+    let mut all_members = Vec::new();
+    for (summaries, members) in summarized {
+        let offset = all_summaries.len() as u32;
+        all_summaries.extend(summaries.into_iter().map(|mut summary| {
+            summary.cluster_id += offset;
+            summary
+        }));
+        all_members.extend(members.into_iter().map(|mut member| {
+            member.cluster_id += offset;
+            member
+        }));
+    }
+    (all_summaries, all_members)
+}
+
+/// A straight line fit through some points over time, from fit_linear.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearFit {
+    pub vx: f64,
+    pub vy: f64,
+    /// The RMS of the distances between each point and the line.
+    pub residual_rms: f64,
+}
+
+/// Least-squares fit x(t) = x0 + vx*t and y(t) = y0 + vy*t to the points,
+/// and return the RMS of the distances between each point and the fit. If
+/// every point shares the same t, the fit is just the centroid.
+pub fn fit_linear_residual(points: &[XYTPoint<f64>]) -> f64 {
+    fit_linear(points).residual_rms
+}
+
+/// The same fit as fit_linear_residual, along with its velocity. If every
+/// point shares the same t, the velocity is 0.
+pub fn fit_linear(points: &[XYTPoint<f64>]) -> LinearFit {
+    if points.is_empty() {
+        return LinearFit {
+            vx: 0.0,
+            vy: 0.0,
+            residual_rms: 0.0,
+        };
+    }
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|p| p.t).sum::<f64>() / n;
+    let mean_x = points.iter().map(|p| p.x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.y).sum::<f64>() / n;
+
+    let mut var_t = 0.0;
+    let mut cov_tx = 0.0;
+    let mut cov_ty = 0.0;
     for p in points.iter() {
-        let new_point = XYPoint {
-            x: p.x - vx * p.t,
-            y: p.y - vy * p.t,
+        let dt = p.t - mean_t;
+        var_t += dt * dt;
+        cov_tx += dt * (p.x - mean_x);
+        cov_ty += dt * (p.y - mean_y);
+    }
+    let (vx, vy) = if var_t > 0.0 {
+        (cov_tx / var_t, cov_ty / var_t)
+    } else {
+        (0.0, 0.0)
+    };
+
+    let sum_squares: f64 = points
+        .iter()
+        .map(|p| {
+            let dt = p.t - mean_t;
+            let rx = p.x - (mean_x + vx * dt);
+            let ry = p.y - (mean_y + vy * dt);
+            rx * rx + ry * ry
+        })
+        .sum();
+    LinearFit {
+        vx,
+        vy,
+        residual_rms: (sum_squares / n).sqrt(),
+    }
+}
+
+/// Sort results by (vx, vy), and then by eps. Worker threads finish in no
+/// particular order, and cluster IDs are handed out in result order, so this
+/// keeps them the same from run to run.
+pub fn sort_results(results: &mut [GridSearchResult]) {
+    results.sort_by(|a, b| search_order((a.vx, a.vy, a.eps), (b.vx, b.vy, b.eps)));
+}
+
+/// The order of two (vx, vy, eps) searches in sort_results.
+fn search_order(a: (f64, f64, f64), b: (f64, f64, f64)) -> std::cmp::Ordering {
+    a.0.total_cmp(&b.0)
+        .then(a.1.total_cmp(&b.1))
+        .then(a.2.total_cmp(&b.2))
+}
+
+/// Drop clusters with exactly the same members as an earlier cluster, along
+/// with their memberships. Adjacent velocities often find the same cluster;
+/// only the first is kept, and it keeps its ID.
+pub fn dedup_clusters(
+    summaries: Vec<ClusterSummary>,
+    members: Vec<ClusterMember>,
+) -> (Vec<ClusterSummary>, Vec<ClusterMember>) {
+    let kept: HashSet<u32> = {
+        let mut obs_ids: HashMap<u32, Vec<&str>> = HashMap::new();
+        for member in members.iter() {
+            obs_ids
+                .entry(member.cluster_id)
+                .or_default()
+                .push(&member.obs_id);
+        }
+        let mut seen = HashSet::new();
+        summaries
+            .iter()
+            .filter(|summary| {
+                let mut ids = obs_ids.remove(&summary.cluster_id).unwrap_or_default();
+                ids.sort_unstable();
+                seen.insert(ids)
+            })
+            .map(|summary| summary.cluster_id)
+            .collect()
+    };
+    retain_clusters(summaries, members, |summary| {
+        kept.contains(&summary.cluster_id)
+    })
+}
+
+/// Keep only the clusters for which keep returns true, along with their
+/// memberships.
+pub fn retain_clusters(
+    summaries: Vec<ClusterSummary>,
+    members: Vec<ClusterMember>,
+    keep: impl Fn(&ClusterSummary) -> bool,
+) -> (Vec<ClusterSummary>, Vec<ClusterMember>) {
+    let (summaries, dropped): (Vec<_>, Vec<_>) = summaries.into_iter().partition(keep);
+    let dropped: HashSet<u32> = dropped.iter().map(|s| s.cluster_id).collect();
+    let members = members
+        .into_iter()
+        .filter(|member| !dropped.contains(&member.cluster_id))
+        .collect();
+    (summaries, members)
+}
+
+/// Build grid_search's pair of output tables from cluster summaries and
+/// memberships: a table of the summaries, with cluster_table_schema, and a
+/// table of the memberships, with cluster_members_table_schema. obs_id is
+/// dictionary encoded if dictionary_obs_ids is true.
+pub fn to_record_batches(
+    summaries: &[ClusterSummary],
+    members: &[ClusterMember],
+    dictionary_obs_ids: bool,
+) -> Result<(RecordBatch, RecordBatch), ArrowError> {
+    let mut cluster_id_builder = UInt32Builder::new();
+    let mut vx_builder = Float64Builder::new();
+    let mut vy_builder = Float64Builder::new();
+    let mut arc_length_builder = Float64Builder::new();
+    let mut n_obs_builder = UInt32Builder::new();
+    let mut mean_x_builder = Float64Builder::new();
+    let mut mean_y_builder = Float64Builder::new();
+    let mut n_nights_builder = UInt32Builder::new();
+    let mut residual_rms_builder = Float64Builder::new();
+    let mut vx_fit_builder = Float64Builder::new();
+    let mut vy_fit_builder = Float64Builder::new();
+    let mut single_exposure_builder = BooleanBuilder::new();
+    let mut eps_builder = Float64Builder::new();
+    for summary in summaries.iter() {
+        cluster_id_builder.append_value(summary.cluster_id);
+        vx_builder.append_value(summary.vx);
+        vy_builder.append_value(summary.vy);
+        arc_length_builder.append_value(summary.arc_length);
+        n_obs_builder.append_value(summary.n_obs);
+        mean_x_builder.append_value(summary.mean_x);
+        mean_y_builder.append_value(summary.mean_y);
+        n_nights_builder.append_value(summary.n_nights);
+        residual_rms_builder.append_value(summary.residual_rms);
+        vx_fit_builder.append_value(summary.vx_fit);
+        vy_fit_builder.append_value(summary.vy_fit);
+        single_exposure_builder.append_value(!summary.has_motion());
+        eps_builder.append_value(summary.eps);
+    }
+
+    let mut cluster_id_members_builder = UInt32Builder::new();
+    for member in members.iter() {
+        cluster_id_members_builder.append_value(member.cluster_id);
+    }
+
+    let cluster_table = RecordBatch::try_new(
+        Arc::new(cluster_table_schema()),
+        vec![
+            Arc::new(cluster_id_builder.finish()),
+            Arc::new(vx_builder.finish()),
+            Arc::new(vy_builder.finish()),
+            Arc::new(arc_length_builder.finish()),
+            Arc::new(n_obs_builder.finish()),
+            Arc::new(mean_x_builder.finish()),
+            Arc::new(mean_y_builder.finish()),
+            Arc::new(n_nights_builder.finish()),
+            Arc::new(residual_rms_builder.finish()),
+            Arc::new(vx_fit_builder.finish()),
+            Arc::new(vy_fit_builder.finish()),
+            Arc::new(single_exposure_builder.finish()),
+            Arc::new(eps_builder.finish()),
+        ],
+    )?;
+
+    let cluster_members_table = RecordBatch::try_new(
+        Arc::new(cluster_members_table_schema(dictionary_obs_ids)),
+        vec![
+            Arc::new(cluster_id_members_builder.finish()),
+            obs_id_column(members, dictionary_obs_ids),
+        ],
+    )?;
+    Ok((cluster_table, cluster_members_table))
+}
+
+/// Build a table of the obs_ids of the points which were never clustered,
+/// given whether each point ever was, with noise_table_schema. ids and
+/// ever_clustered must line up.
+pub fn noise_table(ids: &[String], ever_clustered: &[bool]) -> Result<RecordBatch, ArrowError> {
+    let mut obs_id_builder = StringBuilder::new();
+    for (id, clustered) in ids.iter().zip(ever_clustered) {
+        if !clustered {
+            obs_id_builder.append_value(id);
+        }
+    }
+    RecordBatch::try_new(
+        Arc::new(noise_table_schema()),
+        vec![Arc::new(obs_id_builder.finish())],
+    )
+}
+
+/// Schema of the noise table returned by grid_search.
+pub fn noise_table_schema() -> Schema {
+    Schema::new(vec![Field::new("obs_id", DataType::Utf8, false)])
+}
+
+/// Schema of the cluster summary table returned by grid_search.
+pub fn cluster_table_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("cluster_id", DataType::UInt32, false),
+        Field::new("vx", DataType::Float64, false),
+        Field::new("vy", DataType::Float64, false),
+        Field::new("arc_length", DataType::Float64, false),
+        Field::new("n_obs", DataType::UInt32, false),
+        Field::new("mean_x", DataType::Float64, false),
+        Field::new("mean_y", DataType::Float64, false),
+        Field::new("n_nights", DataType::UInt32, false),
+        Field::new("residual_rms", DataType::Float64, false),
+        Field::new("vx_fit", DataType::Float64, false),
+        Field::new("vy_fit", DataType::Float64, false),
+        Field::new("single_exposure", DataType::Boolean, false),
+        Field::new("eps", DataType::Float64, false),
+    ])
+}
+
+/// Schema of the cluster membership table returned by grid_search.
+pub fn cluster_members_table_schema(dictionary_obs_ids: bool) -> Schema {
+    Schema::new(vec![
+        Field::new("cluster_id", DataType::UInt32, false),
+        obs_id_field(dictionary_obs_ids),
+    ])
+}
+
+/// Dictionary encode the obs_id of each cluster member, for grid_search's
+/// output. The keys are 64-bit and the values are large strings, with 64-bit
+/// offsets, so the dictionary can't run out of room however many distinct
+/// IDs there are, or however long they are in total.
+pub(crate) fn obs_id_array(members: &[ClusterMember]) -> DictionaryArray<Int64Type> {
+    let mut builder = LargeStringDictionaryBuilder::<Int64Type>::new();
+    for member in members.iter() {
+        builder.append_value(&member.obs_id);
+    }
+    builder.finish()
+}
+
+/// The obs_id column of grid_search's output: either obs_id_array, or plain
+/// strings.
+pub(crate) fn obs_id_column(members: &[ClusterMember], dictionary: bool) -> ArrayRef {
+    if dictionary {
+        return Arc::new(obs_id_array(members));
+    }
+    let mut builder = StringBuilder::new();
+    for member in members.iter() {
+        builder.append_value(&member.obs_id);
+    }
+    Arc::new(builder.finish())
+}
+
+/// The obs_id field of grid_search's output; see obs_id_column.
+pub(crate) fn obs_id_field(dictionary: bool) -> Field {
+    if dictionary {
+        Field::new_dictionary("obs_id", DataType::Int64, DataType::LargeUtf8, false)
+    } else {
+        Field::new("obs_id", DataType::Utf8, false)
+    }
+}
+
+/// Group observations into connected components, linking two observations
+/// whenever they were members of the same cluster. The same object often
+/// turns up at several nearby velocities with slightly different members;
+/// this merges all of those sightings into one group.
+///
+/// Components are in order of their first member, and each lists its
+/// observations in the order they first appear in members.
+pub fn connected_components(members: &[ClusterMember]) -> Vec<Vec<String>> {
+    let mut obs_ids: Vec<&str> = Vec::new();
+    let mut obs_idxs: HashMap<&str, usize> = HashMap::new();
+    let mut parents: Vec<usize> = Vec::new();
+    // The first observation seen in each cluster, which the rest are
+    // linked to.
+    let mut cluster_roots: HashMap<u32, usize> = HashMap::new();
+
+    for member in members.iter() {
+        let idx = *obs_idxs.entry(&member.obs_id).or_insert_with(|| {
+            obs_ids.push(&member.obs_id);
+            parents.push(parents.len());
+            parents.len() - 1
+        });
+        let first = *cluster_roots.entry(member.cluster_id).or_insert(idx);
+        let (a, b) = (find_root(&mut parents, first), find_root(&mut parents, idx));
+        // Point the later root at the earlier one, so that each component's
+        // root is its first observation.
+        parents[a.max(b)] = a.min(b);
+    }
+
+    let mut components: Vec<Vec<String>> = Vec::new();
+    let mut component_idxs: HashMap<usize, usize> = HashMap::new();
+    for (idx, obs_id) in obs_ids.iter().enumerate() {
+        let root = find_root(&mut parents, idx);
+        let component = *component_idxs.entry(root).or_insert_with(|| {
+            components.push(Vec::new());
+            components.len() - 1
+        });
+        components[component].push(obs_id.to_string());
+    }
+    components
+}
+
+fn find_root(parents: &mut [usize], idx: usize) -> usize {
+    let mut root = idx;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    // Compress the path for later lookups.
+    let mut idx = idx;
+    while parents[idx] != root {
+        let next = parents[idx];
+        parents[idx] = root;
+        idx = next;
+    }
+    root
+}
+
+/// Reports the progress of a grid search. Each time another `every`
+/// velocities have been searched, and once all `total` have, the number
+/// searched so far is sent on the channel.
+pub struct Progress {
+    completed: AtomicUsize,
+    every: usize,
+    total: usize,
+    sender: Sender<usize>,
+}
+
+impl Progress {
+    pub fn new(every: usize, total: usize, sender: Sender<usize>) -> Self {
+        Progress {
+            completed: AtomicUsize::new(0),
+            every: every.max(1),
+            total,
+            sender,
+        }
+    }
+
+    fn tick(&self) {
+        let completed = self.completed.fetch_add(1, Ordering::Relaxed) + 1;
+        if completed.is_multiple_of(self.every) || completed == self.total {
+            // The receiver may have gone away; the search carries on anyway.
+            let _ = self.sender.send(completed);
+        }
+    }
+}
+
+/// What a grid search did, for performance tuning.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    /// The number of (vx, vy) pairs searched, counting each pair once for
+    /// each eps it was searched with.
+    pub n_pairs: usize,
+    /// The number of pairs with at least one cluster.
+    pub n_pairs_with_clusters: usize,
+    /// The number of clusters found across all pairs, before any were
+    /// dropped by max_cluster_size or min_arc_length.
+    pub n_clusters: usize,
+    /// The wall time the whole search took.
+    pub wall_time: Duration,
+}
+
+/// Diagnostics counts, shared between the threads of a grid search. Every
+/// log_interval pairs out of total, the counts so far are logged.
+struct Counters {
+    n_pairs: AtomicUsize,
+    n_pairs_with_clusters: AtomicUsize,
+    n_clusters: AtomicUsize,
+    total: usize,
+    log_interval: usize,
+}
+
+impl Counters {
+    fn new(total: usize, log_interval: usize) -> Self {
+        Counters {
+            n_pairs: AtomicUsize::new(0),
+            n_pairs_with_clusters: AtomicUsize::new(0),
+            n_clusters: AtomicUsize::new(0),
+            total,
+            log_interval,
+        }
+    }
+
+    fn record(&self, labels: &[i32]) {
+        let n_clusters = count_clusters(labels);
+        if n_clusters > 0 {
+            self.n_pairs_with_clusters.fetch_add(1, Ordering::Relaxed);
+        }
+        let clusters_so_far = self.n_clusters.fetch_add(n_clusters, Ordering::Relaxed) + n_clusters;
+        let pairs_so_far = self.n_pairs.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.log_interval > 0 && pairs_so_far.is_multiple_of(self.log_interval) {
+            info!(
+                "grid search: searched {}/{} velocity pairs, {} clusters so far",
+                pairs_so_far, self.total, clusters_so_far
+            );
+        }
+    }
+
+    fn finish(self, wall_time: Duration) -> Diagnostics {
+        let diagnostics = Diagnostics {
+            n_pairs: self.n_pairs.into_inner(),
+            n_pairs_with_clusters: self.n_pairs_with_clusters.into_inner(),
+            n_clusters: self.n_clusters.into_inner(),
+            wall_time,
         };
-        new_points.push(new_point);
+        info!(
+            "grid search: {} velocity pairs, {} with clusters, {} clusters before filtering, in {:?}",
+            diagnostics.n_pairs,
+            diagnostics.n_pairs_with_clusters,
+            diagnostics.n_clusters,
+            diagnostics.wall_time
+        );
+        diagnostics
     }
-    new_points
 }
 
+/// A region of velocity space: speeds from min_speed to max_speed, at angles
+/// from min_angle to max_angle. Angles are in degrees, counterclockwise from
+/// the +x axis. If min_angle is greater than max_angle, the region wraps
+/// through 0 degrees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VelocityAnnulus {
+    pub min_speed: f64,
+    pub max_speed: f64,
+    pub min_angle: f64,
+    pub max_angle: f64,
+}
+
+impl VelocityAnnulus {
+    pub fn contains(&self, vx: f64, vy: f64) -> bool {
+        let speed = vx.hypot(vy);
+        if speed < self.min_speed || speed > self.max_speed {
+            return false;
+        }
+        if self.max_angle - self.min_angle >= 360.0 {
+            return true;
+        }
+        let angle = vy.atan2(vx).to_degrees().rem_euclid(360.0);
+        let min_angle = self.min_angle.rem_euclid(360.0);
+        let max_angle = self.max_angle.rem_euclid(360.0);
+        if min_angle <= max_angle {
+            min_angle <= angle && angle <= max_angle
+        } else {
+            angle >= min_angle || angle <= max_angle
+        }
+    }
+}
+
+/// n_steps evenly spaced velocities from v_min to v_max, including both ends.
+pub fn velocity_range(v_min: f64, v_max: f64, n_steps: usize) -> Vec<f64> {
+    if n_steps <= 1 {
+        return vec![v_min; n_steps];
+    }
+    let step = (v_max - v_min) / (n_steps - 1) as f64;
+    (0..n_steps)
+        .map(|i| {
+            if i == n_steps - 1 {
+                v_max
+            } else {
+                v_min + step * i as f64
+            }
+        })
+        .collect()
+}
+
+/// The (vx, vy) pairs of a square grid with velocity_range(v_min, v_max,
+/// n_steps) along each axis, in the order cluster_grid_search searches them.
+/// If an annulus is given, only pairs inside of it are kept.
+pub fn velocity_grid(
+    v_min: f64,
+    v_max: f64,
+    n_steps: usize,
+    annulus: Option<&VelocityAnnulus>,
+) -> Vec<(f64, f64)> {
+    let vs = velocity_range(v_min, v_max, n_steps);
+    vs.iter()
+        .flat_map(|vx| vs.iter().map(move |vy| (*vx, *vy)))
+        .filter(|(vx, vy)| match annulus {
+            Some(annulus) => annulus.contains(*vx, *vy),
+            None => true,
+        })
+        .collect()
+}
+
+/// How to cluster the points at each velocity of a grid search. Only eps and
+/// min_cluster_size are required; the rest have defaults which can be
+/// overridden with the builder methods:
+///
+/// ```
+/// use thor_cluster::gridsearch::GridSearchConfig;
+/// use thor_cluster::ClusterAlgorithm;
+///
+/// let config = GridSearchConfig::new(0.02, 4)
+///     .algorithm(ClusterAlgorithm::DbscanRStar)
+///     .n_threads(8);
+/// assert_eq!(config.min_arc_length, 0.0);
+/// ```
+#[derive(Clone)]
+pub struct GridSearchConfig {
+    pub eps: f64,
+    /// Search every velocity at each of these eps values, rather than just
+    /// at eps, to see how the clusters change with it. Each one is a whole
+    /// extra search, so this multiplies the cost by its length. Defaults to
+    /// empty, which searches at eps alone.
+    pub eps_values: Vec<f64>,
+    pub min_cluster_size: usize,
+    /// Defaults to DBSCAN.
+    pub algorithm: ClusterAlgorithm,
+    /// Defaults to Euclidean.
+    pub metric: DistanceMetric,
+    /// Clusters spanning less than this in dt are relabeled as noise.
+    /// Defaults to 0.0, which keeps every cluster.
+    pub min_arc_length: f64,
+    /// The number of threads to search with. Defaults to 0, which means one
+    /// per CPU. More threads than CPUs, or than velocities to search, would
+    /// only contend with each other, so no more than that are started.
+    /// Ignored if thread_pool is set.
+    pub n_threads: usize,
+    /// A rayon thread pool to search in, instead of starting one of
+    /// n_threads threads for every search. Sharing the pool that the rest of
+    /// a program runs in keeps the search from competing with it for CPUs,
+    /// and saves starting threads for each search. Defaults to None.
+    pub thread_pool: Option<Arc<ThreadPool>>,
+    /// Velocities faster than this are skipped entirely. Defaults to None,
+    /// which searches every velocity.
+    pub max_speed: Option<f64>,
+    /// Clusters with more points than this are relabeled as noise. Defaults
+    /// to None, which keeps every cluster.
+    pub max_cluster_size: Option<usize>,
+    /// Search vxs and vys pairwise, as a list of (vx, vy) velocities, rather
+    /// than every combination of them. vxs and vys must then be the same
+    /// length. Defaults to false.
+    pub velocity_pairs: bool,
+    /// Log the progress of the search at info level every this many
+    /// velocities. Defaults to 0, which only logs a summary at the end.
+    pub log_interval: usize,
+    /// List each cluster's members together, in order of dt, in
+    /// cluster_grid_search_summaries. Defaults to false, which lists them in
+    /// the order of the points, and is a little faster.
+    pub sort_members_by_dt: bool,
+    /// The length of a night in dt units, used to count the nights each
+    /// cluster spans when summarizing them. Defaults to 1.0.
+    pub night_length: f64,
+}
+
+impl GridSearchConfig {
+    pub fn new(eps: f64, min_cluster_size: usize) -> GridSearchConfig {
+        GridSearchConfig {
+            eps,
+            eps_values: Vec::new(),
+            min_cluster_size,
+            algorithm: ClusterAlgorithm::DBSCAN,
+            metric: DistanceMetric::Euclidean,
+            min_arc_length: 0.0,
+            n_threads: 0,
+            thread_pool: None,
+            max_speed: None,
+            max_cluster_size: None,
+            velocity_pairs: false,
+            log_interval: 0,
+            sort_members_by_dt: false,
+            night_length: 1.0,
+        }
+    }
+
+    pub fn eps(mut self, eps: f64) -> Self {
+        self.eps = eps;
+        self
+    }
+
+    pub fn eps_values(mut self, eps_values: Vec<f64>) -> Self {
+        self.eps_values = eps_values;
+        self
+    }
+
+    pub fn min_cluster_size(mut self, min_cluster_size: usize) -> Self {
+        self.min_cluster_size = min_cluster_size;
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: ClusterAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn metric(mut self, metric: DistanceMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn min_arc_length(mut self, min_arc_length: f64) -> Self {
+        self.min_arc_length = min_arc_length;
+        self
+    }
+
+    pub fn n_threads(mut self, n_threads: usize) -> Self {
+        self.n_threads = n_threads;
+        self
+    }
+
+    pub fn thread_pool(mut self, thread_pool: Arc<ThreadPool>) -> Self {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
+
+    pub fn max_speed(mut self, max_speed: f64) -> Self {
+        self.max_speed = Some(max_speed);
+        self
+    }
+
+    pub fn max_cluster_size(mut self, max_cluster_size: usize) -> Self {
+        self.max_cluster_size = Some(max_cluster_size);
+        self
+    }
+
+    pub fn velocity_pairs(mut self, velocity_pairs: bool) -> Self {
+        self.velocity_pairs = velocity_pairs;
+        self
+    }
+
+    pub fn log_interval(mut self, log_interval: usize) -> Self {
+        self.log_interval = log_interval;
+        self
+    }
+
+    pub fn sort_members_by_dt(mut self, sort_members_by_dt: bool) -> Self {
+        self.sort_members_by_dt = sort_members_by_dt;
+        self
+    }
+
+    pub fn night_length(mut self, night_length: f64) -> Self {
+        self.night_length = night_length;
+        self
+    }
+
+    /// The (vx, vy) pairs a grid search over vxs and vys visits, in order:
+    /// vx-major, then vy, leaving out any faster than max_speed. With
+    /// velocity_pairs, they're vxs and vys zipped together instead.
+    pub fn velocities(&self, vxs: &[f64], vys: &[f64]) -> Vec<(f64, f64)> {
+        let velocities: Vec<(f64, f64)> = if self.velocity_pairs {
+            vxs.iter().copied().zip(vys.iter().copied()).collect()
+        } else {
+            vxs.iter()
+                .flat_map(|vx| vys.iter().map(move |vy| (*vx, *vy)))
+                .collect()
+        };
+        velocities
+            .into_iter()
+            .filter(|(vx, vy)| match self.max_speed {
+                Some(max_speed) => vx.hypot(*vy) <= max_speed,
+                None => true,
+            })
+            .collect()
+    }
+
+    /// The (vx, vy, eps) searches a grid search over vxs and vys makes, in
+    /// order: each of velocities, at each eps of eps_values in turn, or just
+    /// at eps if there are none.
+    pub fn searches(&self, vxs: &[f64], vys: &[f64]) -> Vec<(f64, f64, f64)> {
+        let eps_values = if self.eps_values.is_empty() {
+            vec![self.eps]
+        } else {
+            self.eps_values.clone()
+        };
+        self.velocities(vxs, vys)
+            .into_iter()
+            .flat_map(|(vx, vy)| eps_values.iter().map(move |&eps| (vx, vy, eps)))
+            .collect()
+    }
+}
+
+/// Cluster the points at every combination of vx and vy, or at each (vx, vy)
+/// pair with config.velocity_pairs. Results are in the same order as the
+/// serial search: vx-major, then vy. Velocities faster than config.max_speed
+/// are skipped, and have no result.
+///
+/// With more than one thread, the vx, vy pairs are shared out across a rayon
+/// thread pool of config.n_threads threads, or across config.thread_pool if
+/// it's set.
+///
+/// If cancel is given and gets set, the search stops early. Only velocities
+/// which had already been searched are returned, still in order. If progress
+/// is given, it's told about each velocity as it's searched.
 pub fn cluster_grid_search(
     points: &Vec<XYTPoint<f64>>,
     vxs: Vec<f64>,
     vys: Vec<f64>,
-    alg: ClusterAlgorithm,
-    eps: f64,
-    min_cluster_size: usize,
-    n_threads: usize,
+    config: &GridSearchConfig,
+    cancel: Option<&AtomicBool>,
+    progress: Option<&Progress>,
 ) -> Vec<GridSearchResult> {
-    if n_threads == 1 {
-        return cluster_grid_search_serial(points, vxs, vys, alg, eps, min_cluster_size);
-    }
-    let mut vx_chunks = vxs.chunks(work_chunk_size(n_threads, vxs.len()));
-    let (tx, rx) = channel();
-    let mut handles = Vec::new();
-    for _i in 1..n_threads {
-        let tx = tx.clone();
-        let vx_chunk = vx_chunks.next();
-        if vx_chunk.is_none() {
-            // We've run out of chunks to process
-            break;
-        }
-        let vx_chunk = vx_chunk.unwrap().to_vec();
-        let vys = vys.clone();
-        let points = points.clone();
-        let alg = alg.clone();
-        let eps = eps.clone();
-        let min_cluster_size = min_cluster_size.clone();
-
-        let thread_name = format!("grid_search_{}", _i);
-        let threadbuilder = thread::Builder::new().name(thread_name);
-
-        let core_ids = core_affinity::get_core_ids().unwrap();
-        let core_id = core_ids[_i % core_ids.len()];
-        let handle = threadbuilder.spawn(move || {
-            core_affinity::set_for_current(core_id);
-            for vx in vx_chunk.iter() {
-                for vy in &vys {
-                    let xy_points = apply_velocity(*vx, *vy, &points);
-                    let cluster_labels = find_clusters(&xy_points, eps, min_cluster_size, &alg);
-                    let result = GridSearchResult {
-                        vx: *vx,
-                        vy: *vy,
-                        cluster_labels: cluster_labels,
-                    };
-                    tx.send(result).unwrap();
+    cluster_grid_search_with_diagnostics(points, vxs, vys, config, cancel, progress).0
+}
+
+/// Like cluster_grid_search, but also report diagnostics about the search.
+pub fn cluster_grid_search_with_diagnostics(
+    points: &Vec<XYTPoint<f64>>,
+    vxs: Vec<f64>,
+    vys: Vec<f64>,
+    config: &GridSearchConfig,
+    cancel: Option<&AtomicBool>,
+    progress: Option<&Progress>,
+) -> (Vec<GridSearchResult>, Diagnostics) {
+    search_velocities(points, vxs, vys, config, cancel, progress, |result| result)
+}
+
+/// The number of distinct clusters in a set of labels, leaving out noise.
+fn count_clusters(labels: &[i32]) -> usize {
+    labels
+        .iter()
+        .filter(|label| **label >= 0)
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+/// Like cluster_grid_search, but only count the clusters found at each
+/// velocity, as (vx, vy, n_clusters), for a quick look at which velocities
+/// are productive. Each velocity's labels are dropped as soon as they're
+/// counted. The counts are in the order sort_results would put the results
+/// in, so with config.eps_values, there's one for each eps in turn.
+pub fn cluster_grid_search_counts(
+    points: &Vec<XYTPoint<f64>>,
+    vxs: Vec<f64>,
+    vys: Vec<f64>,
+    config: &GridSearchConfig,
+    cancel: Option<&AtomicBool>,
+    progress: Option<&Progress>,
+) -> Vec<(f64, f64, usize)> {
+    let (mut counts, _) = search_velocities(points, vxs, vys, config, cancel, progress, |result| {
+        (
+            (result.vx, result.vy, result.eps),
+            count_clusters(&result.cluster_labels),
+        )
+    });
+    counts.sort_by(|(a, _), (b, _)| search_order(*a, *b));
+    counts
+        .into_iter()
+        .map(|((vx, vy, _), n_clusters)| (vx, vy, n_clusters))
+        .collect()
+}
+
+/// Like cluster_grid_search followed by sort_results and summarize_clusters,
+/// but without holding on to every result. Each velocity's labels are
+/// summarized as soon as they're found, and then dropped, so memory grows
+/// with the number of clusters found rather than with the number of points
+/// times the number of velocities.
+///
+/// Also returns whether each point was ever clustered, at any velocity, so
+/// that points which were noise at every velocity can be picked out.
+pub fn cluster_grid_search_summaries(
+    points: &Vec<XYTPoint<f64>>,
+    ids: &[String],
+    vxs: Vec<f64>,
+    vys: Vec<f64>,
+    config: &GridSearchConfig,
+    cancel: Option<&AtomicBool>,
+    progress: Option<&Progress>,
+) -> (
+    Vec<ClusterSummary>,
+    Vec<ClusterMember>,
+    Vec<bool>,
+    Diagnostics,
+) {
+    let ever_clustered: Vec<AtomicBool> = points.iter().map(|_| AtomicBool::new(false)).collect();
+    let (mut summarized, diagnostics) =
+        search_velocities(points, vxs, vys, config, cancel, progress, |result| {
+            for (label, clustered) in result.cluster_labels.iter().zip(ever_clustered.iter()) {
+                if *label >= 0 {
+                    clustered.store(true, Ordering::Relaxed);
                 }
             }
+            let (summaries, members) = summarize_result(
+                &result,
+                points,
+                ids,
+                config.night_length,
+                config.sort_members_by_dt,
+            );
+            ((result.vx, result.vy, result.eps), summaries, members)
         });
-        handles.push(handle.unwrap());
-    }
-    drop(tx);
-    let mut results = Vec::new();
-    while let Ok(result) = rx.recv() {
-        results.push(result);
-    }
-    for handle in handles {
-        handle.join().unwrap();
+    // The same order sort_results would put the results in.
+    summarized.sort_by(|(a, _, _), (b, _, _)| search_order(*a, *b));
+    let (summaries, members) = number_clusters(
+        summarized
+            .into_iter()
+            .map(|(_, summaries, members)| (summaries, members)),
+    );
+    let ever_clustered = ever_clustered
+        .into_iter()
+        .map(AtomicBool::into_inner)
+        .collect();
+    (summaries, members, ever_clustered, diagnostics)
+}
+
+/// Cluster points where they are, as one velocity, (0, 0), of a grid search
+/// would, and summarize the clusters, for points which have already been
+/// shifted to a known velocity. Only config's eps, min_cluster_size,
+/// algorithm, metric, min_arc_length, max_cluster_size, sort_members_by_dt,
+/// and night_length apply.
+pub fn cluster_single(
+    points: &Vec<XYTPoint<f64>>,
+    ids: &[String],
+    config: &GridSearchConfig,
+) -> (Vec<ClusterSummary>, Vec<ClusterMember>) {
+    let counters = Counters::new(1, 0);
+    let result = cluster_at_velocity(points, 0.0, 0.0, config.eps, config, &counters);
+    summarize_result(
+        &result,
+        points,
+        ids,
+        config.night_length,
+        config.sort_members_by_dt,
+    )
+}
+
+/// Run cluster_grid_search_summaries separately on each of several
+/// independent cells of points, such as patches of sky, so that points in
+/// different cells are never clustered together. cells gives the cell of
+/// each point, and must line up with points and ids.
+///
+/// The cells are spread across threads, each searched on just one, rather
+/// than spreading each cell's velocities across them, which keeps the threads
+/// busy through many small cells. config.n_threads and config.thread_pool
+/// pick the threads as they do for cluster_grid_search.
+///
+/// Cluster IDs count up from 1 across every cell, in order of cell. Also
+/// returns the cell of each summary.
+pub fn cluster_grid_search_cells(
+    points: &[XYTPoint<f64>],
+    ids: &[String],
+    cells: &[i64],
+    vxs: Vec<f64>,
+    vys: Vec<f64>,
+    config: &GridSearchConfig,
+    cancel: Option<&AtomicBool>,
+) -> (Vec<ClusterSummary>, Vec<ClusterMember>, Vec<i64>) {
+    let mut cell_rows: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+    for (i, &cell) in cells.iter().enumerate() {
+        cell_rows.entry(cell).or_default().push(i);
     }
-    results
+    let cell_rows: Vec<(i64, Vec<usize>)> = cell_rows.into_iter().collect();
+
+    let mut cell_config = config.clone().n_threads(1);
+    cell_config.thread_pool = None;
+    let search_cell = |(cell, rows): &(i64, Vec<usize>)| {
+        let cell_points: Vec<XYTPoint<f64>> = rows.iter().map(|&i| points[i]).collect();
+        let cell_ids: Vec<String> = rows.iter().map(|&i| ids[i].clone()).collect();
+        let (summaries, members, _, _) = cluster_grid_search_summaries(
+            &cell_points,
+            &cell_ids,
+            vxs.clone(),
+            vys.clone(),
+            &cell_config,
+            cancel,
+            None,
+        );
+        (*cell, summaries, members)
+    };
+    let searched: Vec<(i64, Vec<ClusterSummary>, Vec<ClusterMember>)> = match &config.thread_pool {
+        Some(pool) => pool.install(|| cell_rows.par_iter().map(search_cell).collect()),
+        None => {
+            let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+            match worker_threads(config.n_threads, available, cell_rows.len()) {
+                1 => cell_rows.iter().map(search_cell).collect(),
+                n_threads => match ThreadPoolBuilder::new()
+                    .num_threads(n_threads)
+                    .thread_name(|i| format!("grid_search_{}", i))
+                    .build()
+                {
+                    Ok(pool) => pool.install(|| cell_rows.par_iter().map(search_cell).collect()),
+                    Err(err) => {
+                        warn!(
+                            "can't start grid search threads, searching serially: {}",
+                            err
+                        );
+                        cell_rows.iter().map(search_cell).collect()
+                    }
+                },
+            }
+        }
+    };
+
+    let summary_cells = searched
+        .iter()
+        .flat_map(|(cell, summaries, _)| std::iter::repeat_n(*cell, summaries.len()))
+        .collect();
+    let (summaries, members) = number_clusters(
+        searched
+            .into_iter()
+            .map(|(_, summaries, members)| (summaries, members)),
+    );
+    (summaries, members, summary_cells)
 }
 
-fn cluster_grid_search_serial(
+/// Search every velocity, passing each result through handle as soon as
+/// it's found.
+fn search_velocities<T: Send>(
     points: &Vec<XYTPoint<f64>>,
     vxs: Vec<f64>,
     vys: Vec<f64>,
-    alg: ClusterAlgorithm,
-    eps: f64,
-    min_cluster_size: usize,
-) -> Vec<GridSearchResult> {
-    let mut results = Vec::new();
-    for vx in vxs.iter() {
-        for vy in vys.iter() {
-            let xy_points = apply_velocity(*vx, *vy, &points);
-            let cluster_labels = find_clusters(&xy_points, eps, min_cluster_size, &alg);
-            let result = GridSearchResult {
-                vx: *vx,
-                vy: *vy,
-                cluster_labels: cluster_labels,
-            };
-            results.push(result);
+    config: &GridSearchConfig,
+    cancel: Option<&AtomicBool>,
+    progress: Option<&Progress>,
+    handle: impl Fn(GridSearchResult) -> T + Sync,
+) -> (Vec<T>, Diagnostics) {
+    let start = Instant::now();
+    let searches = config.searches(&vxs, &vys);
+    let counters = Counters::new(searches.len(), config.log_interval);
+    let search = VelocitySearch {
+        points,
+        config,
+        cancel,
+        progress,
+        counters: &counters,
+    };
+    let results = if config.n_threads == 1 && config.thread_pool.is_none() {
+        search.serial(&searches, handle)
+    } else {
+        search.parallel(&searches, handle)
+    };
+    (results, counters.finish(start.elapsed()))
+}
+
+/// The state shared by every (vx, vy, eps) search of one grid search.
+struct VelocitySearch<'a> {
+    points: &'a Vec<XYTPoint<f64>>,
+    config: &'a GridSearchConfig,
+    cancel: Option<&'a AtomicBool>,
+    progress: Option<&'a Progress>,
+    counters: &'a Counters,
+}
+
+impl VelocitySearch<'_> {
+    fn search(&self, vx: f64, vy: f64, eps: f64) -> GridSearchResult {
+        let result = cluster_at_velocity(self.points, vx, vy, eps, self.config, self.counters);
+        if let Some(progress) = self.progress {
+            progress.tick();
+        }
+        result
+    }
+
+    fn parallel<T: Send>(
+        &self,
+        searches: &[(f64, f64, f64)],
+        handle: impl Fn(GridSearchResult) -> T + Sync,
+    ) -> Vec<T> {
+        if let Some(pool) = &self.config.thread_pool {
+            return self.install(pool, searches, handle);
+        }
+        let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let n_threads = worker_threads(self.config.n_threads, available, searches.len());
+        if self.config.n_threads > n_threads {
+            info!(
+                "making {} searches with {} threads rather than the {} asked for, with {} CPUs",
+                searches.len(),
+                n_threads,
+                self.config.n_threads,
+                available
+            );
+        }
+        if n_threads <= 1 {
+            return self.serial(searches, handle);
+        }
+        let pool = match ThreadPoolBuilder::new()
+            .num_threads(n_threads)
+            .thread_name(|i| format!("grid_search_{}", i))
+            .build()
+        {
+            Ok(pool) => pool,
+            Err(err) => {
+                // Restricted containers may not let us start threads. Searching
+                // on this one is slower, but gets the same results.
+                warn!(
+                    "can't start grid search threads, searching serially: {}",
+                    err
+                );
+                return self.serial(searches, handle);
+            }
+        };
+        self.install(&pool, searches, handle)
+    }
+
+    fn install<T: Send>(
+        &self,
+        pool: &ThreadPool,
+        searches: &[(f64, f64, f64)],
+        handle: impl Fn(GridSearchResult) -> T + Sync,
+    ) -> Vec<T> {
+        pool.install(|| {
+            searches
+                .par_iter()
+                .filter_map(|(vx, vy, eps)| {
+                    if cancelled(self.cancel) {
+                        return None;
+                    }
+                    Some(handle(self.search(*vx, *vy, *eps)))
+                })
+                .collect()
+        })
+    }
+
+    fn serial<T>(
+        &self,
+        searches: &[(f64, f64, f64)],
+        handle: impl Fn(GridSearchResult) -> T,
+    ) -> Vec<T> {
+        let mut results = Vec::new();
+        for (vx, vy, eps) in searches.iter() {
+            if cancelled(self.cancel) {
+                return results;
+            }
+            results.push(handle(self.search(*vx, *vy, *eps)));
         }
+        results
     }
-    results
 }
 
-fn work_chunk_size(n_threads: usize, n_vxs: usize) -> usize {
-    let chunk_size = n_vxs / n_threads;
-    if n_vxs % n_threads == 0 {
-        chunk_size
-    } else {
-        chunk_size + 1
-    }
-}
-
-#[test]
-fn test_work_chunk_size() {
-    // Partition the work to be done
-    // for 2 threads
-    // 10 -> 5, 5
-    // 11 -> 6, 5
-    // 12 -> 6, 6
-    // 13 -> 7, 6
-    assert_eq!(work_chunk_size(2, 10), 5);
-    assert_eq!(work_chunk_size(2, 11), 6);
-    assert_eq!(work_chunk_size(2, 12), 6);
-    assert_eq!(work_chunk_size(2, 13), 7);
-    // for 3 threads
-    // 10 -> 4, 4, 2
-    // 11 -> 4, 4, 3
-    // 12 -> 4, 4, 4
-    // 13 -> 5, 5, 3
-    // 14 -> 5, 5, 4
-    // 15 -> 5, 5, 5
-    // 16 -> 6, 5, 5
-    assert_eq!(work_chunk_size(3, 10), 4);
-    assert_eq!(work_chunk_size(3, 11), 4);
-    assert_eq!(work_chunk_size(3, 12), 4);
-    assert_eq!(work_chunk_size(3, 13), 5);
-    assert_eq!(work_chunk_size(3, 14), 5);
-    assert_eq!(work_chunk_size(3, 15), 5);
-    assert_eq!(work_chunk_size(3, 16), 6);
+/// How many threads to make n_searches searches with, when n_threads
+/// are asked for and there are available CPUs: no more than either, since
+/// extra threads would sit idle or fight over CPUs. 0 asks for one per CPU.
+fn worker_threads(n_threads: usize, available: usize, n_searches: usize) -> usize {
+    let n_threads = if n_threads == 0 { available } else { n_threads };
+    n_threads.min(available).min(n_searches).max(1)
+}
+
+/// Relabel the points of any cluster spanning less than min_arc_length in dt
+/// as noise.
+fn drop_short_arcs(labels: &mut [i32], points: &[XYTPoint<f64>], min_arc_length: f64) {
+    let arcs = arcs(labels, points.iter().map(|p| p.t));
+    for label in labels.iter_mut() {
+        if let Some((start, end)) = arcs.get(label) {
+            if end - start < min_arc_length {
+                *label = -1;
+            }
+        }
+    }
+}
+
+/// The earliest and latest dt of each cluster, by label. Noise is left out.
+fn arcs(labels: &[i32], dts: impl IntoIterator<Item = f64>) -> BTreeMap<i32, (f64, f64)> {
+    let mut arcs = BTreeMap::new();
+    for (label, dt) in labels.iter().zip(dts) {
+        if *label < 0 {
+            continue;
+        }
+        let arc = arcs.entry(*label).or_insert((dt, dt));
+        arc.0 = arc.0.min(dt);
+        arc.1 = arc.1.max(dt);
+    }
+    arcs
+}
+
+/// The arc length, max(dt) - min(dt), of each cluster in labels, in order of
+/// label. Noise is left out. labels and dts must line up.
+pub fn arc_lengths(labels: &[i32], dts: &[f64]) -> Vec<(i32, f64)> {
+    arcs(labels, dts.iter().copied())
+        .into_iter()
+        .map(|(label, (start, end))| (label, end - start))
+        .collect()
+}
+
+fn cancelled(cancel: Option<&AtomicBool>) -> bool {
+    cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+}
+
+fn cluster_at_velocity(
+    points: &Vec<XYTPoint<f64>>,
+    vx: f64,
+    vy: f64,
+    eps: f64,
+    config: &GridSearchConfig,
+    counters: &Counters,
+) -> GridSearchResult {
+    let xy_points: Vec<XYPoint<f64>> = points.iter().map(|p| p.shift_by_velocity(vx, vy)).collect();
+    let mut cluster_labels = find_clusters(
+        &xy_points,
+        eps,
+        config.min_cluster_size,
+        &config.algorithm,
+        &config.metric,
+    );
+    counters.record(&cluster_labels);
+    if let Some(max_cluster_size) = config.max_cluster_size {
+        drop_large_clusters(&mut cluster_labels, max_cluster_size);
+    }
+    if config.min_arc_length > 0.0 {
+        drop_short_arcs(&mut cluster_labels, points, config.min_arc_length);
+    }
+    GridSearchResult {
+        vx,
+        vy,
+        eps,
+        cluster_labels,
+    }
+}
+
+#[test]
+fn test_summarize_clusters() {
+    let results = vec![
+        GridSearchResult {
+            vx: 0.0,
+            vy: 0.5,
+            eps: 0.1,
+            cluster_labels: vec![1, 1, -1, 2, 2],
+        },
+        GridSearchResult {
+            vx: 1.0,
+            vy: 0.5,
+            eps: 0.1,
+            cluster_labels: vec![-1, 3, 3, 3, -1],
+        },
+    ];
+    let points = vec![
+        XYTPoint::new(1.0, 2.0, 0.0),
+        XYTPoint::new(3.0, 4.0, 1.5),
+        XYTPoint::new(2.0, 1.25, 2.0),
+        XYTPoint::new(4.0, 0.0, 4.0),
+        XYTPoint::new(6.0, 0.0, 3.0),
+    ];
+    let ids: Vec<String> = ["a", "b", "c", "d", "e"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    let (summaries, members) = summarize_clusters(&results, &points, &ids, 1.0);
+    // Two points always fit a line exactly.
+    let residuals: Vec<f64> = summaries.iter().map(|s| s.residual_rms).collect();
+    assert!(residuals[0].abs() < 1e-12);
+    assert!(residuals[1].abs() < 1e-12);
+    assert!((residuals[2] - 1.019892).abs() < 1e-6);
+    let fits: Vec<(f64, f64)> = summaries.iter().map(|s| (s.vx_fit, s.vy_fit)).collect();
+    let want_fits = [
+        (4.0 / 3.0, 4.0 / 3.0),
+        (-2.0, 0.0),
+        (2.0 / 3.5, -4.625 / 3.5),
+    ];
+    for (fit, want) in fits.iter().zip(want_fits) {
+        assert!((fit.0 - want.0).abs() < 1e-12, "{:?} != {:?}", fit, want);
+        assert!((fit.1 - want.1).abs() < 1e-12, "{:?} != {:?}", fit, want);
+    }
+    assert_eq!(
+        summaries,
+        vec![
+            ClusterSummary {
+                cluster_id: 1,
+                vx: 0.0,
+                vy: 0.5,
+                arc_length: 1.5,
+                n_obs: 2,
+                mean_x: 2.0,
+                mean_y: 2.625,
+                n_nights: 2,
+                residual_rms: residuals[0],
+                vx_fit: fits[0].0,
+                vy_fit: fits[0].1,
+                eps: 0.1,
+            },
+            ClusterSummary {
+                cluster_id: 2,
+                vx: 0.0,
+                vy: 0.5,
+                arc_length: 1.0,
+                n_obs: 2,
+                mean_x: 5.0,
+                mean_y: -1.75,
+                n_nights: 2,
+                residual_rms: residuals[1],
+                vx_fit: fits[1].0,
+                vy_fit: fits[1].1,
+                eps: 0.1,
+            },
+            ClusterSummary {
+                cluster_id: 3,
+                vx: 1.0,
+                vy: 0.5,
+                arc_length: 2.5,
+                n_obs: 3,
+                mean_x: 0.5,
+                mean_y: 0.5,
+                n_nights: 3,
+                residual_rms: residuals[2],
+                vx_fit: fits[2].0,
+                vy_fit: fits[2].1,
+                eps: 0.1,
+            },
+        ]
+    );
+    let members: Vec<(u32, &str)> = members
+        .iter()
+        .map(|m| (m.cluster_id, m.obs_id.as_str()))
+        .collect();
+    assert_eq!(
+        members,
+        vec![
+            (1, "a"),
+            (1, "b"),
+            (2, "d"),
+            (2, "e"),
+            (3, "b"),
+            (3, "c"),
+            (3, "d"),
+        ]
+    );
 }
 
 #[test]
@@ -181,7 +1450,14 @@ fn test_grid_search() {
     ];
     let vxs = vec![0.0, 0.5, 1.0];
     let vys = vec![0.0, 0.5, 1.0];
-    let results = cluster_grid_search(&points, vxs, vys, ClusterAlgorithm::DBSCAN, 1.0, 4, 1);
+    let results = cluster_grid_search(
+        &points,
+        vxs,
+        vys,
+        &GridSearchConfig::new(1.0, 4).n_threads(1),
+        None,
+        None,
+    );
     assert_eq!(results.len(), 9);
     assert_eq!(results[0].vx, 0.0);
     assert_eq!(results[0].vy, 0.0);
@@ -191,7 +1467,9 @@ fn test_grid_search() {
     assert_eq!(results[8].vx, 1.0);
     assert_eq!(results[8].vy, 1.0);
     assert_eq!(results[8].cluster_labels.len(), 6);
-    assert_eq!(results[8].cluster_labels, vec![1, 1, -1, 1, 1, 1]);
+    // The third point is only within eps of the fourth, which is a core
+    // point, so it's a border point of the cluster.
+    assert_eq!(results[8].cluster_labels, vec![1, 1, 1, 1, 1, 1]);
 }
 
 #[test]
@@ -288,8 +1566,15 @@ fn test_grid_search_multithreaded() {
 
     let n_points = points.len();
     let n_vxvy_pairs = vxs.len() * vys.len();
-    // Find clusters using 4 threads (1 main, 3 workers).
-    let results = cluster_grid_search(&points, vxs, vys, ClusterAlgorithm::DBSCAN, 0.5, 4, 4);
+    // Find clusters using 4 threads.
+    let results = cluster_grid_search(
+        &points,
+        vxs,
+        vys,
+        &GridSearchConfig::new(0.5, 4).n_threads(4),
+        None,
+        None,
+    );
 
     assert_eq!(
         results.len(),
@@ -362,3 +1647,1041 @@ fn test_grid_search_multithreaded() {
         };
     }
 }
+
+#[test]
+fn test_grid_search_more_threads_than_vxs() {
+    // A single vx should still be spread across every thread, and give the
+    // same results as a serial search.
+    let points = vec![
+        XYTPoint {
+            x: 0.0,
+            y: 0.0,
+            t: 0.0,
+        },
+        XYTPoint {
+            x: 0.0,
+            y: 1.0,
+            t: 1.0,
+        },
+        XYTPoint {
+            x: 0.0,
+            y: 2.1,
+            t: 2.0,
+        },
+        XYTPoint {
+            x: 5.0,
+            y: 5.0,
+            t: 1.0,
+        },
+    ];
+    let vxs = vec![0.0];
+    let vys = vec![-1.0, -0.5, 0.0, 0.5, 1.0, 1.5];
+
+    let serial = cluster_grid_search(
+        &points,
+        vxs.clone(),
+        vys.clone(),
+        &GridSearchConfig::new(0.5, 3).n_threads(1),
+        None,
+        None,
+    );
+    let parallel = cluster_grid_search(
+        &points,
+        vxs,
+        vys,
+        &GridSearchConfig::new(0.5, 3).n_threads(8),
+        None,
+        None,
+    );
+
+    assert_eq!(parallel.len(), 6);
+    for (s, p) in serial.iter().zip(parallel.iter()) {
+        assert_eq!((s.vx, s.vy), (p.vx, p.vy));
+        assert_eq!(s.cluster_labels, p.cluster_labels);
+    }
+    assert_eq!(parallel[4].vy, 1.0);
+    assert_eq!(parallel[4].cluster_labels, vec![1, 1, 1, -1]);
+}
+
+#[test]
+fn test_dedup_clusters() {
+    // All of the points are at dt=0, so every velocity finds the same
+    // four-point cluster.
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(0.1, 0.0, 0.0),
+        XYTPoint::new(0.0, 0.1, 0.0),
+        XYTPoint::new(0.1, 0.1, 0.0),
+        XYTPoint::new(5.0, 5.0, 1.0),
+    ];
+    let ids: Vec<String> = ["a", "b", "c", "d", "e"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let results = cluster_grid_search(
+        &points,
+        vec![0.0, 0.1],
+        vec![0.0],
+        &GridSearchConfig::new(0.5, 4).n_threads(1),
+        None,
+        None,
+    );
+
+    let (summaries, members) = summarize_clusters(&results, &points, &ids, 1.0);
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(members.len(), 8);
+
+    let (summaries, members) = dedup_clusters(summaries, members);
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].cluster_id, 1);
+    assert_eq!(summaries[0].vx, 0.0);
+    let obs_ids: Vec<&str> = members.iter().map(|m| m.obs_id.as_str()).collect();
+    assert_eq!(obs_ids, vec!["a", "b", "c", "d"]);
+}
+
+#[test]
+fn test_velocity_range() {
+    assert_eq!(
+        velocity_range(-1.0, 1.0, 5),
+        vec![-1.0, -0.5, 0.0, 0.5, 1.0]
+    );
+    // The last step lands exactly on v_max, even when the step size doesn't
+    // divide evenly.
+    let vs = velocity_range(0.0, 1.0, 7);
+    assert_eq!(vs.len(), 7);
+    assert_eq!(vs[0], 0.0);
+    assert_eq!(vs[6], 1.0);
+    assert_eq!(velocity_range(0.5, 1.0, 1), vec![0.5]);
+    assert_eq!(velocity_range(0.5, 1.0, 0), Vec::<f64>::new());
+}
+
+#[test]
+fn test_velocity_grid_annulus() {
+    let grid = velocity_grid(-1.0, 1.0, 3, None);
+    assert_eq!(grid.len(), 9);
+    assert_eq!(grid[0], (-1.0, -1.0));
+    assert_eq!(grid[1], (-1.0, 0.0));
+    assert_eq!(grid[8], (1.0, 1.0));
+
+    // Speeds from 0.5 to 1.0 drop the origin and the corners.
+    let annulus = VelocityAnnulus {
+        min_speed: 0.5,
+        max_speed: 1.0,
+        min_angle: 0.0,
+        max_angle: 360.0,
+    };
+    let grid = velocity_grid(-1.0, 1.0, 3, Some(&annulus));
+    assert_eq!(grid, vec![(-1.0, 0.0), (0.0, -1.0), (0.0, 1.0), (1.0, 0.0)]);
+
+    // An angle range which wraps through 0 degrees.
+    let annulus = VelocityAnnulus {
+        min_speed: 0.5,
+        max_speed: 1.0,
+        min_angle: 270.0,
+        max_angle: 90.0,
+    };
+    let grid = velocity_grid(-1.0, 1.0, 3, Some(&annulus));
+    assert_eq!(grid, vec![(0.0, -1.0), (0.0, 1.0), (1.0, 0.0)]);
+}
+
+#[test]
+fn test_grid_search_cancel() {
+    let points = (0..200)
+        .map(|i| XYTPoint::new(i as f64 * 0.01, 0.0, (i % 10) as f64))
+        .collect::<Vec<_>>();
+    let vs = velocity_range(-1.0, 1.0, 200);
+    let n_pairs = vs.len() * vs.len();
+
+    // Cancelled before it starts, nothing is searched.
+    let cancel = AtomicBool::new(true);
+    let results = cluster_grid_search(
+        &points,
+        vs.clone(),
+        vs.clone(),
+        &GridSearchConfig::new(0.1, 4).n_threads(1),
+        Some(&cancel),
+        None,
+    );
+    assert!(results.is_empty());
+
+    // Cancelled partway through, the results so far come back.
+    for n_threads in [1, 4] {
+        let cancel = AtomicBool::new(false);
+        let results = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                cancel.store(true, Ordering::Relaxed);
+            });
+            cluster_grid_search(
+                &points,
+                vs.clone(),
+                vs.clone(),
+                &GridSearchConfig::new(0.1, 4).n_threads(n_threads),
+                Some(&cancel),
+                None,
+            )
+        });
+        assert!(!results.is_empty());
+        assert!(results.len() < n_pairs);
+    }
+}
+
+#[test]
+fn test_grid_search_empty() {
+    for n_threads in [1, 4] {
+        let config = GridSearchConfig::new(1.0, 2).n_threads(n_threads);
+        let results = cluster_grid_search(&vec![], vec![0.0], vec![0.0, 1.0], &config, None, None);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.cluster_labels.is_empty()));
+
+        let (summaries, members) = summarize_clusters(&results, &[], &[], 1.0);
+        assert!(summaries.is_empty());
+        assert!(members.is_empty());
+    }
+}
+
+#[test]
+fn test_grid_search_progress() {
+    let points = vec![XYTPoint::new(0.0, 0.0, 0.0), XYTPoint::new(1.0, 1.0, 1.0)];
+    for n_threads in [1, 4] {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let progress = Progress::new(5, 12, tx);
+        let results = cluster_grid_search(
+            &points,
+            vec![0.0, 1.0, 2.0],
+            vec![0.0, 1.0, 2.0, 3.0],
+            &GridSearchConfig::new(0.5, 2).n_threads(n_threads),
+            None,
+            Some(&progress),
+        );
+        drop(progress);
+        assert_eq!(results.len(), 12);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![5, 10, 12]);
+    }
+}
+
+#[test]
+fn test_grid_search_min_arc_length() {
+    let points = vec![
+        // A cluster spanning 0.3 in dt.
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(0.1, 0.0, 0.1),
+        XYTPoint::new(0.0, 0.1, 0.2),
+        XYTPoint::new(0.1, 0.1, 0.3),
+        // A cluster spanning 3 in dt.
+        XYTPoint::new(5.0, 5.0, 0.0),
+        XYTPoint::new(5.1, 5.0, 1.0),
+        XYTPoint::new(5.0, 5.1, 2.0),
+        XYTPoint::new(5.1, 5.1, 3.0),
+    ];
+    let search = |min_arc_length| {
+        cluster_grid_search(
+            &points,
+            vec![0.0],
+            vec![0.0],
+            &GridSearchConfig::new(0.5, 4)
+                .min_arc_length(min_arc_length)
+                .n_threads(1),
+            None,
+            None,
+        )
+    };
+
+    let results = search(0.0);
+    assert_eq!(results[0].cluster_labels, vec![1, 1, 1, 1, 2, 2, 2, 2]);
+
+    let results = search(1.0);
+    assert_eq!(results[0].cluster_labels, vec![-1, -1, -1, -1, 2, 2, 2, 2]);
+}
+
+#[test]
+fn test_min_nights() {
+    let results = vec![GridSearchResult {
+        vx: 0.0,
+        vy: 0.0,
+        eps: 0.1,
+        cluster_labels: vec![1, 1, 1, 2, 2, 2],
+    }];
+    let points = vec![
+        // Cluster 1 is all on one night.
+        XYTPoint::new(0.0, 0.0, 0.1),
+        XYTPoint::new(0.0, 0.0, 0.2),
+        XYTPoint::new(0.0, 0.0, 0.3),
+        // Cluster 2 spans two.
+        XYTPoint::new(5.0, 5.0, 0.1),
+        XYTPoint::new(5.0, 5.0, 0.2),
+        XYTPoint::new(5.0, 5.0, 1.1),
+    ];
+    let ids: Vec<String> = ["a", "b", "c", "d", "e", "f"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    let (summaries, members) = summarize_clusters(&results, &points, &ids, 1.0);
+    let n_nights: Vec<u32> = summaries.iter().map(|s| s.n_nights).collect();
+    assert_eq!(n_nights, vec![1, 2]);
+
+    let (summaries, members) = retain_clusters(summaries, members, |s| s.n_nights >= 2);
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].cluster_id, 2);
+    let obs_ids: Vec<&str> = members.iter().map(|m| m.obs_id.as_str()).collect();
+    assert_eq!(obs_ids, vec!["d", "e", "f"]);
+
+    // With half-day nights, cluster 1 spans one night and cluster 2 spans
+    // two (0.1 and 0.2 share a night, 1.1 is later).
+    let (summaries, _) = summarize_clusters(&results, &points, &ids, 0.5);
+    let n_nights: Vec<u32> = summaries.iter().map(|s| s.n_nights).collect();
+    assert_eq!(n_nights, vec![1, 2]);
+}
+
+#[test]
+fn test_fit_linear_residual() {
+    // Points moving in a straight line at constant speed fit exactly.
+    let points: Vec<XYTPoint<f64>> = (0..5)
+        .map(|i| {
+            let t = i as f64 * 0.7;
+            XYTPoint::new(1.0 + 0.3 * t, -2.0 - 0.1 * t, t)
+        })
+        .collect();
+    assert!(fit_linear_residual(&points) < 1e-12);
+
+    // Scattered points at a single time are measured from their centroid.
+    let points = vec![
+        XYTPoint::new(1.0, 0.0, 0.0),
+        XYTPoint::new(-1.0, 0.0, 0.0),
+        XYTPoint::new(0.0, 1.0, 0.0),
+        XYTPoint::new(0.0, -1.0, 0.0),
+    ];
+    assert!((fit_linear_residual(&points) - 1.0).abs() < 1e-12);
+
+    // Scatter about a line leaves a residual.
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(1.5, 0.0, 1.0),
+        XYTPoint::new(2.0, 0.0, 2.0),
+        XYTPoint::new(3.5, 0.0, 3.0),
+    ];
+    // The best fit is x = 0.1 + 1.1t, which misses by 0.1, 0.3, 0.3 and 0.1.
+    let residual = fit_linear_residual(&points);
+    assert!((residual - 0.05_f64.sqrt()).abs() < 1e-12);
+}
+
+#[test]
+fn test_has_motion() {
+    // A blob of detections from one exposure, and a stationary object seen
+    // in several.
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 1.0),
+        XYTPoint::new(0.1, 0.0, 1.0),
+        XYTPoint::new(0.0, 0.1, 1.0),
+        XYTPoint::new(5.0, 5.0, 0.0),
+        XYTPoint::new(5.0, 5.0, 1.0),
+        XYTPoint::new(5.0, 5.0, 2.0),
+    ];
+    let ids: Vec<String> = ["blob1", "blob2", "blob3", "obj1", "obj2", "obj3"]
+        .map(String::from)
+        .to_vec();
+    let results = vec![GridSearchResult {
+        vx: 0.0,
+        vy: 0.0,
+        eps: 0.1,
+        cluster_labels: vec![1, 1, 1, 2, 2, 2],
+    }];
+    let (summaries, members) = summarize_clusters(&results, &points, &ids, 1.0);
+    let motion: Vec<bool> = summaries.iter().map(|s| s.has_motion()).collect();
+    assert_eq!(motion, vec![false, true]);
+    // Exactly zero, not just small, so that it can be checked for before
+    // dividing by it.
+    assert_eq!(summaries[0].arc_length, 0.0);
+    assert_eq!((summaries[0].vx_fit, summaries[0].vy_fit), (0.0, 0.0));
+
+    let (clusters, _) = to_record_batches(&summaries, &members, false).unwrap();
+    let single_exposure = clusters
+        .column_by_name("single_exposure")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<arrow::array::BooleanArray>()
+        .unwrap();
+    assert_eq!(
+        single_exposure.iter().collect::<Vec<_>>(),
+        vec![Some(true), Some(false)]
+    );
+
+    let (summaries, members) = retain_clusters(summaries, members, |s| s.has_motion());
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].n_obs, 3);
+    assert!(members.iter().all(|m| m.obs_id.starts_with("obj")));
+}
+
+#[test]
+fn test_grid_search_fits_velocity() {
+    // An object moving between grid velocities, with a little deterministic
+    // scatter. The search finds it at the nearest velocity on the grid, but
+    // the fit recovers the true one.
+    let (vx, vy) = (0.31, -0.17);
+    let points: Vec<XYTPoint<f64>> = (0..10)
+        .map(|i| {
+            let t = i as f64 * 0.3;
+            let scatter = 0.001 * (i as f64 * 2.1).sin();
+            XYTPoint::new(5.0 + vx * t + scatter, 2.0 + vy * t - scatter, t)
+        })
+        .collect();
+    let ids: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+    let config = GridSearchConfig::new(0.1, 5).n_threads(1);
+    let (summaries, _, _, _) =
+        cluster_grid_search_summaries(&points, &ids, vec![0.3], vec![-0.2], &config, None, None);
+    assert_eq!(summaries.len(), 1);
+    let summary = &summaries[0];
+    assert_eq!((summary.vx, summary.vy), (0.3, -0.2));
+    assert!((summary.vx_fit - vx).abs() < 1e-3, "{}", summary.vx_fit);
+    assert!((summary.vy_fit - vy).abs() < 1e-3, "{}", summary.vy_fit);
+}
+
+#[test]
+fn test_sort_results_makes_ids_stable() {
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(0.1, 0.0, 1.0),
+        XYTPoint::new(0.2, 0.0, 2.0),
+        XYTPoint::new(0.3, 0.0, 3.0),
+        XYTPoint::new(5.0, 5.0, 0.0),
+        XYTPoint::new(5.0, 5.1, 1.0),
+        XYTPoint::new(5.0, 5.2, 2.0),
+    ];
+    let ids: Vec<String> = (0..points.len()).map(|i| i.to_string()).collect();
+    let vs = velocity_range(-0.2, 0.2, 5);
+
+    let mut serial = cluster_grid_search(
+        &points,
+        vs.clone(),
+        vs.clone(),
+        &GridSearchConfig::new(0.15, 3).n_threads(1),
+        None,
+        None,
+    );
+    // Shuffle the results as a multithreaded search might.
+    serial.reverse();
+    serial.swap(3, 11);
+    sort_results(&mut serial);
+    let mut threaded = cluster_grid_search(
+        &points,
+        vs.clone(),
+        vs,
+        &GridSearchConfig::new(0.15, 3).n_threads(4),
+        None,
+        None,
+    );
+    sort_results(&mut threaded);
+
+    let velocities: Vec<(f64, f64)> = serial.iter().map(|r| (r.vx, r.vy)).collect();
+    assert!(velocities.windows(2).all(|w| w[0] < w[1]));
+    let (serial_summaries, serial_members) = summarize_clusters(&serial, &points, &ids, 1.0);
+    let (threaded_summaries, threaded_members) = summarize_clusters(&threaded, &points, &ids, 1.0);
+    assert!(!serial_summaries.is_empty());
+    assert_eq!(serial_summaries, threaded_summaries);
+    assert_eq!(serial_members, threaded_members);
+}
+
+#[test]
+fn test_arc_lengths() {
+    let labels = vec![2, 1, -1, 2, 1, 2, -1];
+    let dts = vec![0.5, 1.0, 9.0, 3.0, 1.25, 2.0, -4.0];
+    assert_eq!(arc_lengths(&labels, &dts), vec![(1, 0.25), (2, 2.5)]);
+    assert_eq!(arc_lengths(&[-1, -1], &[0.0, 1.0]), vec![]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_grid_search_result_serde() {
+    let result = GridSearchResult {
+        vx: 0.25,
+        vy: -1.5,
+        eps: 0.1,
+        cluster_labels: vec![1, -1, 2, 1],
+    };
+    let json = serde_json::to_string(&result).unwrap();
+    let round_trip: GridSearchResult = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_trip, result);
+
+    let point = XYTPoint::new(1.0, 2.0, 3.0);
+    let json = serde_json::to_string(&point).unwrap();
+    assert_eq!(json, r#"{"x":1.0,"y":2.0,"t":3.0}"#);
+    assert_eq!(serde_json::from_str::<XYTPoint<f64>>(&json).unwrap(), point);
+}
+
+#[test]
+fn test_grid_search_config_builder() {
+    let config = GridSearchConfig::new(1.0, 4)
+        .eps(0.5)
+        .min_cluster_size(3)
+        .algorithm(ClusterAlgorithm::DbscanRStar)
+        .metric(DistanceMetric::Manhattan)
+        .min_arc_length(1.0)
+        .n_threads(2);
+    assert_eq!(config.eps, 0.5);
+    assert_eq!(config.min_cluster_size, 3);
+    assert!(config.algorithm == ClusterAlgorithm::DbscanRStar);
+    assert!(config.metric == DistanceMetric::Manhattan);
+    assert_eq!(config.min_arc_length, 1.0);
+    assert_eq!(config.n_threads, 2);
+
+    let points = vec![
+        // Moving at vx=1, across 2 in dt.
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(1.0, 0.0, 1.0),
+        XYTPoint::new(2.0, 0.0, 2.0),
+        // Stationary, but only across 0.2 in dt.
+        XYTPoint::new(5.0, 5.0, 0.0),
+        XYTPoint::new(5.0, 5.0, 0.1),
+        XYTPoint::new(5.0, 5.0, 0.2),
+    ];
+    let results = cluster_grid_search(&points, vec![0.0, 1.0], vec![0.0], &config, None, None);
+    assert_eq!(results.len(), 2);
+    // The stationary cluster is too short an arc.
+    assert_eq!(results[0].cluster_labels, vec![-1; 6]);
+    assert_eq!(results[1].cluster_labels, vec![1, 1, 1, -1, -1, -1]);
+}
+
+#[test]
+fn test_connected_components() {
+    let member = |cluster_id, obs_id: &str| ClusterMember {
+        cluster_id,
+        obs_id: obs_id.to_string(),
+    };
+    let members = vec![
+        // Clusters 1 and 3 share "c", so they're one object.
+        member(1, "a"),
+        member(1, "b"),
+        member(1, "c"),
+        member(2, "x"),
+        member(2, "y"),
+        member(3, "d"),
+        member(3, "c"),
+        // Cluster 4 is a subset of cluster 2.
+        member(4, "y"),
+        member(4, "x"),
+        // Cluster 5 links d and a, which are already together.
+        member(5, "d"),
+        member(5, "a"),
+    ];
+    assert_eq!(
+        connected_components(&members),
+        vec![vec!["a", "b", "c", "d"], vec!["x", "y"]]
+    );
+    assert!(connected_components(&[]).is_empty());
+}
+
+#[test]
+fn test_grid_search_max_speed() {
+    let points = vec![XYTPoint::new(0.0, 0.0, 0.0), XYTPoint::new(1.0, 1.0, 1.0)];
+    let vs = vec![-1.0, 0.0, 1.0];
+    for n_threads in [1, 4] {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let progress = Progress::new(1, 5, tx);
+        let config = GridSearchConfig::new(0.5, 2)
+            .n_threads(n_threads)
+            .max_speed(1.0);
+        let results = cluster_grid_search(
+            &points,
+            vs.clone(),
+            vs.clone(),
+            &config,
+            None,
+            Some(&progress),
+        );
+        drop(progress);
+        // The four corners, at speed sqrt(2), are skipped.
+        let velocities: Vec<(f64, f64)> = results.iter().map(|r| (r.vx, r.vy)).collect();
+        assert_eq!(
+            velocities,
+            vec![(-1.0, 0.0), (0.0, -1.0), (0.0, 0.0), (0.0, 1.0), (1.0, 0.0)]
+        );
+        assert_eq!(rx.iter().last(), Some(5));
+    }
+}
+
+#[test]
+fn test_grid_search_diagnostics() {
+    // Only moving at (1, 1) lines these points up.
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(1.0, 1.0, 1.0),
+        XYTPoint::new(2.0, 2.0, 2.0),
+    ];
+    let vxs = vec![-1.0, 0.0, 1.0];
+    let vys = vec![-1.0, 0.0, 1.0, 2.0];
+    for n_threads in [1, 4] {
+        // The cluster is counted even though min_arc_length then drops it.
+        let config = GridSearchConfig::new(0.5, 2)
+            .n_threads(n_threads)
+            .min_arc_length(5.0);
+        let (results, diagnostics) = cluster_grid_search_with_diagnostics(
+            &points,
+            vxs.clone(),
+            vys.clone(),
+            &config,
+            None,
+            None,
+        );
+        assert_eq!(results.len(), vxs.len() * vys.len());
+        assert_eq!(diagnostics.n_pairs, vxs.len() * vys.len());
+        assert_eq!(diagnostics.n_pairs_with_clusters, 1);
+        assert_eq!(diagnostics.n_clusters, 1);
+        assert!(results
+            .iter()
+            .all(|r| r.cluster_labels.iter().all(|l| *l == -1)));
+    }
+}
+
+/// Collects the messages logged while the tests run.
+#[cfg(test)]
+struct CaptureLogger(std::sync::Mutex<Vec<String>>);
+
+#[cfg(test)]
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+static CAPTURE_LOGGER: CaptureLogger = CaptureLogger(std::sync::Mutex::new(Vec::new()));
+
+#[test]
+fn test_grid_search_logs_progress() {
+    let _ = log::set_logger(&CAPTURE_LOGGER);
+    log::set_max_level(log::LevelFilter::Info);
+
+    // The points line up at vx=1, vy=1, so there's a cluster at vx = 0.75,
+    // 1.0, and 1.25.
+    let points = vec![XYTPoint::new(0.0, 0.0, 0.0), XYTPoint::new(1.0, 1.0, 1.0)];
+    let vxs: Vec<f64> = (0..13).map(|i| i as f64 * 0.25).collect();
+    let config = GridSearchConfig::new(0.4, 2).n_threads(1).log_interval(5);
+    cluster_grid_search(&points, vxs, vec![1.0], &config, None, None);
+
+    // Other tests log too, so look for the lines only this search logs.
+    let logged = CAPTURE_LOGGER.0.lock().unwrap();
+    for want in [
+        "grid search: searched 5/13 velocity pairs, 2 clusters so far",
+        "grid search: searched 10/13 velocity pairs, 3 clusters so far",
+        "grid search: 13 velocity pairs, 3 with clusters, 3 clusters before filtering, in ",
+    ] {
+        assert!(
+            logged.iter().any(|line| line.starts_with(want)),
+            "{:?} not in {:?}",
+            want,
+            logged
+        );
+    }
+}
+
+#[test]
+fn test_grid_search_velocity_pairs() {
+    // Only moving at (1, 1) lines these points up.
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(1.0, 1.0, 1.0),
+        XYTPoint::new(2.0, 2.0, 2.0),
+    ];
+    let vxs = vec![-1.0, 0.0, 1.0, 2.0];
+    let vys = vec![2.0, 0.0, 1.0, -1.0];
+    for n_threads in [1, 4] {
+        let config = GridSearchConfig::new(0.5, 2)
+            .n_threads(n_threads)
+            .velocity_pairs(true);
+        let (results, diagnostics) = cluster_grid_search_with_diagnostics(
+            &points,
+            vxs.clone(),
+            vys.clone(),
+            &config,
+            None,
+            None,
+        );
+        assert_eq!(results.len(), vxs.len());
+        assert_eq!(diagnostics.n_pairs, vxs.len());
+        let velocities: Vec<(f64, f64)> = results.iter().map(|r| (r.vx, r.vy)).collect();
+        assert_eq!(
+            velocities,
+            vec![(-1.0, 2.0), (0.0, 0.0), (1.0, 1.0), (2.0, -1.0)]
+        );
+        assert_eq!(results[2].cluster_labels, vec![1, 1, 1]);
+        assert_eq!(diagnostics.n_pairs_with_clusters, 1);
+    }
+}
+
+#[test]
+fn test_grid_search_counts() {
+    // Two objects moving at (0.5, 0), far apart, and one stationary.
+    let points: Vec<XYTPoint<f64>> = (0..4)
+        .flat_map(|t| {
+            let t = t as f64;
+            [
+                XYTPoint::new(0.5 * t, 0.0, t),
+                XYTPoint::new(10.0 + 0.5 * t, 10.0, t),
+                XYTPoint::new(-10.0, 5.0, t),
+            ]
+        })
+        .collect();
+    let vxs = vec![0.0, 0.5, 1.0];
+    let vys = vec![0.0];
+    for n_threads in [1, 4] {
+        let config = GridSearchConfig::new(0.1, 4).n_threads(n_threads);
+        let counts =
+            cluster_grid_search_counts(&points, vxs.clone(), vys.clone(), &config, None, None);
+        assert_eq!(counts, vec![(0.0, 0.0, 1), (0.5, 0.0, 2), (1.0, 0.0, 0)]);
+    }
+}
+
+#[test]
+fn test_grid_search_eps_values() {
+    // Two objects moving at (0.5, 0), and one stationary, as above. Off by
+    // 0.5 from an object's velocity, its points are spread over 1.5, which
+    // only the larger eps takes in.
+    let points: Vec<XYTPoint<f64>> = (0..4)
+        .flat_map(|t| {
+            let t = t as f64;
+            [
+                XYTPoint::new(0.5 * t, 0.0, t),
+                XYTPoint::new(10.0 + 0.5 * t, 10.0, t),
+                XYTPoint::new(-10.0, 5.0, t),
+            ]
+        })
+        .collect();
+    let (vxs, vys) = (vec![0.0, 0.5, 1.0], vec![0.0]);
+    let config = GridSearchConfig::new(0.1, 4).eps_values(vec![0.1, 1.6]);
+    assert_eq!(config.searches(&vxs, &vys).len(), 6);
+    for n_threads in [1, 4] {
+        let config = config.clone().n_threads(n_threads);
+        let counts =
+            cluster_grid_search_counts(&points, vxs.clone(), vys.clone(), &config, None, None);
+        assert_eq!(
+            counts,
+            vec![
+                (0.0, 0.0, 1),
+                (0.0, 0.0, 3),
+                (0.5, 0.0, 2),
+                (0.5, 0.0, 3),
+                (1.0, 0.0, 0),
+                (1.0, 0.0, 2),
+            ]
+        );
+
+        let mut results =
+            cluster_grid_search(&points, vxs.clone(), vys.clone(), &config, None, None);
+        sort_results(&mut results);
+        let eps: Vec<f64> = results.iter().map(|r| r.eps).collect();
+        assert_eq!(eps, [0.1, 1.6, 0.1, 1.6, 0.1, 1.6]);
+    }
+
+    // Without eps_values, only eps is searched.
+    let results = cluster_grid_search(
+        &points,
+        vxs.clone(),
+        vys.clone(),
+        &GridSearchConfig::new(1.6, 4).n_threads(1),
+        None,
+        None,
+    );
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|r| r.eps == 1.6));
+}
+
+#[test]
+fn test_grid_search_cells() {
+    // The same object, moving at (0.5, 0), in two cells, with the points of
+    // one cell right on top of the other's. A stray point in a third cell
+    // sits in the middle of the first cell's cluster.
+    let mut points = Vec::new();
+    let mut cells = Vec::new();
+    for cell in [7, 3] {
+        for t in 0..4 {
+            let t = t as f64;
+            points.push(XYTPoint::new(0.5 * t, 0.01 * cell as f64, t));
+            cells.push(cell);
+        }
+    }
+    points.push(XYTPoint::new(0.5, 0.0, 1.0));
+    cells.push(11);
+    let ids: Vec<String> = (0..points.len()).map(|i| i.to_string()).collect();
+    let (vxs, vys) = (vec![0.0, 0.5], vec![0.0]);
+
+    for n_threads in [1, 4] {
+        let config = GridSearchConfig::new(0.1, 4).n_threads(n_threads);
+        let (summaries, members, summary_cells) = cluster_grid_search_cells(
+            &points,
+            &ids,
+            &cells,
+            vxs.clone(),
+            vys.clone(),
+            &config,
+            None,
+        );
+        // Together, the cells' points would make one cluster of 9 at
+        // (0.5, 0). Apart, each of the first two makes its own, numbered in
+        // order of cell, and the stray point is left out.
+        assert_eq!(summary_cells, vec![3, 7]);
+        let ids: Vec<u32> = summaries.iter().map(|s| s.cluster_id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(summaries.iter().all(|s| s.vx == 0.5 && s.n_obs == 4));
+        let members_of = |cluster_id: u32| -> Vec<&str> {
+            members
+                .iter()
+                .filter(|m| m.cluster_id == cluster_id)
+                .map(|m| m.obs_id.as_str())
+                .collect()
+        };
+        assert_eq!(members_of(1), vec!["4", "5", "6", "7"]);
+        assert_eq!(members_of(2), vec!["0", "1", "2", "3"]);
+    }
+}
+
+#[test]
+fn test_worker_threads() {
+    assert_eq!(worker_threads(4, 8, 100), 4);
+    assert_eq!(worker_threads(64, 4, 100), 4);
+    assert_eq!(worker_threads(64, 4, 3), 3);
+    assert_eq!(worker_threads(0, 8, 100), 8);
+    assert_eq!(worker_threads(0, 8, 0), 1);
+}
+
+#[test]
+fn test_grid_search_too_many_threads() {
+    let points: Vec<XYTPoint<f64>> = (0..4)
+        .map(|t| XYTPoint::new(0.1 * t as f64, 0.0, t as f64))
+        .collect();
+    let (vxs, vys) = (vec![0.0, 0.1, 0.2], vec![0.0]);
+    let serial = cluster_grid_search(
+        &points,
+        vxs.clone(),
+        vys.clone(),
+        &GridSearchConfig::new(0.05, 4).n_threads(1),
+        None,
+        None,
+    );
+    assert_eq!(serial[1].cluster_labels, vec![1; 4]);
+
+    let config = GridSearchConfig::new(0.05, 4).n_threads(10_000);
+    let mut threaded = cluster_grid_search(&points, vxs.clone(), vys.clone(), &config, None, None);
+    sort_results(&mut threaded);
+    assert_eq!(threaded, serial);
+
+    // Only as many threads as there are velocities are started; the search
+    // is serial, on this thread, if that's all there's room for.
+    let (threads, _) = search_velocities(&points, vxs, vys, &config, None, None, |_| {
+        std::thread::current().name().map(String::from)
+    });
+    for name in threads.iter().flatten() {
+        if let Some(i) = name.strip_prefix("grid_search_") {
+            assert!(i.parse::<usize>().unwrap() < 3, "{}", name);
+        }
+    }
+}
+
+#[test]
+fn test_grid_search_in_thread_pool() {
+    let points: Vec<XYTPoint<f64>> = (0..4)
+        .flat_map(|t| {
+            let t = t as f64;
+            [
+                XYTPoint::new(0.1 * t, 0.0, t),
+                XYTPoint::new(5.0, 5.0 - 0.1 * t, t),
+            ]
+        })
+        .collect();
+    let vs = velocity_range(-0.2, 0.2, 5);
+    let serial = cluster_grid_search(
+        &points,
+        vs.clone(),
+        vs.clone(),
+        &GridSearchConfig::new(0.05, 4).n_threads(1),
+        None,
+        None,
+    );
+    assert!(serial.iter().any(|r| r.cluster_labels.contains(&1)));
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(2)
+        .thread_name(|i| format!("user_pool_{}", i))
+        .build()
+        .unwrap();
+    // n_threads is ignored in favor of the pool, even when it's 1.
+    let config = GridSearchConfig::new(0.05, 4)
+        .n_threads(1)
+        .thread_pool(Arc::new(pool));
+    let mut pooled = cluster_grid_search(&points, vs.clone(), vs.clone(), &config, None, None);
+    sort_results(&mut pooled);
+    assert_eq!(pooled, serial);
+
+    let (threads, _) = search_velocities(&points, vs.clone(), vs, &config, None, None, |_| {
+        std::thread::current().name().map(String::from)
+    });
+    assert!(threads.iter().all(|name| name
+        .as_deref()
+        .is_some_and(|name| name.starts_with("user_pool_"))));
+}
+
+#[test]
+fn test_grid_search_summaries_match_collected() {
+    // Each y is a little different, so that at vy=-0.2 no more than a
+    // bucket's worth of points in kiddo's trees share a coordinate.
+    let points = (0..200)
+        .map(|i| {
+            let t = (i % 10) as f64;
+            let y = (i % 3) as f64 * 0.1 + i as f64 * 0.001 - 0.2 * t;
+            XYTPoint::new((i / 10) as f64 + 0.3 * t, y, t)
+        })
+        .collect::<Vec<_>>();
+    let ids = (0..points.len())
+        .map(|i| format!("obs{}", i))
+        .collect::<Vec<_>>();
+    // Unsorted, so the summaries have to be put in sort_results order.
+    let vxs = vec![0.3, -0.5, 0.0, 0.25];
+    let vys = vec![0.1, -0.2, -0.25];
+    for n_threads in [1, 4] {
+        let config = GridSearchConfig::new(0.5, 5)
+            .n_threads(n_threads)
+            .min_arc_length(2.0)
+            .night_length(3.0);
+        let mut results =
+            cluster_grid_search(&points, vxs.clone(), vys.clone(), &config, None, None);
+        sort_results(&mut results);
+        let collected = summarize_clusters(&results, &points, &ids, 3.0);
+
+        let (summaries, members, _, diagnostics) = cluster_grid_search_summaries(
+            &points,
+            &ids,
+            vxs.clone(),
+            vys.clone(),
+            &config,
+            None,
+            None,
+        );
+        assert!(!summaries.is_empty());
+        assert_eq!((summaries, members), collected);
+        assert_eq!(diagnostics.n_pairs, vxs.len() * vys.len());
+    }
+}
+
+#[test]
+fn test_to_record_batches() {
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(0.1, 0.0, 1.0),
+        XYTPoint::new(5.0, 5.0, 0.0),
+        XYTPoint::new(5.0, 5.1, 1.0),
+        XYTPoint::new(9.0, 9.0, 0.0),
+    ];
+    let ids = ["a", "b", "c", "d", "e"].map(String::from);
+    let results = vec![GridSearchResult {
+        vx: 0.0,
+        vy: 0.0,
+        eps: 0.1,
+        cluster_labels: vec![1, 1, 2, 2, -1],
+    }];
+    let (summaries, members) = summarize_clusters(&results, &points, &ids, 1.0);
+    for dictionary_obs_ids in [true, false] {
+        let (clusters, cluster_members) =
+            to_record_batches(&summaries, &members, dictionary_obs_ids).unwrap();
+        assert_eq!(clusters.schema().as_ref(), &cluster_table_schema());
+        assert_eq!(
+            cluster_members.schema().as_ref(),
+            &cluster_members_table_schema(dictionary_obs_ids)
+        );
+        assert_eq!(clusters.num_rows(), 2);
+        assert_eq!(cluster_members.num_rows(), 4);
+    }
+
+    let (clusters, cluster_members) = to_record_batches(&[], &[], true).unwrap();
+    assert_eq!(clusters.num_rows(), 0);
+    assert_eq!(cluster_members.num_rows(), 0);
+}
+
+#[test]
+fn test_grid_search_summaries_ever_clustered() {
+    // A cluster moving at (1, 0), a point far from everything, and a point
+    // which only joins the cluster at vx=0.
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(1.0, 0.0, 1.0),
+        XYTPoint::new(2.0, 0.0, 2.0),
+        XYTPoint::new(50.0, 50.0, 1.0),
+        XYTPoint::new(0.0, 0.1, 1.0),
+    ];
+    let ids = ["a", "b", "c", "noise", "e"].map(String::from);
+    let config = GridSearchConfig::new(0.5, 2).n_threads(1);
+    let (_, members, ever_clustered, _) = cluster_grid_search_summaries(
+        &points,
+        &ids,
+        vec![0.0, 1.0],
+        vec![0.0],
+        &config,
+        None,
+        None,
+    );
+    assert_eq!(ever_clustered, vec![true, true, true, false, true]);
+    assert!(members.iter().all(|member| member.obs_id != "noise"));
+}
+
+#[test]
+fn test_grid_search_summaries_sort_members_by_dt() {
+    // Two stationary clusters, with their points interleaved and out of
+    // order in dt.
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 3.0),
+        XYTPoint::new(10.0, 10.0, 2.0),
+        XYTPoint::new(0.0, 0.0, 1.0),
+        XYTPoint::new(10.0, 10.0, 0.0),
+        XYTPoint::new(0.0, 0.0, 2.0),
+    ];
+    let ids = ["a3", "b2", "a1", "b0", "a2"].map(String::from);
+    let search = |config: &GridSearchConfig| {
+        let (_, members, _, _) =
+            cluster_grid_search_summaries(&points, &ids, vec![0.0], vec![0.0], config, None, None);
+        members
+            .into_iter()
+            .map(|member| (member.cluster_id, member.obs_id))
+            .collect::<Vec<_>>()
+    };
+
+    let config = GridSearchConfig::new(0.5, 2).n_threads(1);
+    let unsorted = search(&config);
+    let obs_ids: Vec<&str> = unsorted.iter().map(|(_, id)| id.as_str()).collect();
+    assert_eq!(obs_ids, vec!["a3", "b2", "a1", "b0", "a2"]);
+
+    let sorted = search(&config.sort_members_by_dt(true));
+    let want = [(1, "a1"), (1, "a2"), (1, "a3"), (2, "b0"), (2, "b2")];
+    assert_eq!(
+        sorted,
+        want.map(|(cluster_id, obs_id)| (cluster_id, obs_id.to_string()))
+    );
+}
+
+#[test]
+fn test_cluster_single() {
+    let points = vec![
+        XYTPoint::new(0.0, 0.0, 0.0),
+        XYTPoint::new(0.1, 0.0, 1.0),
+        XYTPoint::new(0.0, 0.1, 2.0),
+        XYTPoint::new(5.0, 5.0, 0.0),
+        XYTPoint::new(5.0, 5.1, 0.0),
+        XYTPoint::new(9.0, 9.0, 0.0),
+    ];
+    let ids = ["a", "b", "c", "d", "e", "f"].map(String::from);
+    let config = GridSearchConfig::new(0.5, 2).n_threads(1);
+    let (summaries, members) = cluster_single(&points, &ids, &config);
+    let (want_summaries, want_members, _, _) =
+        cluster_grid_search_summaries(&points, &ids, vec![0.0], vec![0.0], &config, None, None);
+    assert_eq!(summaries.len(), 2);
+    assert_eq!((summaries, members), (want_summaries, want_members));
+
+    // The second cluster spans no time, so min_arc_length drops it.
+    let (summaries, _) = cluster_single(&points, &ids, &config.min_arc_length(1.0));
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].n_obs, 3);
+}
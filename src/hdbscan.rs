@@ -0,0 +1,362 @@
+use crate::dbscan::{neighbors_within, SearchTree};
+use crate::points::XYPoint;
+use crate::DistanceMetric;
+
+/// Find clusters with HDBSCAN. Rather than cutting at a single eps, clusters
+/// are picked from the whole hierarchy of densities, keeping the ones which
+/// persist longest. eps only caps the neighborhoods searched: points farther
+/// apart than eps are never linked directly. Noise is labeled -1 and clusters
+/// are labeled from 1.
+pub fn find_clusters<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let n = points.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let tree: T = T::from_points(points);
+    // A cluster of one point is meaningless.
+    let min_cluster_size = min_cluster_size.max(2);
+
+    let neighbors: Vec<Vec<(f64, usize)>> = points
+        .iter()
+        .map(|point| {
+            let mut neighbors: Vec<(f64, usize)> =
+                neighbors_within(points, &tree, point, eps, metric)
+                    .into_iter()
+                    .map(|j| (metric.distance(point, &points[j]), j))
+                    .collect();
+            neighbors.sort_by(|a, b| a.0.total_cmp(&b.0));
+            neighbors
+        })
+        .collect();
+    // The core distance of a point is the distance to its
+    // min_cluster_size-th nearest neighbor, counting itself.
+    let core_distances: Vec<f64> = neighbors
+        .iter()
+        .map(|neighbors| match neighbors.get(min_cluster_size - 1) {
+            Some((distance, _)) => *distance,
+            None => f64::INFINITY,
+        })
+        .collect();
+
+    // Edges weighted by mutual reachability distance, sorted for Kruskal's
+    // algorithm.
+    let mut edges = Vec::new();
+    for (i, neighbors) in neighbors.iter().enumerate() {
+        for &(distance, j) in neighbors.iter() {
+            if j <= i {
+                continue;
+            }
+            let weight = distance.max(core_distances[i]).max(core_distances[j]);
+            if weight.is_finite() {
+                edges.push((weight, i, j));
+            }
+        }
+    }
+    edges.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let merges = single_linkage(n, &edges);
+    let tree = condense(n, &merges, min_cluster_size);
+    let selected = select_clusters(&tree);
+    label_points(&tree, &selected)
+}
+
+/// Two nodes of the single linkage tree joined at a distance. Nodes below n
+/// are points; node n + i is the result of the i-th merge.
+struct Merge {
+    left: usize,
+    right: usize,
+    distance: f64,
+    size: usize,
+}
+
+/// Build the single linkage tree from edges sorted by weight: a minimum
+/// spanning forest, in order. Whatever is left unconnected at the end is
+/// joined at infinite distance, so the last merge is always the root.
+fn single_linkage(n: usize, edges: &[(f64, usize, usize)]) -> Vec<Merge> {
+    let mut parents: Vec<usize> = (0..n).collect();
+    let mut sizes = vec![1; n];
+    let mut merges: Vec<Merge> = Vec::with_capacity(n.saturating_sub(1));
+
+    let mut merge = |a: usize, b: usize, distance: f64, parents: &mut Vec<usize>| {
+        let node = parents.len();
+        parents[a] = node;
+        parents[b] = node;
+        parents.push(node);
+        sizes.push(sizes[a] + sizes[b]);
+        merges.push(Merge {
+            left: a,
+            right: b,
+            distance,
+            size: sizes[node],
+        });
+    };
+
+    for &(distance, i, j) in edges.iter() {
+        let (a, b) = (find_root(&mut parents, i), find_root(&mut parents, j));
+        if a != b {
+            merge(a, b, distance, &mut parents);
+        }
+    }
+    let mut roots = (0..parents.len()).filter(|&node| parents[node] == node);
+    if let Some(mut root) = roots.next() {
+        let rest: Vec<usize> = roots.collect();
+        for other in rest {
+            merge(root, other, f64::INFINITY, &mut parents);
+            root = parents.len() - 1;
+        }
+    }
+    merges
+}
+
+fn find_root(parents: &mut [usize], node: usize) -> usize {
+    let mut root = node;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    // Compress the path for later lookups.
+    let mut node = node;
+    while parents[node] != root {
+        let next = parents[node];
+        parents[node] = root;
+        node = next;
+    }
+    root
+}
+
+/// A cluster in the condensed tree.
+struct Cluster {
+    parent: Option<usize>,
+    // The lambda (1 / distance) at which the cluster split off its parent.
+    birth: f64,
+    stability: f64,
+}
+
+/// The condensed tree: the clusters, and for each point, the cluster it
+/// falls out of and the lambda at which it does.
+struct CondensedTree {
+    clusters: Vec<Cluster>,
+    fall_out: Vec<(usize, f64)>,
+}
+
+fn lambda(distance: f64) -> f64 {
+    if distance > 0.0 {
+        1.0 / distance
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// How long something persisted in a cluster, from the cluster's birth to the
+/// lambda at which it left.
+fn persistence(lambda: f64, birth: f64) -> f64 {
+    if lambda > birth {
+        lambda - birth
+    } else {
+        0.0
+    }
+}
+
+/// Walk the single linkage tree from the root, only starting a new cluster
+/// when a merge splits into two pieces of at least min_cluster_size points.
+/// Smaller pieces are points falling out of the current cluster.
+fn condense(n: usize, merges: &[Merge], min_cluster_size: usize) -> CondensedTree {
+    let size = |node: usize| if node < n { 1 } else { merges[node - n].size };
+    let mut clusters = vec![Cluster {
+        parent: None,
+        birth: 0.0,
+        stability: 0.0,
+    }];
+    let mut fall_out = vec![(0, 0.0); n];
+
+    let mut stack = Vec::new();
+    if n > 1 {
+        stack.push((n + merges.len() - 1, 0));
+    }
+    while let Some((node, cluster)) = stack.pop() {
+        let merge = &merges[node - n];
+        let lambda = lambda(merge.distance);
+        let big = |child: usize| size(child) >= min_cluster_size;
+
+        if big(merge.left) && big(merge.right) {
+            for child in [merge.left, merge.right] {
+                let new_cluster = clusters.len();
+                clusters.push(Cluster {
+                    parent: Some(cluster),
+                    birth: lambda,
+                    stability: 0.0,
+                });
+                let birth = clusters[cluster].birth;
+                clusters[cluster].stability += persistence(lambda, birth) * size(child) as f64;
+                stack.push((child, new_cluster));
+            }
+            continue;
+        }
+        for child in [merge.left, merge.right] {
+            if big(child) {
+                // The cluster carries on, just smaller.
+                stack.push((child, cluster));
+                continue;
+            }
+            let birth = clusters[cluster].birth;
+            for point in leaves(n, merges, child) {
+                fall_out[point] = (cluster, lambda);
+                clusters[cluster].stability += persistence(lambda, birth);
+            }
+        }
+    }
+    CondensedTree { clusters, fall_out }
+}
+
+fn leaves(n: usize, merges: &[Merge], node: usize) -> Vec<usize> {
+    let mut leaves = Vec::new();
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        if node < n {
+            leaves.push(node);
+        } else {
+            stack.push(merges[node - n].left);
+            stack.push(merges[node - n].right);
+        }
+    }
+    leaves
+}
+
+/// Pick the clusters with the most excess of mass: a cluster is kept if it's
+/// at least as stable as its selected descendants put together. The root is
+/// never selected.
+fn select_clusters(tree: &CondensedTree) -> Vec<bool> {
+    let n_clusters = tree.clusters.len();
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); n_clusters];
+    for (c, cluster) in tree.clusters.iter().enumerate() {
+        if let Some(parent) = cluster.parent {
+            children[parent].push(c);
+        }
+    }
+
+    let mut selected = vec![false; n_clusters];
+    let mut subtree_stability: Vec<f64> = tree.clusters.iter().map(|c| c.stability).collect();
+    // Children always come after their parents.
+    for c in (1..n_clusters).rev() {
+        let children_stability: f64 = children[c].iter().map(|&k| subtree_stability[k]).sum();
+        if children[c].is_empty() || tree.clusters[c].stability >= children_stability {
+            selected[c] = true;
+            let mut descendants = children[c].clone();
+            while let Some(d) = descendants.pop() {
+                selected[d] = false;
+                descendants.extend(children[d].iter());
+            }
+        } else {
+            subtree_stability[c] = children_stability;
+        }
+    }
+    selected
+}
+
+/// Label each point with the selected cluster it falls out of, or the
+/// selected cluster above that one. Points which fall out of a cluster as
+/// soon as it's born were never part of anything denser, so they're noise.
+/// Labels are numbered in order of each cluster's first point.
+fn label_points(tree: &CondensedTree, selected: &[bool]) -> Vec<i32> {
+    let mut cluster_labels = vec![-1; selected.len()];
+    let mut label = 0;
+
+    tree.fall_out
+        .iter()
+        .map(|&(cluster, lambda)| {
+            if lambda <= tree.clusters[cluster].birth {
+                return -1;
+            }
+            let mut cluster = Some(cluster);
+            while let Some(c) = cluster {
+                if selected[c] {
+                    if cluster_labels[c] == -1 {
+                        label += 1;
+                        cluster_labels[c] = label;
+                    }
+                    return cluster_labels[c];
+                }
+                cluster = tree.clusters[c].parent;
+            }
+            -1
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbscan::float32_kdtree::PointTree;
+
+    #[test]
+    fn test_hdbscan_two_hits() {
+        let points = vec![
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+        ];
+        // eps is generous; HDBSCAN finds the split on its own.
+        let labels = find_clusters::<PointTree>(&points, 10.0, 4, &DistanceMetric::Euclidean);
+        assert_eq!(labels, vec![1, 1, 1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_hdbscan_near_miss() {
+        let points = vec![
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(1.0, 1.0),
+            XYPoint::new(2.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+        ];
+        let labels = find_clusters::<PointTree>(&points, 10.0, 4, &DistanceMetric::Euclidean);
+        // The four points at x=2 form a cluster, which doesn't take in any
+        // of the sparser points.
+        assert_ne!(labels[4], -1);
+        assert!(labels[4..].iter().all(|&l| l == labels[4]));
+        assert!(labels[..4].iter().all(|&l| l != labels[4]));
+    }
+
+    #[test]
+    fn test_hdbscan_disconnected() {
+        // Groups farther apart than eps are never linked, but are still
+        // found as clusters.
+        let points = vec![
+            XYPoint::new(0.0, 0.0),
+            XYPoint::new(0.1, 0.0),
+            XYPoint::new(0.0, 0.1),
+            XYPoint::new(5.0, 5.0),
+            XYPoint::new(5.1, 5.0),
+            XYPoint::new(5.0, 5.1),
+            XYPoint::new(9.0, 0.0),
+        ];
+        let labels = find_clusters::<PointTree>(&points, 1.0, 3, &DistanceMetric::Euclidean);
+        assert_eq!(labels, vec![1, 1, 1, 2, 2, 2, -1]);
+    }
+
+    #[test]
+    fn test_hdbscan_small_inputs() {
+        let metric = DistanceMetric::Euclidean;
+        assert_eq!(
+            find_clusters::<PointTree>(&vec![], 1.0, 2, &metric),
+            Vec::<i32>::new()
+        );
+        let points = vec![XYPoint::new(0.0, 0.0)];
+        assert_eq!(
+            find_clusters::<PointTree>(&points, 1.0, 2, &metric),
+            vec![-1]
+        );
+    }
+}
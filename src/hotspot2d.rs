@@ -1,90 +1,173 @@
 use crate::points::XYPoint;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+/// A grid cell which became a cluster, from find_clusters_hotspot2d_cells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotspotCell {
+    pub label: i32,
+    /// The cell's position in the grid of the pass which found it. Passes
+    /// are shifted against each other, so cells from different passes can
+    /// share these; x and y place them all in the same coordinates.
+    pub cell_x: i64,
+    pub cell_y: i64,
+    /// The center of the cell, in the same coordinates as the points.
+    pub x: f64,
+    pub y: f64,
+    /// How many points fell in the cell.
+    pub count: usize,
+}
+
+/// Find clusters by binning points into a grid of eps-sized cells, and
+/// labeling each cell holding at least min_cluster_size points.
+///
+/// A cluster straddling a cell boundary would be missed, so the binning is
+/// repeated with the grid shifted by 0, 1/offsets, 2/offsets, ... of eps
+/// along each axis, for offsets² passes in all. More offsets catch more near
+/// misses, at the cost of speed. With 2, the passes are shifted by (0, 0),
+/// (eps/2, 0), (0, eps/2), and (eps/2, eps/2).
 pub fn find_clusters_hotspot2d(
     points: &Vec<XYPoint<f64>>,
     eps: f64,
     min_cluster_size: usize,
+    offsets: usize,
 ) -> Vec<i32> {
-    // Run 4 times with different quantization to catch near misses.
-    let quantized1 = quantize(points, eps);
-    let map1 = hist2d(&quantized1);
-    let labels1 = label_cluster_map(&quantized1, map1, min_cluster_size);
+    let passes: Vec<Vec<i32>> = shifts(eps, offsets)
+        .map(|(dx, dy)| {
+            let quantized = quantize(&shift(points, dx, dy), eps);
+            let map = hist2d(&quantized);
+            label_cluster_map(&quantized, map, min_cluster_size)
+        })
+        .collect();
+    merge_cluster_labels(&passes)
+}
 
-    let points2 = &points
-        .iter()
-        .map(|p| XYPoint {
-            x: p.x + eps / 2.0,
-            y: p.y,
+/// Like find_clusters_hotspot2d, but also describes the cell each cluster
+/// came from, for quick-look density maps. The clusters are numbered 1, 2,
+/// 3, ... with no gaps, and there's one cell for each, in order of label.
+///
+/// A cell's count is every point which fell in it, which can be more than
+/// the points labeled with it: a point keeps its label from the first pass
+/// that put it in a cluster.
+pub fn find_clusters_hotspot2d_cells(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    offsets: usize,
+) -> (Vec<i32>, Vec<HotspotCell>) {
+    let mut passes = Vec::new();
+    let mut cells = Vec::new();
+    for (dx, dy) in shifts(eps, offsets) {
+        let quantized = quantize(&shift(points, dx, dy), eps);
+        let map = hist2d(&quantized);
+        let (labels, clustered) = label_cluster_cells(&quantized, map, min_cluster_size);
+        // The same shift merge_cluster_labels gives this pass's labels.
+        let offset = cells.len() as i32;
+        cells.extend(
+            clustered
+                .into_iter()
+                .enumerate()
+                .map(|(k, (cell, count))| HotspotCell {
+                    label: offset + k as i32 + 1,
+                    cell_x: cell.x,
+                    cell_y: cell.y,
+                    x: cell.x as f64 * eps - dx,
+                    y: cell.y as f64 * eps - dy,
+                    count,
+                }),
+        );
+        passes.push(labels);
+    }
+    let mut labels = merge_cluster_labels(&passes);
+
+    // Drop the cells whose points were all claimed by earlier passes, and
+    // close up the gaps they leave.
+    let used: HashSet<i32> = labels.iter().copied().filter(|&l| l > 0).collect();
+    cells.retain(|cell| used.contains(&cell.label));
+    let renumbered: HashMap<i32, i32> = cells
+        .iter_mut()
+        .enumerate()
+        .map(|(k, cell)| {
+            let old = cell.label;
+            cell.label = k as i32 + 1;
+            (old, cell.label)
         })
-        .collect::<Vec<_>>();
-    let quantized2 = quantize(points2, eps);
-    let map2 = hist2d(&quantized2);
-    let labels2 = label_cluster_map(&quantized2, map2, min_cluster_size);
+        .collect();
+    for label in labels.iter_mut().filter(|l| **l > 0) {
+        *label = renumbered[label];
+    }
+    (labels, cells)
+}
 
-    let points3 = &points
-        .iter()
-        .map(|p| XYPoint {
-            x: p.x,
-            y: p.y + eps / 2.0,
+/// How far each pass shifts the points along x and y: 0, 1/offsets,
+/// 2/offsets, ... of eps along each axis.
+fn shifts(eps: f64, offsets: usize) -> impl Iterator<Item = (f64, f64)> {
+    let offsets = offsets.max(1);
+    (0..offsets).flat_map(move |j| {
+        (0..offsets).map(move |i| {
+            (
+                eps * i as f64 / offsets as f64,
+                eps * j as f64 / offsets as f64,
+            )
         })
-        .collect::<Vec<_>>();
-    let quantized3 = quantize(points3, eps);
-    let map3 = hist2d(&quantized3);
-    let labels3 = label_cluster_map(&quantized3, map3, min_cluster_size);
+    })
+}
 
-    let points4 = &points
+fn shift(points: &[XYPoint<f64>], dx: f64, dy: f64) -> Vec<XYPoint<f64>> {
+    points
         .iter()
         .map(|p| XYPoint {
-            x: p.x + eps / 2.0,
-            y: p.y + eps / 2.0,
+            x: p.x + dx,
+            y: p.y + dy,
         })
-        .collect::<Vec<_>>();
-    let quantized4 = quantize(points4, eps);
-    let map4 = hist2d(&quantized4);
-    let labels4 = label_cluster_map(&quantized4, map4, min_cluster_size);
-
-    merge_cluster_labels(&labels1, &labels2, &labels3, &labels4)
+        .collect()
 }
 
-pub fn merge_cluster_labels(
-    l1: &Vec<i32>,
-    l2: &Vec<i32>,
-    l3: &Vec<i32>,
-    l4: &Vec<i32>,
-) -> Vec<i32> {
-    let mut labels = vec![0; l1.len()];
-    for (i, l) in l1.iter().enumerate() {
-        if *l != -1 {
-            labels[i] = *l;
-        } else if l2[i] != -1 {
-            labels[i] = l2[i];
-        } else if l3[i] != -1 {
-            labels[i] = l3[i];
-        } else if l4[i] != -1 {
-            labels[i] = l4[i];
-        } else {
-            labels[i] = -1;
+/// Combine the labels from each quantization pass. A point takes its label
+/// from the first pass that put it in a cluster. Each pass numbers its
+/// clusters from 1, so labels from later passes are shifted past those of
+/// earlier ones; otherwise, different clusters could end up sharing a label.
+pub fn merge_cluster_labels(passes: &[Vec<i32>]) -> Vec<i32> {
+    let mut offsets = vec![0; passes.len()];
+    for k in 1..passes.len() {
+        let n_clusters = passes[k - 1].iter().copied().max().unwrap_or(0).max(0);
+        offsets[k] = offsets[k - 1] + n_clusters;
+    }
+
+    let n_points = passes.first().map_or(0, |labels| labels.len());
+    let mut labels = vec![-1; n_points];
+    for (i, label) in labels.iter_mut().enumerate() {
+        if let Some((l, offset)) = passes.iter().zip(offsets.iter()).find(|(l, _)| l[i] != -1) {
+            *label = l[i] + offset;
         }
     }
     labels
 }
 
-/// Mark points as belonging to a cluster. A value of -1 means the
-/// point is not in a cluster.
+/// Mark points as belonging to a cluster. Clusters are labeled from 1, as
+/// with DBSCAN. A value of -1 means the point is not in a cluster.
 ///
 pub fn label_cluster_map(
     points: &Vec<XYPoint<i64>>,
     cluster_map: HashMap<XYPoint<i64>, Vec<usize>>,
     min_size: usize,
 ) -> Vec<i32> {
+    label_cluster_cells(points, cluster_map, min_size).0
+}
+
+/// Like label_cluster_map, but also returns each cluster's cell and how many
+/// points are in it. Cluster k's cell is at index k - 1.
+pub fn label_cluster_cells(
+    points: &Vec<XYPoint<i64>>,
+    cluster_map: HashMap<XYPoint<i64>, Vec<usize>>,
+    min_size: usize,
+) -> (Vec<i32>, Vec<(XYPoint<i64>, usize)>) {
     let mut labels = vec![0; points.len()];
     let mut label_map = HashMap::new();
-    let mut label = 0;
+    let mut cells = Vec::new();
     cluster_map.iter().for_each(|(p, v)| {
         if v.len() >= min_size {
-            label_map.insert(p, label);
-            label += 1;
+            cells.push((*p, v.len()));
+            label_map.insert(p, cells.len() as i32);
         }
     });
 
@@ -96,7 +179,7 @@ pub fn label_cluster_map(
             labels[i] = -1;
         }
     }
-    labels
+    (labels, cells)
 }
 
 pub fn hist2d(points: &Vec<XYPoint<i64>>) -> HashMap<XYPoint<i64>, Vec<usize>> {
@@ -203,6 +286,97 @@ mod tests {
         assert_eq!(map[&XYPoint { x: 2, y: 2 }], vec![3, 4]);
     }
 
+    #[test]
+    fn test_label_cluster_map() {
+        let points = vec![
+            XYPoint { x: 0, y: 0 },
+            XYPoint { x: 0, y: 0 },
+            XYPoint { x: 1, y: 1 },
+            XYPoint { x: 2, y: 2 },
+            XYPoint { x: 2, y: 2 },
+        ];
+        let labels = label_cluster_map(&points, hist2d(&points), 2);
+        // Cells are labeled in no particular order, but from 1.
+        assert_eq!(labels[2], -1);
+        assert_eq!(labels[0], labels[1]);
+        assert_eq!(labels[3], labels[4]);
+        let mut found = vec![labels[0], labels[3]];
+        found.sort();
+        assert_eq!(found, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_merge_cluster_labels() {
+        let l1 = vec![1, 1, -1, -1, -1, 2];
+        let l2 = vec![1, 1, 1, -1, -1, -1];
+        let l3 = vec![-1, -1, -1, 1, -1, -1];
+        let l4 = vec![-1, -1, -1, -1, -1, -1];
+        let labels = merge_cluster_labels(&[l1, l2, l3, l4]);
+        // l2's cluster 1 and l3's cluster 1 are shifted past l1's clusters,
+        // and past each other.
+        assert_eq!(labels, vec![1, 1, 3, 4, -1, 2]);
+    }
+
+    #[test]
+    fn test_more_offsets_catch_near_misses() {
+        // Spread over 0.6 of eps in x, these straddle a cell boundary
+        // whichever way a 2x2 grid of offsets shifts them, but fit in one
+        // cell of the 3x3 grid shifted by eps/3.
+        let points = vec![
+            XYPoint { x: 0.45, y: 0.0 },
+            XYPoint { x: 0.65, y: 0.0 },
+            XYPoint { x: 0.85, y: 0.0 },
+            XYPoint { x: 1.05, y: 0.0 },
+        ];
+        assert_eq!(find_clusters_hotspot2d(&points, 1.0, 4, 2), vec![-1; 4]);
+        let labels = find_clusters_hotspot2d(&points, 1.0, 4, 3);
+        assert_ne!(labels[0], -1);
+        assert!(labels.iter().all(|&l| l == labels[0]));
+    }
+
+    #[test]
+    fn test_find_clusters_hotspot2d_cells() {
+        // A dense cell around (2, -1), a sparse scatter, and a pair of points
+        // too few to count.
+        let mut points: Vec<XYPoint<f64>> = (0..6)
+            .map(|i| XYPoint::new(2.0 + 0.01 * i as f64, -1.0 - 0.01 * i as f64))
+            .collect();
+        points.extend([
+            XYPoint::new(10.0, 10.0),
+            XYPoint::new(20.0, 10.0),
+            XYPoint::new(-5.0, 5.0),
+            XYPoint::new(-5.05, 5.0),
+        ]);
+        let (labels, cells) = find_clusters_hotspot2d_cells(&points, 0.5, 4, 2);
+        assert_eq!(labels, [1, 1, 1, 1, 1, 1, -1, -1, -1, -1]);
+        // Every pass finds the dense cell, but only the first one's is kept.
+        assert_eq!(
+            cells,
+            vec![HotspotCell {
+                label: 1,
+                cell_x: 4,
+                cell_y: -2,
+                x: 2.0,
+                y: -1.0,
+                count: 6,
+            }]
+        );
+
+        // Near a cell corner, the clump is split up until a shifted pass
+        // finds it, which puts the cell's center in the shifted grid.
+        let points: Vec<XYPoint<f64>> = [(0.24, 0.24), (0.26, 0.26), (0.24, 0.26), (0.26, 0.24)]
+            .iter()
+            .map(|&(x, y)| XYPoint::new(x, y))
+            .collect();
+        let (labels, cells) = find_clusters_hotspot2d_cells(&points, 0.5, 4, 2);
+        assert_eq!(labels, [1, 1, 1, 1]);
+        assert_eq!(cells.len(), 1);
+        assert_eq!((cells[0].cell_x, cells[0].cell_y), (1, 1));
+        assert_eq!((cells[0].x, cells[0].y), (0.25, 0.25));
+        assert_eq!(cells[0].count, 4);
+        assert_eq!(find_clusters_hotspot2d(&points, 0.5, 4, 2), labels);
+    }
+
     #[test]
     fn test_hist2d_empty() {
         let points = vec![];
@@ -1,60 +1,547 @@
 use log::debug;
 
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-use pyo3::exceptions::PyTypeError;
+use pyo3::exceptions::{PyTypeError, PyValueError};
 use pyo3::prelude::{
-    pyclass, pyfunction, pymodule, Py, PyAny, PyErr, PyModule, PyObject, PyResult, Python,
+    pyclass, pyfunction, pymethods, pymodule, FromPyObject, Py, PyAny, PyErr, PyModule, PyObject,
+    PyResult, Python,
 };
-use pyo3::types::{PyFloat, PyInt, PyTuple};
+use pyo3::types::{PyDict, PyFloat, PyInt, PyTuple};
 use pyo3::wrap_pyfunction;
 
 use arrow::array::{
-    make_array, Array, ArrayBuilder, ArrayData, ArrowPrimitiveType, Float32Builder, Float64Array,
-    Float64Builder, Int32Builder, ListBuilder, PrimitiveArray, StringArray,
-    StringDictionaryBuilder, StructBuilder, UInt32Builder,
+    make_array, Array, ArrayBuilder, ArrayData, ArrayRef, DictionaryArray, Float32Builder,
+    Float64Array, Float64Builder, Int32Array, Int32Builder, Int64Array, Int64Builder,
+    LargeStringArray, ListBuilder, PrimitiveArray, StringArray, StringBuilder,
+    StringDictionaryBuilder, StructArray, StructBuilder, UInt32Array, UInt32Builder,
+};
+
+use arrow::datatypes::{
+    ArrowDictionaryKeyType, DataType, Field, Fields, Float32Type, Float64Type, Int32Type,
+    Int64Type, Int8Type, Schema,
 };
-use arrow::datatypes::{DataType, Field, Fields, Float64Type, Int32Type, Schema};
 use arrow::error::ArrowError;
 use arrow::pyarrow::{FromPyArrow, PyArrowException, ToPyArrow};
 use arrow::record_batch::RecordBatch;
 
-mod dbscan;
+pub mod clusters;
+pub mod dbscan;
 pub mod gridsearch;
-mod hotspot2d;
+mod hdbscan;
+pub mod hotspot2d;
+mod optics;
 pub mod points;
+use dbscan::brute_force;
 use dbscan::fixed16_kdtree;
 use dbscan::float32_kdtree;
+use dbscan::grid;
 use dbscan::rstar;
+use dbscan::sphere_kdtree::SphereTree;
+use dbscan::SearchTree;
 
 pub mod cellsearch;
 
-pub use points::{XYPoint, XYTPoint};
+pub use points::{Anisotropy, XYPoint, XYTPoint, XYZPoint};
 
 fn to_py_err(err: ArrowError) -> PyErr {
     PyArrowException::new_err(err.to_string())
 }
 
-fn as_primitive_array<T: ArrowPrimitiveType>(
-    val: &PyAny,
-    param_name: &str,
-) -> PyResult<PrimitiveArray<T>> {
+/// Accepts either a Float64Array or a Float32Array, which is widened.
+fn as_float_array(val: &PyAny, param_name: &str) -> PyResult<Float64Array> {
+    float_array(ArrayData::from_pyarrow(val)?, param_name)
+}
+
+fn float_array(array_data: ArrayData, param_name: &str) -> PyResult<Float64Array> {
+    match array_data.data_type() {
+        DataType::Float32 => {
+            Ok(PrimitiveArray::<Float32Type>::from(array_data)
+                .unary::<_, Float64Type>(|v| v as f64))
+        }
+        DataType::Float64 => Ok(Float64Array::from(array_data)),
+        data_type => Err(PyTypeError::new_err(format!(
+            "{}: unexpected array type, expected Float64 or Float32 but got {:?}",
+            param_name, data_type
+        ))),
+    }
+}
+
+/// Splits a StructArray with x, y, and dt fields into those three columns.
+/// Rows where the struct itself is null are null in every column.
+fn struct_columns(val: &PyAny, param_name: &str) -> PyResult<[Float64Array; 3]> {
     let array_data = ArrayData::from_pyarrow(val)?;
-    if array_data.data_type() != &T::DATA_TYPE {
+    if !matches!(array_data.data_type(), DataType::Struct(_)) {
         return Err(PyTypeError::new_err(format!(
-            "{}: unexpected array type, expected {:?} but got {:?}",
+            "{}: unexpected array type, expected Struct but got {:?}",
             param_name,
-            T::DATA_TYPE,
             array_data.data_type()
         )));
     }
+    let points = StructArray::from(array_data);
+    let column = |name: &str| -> PyResult<Float64Array> {
+        let field_name = format!("{}.{}", param_name, name);
+        let child = points.column_by_name(name).ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "{}: missing field, expected fields x, y, and dt but got {:?}",
+                param_name,
+                points.column_names()
+            ))
+        })?;
+        let child = float_array(child.to_data(), &field_name)?;
+        if points.null_count() == 0 {
+            return Ok(child);
+        }
+        Ok((0..points.len())
+            .map(|i| (points.is_valid(i) && child.is_valid(i)).then(|| child.value(i)))
+            .collect())
+    };
+    Ok([column("x")?, column("y")?, column("dt")?])
+}
 
-    Ok(PrimitiveArray::<T>::from(array_data))
+/// Returns the indexes of the rows which are non-null in every one of the
+/// arrays. The arrays must all be the same length.
+fn valid_rows(arrays: &[&Float64Array]) -> Vec<usize> {
+    let len = arrays.first().map_or(0, |array| array.len());
+    (0..len)
+        .filter(|&i| arrays.iter().all(|array| array.is_valid(i)))
+        .collect()
 }
 
-fn as_float_array(val: &PyAny, param_name: &str) -> PyResult<Float64Array> {
-    as_primitive_array::<Float64Type>(val, param_name)
+/// Points read from arrow arrays of coordinates. Rows with a null
+/// coordinate are left out of points; rows maps each point back to its
+/// input row, and len is the number of input rows.
+struct ArrowPoints<P> {
+    points: Vec<P>,
+    rows: Vec<usize>,
+    len: usize,
+}
+
+impl<P> ArrowPoints<P> {
+    /// Builds a point from each row which is non-null in every one of the
+    /// columns. The columns must all be the same length.
+    fn new(columns: &[&Float64Array], point: impl Fn(usize) -> P) -> Self {
+        let rows = valid_rows(columns);
+        ArrowPoints {
+            points: rows.iter().map(|&i| point(i)).collect(),
+            rows,
+            len: columns.first().map_or(0, |column| column.len()),
+        }
+    }
+
+    /// Leaves out the points whose row is null in column, too.
+    fn drop_nulls(&mut self, column: &Float64Array) {
+        let (points, rows) = self
+            .points
+            .drain(..)
+            .zip(self.rows.iter())
+            .filter(|(_, &row)| column.is_valid(row))
+            .unzip();
+        self.points = points;
+        self.rows = rows;
+    }
+
+    /// Spreads a label for each point back out over the input rows. Rows
+    /// which were left out are labeled as noise.
+    fn row_labels(&self, labels: &[i32]) -> Vec<i32> {
+        let mut row_labels = vec![-1; self.len];
+        for (label, &row) in labels.iter().zip(self.rows.iter()) {
+            row_labels[row] = *label;
+        }
+        row_labels
+    }
+}
+
+/// Reads x-y points from arrow arrays of x and y values, each Float64 or
+/// Float32.
+fn points_from_arrow(xs: ArrayData, ys: ArrayData) -> PyResult<ArrowPoints<XYPoint<f64>>> {
+    let xs = float_array(xs, "xs")?;
+    let ys = float_array(ys, "ys")?;
+    if xs.len() != ys.len() {
+        return Err(PyArrowException::new_err(
+            "x and y arrays must be the same length",
+        ));
+    }
+    Ok(ArrowPoints::new(&[&xs, &ys], |i| {
+        XYPoint::new(xs.value(i), ys.value(i))
+    }))
+}
+
+/// Returns xs and ys as Float64Arrays if they can be read in place: both
+/// Float64, the same length, and without nulls. Anything else has to go
+/// through points_from_arrow.
+fn dense_columns(xs: &ArrayData, ys: &ArrayData) -> Option<(Float64Array, Float64Array)> {
+    let dense =
+        |column: &ArrayData| column.data_type() == &DataType::Float64 && column.null_count() == 0;
+    if !dense(xs) || !dense(ys) || xs.len() != ys.len() {
+        return None;
+    }
+    Some((
+        Float64Array::from(xs.clone()),
+        Float64Array::from(ys.clone()),
+    ))
+}
+
+/// Reads x-y-t points from arrow arrays of x, y, and dt values, each Float64
+/// or Float32. NaN and infinite values are rejected.
+fn xyt_points_from_arrow(
+    xs: ArrayData,
+    ys: ArrayData,
+    dts: ArrayData,
+) -> PyResult<ArrowPoints<XYTPoint<f64>>> {
+    let xs = float_array(xs, "xs")?;
+    let ys = float_array(ys, "ys")?;
+    let dts = float_array(dts, "dts")?;
+    if xs.len() != ys.len() || xs.len() != dts.len() {
+        return Err(PyArrowException::new_err(
+            "x, y, and dts arrays must be the same length",
+        ));
+    }
+    check_finite(&xs, "xs")?;
+    check_finite(&ys, "ys")?;
+    check_finite(&dts, "dts")?;
+    Ok(ArrowPoints::new(&[&xs, &ys, &dts], |i| {
+        XYTPoint::new(xs.value(i), ys.value(i), dts.value(i))
+    }))
+}
+
+/// Rejects NaN and infinite values, which would corrupt the search trees.
+/// Nulls are allowed.
+fn check_finite(array: &Float64Array, param_name: &str) -> PyResult<()> {
+    match array
+        .iter()
+        .enumerate()
+        .find(|(_, v)| v.is_some_and(|v| !v.is_finite()))
+    {
+        Some((i, Some(v))) => Err(PyValueError::new_err(format!(
+            "{}: values must be finite, but got {} at index {}",
+            param_name, v, i
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that an array of velocities is finite, and has at least one
+/// velocity that isn't null.
+fn check_velocities(array: &Float64Array, param_name: &str) -> PyResult<()> {
+    check_finite(array, param_name)?;
+    if array.null_count() == array.len() {
+        return Err(PyValueError::new_err(format!(
+            "{}: at least one velocity is required",
+            param_name
+        )));
+    }
+    Ok(())
+}
+
+/// The eps to search with, and the eps_values for GridSearchConfig. eps is
+/// either a single float, or an array of them to search each of in turn.
+fn eps_values(eps: &PyAny) -> PyResult<(f64, Vec<f64>)> {
+    if let Ok(eps) = eps.extract::<f64>() {
+        return Ok((eps, Vec::new()));
+    }
+    let array = as_float_array(eps, "eps")?;
+    check_finite(&array, "eps")?;
+    let values: Vec<f64> = array.iter().flatten().collect();
+    match values.first() {
+        Some(&eps) => Ok((eps, values)),
+        None => Err(PyValueError::new_err("eps: at least one value is required")),
+    }
+}
+
+/// The velocities to search, with nulls left out. With pairs, vxs and vys
+/// are read together as (vx, vy) pairs, so they must be the same length, and
+/// a null in either drops the pair.
+fn velocity_lists(
+    vxs: &Float64Array,
+    vys: &Float64Array,
+    pairs: bool,
+) -> PyResult<(Vec<f64>, Vec<f64>)> {
+    if !pairs {
+        return Ok((
+            vxs.iter().flatten().collect(),
+            vys.iter().flatten().collect(),
+        ));
+    }
+    if vxs.len() != vys.len() {
+        return Err(PyValueError::new_err(
+            "vxs and vys must be the same length with velocity_pairs",
+        ));
+    }
+    Ok(vxs
+        .iter()
+        .zip(vys.iter())
+        .filter_map(|(vx, vy)| Some((vx?, vy?)))
+        .unzip())
+}
+
+/// Checks that there's an ID for every row of the points.
+fn check_ids_len(ids: &StringArray, len: usize) -> PyResult<()> {
+    if ids.len() != len {
+        return Err(PyArrowException::new_err(format!(
+            "ids must be the same length as x, y, and dts, but got {} ids for {} rows",
+            ids.len(),
+            len
+        )));
+    }
+    Ok(())
+}
+
+/// The ID of each point, looked up from its row of ids.
+fn row_ids(ids: &StringArray, rows: &[usize], len: usize) -> PyResult<Vec<String>> {
+    check_ids_len(ids, len)?;
+    Ok(rows.iter().map(|&i| ids.value(i).to_string()).collect())
+}
+
+/// Read a pyfunction's **options, calling set with the name and value of
+/// each. Names not in accepted raise TypeError, as Python does for an
+/// unexpected keyword argument.
+fn parse_options<'a>(
+    function: &str,
+    accepted: &[&str],
+    options: Option<&'a PyDict>,
+    mut set: impl FnMut(&str, &'a PyAny) -> PyResult<()>,
+) -> PyResult<()> {
+    for (name, value) in options.into_iter().flatten() {
+        let name: &str = name.extract()?;
+        if !accepted.contains(&name) {
+            return Err(PyTypeError::new_err(format!(
+                "{}() got an unexpected keyword argument '{}'",
+                function, name
+            )));
+        }
+        set(name, value)?;
+    }
+    Ok(())
+}
+
+/// Extract the value of the option called name. A value of the wrong type
+/// raises a TypeError naming the option, as it would for any other argument.
+fn extract_option<'a, T: FromPyObject<'a>>(name: &str, value: &'a PyAny) -> PyResult<T> {
+    value.extract().map_err(|err| {
+        if err.is_instance_of::<PyTypeError>(value.py()) {
+            PyTypeError::new_err(format!("argument '{}': {}", name, err.value(value.py())))
+        } else {
+            err
+        }
+    })
+}
+
+/// The keyword options of grid_search, with their defaults. Its variants
+/// each take some of them.
+struct GridSearchOptions {
+    metric: DistanceMetric,
+    dedup: bool,
+    progress: Option<PyObject>,
+    progress_interval: usize,
+    min_arc_length: f64,
+    min_nights: u32,
+    night_length: f64,
+    joined: bool,
+    max_speed: Option<f64>,
+    dictionary_obs_ids: bool,
+    noise: bool,
+    velocity_pairs: bool,
+    log_interval: usize,
+    sort_members: bool,
+    require_motion: bool,
+    max_cluster_size: Option<usize>,
+}
+
+impl Default for GridSearchOptions {
+    fn default() -> Self {
+        GridSearchOptions {
+            metric: DistanceMetric::Euclidean,
+            dedup: false,
+            progress: None,
+            progress_interval: 100,
+            min_arc_length: 0.0,
+            min_nights: 0,
+            night_length: 1.0,
+            joined: false,
+            max_speed: None,
+            dictionary_obs_ids: true,
+            noise: false,
+            velocity_pairs: false,
+            log_interval: 0,
+            sort_members: false,
+            require_motion: false,
+            max_cluster_size: None,
+        }
+    }
+}
+
+impl GridSearchOptions {
+    /// The names of every option, all of which grid_search takes.
+    const ALL: [&'static str; 16] = [
+        "metric",
+        "dedup",
+        "progress",
+        "progress_interval",
+        "min_arc_length",
+        "min_nights",
+        "night_length",
+        "output",
+        "max_speed",
+        "dictionary_obs_ids",
+        "noise",
+        "velocity_pairs",
+        "log_interval",
+        "sort_members",
+        "require_motion",
+        "max_cluster_size",
+    ];
+
+    /// Read the options that function was called with. It takes those named
+    /// in accepted, and the rest keep their defaults.
+    fn parse(function: &str, accepted: &[&str], options: Option<&PyDict>) -> PyResult<Self> {
+        let mut parsed = GridSearchOptions::default();
+        parse_options(function, accepted, options, |name, value| {
+            match name {
+                "metric" => parsed.metric = extract_option(name, value)?,
+                "dedup" => parsed.dedup = extract_option(name, value)?,
+                "progress" => parsed.progress = extract_option(name, value)?,
+                "progress_interval" => parsed.progress_interval = extract_option(name, value)?,
+                "min_arc_length" => parsed.min_arc_length = extract_option(name, value)?,
+                "min_nights" => parsed.min_nights = extract_option(name, value)?,
+                "night_length" => parsed.night_length = extract_option(name, value)?,
+                "output" => parsed.joined = output_joined(extract_option(name, value)?)?,
+                "max_speed" => parsed.max_speed = extract_option(name, value)?,
+                "dictionary_obs_ids" => parsed.dictionary_obs_ids = extract_option(name, value)?,
+                "noise" => parsed.noise = extract_option(name, value)?,
+                "velocity_pairs" => parsed.velocity_pairs = extract_option(name, value)?,
+                "log_interval" => parsed.log_interval = extract_option(name, value)?,
+                "sort_members" => parsed.sort_members = extract_option(name, value)?,
+                "require_motion" => parsed.require_motion = extract_option(name, value)?,
+                "max_cluster_size" => parsed.max_cluster_size = extract_option(name, value)?,
+                _ => unreachable!("{} isn't a grid_search option", name),
+            }
+            Ok(())
+        })?;
+        Ok(parsed)
+    }
+
+    /// The config for a search with these options, which clusters at eps
+    /// with alg.
+    fn config(
+        &self,
+        eps: f64,
+        min_cluster_size: usize,
+        alg: ClusterAlgorithm,
+    ) -> gridsearch::GridSearchConfig {
+        let mut config = gridsearch::GridSearchConfig::new(eps, min_cluster_size)
+            .algorithm(alg)
+            .metric(self.metric.clone())
+            .min_arc_length(self.min_arc_length)
+            .velocity_pairs(self.velocity_pairs)
+            .log_interval(self.log_interval)
+            .sort_members_by_dt(self.sort_members)
+            .night_length(self.night_length);
+        config.max_speed = self.max_speed;
+        config.max_cluster_size = self.max_cluster_size;
+        config
+    }
+
+    /// Drop the clusters that min_nights, require_motion, and dedup rule out.
+    fn retain_clusters(
+        &self,
+        summaries: Vec<gridsearch::ClusterSummary>,
+        members: Vec<gridsearch::ClusterMember>,
+    ) -> (
+        Vec<gridsearch::ClusterSummary>,
+        Vec<gridsearch::ClusterMember>,
+    ) {
+        let (summaries, members) = gridsearch::retain_clusters(summaries, members, |summary| {
+            summary.n_nights >= self.min_nights && (summary.has_motion() || !self.require_motion)
+        });
+        if self.dedup {
+            gridsearch::dedup_clusters(summaries, members)
+        } else {
+            (summaries, members)
+        }
+    }
+}
+
+/// The positional arguments that grid_search and its variants share.
+struct GridSearchArgs<'a> {
+    vxs: &'a PyAny,
+    vys: &'a PyAny,
+    eps: f64,
+    eps_values: Vec<f64>,
+    min_cluster_size: usize,
+    n_threads: usize,
+    alg: ClusterAlgorithm,
+}
+
+impl GridSearchArgs<'_> {
+    /// Check the arguments, and read the search's config, and the vxs and
+    /// vys to search, from them and options.
+    fn parse(
+        self,
+        options: &GridSearchOptions,
+    ) -> PyResult<(gridsearch::GridSearchConfig, Vec<f64>, Vec<f64>)> {
+        let vxs = as_float_array(self.vxs, "vxs")?;
+        let vys = as_float_array(self.vys, "vys")?;
+        check_velocities(&vxs, "vxs")?;
+        check_velocities(&vys, "vys")?;
+        let (vxs, vys) = velocity_lists(&vxs, &vys, options.velocity_pairs)?;
+
+        let config = options
+            .config(self.eps, self.min_cluster_size, self.alg)
+            .eps_values(self.eps_values)
+            .n_threads(self.n_threads);
+        Ok((config, vxs, vys))
+    }
+}
+
+/// Whether grid_search's output argument asks for one joined table, rather
+/// than separate cluster and member tables.
+fn output_joined(output: &str) -> PyResult<bool> {
+    match output {
+        "tables" => Ok(false),
+        "joined" => Ok(true),
+        _ => Err(PyValueError::new_err(format!(
+            "output: expected \"tables\" or \"joined\", but got {:?}",
+            output
+        ))),
+    }
+}
+
+/// Convert grid search clusters to the tables grid_search returns, with the
+/// noise table after them if there is one.
+fn clusters_to_py(
+    summaries: &[gridsearch::ClusterSummary],
+    members: &[gridsearch::ClusterMember],
+    joined: bool,
+    dictionary_obs_ids: bool,
+    noise_table: Option<PyObject>,
+    py: Python,
+) -> PyResult<PyObject> {
+    if joined {
+        let joined = joined_table(summaries, members, dictionary_obs_ids)?.to_pyarrow(py)?;
+        return match noise_table {
+            Some(noise_table) => Ok(PyTuple::new(py, vec![joined, noise_table]).into()),
+            None => Ok(joined),
+        };
+    }
+
+    let (cluster_table, cluster_members_table) =
+        gridsearch::to_record_batches(summaries, members, dictionary_obs_ids).map_err(to_py_err)?;
+
+    // Convert to Python objects for output
+    let cluster_table = cluster_table.to_pyarrow(py)?;
+    let cluster_members_table = cluster_members_table.to_pyarrow(py)?;
+
+    // Combine into a tuple.
+    let mut tables = vec![cluster_table, cluster_members_table];
+    tables.extend(noise_table);
+    Ok(PyTuple::new(py, tables).into())
 }
 
 fn as_string_array(val: &PyAny, param_name: &str) -> PyResult<StringArray> {
@@ -77,37 +564,220 @@ pub enum ClusterAlgorithm {
     Hotspot2D = 2,
     DbscanRStar = 3,
     DbscanFixed16 = 4,
+    Optics = 5,
+    Hdbscan = 6,
+    DbscanBruteForce = 7,
+    GridDbscan = 8,
+}
+
+impl ClusterAlgorithm {
+    const NAMES: [(&'static str, ClusterAlgorithm); 8] = [
+        ("dbscan", ClusterAlgorithm::DBSCAN),
+        ("hotspot2d", ClusterAlgorithm::Hotspot2D),
+        ("dbscan_rstar", ClusterAlgorithm::DbscanRStar),
+        ("dbscan_fixed16", ClusterAlgorithm::DbscanFixed16),
+        ("optics", ClusterAlgorithm::Optics),
+        ("hdbscan", ClusterAlgorithm::Hdbscan),
+        ("dbscan_brute_force", ClusterAlgorithm::DbscanBruteForce),
+        ("grid_dbscan", ClusterAlgorithm::GridDbscan),
+    ];
+}
+
+/// Parses names like "dbscan" or "dbscan_rstar", ignoring case.
+impl FromStr for ClusterAlgorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let lower = name.to_lowercase();
+        match ClusterAlgorithm::NAMES.iter().find(|(n, _)| *n == lower) {
+            Some((_, alg)) => Ok(alg.clone()),
+            None => {
+                let names: Vec<&str> = ClusterAlgorithm::NAMES.iter().map(|(n, _)| *n).collect();
+                Err(format!(
+                    "unknown cluster algorithm {:?}, expected one of: {}",
+                    name,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+#[pymethods]
+impl ClusterAlgorithm {
+    /// Look up an algorithm by name, like "dbscan" or "dbscan_rstar",
+    /// ignoring case. Raises ValueError for unknown names.
+    #[staticmethod]
+    #[pyo3(name = "from_str")]
+    fn from_str_py(name: &str) -> PyResult<Self> {
+        name.parse().map_err(PyValueError::new_err)
+    }
+}
+
+/// The distance metric used to decide whether two points are within eps of
+/// each other.
+///
+/// eps is always measured in the chosen metric. Manhattan distances are never
+/// shorter than Euclidean ones, and up to sqrt(2) times longer, so the same
+/// eps makes a tighter neighborhood under Manhattan: a diamond inscribed in
+/// the Euclidean circle.
+///
+/// Haversine treats x and y as RA and Dec in degrees, and eps as a
+/// great-circle distance in radians. It avoids the distortion of planar
+/// distances near the poles and across wide fields.
+#[derive(Clone, PartialEq, Eq)]
+#[pyclass]
+pub enum DistanceMetric {
+    Euclidean = 1,
+    Manhattan = 2,
+    Haversine = 3,
 }
 
+impl DistanceMetric {
+    pub fn distance(&self, a: &XYPoint<f64>, b: &XYPoint<f64>) -> f64 {
+        match self {
+            DistanceMetric::Euclidean => a.euclidean_distance(b),
+            DistanceMetric::Manhattan => a.manhattan_distance(b),
+            DistanceMetric::Haversine => {
+                let d = *a - *b;
+                let (dec_a, dec_b) = (a.y.to_radians(), b.y.to_radians());
+                let sin_ddec = (d.y.to_radians() / 2.0).sin();
+                let sin_dra = (d.x.to_radians() / 2.0).sin();
+                let h = sin_ddec * sin_ddec + dec_a.cos() * dec_b.cos() * sin_dra * sin_dra;
+                2.0 * h.sqrt().min(1.0).asin()
+            }
+        }
+    }
+}
+
+/// How often grid_search checks for signals from Python, and reports
+/// progress, while it runs.
+const CHECK_IN_INTERVAL: Duration = Duration::from_millis(50);
+
 /// Clusters X-Y points, searching across a grid of possibly vx and vy values.
 ///
+/// Rows with a null x, y, or dt are left out of the search, as are null
+/// velocities. NaN or infinite values raise ValueError, as do vxs or vys
+/// with no velocities in them.
+///
+/// The search can be interrupted with Ctrl-C, which raises KeyboardInterrupt.
+/// An exception raised by the progress callback stops the search too.
+///
+/// The arguments from metric on can only be passed by keyword.
+///
 /// Arguments:
 ///     ids: A list of observation IDs as a StringArray.
-///     xs: A list of x coordinates as a Float64Array.
-///     ys: A list of y coordinates as a Float64Array.
-///     dts: A list of time deltas as a Float64Array. These are the time deltas between
+///     xs: A list of x coordinates as a Float64Array or Float32Array.
+///     ys: A list of y coordinates as a Float64Array or Float32Array.
+///     dts: A list of time deltas as a Float64Array or Float32Array. These are the time deltas between
 ///          the point and the minimum time in the dataset, in MJD.
-///     vxs: A list of possible x velocities as a Float64Array.
-///     vys: A list of possible y velocities as a Float64Array.
+///     vxs: A list of possible x velocities as a Float64Array or Float32Array.
+///     vys: A list of possible y velocities as a Float64Array or Float32Array.
 ///     eps: The maximum distance between two points for them to be considered in the same
-///          neighborhood.
+///          neighborhood. This may also be a Float64Array or Float32Array of
+///          eps values, to search every velocity at each of them; each
+///          cluster's eps column says which it was found with. Every value is
+///          a whole extra search, so this multiplies the run time by their
+///          number.
 ///     min_cluster_size: The minimum number of points in a cluster.
 ///     n_threads: The number of threads to use for clustering.
 ///     alg: The clustering algorithm to use.
+///     metric: The distance metric used to compare points against eps. Defaults
+///          to Euclidean. Hotspot2D ignores this.
+///     dedup: If true, drop clusters with exactly the same members as a cluster
+///          found at an earlier velocity. Defaults to false.
+///     progress: An optional callable, called as progress(completed, total)
+///          as velocity pairs are searched. With an array of eps, each pair
+///          counts once for each eps.
+///     progress_interval: How many velocity pairs to search between calls to
+///          progress. It's always called once all pairs are done. Defaults
+///          to 100.
+///     min_arc_length: Clusters spanning less than this in dt are dropped.
+///          Defaults to 0.0, which keeps every cluster.
+///     min_nights: Clusters with observations on fewer than this many distinct
+///          nights are dropped. Defaults to 0, which keeps every cluster.
+///     require_motion: If true, clusters whose observations all share a
+///          single dt are dropped. They come from a single exposure, so
+///          they're blends rather than moving objects. Defaults to false.
+///     night_length: The length of a night in dt units, used to count nights.
+///          Defaults to 1.0.
+///     max_speed: If given, velocity pairs faster than this are skipped, and
+///          not counted toward progress. Defaults to no limit.
+///     dictionary_obs_ids: Whether obs_id columns are dictionary encoded.
+///          Defaults to true.
+///     output: "tables" or "joined". Defaults to "tables".
+///     noise: If true, also return a table of the observations which weren't
+///          in a cluster at any velocity. Defaults to false.
+///     velocity_pairs: If true, search each (vx, vy) pair of vxs and vys,
+///          which must be the same length, rather than every combination of
+///          them. Defaults to false.
+///     log_interval: Log progress through Python's logging, at INFO level,
+///          every this many velocity pairs. A summary is always logged once
+///          the search finishes. Defaults to 0, which only logs the summary.
+///     sort_members: If true, list each cluster's members together, in order
+///          of dt. Defaults to false, which lists them in the order of the
+///          points, and is a little faster.
+///     max_cluster_size: Clusters with more points than this are dropped, as
+///          star fields or artifacts rather than moving objects. Defaults to
+///          None, which keeps every cluster.
 ///
 /// Returns:
-///     A pair of RecordBatches.
+///     With output="tables", a pair of RecordBatches.
 ///     The first summarizes all of the clusters. It has the following schema:
-///         cluster_id: string
+///         cluster_id: uint32
 ///         vx: float64
 ///         vy: float64
 ///         arc_length: float64
+///         n_obs: uint32
+///         mean_x: float64
+///         mean_y: float64
+///         n_nights: uint32
+///         residual_rms: float64
+///         vx_fit: float64
+///         vy_fit: float64
+///         single_exposure: bool
+///         eps: float64
+///     mean_x and mean_y are the cluster's centroid at dt=0, after moving each
+///     point back along the cluster's velocity. n_nights is the number of
+///     distinct nights, of night_length each, that the cluster spans.
+///     residual_rms is the RMS distance of the cluster's observations from a
+///     straight line fit through them over time, and vx_fit and vy_fit are
+///     that line's velocity: an estimate of the cluster's velocity from its
+///     own observations, finer than the grid's. single_exposure is true if
+///     every observation in the cluster shares one dt, so that arc_length is
+///     exactly 0.0: anything which divides by arc_length should skip these,
+///     or pass require_motion to drop them. eps is the eps the cluster was
+///     found with.
 ///     The second contains the cluster assignments for each point. It
 ///     has the following schema:
-///         cluster_id: string
+///         cluster_id: uint32
+///         obs_id: dictionary<values=large_string, indices=int64>
+///     With output="joined", a single RecordBatch with one row for each
+///     clustered observation, so that no join is needed in Python:
+///         cluster_id: uint32
+///         vx: float64
+///         vy: float64
+///         arc_length: float64
+///         obs_id: dictionary<values=large_string, indices=int64>
+///     obs_id is dictionary encoded, so an observation that turns up in many
+///     clusters only stores its ID once. The indices are 64-bit, and the values
+///     large strings, so that there is no limit on the number or total length
+///     of distinct IDs, at the cost of 8 bytes per row rather than 4. With
+///     dictionary_obs_ids=False, obs_id is a plain string column instead, for
+///     consumers which can't handle dictionaries.
+///     With noise=True, one more RecordBatch follows the others: the
+///     observations which weren't in any cluster at any velocity searched,
+///     before min_nights, require_motion, and dedup were applied. Rows with a
+///     null x, y, or dt aren't searched, so aren't listed. It has the
+///     following schema:
 ///         obs_id: string
 #[pyfunction]
-#[pyo3(name = "grid_search")]
+#[pyo3(
+    name = "grid_search",
+    signature = (ids, xs, ys, dts, vxs, vys, eps, min_cluster_size, n_threads, alg, **options)
+)]
+// Each of the positional arguments has to be a parameter of its own.
+#[allow(clippy::too_many_arguments)]
 fn grid_search_py(
     ids: &PyAny,
     xs: &PyAny,
@@ -115,10 +785,40 @@ fn grid_search_py(
     dts: &PyAny,
     vxs: &PyAny,
     vys: &PyAny,
-    eps: &PyFloat,
+    eps: &PyAny,
     min_cluster_size: &PyInt,
     n_threads: &PyInt,
     alg: Py<ClusterAlgorithm>,
+    options: Option<&PyDict>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let options = GridSearchOptions::parse("grid_search", &GridSearchOptions::ALL, options)?;
+    // Rows with nulls are dropped; rows maps each point back to its input
+    // row.
+    let points = xyt_points_from_arrow(
+        ArrayData::from_pyarrow(xs)?,
+        ArrayData::from_pyarrow(ys)?,
+        ArrayData::from_pyarrow(dts)?,
+    )?;
+    let (eps, eps_values) = eps_values(eps)?;
+    let args = GridSearchArgs {
+        vxs,
+        vys,
+        eps,
+        eps_values,
+        min_cluster_size: min_cluster_size.extract()?,
+        n_threads: n_threads.extract()?,
+        alg: alg.extract(py)?,
+    };
+    grid_search(ids, points, args, &options, py)
+}
+
+/// Run grid_search over points, once its arguments have been read.
+fn grid_search(
+    ids: &PyAny,
+    points: ArrowPoints<XYTPoint<f64>>,
+    args: GridSearchArgs,
+    options: &GridSearchOptions,
     py: Python,
 ) -> PyResult<PyObject> {
     // Handle the Python-to-rust conversion up front
@@ -128,254 +828,1789 @@ fn grid_search_py(
         .downcast_ref::<StringArray>()
         .ok_or_else(|| ArrowError::ParseError("Expects a string array".to_string()))
         .map_err(to_py_err)?;
+    let ArrowPoints { points, rows, len } = points;
+    let ids = row_ids(ids, &rows, len)?;
+    let (config, vxs, vys) = args.parse(options)?;
 
-    let xs = as_float_array(xs, "invalid value for xs")?;
-
-    let ys = make_array(ArrayData::from_pyarrow(ys)?);
-
-    let ys = ys
-        .as_any()
-        .downcast_ref::<Float64Array>()
-        .ok_or_else(|| ArrowError::ParseError("Expects a float64 array".to_string()))
-        .map_err(to_py_err)?;
-
-    let dts = make_array(ArrayData::from_pyarrow(dts)?);
-    let dts = dts
-        .as_any()
-        .downcast_ref::<Float64Array>()
-        .ok_or_else(|| ArrowError::ParseError("Expects a float64 array".to_string()))
-        .map_err(to_py_err)?;
-
-    if xs.len() != ys.len() || xs.len() != dts.len() {
-        return Err(PyArrowException::new_err(
-            "x y, and dts arrays must be the same length",
-        ));
+    // The search is pure Rust, so let other Python threads run meanwhile.
+    // It runs on its own thread, so that this one can watch for signals like
+    // Ctrl-C and cancel it, and pass progress on to the callback.
+    let cancel = AtomicBool::new(false);
+    let total = config.searches(&vxs, &vys).len();
+    let (progress_tx, progress_rx) = channel();
+    let tracker = gridsearch::Progress::new(options.progress_interval, total, progress_tx);
+    let tracker = options.progress.as_ref().map(|_| &tracker);
+    let (results, interrupted) = py.allow_threads(|| {
+        let progress_rx = progress_rx;
+        let check_in = |py: Python| -> PyResult<()> {
+            py.check_signals()?;
+            if let Some(callback) = &options.progress {
+                for completed in progress_rx.try_iter() {
+                    callback.call1(py, (completed, total))?;
+                }
+            }
+            Ok(())
+        };
+        thread::scope(|scope| {
+            let search = scope.spawn(|| {
+                gridsearch::cluster_grid_search_summaries(
+                    &points,
+                    &ids,
+                    vxs,
+                    vys,
+                    &config,
+                    Some(&cancel),
+                    tracker,
+                )
+            });
+            let mut interrupted = None;
+            loop {
+                let finished = search.is_finished();
+                if let Err(err) = Python::with_gil(check_in) {
+                    cancel.store(true, Ordering::Relaxed);
+                    interrupted = Some(err);
+                    break;
+                }
+                if finished {
+                    break;
+                }
+                thread::sleep(CHECK_IN_INTERVAL);
+            }
+            (search.join().unwrap(), interrupted)
+        })
+    });
+    if let Some(err) = interrupted {
+        return Err(err);
     }
+    let (summaries, members, ever_clustered, _) = results;
+    let noise_table = if options.noise {
+        let table = gridsearch::noise_table(&ids, &ever_clustered).map_err(to_py_err)?;
+        Some(table.to_pyarrow(py)?)
+    } else {
+        None
+    };
 
-    let vxs = make_array(ArrayData::from_pyarrow(vxs)?);
-    let vxs = vxs
-        .as_any()
-        .downcast_ref::<Float64Array>()
-        .ok_or_else(|| ArrowError::ParseError("Expects a float64 array".to_string()))
-        .map_err(to_py_err)?;
-
-    let vys = make_array(ArrayData::from_pyarrow(vys)?);
-    let vys = vys
-        .as_any()
-        .downcast_ref::<Float64Array>()
-        .ok_or_else(|| ArrowError::ParseError("Expects a float64 array".to_string()))
-        .map_err(to_py_err)?;
-
-    let eps = eps.extract::<f64>()?;
-    let min_cluster_size = min_cluster_size.extract::<u8>()? as usize;
-    let alg = alg.extract::<ClusterAlgorithm>(py)?;
-    let n_threads = n_threads.extract::<usize>()?;
+    let (summaries, members) = options.retain_clusters(summaries, members);
+    clusters_to_py(
+        &summaries,
+        &members,
+        options.joined,
+        options.dictionary_obs_ids,
+        noise_table,
+        py,
+    )
+}
 
-    // Turn xs ys, and dts into Vec<XYTPoint> for easier processing.
-    let points = xs
-        .iter()
-        .zip(ys.iter())
-        .zip(dts.iter())
-        .map(|((x, y), dt)| XYTPoint::new(x.unwrap_or(0.0), y.unwrap_or(0.0), dt.unwrap_or(0.0)))
-        .collect::<Vec<_>>();
+/// Cluster points which have already been shifted to a single known
+/// velocity, and summarize the clusters. This is grid_search with just one
+/// velocity, (0, 0), so the points are clustered exactly where they are.
+///
+/// Arguments:
+///     ids, xs, ys, dts, eps, min_cluster_size, alg: As for grid_search.
+///     metric, min_arc_length, min_nights, night_length, output,
+///     dictionary_obs_ids, require_motion: As for grid_search, with the same
+///          defaults.
+///
+/// Returns:
+///     The same as grid_search. Every cluster has vx and vy of 0.
+#[pyfunction]
+#[pyo3(
+    name = "cluster_single",
+    signature = (ids, xs, ys, dts, eps, min_cluster_size, alg, **options)
+)]
+#[allow(clippy::too_many_arguments)]
+fn cluster_single_py(
+    ids: &PyAny,
+    xs: &PyAny,
+    ys: &PyAny,
+    dts: &PyAny,
+    eps: f64,
+    min_cluster_size: usize,
+    alg: ClusterAlgorithm,
+    options: Option<&PyDict>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let options = GridSearchOptions::parse(
+        "cluster_single",
+        &[
+            "metric",
+            "min_arc_length",
+            "min_nights",
+            "night_length",
+            "output",
+            "dictionary_obs_ids",
+            "require_motion",
+        ],
+        options,
+    )?;
+    let ids = as_string_array(ids, "ids")?;
+    let ArrowPoints { points, rows, len } = xyt_points_from_arrow(
+        ArrayData::from_pyarrow(xs)?,
+        ArrayData::from_pyarrow(ys)?,
+        ArrayData::from_pyarrow(dts)?,
+    )?;
+    let ids = row_ids(&ids, &rows, len)?;
 
-    // Turn vxs and vys into Vec<f64> for easier processing.
-    let vxs = vxs.iter().map(|x| x.unwrap_or(0.0)).collect::<Vec<_>>();
-    let vys = vys.iter().map(|x| x.unwrap_or(0.0)).collect::<Vec<_>>();
+    let config = options.config(eps, min_cluster_size, alg);
+    let (summaries, members) =
+        py.allow_threads(|| gridsearch::cluster_single(&points, &ids, &config));
+    let (summaries, members) = options.retain_clusters(summaries, members);
+    clusters_to_py(
+        &summaries,
+        &members,
+        options.joined,
+        options.dictionary_obs_ids,
+        None,
+        py,
+    )
+}
 
-    let results =
-        gridsearch::cluster_grid_search(&points, vxs, vys, alg, eps, min_cluster_size, n_threads);
+/// Like grid_search, but takes the points as a single StructArray rather
+/// than as three separate arrays.
+///
+/// Arguments:
+///     ids: A list of observation IDs as a StringArray.
+///     points: A StructArray with fields x, y, and dt, each Float64 or
+///          Float32, as for grid_search's xs, ys, and dts. Other fields are
+///          ignored. A null struct is treated like a null x, y, or dt.
+///     The remaining arguments are as for grid_search.
+///
+/// Returns:
+///     The same as grid_search.
+#[pyfunction]
+#[pyo3(
+    name = "grid_search_struct",
+    signature = (ids, points, vxs, vys, eps, min_cluster_size, n_threads, alg, **options)
+)]
+#[allow(clippy::too_many_arguments)]
+fn grid_search_struct_py(
+    ids: &PyAny,
+    points: &PyAny,
+    vxs: &PyAny,
+    vys: &PyAny,
+    eps: &PyAny,
+    min_cluster_size: &PyInt,
+    n_threads: &PyInt,
+    alg: Py<ClusterAlgorithm>,
+    options: Option<&PyDict>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let options = GridSearchOptions::parse("grid_search_struct", &GridSearchOptions::ALL, options)?;
+    let [xs, ys, dts] = struct_columns(points, "points")?;
+    let points = xyt_points_from_arrow(xs.to_data(), ys.to_data(), dts.to_data())?;
+    let (eps, eps_values) = eps_values(eps)?;
+    let args = GridSearchArgs {
+        vxs,
+        vys,
+        eps,
+        eps_values,
+        min_cluster_size: min_cluster_size.extract()?,
+        n_threads: n_threads.extract()?,
+        alg: alg.extract(py)?,
+    };
+    grid_search(ids, points, args, &options, py)
+}
 
-    // Result shape is a pair of values.
-    //
-    // The first value is a table of cluster ID, vx, vy, and arc length (difference between min and max dt).
-    //
-    // The second value is a table of cluster IDs and observation IDs.
-    let cluster_table_schema = Schema::new(vec![
-        Field::new("cluster_id", DataType::UInt32, false),
-        Field::new("vx", DataType::Float64, false),
-        Field::new("vy", DataType::Float64, false),
-        Field::new("arc_length", DataType::Float64, false),
-    ]);
+/// Like grid_search, but returns the raw cluster labels of every point at
+/// every velocity, rather than summarizing the clusters.
+///
+/// The output holds one int32 per point per velocity searched, so it can be
+/// large: 100,000 points over a 100x100 velocity grid is 4 GB. Prefer
+/// grid_search unless the full label matrix is really needed.
+///
+/// Arguments:
+///     xs: A list of x coordinates as a Float64Array or Float32Array.
+///     ys: A list of y coordinates as a Float64Array or Float32Array.
+///     dts: A list of time deltas as a Float64Array or Float32Array.
+///     vxs: A list of possible x velocities as a Float64Array or Float32Array.
+///     vys: A list of possible y velocities as a Float64Array or Float32Array.
+///     eps, min_cluster_size, n_threads, alg, metric, min_arc_length,
+///     max_speed, velocity_pairs, max_cluster_size: As for grid_search.
+///
+/// Returns:
+///     A RecordBatch with a row for each velocity searched, in the order
+///     grid_search searches them, with the following schema:
+///         vx: float64
+///         vy: float64
+///         labels: list<int32>
+///     Each list of labels lines up with the input points. Noise, and rows
+///     with a null x, y, or dt, are labeled -1.
+#[pyfunction]
+#[pyo3(
+    name = "grid_search_labels",
+    signature = (xs, ys, dts, vxs, vys, eps, min_cluster_size, n_threads, alg, **options)
+)]
+#[allow(clippy::too_many_arguments)]
+fn grid_search_labels_py(
+    xs: &PyAny,
+    ys: &PyAny,
+    dts: &PyAny,
+    vxs: &PyAny,
+    vys: &PyAny,
+    eps: f64,
+    min_cluster_size: usize,
+    n_threads: usize,
+    alg: ClusterAlgorithm,
+    options: Option<&PyDict>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let options = GridSearchOptions::parse(
+        "grid_search_labels",
+        &[
+            "metric",
+            "min_arc_length",
+            "max_speed",
+            "velocity_pairs",
+            "max_cluster_size",
+        ],
+        options,
+    )?;
+    let points = xyt_points_from_arrow(
+        ArrayData::from_pyarrow(xs)?,
+        ArrayData::from_pyarrow(ys)?,
+        ArrayData::from_pyarrow(dts)?,
+    )?;
+    let (config, vxs, vys) = GridSearchArgs {
+        vxs,
+        vys,
+        eps,
+        eps_values: Vec::new(),
+        min_cluster_size,
+        n_threads,
+        alg,
+    }
+    .parse(&options)?;
 
-    let cluster_members_table_schema = Schema::new(vec![
-        Field::new("cluster_id", DataType::UInt32, false),
-        Field::new_dictionary("obs_id", DataType::Int32, DataType::Utf8, false),
-    ]);
+    let mut results = py.allow_threads(|| {
+        gridsearch::cluster_grid_search(&points.points, vxs, vys, &config, None, None)
+    });
+    gridsearch::sort_results(&mut results);
 
-    // Assemble the arrays.
-    let mut cluster_id_builder = UInt32Builder::new();
     let mut vx_builder = Float64Builder::new();
     let mut vy_builder = Float64Builder::new();
-    let mut arc_length_builder = Float64Builder::new();
-
-    let mut cluster_id_members_builder = UInt32Builder::new();
-    let mut obs_id_members_builder = StringDictionaryBuilder::<Int32Type>::new();
-
-    let mut cluster_id: u32 = 0;
-    for result in results.into_iter() {
-        let mut label_id_map: HashMap<i32, u32> = HashMap::new();
-        let mut cluster_arc_starts: HashMap<u32, f64> = HashMap::new();
-        let mut cluster_arc_ends: HashMap<u32, f64> = HashMap::new();
-        let mut cluster_ids = Vec::new();
-        for (i, label) in result.cluster_labels.iter().enumerate() {
-            if *label < 0 {
-                continue;
-            }
-            let cluster_id = match label_id_map.get(label) {
-                Some(val) => *val,
-                None => {
-                    let val = cluster_id + 1;
-                    cluster_id += 1;
-                    label_id_map.insert(*label, val);
-                    cluster_ids.push(val);
-                    vx_builder.append_value(result.vx);
-                    vy_builder.append_value(result.vy);
-                    val
-                }
-            };
-            cluster_id_members_builder.append_value(cluster_id);
-            obs_id_members_builder.append_value(ids.value(i));
-            // Keep track of max/min dt for each cluster.
-            let dt = dts.value(i);
-            match cluster_arc_starts.get(&cluster_id) {
-                Some(val) => {
-                    if dt < *val {
-                        cluster_arc_starts.insert(cluster_id, dt);
-                    }
-                }
-                None => {
-                    cluster_arc_starts.insert(cluster_id, dt);
-                }
-            };
-            match cluster_arc_ends.get(&cluster_id) {
-                Some(val) => {
-                    if dt > *val {
-                        cluster_arc_ends.insert(cluster_id, dt);
-                    }
-                }
-                None => {
-                    cluster_arc_ends.insert(cluster_id, dt);
-                }
-            }
-        }
-        // Now that we've processed all the points, we can add the arc lengths.
-        for cluster_id in cluster_ids.iter() {
-            cluster_id_builder.append_value(*cluster_id);
-            let arc_length = cluster_arc_ends.get(cluster_id).unwrap()
-                - cluster_arc_starts.get(cluster_id).unwrap();
-            arc_length_builder.append_value(arc_length);
-        }
+    let mut labels_builder = ListBuilder::new(Int32Builder::new());
+    for result in results.iter() {
+        vx_builder.append_value(result.vx);
+        vy_builder.append_value(result.vy);
+        labels_builder
+            .values()
+            .append_slice(&points.row_labels(&result.cluster_labels));
+        labels_builder.append(true);
     }
 
-    // Build the tables (as RecordBatches)
-    let cluster_table = RecordBatch::try_new(
-        Arc::new(cluster_table_schema),
+    let schema = Schema::new(vec![
+        Field::new("vx", DataType::Float64, false),
+        Field::new("vy", DataType::Float64, false),
+        Field::new_list("labels", Field::new("item", DataType::Int32, true), false),
+    ]);
+    RecordBatch::try_new(
+        Arc::new(schema),
         vec![
-            Arc::new(cluster_id_builder.finish()),
             Arc::new(vx_builder.finish()),
             Arc::new(vy_builder.finish()),
-            Arc::new(arc_length_builder.finish()),
+            Arc::new(labels_builder.finish()),
         ],
     )
-    .map_err(to_py_err)?;
+    .map_err(to_py_err)?
+    .to_pyarrow(py)
+}
+
+/// Like grid_search, but only counts the clusters found at each velocity,
+/// for a quick-look heatmap of which velocities are productive. Much cheaper
+/// to hold than grid_search_labels, since each velocity's labels are dropped
+/// as soon as they're counted.
+///
+/// Arguments:
+///     xs, ys, dts, vxs, vys, eps, min_cluster_size, n_threads, alg, metric,
+///     min_arc_length, max_speed, velocity_pairs, max_cluster_size: As for
+///          grid_search_labels.
+///
+/// Returns:
+///     A RecordBatch with a row for each velocity searched, in the order
+///     grid_search searches them, with the following schema:
+///         vx: float64
+///         vy: float64
+///         n_clusters: uint32
+#[pyfunction]
+#[pyo3(
+    name = "grid_search_counts",
+    signature = (xs, ys, dts, vxs, vys, eps, min_cluster_size, n_threads, alg, **options)
+)]
+#[allow(clippy::too_many_arguments)]
+fn grid_search_counts_py(
+    xs: &PyAny,
+    ys: &PyAny,
+    dts: &PyAny,
+    vxs: &PyAny,
+    vys: &PyAny,
+    eps: f64,
+    min_cluster_size: usize,
+    n_threads: usize,
+    alg: ClusterAlgorithm,
+    options: Option<&PyDict>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let options = GridSearchOptions::parse(
+        "grid_search_counts",
+        &[
+            "metric",
+            "min_arc_length",
+            "max_speed",
+            "velocity_pairs",
+            "max_cluster_size",
+        ],
+        options,
+    )?;
+    let points = xyt_points_from_arrow(
+        ArrayData::from_pyarrow(xs)?,
+        ArrayData::from_pyarrow(ys)?,
+        ArrayData::from_pyarrow(dts)?,
+    )?;
+    let (config, vxs, vys) = GridSearchArgs {
+        vxs,
+        vys,
+        eps,
+        eps_values: Vec::new(),
+        min_cluster_size,
+        n_threads,
+        alg,
+    }
+    .parse(&options)?;
+
+    let counts = py.allow_threads(|| {
+        gridsearch::cluster_grid_search_counts(&points.points, vxs, vys, &config, None, None)
+    });
+
+    let mut vx_builder = Float64Builder::new();
+    let mut vy_builder = Float64Builder::new();
+    let mut count_builder = UInt32Builder::new();
+    for (vx, vy, n_clusters) in counts.into_iter() {
+        vx_builder.append_value(vx);
+        vy_builder.append_value(vy);
+        count_builder.append_value(n_clusters as u32);
+    }
+
+    let schema = Schema::new(vec![
+        Field::new("vx", DataType::Float64, false),
+        Field::new("vy", DataType::Float64, false),
+        Field::new("n_clusters", DataType::UInt32, false),
+    ]);
+    RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(vx_builder.finish()),
+            Arc::new(vy_builder.finish()),
+            Arc::new(count_builder.finish()),
+        ],
+    )
+    .map_err(to_py_err)?
+    .to_pyarrow(py)
+}
+
+/// Run grid_search separately on each of many independent cells of points,
+/// such as patches of sky, in one call. Points are only ever clustered with
+/// others in the same cell. Rather than spreading each cell's velocities
+/// across threads, as grid_search does, the cells are spread across them, so
+/// one call keeps every thread busy through many small cells.
+///
+/// Arguments:
+///     cell_ids: The cell of each row, as an Int64Array. Rows with a null
+///          cell_id are skipped.
+///     ids, xs, ys, dts, vxs, vys, eps, min_cluster_size, alg: As for
+///          grid_search. Every cell is searched at the same velocities.
+///     n_threads: The number of threads to spread the cells across. Each
+///          cell is searched on one thread, so more threads than cells
+///          don't help. 0 means one per CPU.
+///     metric, min_arc_length, min_nights, night_length, max_speed,
+///     velocity_pairs, dictionary_obs_ids, require_motion, output,
+///     sort_members, max_cluster_size: As for grid_search, with the same
+///          defaults.
+///
+/// Returns:
+///     The same as grid_search, but with a cell_id: int64 column at the
+///     start of the cluster table, or of the joined table with
+///     output="joined", giving the cell each cluster was found in. Cluster
+///     IDs are unique across every cell, and count up in order of cell_id.
+#[pyfunction]
+#[pyo3(
+    name = "grid_search_batch",
+    signature = (cell_ids, ids, xs, ys, dts, vxs, vys, eps, min_cluster_size, n_threads, alg, **options)
+)]
+#[allow(clippy::too_many_arguments)]
+fn grid_search_batch_py(
+    cell_ids: &PyAny,
+    ids: &PyAny,
+    xs: &PyAny,
+    ys: &PyAny,
+    dts: &PyAny,
+    vxs: &PyAny,
+    vys: &PyAny,
+    eps: &PyAny,
+    min_cluster_size: usize,
+    n_threads: usize,
+    alg: ClusterAlgorithm,
+    options: Option<&PyDict>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let options = GridSearchOptions::parse(
+        "grid_search_batch",
+        &[
+            "metric",
+            "min_arc_length",
+            "min_nights",
+            "night_length",
+            "max_speed",
+            "velocity_pairs",
+            "dictionary_obs_ids",
+            "require_motion",
+            "output",
+            "sort_members",
+            "max_cluster_size",
+        ],
+        options,
+    )?;
+    let cell_ids = make_array(ArrayData::from_pyarrow(cell_ids)?);
+    let cell_ids = cell_ids
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .ok_or_else(|| PyTypeError::new_err("cell_ids: expected an int64 array"))?;
+    let ids = as_string_array(ids, "ids")?;
+    let ArrowPoints { points, rows, len } = xyt_points_from_arrow(
+        ArrayData::from_pyarrow(xs)?,
+        ArrayData::from_pyarrow(ys)?,
+        ArrayData::from_pyarrow(dts)?,
+    )?;
+    let ids = row_ids(&ids, &rows, len)?;
+    if cell_ids.len() != len {
+        return Err(PyArrowException::new_err(format!(
+            "cell_ids must be the same length as x, y, and dts, but got {} cell_ids for {} rows",
+            cell_ids.len(),
+            len
+        )));
+    }
+
+    let (eps, eps_values) = eps_values(eps)?;
+    let (config, vxs, vys) = GridSearchArgs {
+        vxs,
+        vys,
+        eps,
+        eps_values,
+        min_cluster_size,
+        n_threads,
+        alg,
+    }
+    .parse(&options)?;
+
+    // Rows with a null cell_id are dropped along with the null points.
+    let (points, (ids, cells)): (Vec<_>, (Vec<_>, Vec<_>)) = points
+        .into_iter()
+        .zip(ids)
+        .zip(rows)
+        .filter(|(_, i)| cell_ids.is_valid(*i))
+        .map(|((point, id), i)| (point, (id, cell_ids.value(i))))
+        .unzip();
+
+    let (summaries, members, summary_cells) = py.allow_threads(|| {
+        gridsearch::cluster_grid_search_cells(&points, &ids, &cells, vxs, vys, &config, None)
+    });
+    let cell_of: HashMap<u32, i64> = summaries
+        .iter()
+        .map(|summary| summary.cluster_id)
+        .zip(summary_cells)
+        .collect();
+
+    let (summaries, members) = options.retain_clusters(summaries, members);
+    if options.joined {
+        let table = joined_table(&summaries, &members, options.dictionary_obs_ids)?;
+        let cells = members.iter().map(|member| cell_of[&member.cluster_id]);
+        return with_cell_ids(table, cells)?.to_pyarrow(py);
+    }
+    let (cluster_table, cluster_members_table) =
+        gridsearch::to_record_batches(&summaries, &members, options.dictionary_obs_ids)
+            .map_err(to_py_err)?;
+    let cells = summaries.iter().map(|summary| cell_of[&summary.cluster_id]);
+    let tables = vec![
+        with_cell_ids(cluster_table, cells)?.to_pyarrow(py)?,
+        cluster_members_table.to_pyarrow(py)?,
+    ];
+    Ok(PyTuple::new(py, tables).into())
+}
+
+/// Put a cell_id column, holding cells, at the start of table.
+fn with_cell_ids(table: RecordBatch, cells: impl Iterator<Item = i64>) -> PyResult<RecordBatch> {
+    let mut fields = vec![Arc::new(Field::new("cell_id", DataType::Int64, false))];
+    fields.extend(table.schema().fields().iter().cloned());
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(Int64Array::from_iter_values(cells))];
+    columns.extend(table.columns().iter().cloned());
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(to_py_err)
+}
+
+/// Build the output="joined" table for grid_search: one row per cluster
+/// member, carrying its cluster's velocity and arc length.
+fn joined_table(
+    summaries: &[gridsearch::ClusterSummary],
+    members: &[gridsearch::ClusterMember],
+    dictionary_obs_ids: bool,
+) -> PyResult<RecordBatch> {
+    let summaries: HashMap<u32, &gridsearch::ClusterSummary> = summaries
+        .iter()
+        .map(|summary| (summary.cluster_id, summary))
+        .collect();
+
+    let mut cluster_id_builder = UInt32Builder::new();
+    let mut vx_builder = Float64Builder::new();
+    let mut vy_builder = Float64Builder::new();
+    let mut arc_length_builder = Float64Builder::new();
+    for member in members.iter() {
+        let summary = summaries[&member.cluster_id];
+        cluster_id_builder.append_value(member.cluster_id);
+        vx_builder.append_value(summary.vx);
+        vy_builder.append_value(summary.vy);
+        arc_length_builder.append_value(summary.arc_length);
+    }
+
+    RecordBatch::try_new(
+        Arc::new(joined_table_schema(dictionary_obs_ids)),
+        vec![
+            Arc::new(cluster_id_builder.finish()),
+            Arc::new(vx_builder.finish()),
+            Arc::new(vy_builder.finish()),
+            Arc::new(arc_length_builder.finish()),
+            gridsearch::obs_id_column(members, dictionary_obs_ids),
+        ],
+    )
+    .map_err(to_py_err)
+}
+
+/// Generate a square grid of velocities to search, optionally limited to an
+/// annulus in velocity space.
+///
+/// Arguments:
+///     v_min: The smallest vx and vy.
+///     v_max: The largest vx and vy. Both v_min and v_max are included.
+///     n_steps: The number of values along each axis.
+///     min_speed, max_speed: If given, only keep velocities with a speed in
+///          this range. Both default to no limit.
+///     min_angle, max_angle: If given, only keep velocities with a direction
+///          in this range, in degrees counterclockwise from +x. If min_angle is
+///          greater than max_angle, the range wraps through 0.
+///
+/// Returns:
+///     A pair of Float64Arrays, vxs and vys. Together they list (vx, vy)
+///     pairs, in the order grid_search searches them.
+#[pyfunction]
+#[pyo3(
+    name = "velocity_grid",
+    signature = (v_min, v_max, n_steps, min_speed = None, max_speed = None, min_angle = None, max_angle = None)
+)]
+fn velocity_grid_py(
+    v_min: f64,
+    v_max: f64,
+    n_steps: usize,
+    min_speed: Option<f64>,
+    max_speed: Option<f64>,
+    min_angle: Option<f64>,
+    max_angle: Option<f64>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let annulus =
+        if min_speed.is_some() || max_speed.is_some() || min_angle.is_some() || max_angle.is_some()
+        {
+            Some(gridsearch::VelocityAnnulus {
+                min_speed: min_speed.unwrap_or(0.0),
+                max_speed: max_speed.unwrap_or(f64::INFINITY),
+                min_angle: min_angle.unwrap_or(0.0),
+                max_angle: max_angle.unwrap_or(360.0),
+            })
+        } else {
+            None
+        };
+    let grid = gridsearch::velocity_grid(v_min, v_max, n_steps, annulus.as_ref());
+
+    let vxs = Float64Array::from(grid.iter().map(|(vx, _)| *vx).collect::<Vec<_>>());
+    let vys = Float64Array::from(grid.iter().map(|(_, vy)| *vy).collect::<Vec<_>>());
+    let vxs = vxs.to_data().to_pyarrow(py)?;
+    let vys = vys.to_data().to_pyarrow(py)?;
+    Ok(PyTuple::new(py, vec![vxs, vys]).into())
+}
+
+/// Shift points back along a velocity to where they'd have been at dt=0, the
+/// same way grid_search does before clustering them. Useful for checking a
+/// velocity grid, or plotting what grid_search sees at one velocity.
+///
+/// Arguments:
+///     xs, ys, dts: As for grid_search.
+///     vx, vy: The velocity to shift by.
+///
+/// Returns:
+///     A pair of Float64Arrays, the shifted xs and ys: x - vx*dt and
+///     y - vy*dt. Observations of an object moving at (vx, vy) all shift to
+///     the same place. Rows with a null x, y, or dt are null.
+#[pyfunction]
+#[pyo3(name = "apply_velocity")]
+fn apply_velocity_py(
+    xs: &PyAny,
+    ys: &PyAny,
+    dts: &PyAny,
+    vx: f64,
+    vy: f64,
+    py: Python,
+) -> PyResult<PyObject> {
+    let points = xyt_points_from_arrow(
+        ArrayData::from_pyarrow(xs)?,
+        ArrayData::from_pyarrow(ys)?,
+        ArrayData::from_pyarrow(dts)?,
+    )?;
+    let mut shifted_xs = vec![None; points.len];
+    let mut shifted_ys = vec![None; points.len];
+    for (point, &row) in points.points.iter().zip(points.rows.iter()) {
+        let shifted = point.shift_by_velocity(vx, vy);
+        shifted_xs[row] = Some(shifted.x);
+        shifted_ys[row] = Some(shifted.y);
+    }
+
+    let xs = Float64Array::from(shifted_xs).to_data().to_pyarrow(py)?;
+    let ys = Float64Array::from(shifted_ys).to_data().to_pyarrow(py)?;
+    Ok(PyTuple::new(py, vec![xs, ys]).into())
+}
+
+/// Schema of the table returned by grid_search with output="joined".
+fn joined_table_schema(dictionary_obs_ids: bool) -> Schema {
+    Schema::new(vec![
+        Field::new("cluster_id", DataType::UInt32, false),
+        Field::new("vx", DataType::Float64, false),
+        Field::new("vy", DataType::Float64, false),
+        Field::new("arc_length", DataType::Float64, false),
+        gridsearch::obs_id_field(dictionary_obs_ids),
+    ])
+}
+
+/// The keyword options of find_clusters, with their defaults.
+/// find_clusters_grouped takes some of them.
+struct FindClustersOptions<'a> {
+    metric: DistanceMetric,
+    weights: Option<&'a PyAny>,
+    scale: Option<(f64, f64)>,
+    rotation: f64,
+    max_cluster_size: Option<usize>,
+    sigmas: Option<&'a PyAny>,
+    n_sigma: f64,
+    periodic_x: Option<f64>,
+    max_neighbors: Option<usize>,
+}
+
+impl Default for FindClustersOptions<'_> {
+    fn default() -> Self {
+        FindClustersOptions {
+            metric: DistanceMetric::Euclidean,
+            weights: None,
+            scale: None,
+            rotation: 0.0,
+            max_cluster_size: None,
+            sigmas: None,
+            n_sigma: 1.0,
+            periodic_x: None,
+            max_neighbors: None,
+        }
+    }
+}
+
+impl<'a> FindClustersOptions<'a> {
+    /// The names of every option, all of which find_clusters takes.
+    const ALL: [&'static str; 9] = [
+        "metric",
+        "weights",
+        "scale",
+        "rotation",
+        "max_cluster_size",
+        "sigmas",
+        "n_sigma",
+        "periodic_x",
+        "max_neighbors",
+    ];
+
+    /// Read the options that function was called with. It takes those named
+    /// in accepted, and the rest keep their defaults.
+    fn parse(function: &str, accepted: &[&str], options: Option<&'a PyDict>) -> PyResult<Self> {
+        let mut parsed = FindClustersOptions::default();
+        parse_options(function, accepted, options, |name, value| {
+            match name {
+                "metric" => parsed.metric = extract_option(name, value)?,
+                "weights" => parsed.weights = extract_option(name, value)?,
+                "scale" => parsed.scale = extract_option(name, value)?,
+                "rotation" => parsed.rotation = extract_option(name, value)?,
+                "max_cluster_size" => parsed.max_cluster_size = extract_option(name, value)?,
+                "sigmas" => parsed.sigmas = extract_option(name, value)?,
+                "n_sigma" => parsed.n_sigma = extract_option(name, value)?,
+                "periodic_x" => parsed.periodic_x = extract_option(name, value)?,
+                "max_neighbors" => parsed.max_neighbors = extract_option(name, value)?,
+                _ => unreachable!("{} isn't a find_clusters option", name),
+            }
+            Ok(())
+        })?;
+        Ok(parsed)
+    }
+}
+
+/// Label each row of xs and ys with its cluster, for find_clusters and
+/// find_clusters_grouped. Rows with nulls are labeled as noise.
+fn find_row_labels(
+    xs: &PyAny,
+    ys: &PyAny,
+    eps: f64,
+    min_cluster_size: usize,
+    alg: &ClusterAlgorithm,
+    options: &FindClustersOptions,
+) -> PyResult<Vec<i32>> {
+    if let Some((x, y)) = options.scale {
+        if [x, y].iter().any(|s| !s.is_finite() || *s <= 0.0) {
+            return Err(PyValueError::new_err("scale must be finite and positive"));
+        }
+    }
+    let anisotropy = options
+        .scale
+        .map(|scale| Anisotropy::new(scale, options.rotation));
+    let anisotropy = anisotropy.as_ref();
+    let metric = &options.metric;
+    let weights = options.weights;
+    let uncertainties = options.sigmas.map(|sigmas| (sigmas, options.n_sigma));
+    let max_cluster_size = options.max_cluster_size;
+    let periodic_x = options.periodic_x;
+    let max_neighbors = options.max_neighbors;
+
+    if anisotropy.is_some() && metric == &DistanceMetric::Haversine {
+        return Err(PyValueError::new_err(
+            "scale is not supported with the haversine metric",
+        ));
+    }
+    if let Some(period) = periodic_x {
+        if !period.is_finite() || period <= 0.0 {
+            return Err(PyValueError::new_err(
+                "periodic_x must be finite and positive",
+            ));
+        }
+        if metric == &DistanceMetric::Haversine {
+            return Err(PyValueError::new_err(
+                "periodic_x is not supported with the haversine metric, which already wraps RA",
+            ));
+        }
+        // A rotated ellipse mixes x into y, so there's no longer a seam
+        // to wrap around.
+        if anisotropy.is_some_and(|anisotropy| anisotropy.rotation != 0.0) {
+            return Err(PyValueError::new_err(
+                "periodic_x cannot be used with a rotated scale",
+            ));
+        }
+    }
+    let (xs, ys) = (ArrayData::from_pyarrow(xs)?, ArrayData::from_pyarrow(ys)?);
+    if weights.is_none()
+        && anisotropy.is_none()
+        && uncertainties.is_none()
+        && periodic_x.is_none()
+        && max_neighbors.is_none()
+    {
+        if let Some((xs, ys)) = dense_columns(&xs, &ys) {
+            // Cluster the Arrow buffers in place, without copying them into
+            // points first.
+            let labels =
+                find_clusters_columns(xs.values(), ys.values(), eps, min_cluster_size, alg, metric);
+            if let Some(mut labels) = labels {
+                if let Some(max_cluster_size) = max_cluster_size {
+                    dbscan::drop_large_clusters(&mut labels, max_cluster_size);
+                }
+                dbscan::relabel_contiguous(&mut labels);
+                return Ok(labels);
+            }
+        }
+    }
+
+    // Handle the Python-to-rust conversion up front
+    let mut input = points_from_arrow(xs, ys)?;
+    let weights = weights
+        .map(|weights| as_float_array(weights, "weights"))
+        .transpose()?;
+    let sigmas = uncertainties
+        .map(|(sigmas, _)| as_float_array(sigmas, "sigmas"))
+        .transpose()?;
+
+    if weights
+        .as_ref()
+        .is_some_and(|weights| weights.len() != input.len)
+    {
+        return Err(PyArrowException::new_err(
+            "weights must be the same length as x and y",
+        ));
+    }
+    if sigmas
+        .as_ref()
+        .is_some_and(|sigmas| sigmas.len() != input.len)
+    {
+        return Err(PyArrowException::new_err(
+            "sigmas must be the same length as x and y",
+        ));
+    }
+    for column in weights.iter().chain(sigmas.iter()) {
+        input.drop_nulls(column);
+    }
+    if let Some(anisotropy) = anisotropy {
+        for point in input.points.iter_mut() {
+            *point = anisotropy.transform(point);
+        }
+    }
+    let (points, rows) = (&input.points, &input.rows);
+
+    let weights: Option<Vec<f64>> = weights
+        .as_ref()
+        .map(|weights| rows.iter().map(|&i| weights.value(i)).collect());
+    let sigmas: Option<Vec<f64>> = sigmas
+        .as_ref()
+        .map(|sigmas| rows.iter().map(|&i| sigmas.value(i)).collect());
+    if sigmas
+        .iter()
+        .flatten()
+        .any(|sigma| !sigma.is_finite() || *sigma < 0.0)
+    {
+        return Err(PyValueError::new_err(
+            "sigmas must be finite and non-negative",
+        ));
+    }
+    let n_sigma = uncertainties.map_or(1.0, |(_, n_sigma)| n_sigma);
+    let options = dbscan::DbscanOptions {
+        weights: weights.as_deref(),
+        sigmas: sigmas.as_deref().map(|sigmas| (sigmas, n_sigma)),
+        // The scale shrinks x, and the period along with it.
+        period: periodic_x.map(|period| period / anisotropy.map_or(1.0, |a| a.scale.0)),
+        max_neighbors,
+    };
+
+    let mut cluster_labels = if weights.is_none()
+        && sigmas.is_none()
+        && periodic_x.is_none()
+        && max_neighbors.is_none()
+    {
+        find_clusters(points, eps, min_cluster_size, alg, metric)
+    } else {
+        find_clusters_with(points, eps, min_cluster_size, alg, metric, &options).ok_or_else(
+            || {
+                PyValueError::new_err(
+                    "weights, sigmas, periodic_x, and max_neighbors are only supported by the DBSCAN algorithms",
+                )
+            },
+        )?
+    };
+    if let Some(max_cluster_size) = max_cluster_size {
+        dbscan::drop_large_clusters(&mut cluster_labels, max_cluster_size);
+    }
+    dbscan::relabel_contiguous(&mut cluster_labels);
+    Ok(input.row_labels(&cluster_labels))
+}
+
+/// Find clusters of related x-y points.
+///
+/// # Arguments
+///
+/// * `xs` - A arrow float64 or float32 array of x values.
+/// * `ys` - A arrow float64 or float32 array of y values.
+/// * `eps` - The maximum distance between two points for them to be considered as in the same cluster.
+/// * `min_cluster_size` - The minimum number of points in a cluster. Points at exactly the same
+///   coordinates each count, so that many copies of one point are a cluster on their own.
+/// * `alg` - The clustering algorithm to use.
+/// * `metric` - The distance metric used to compare points against eps. Defaults to Euclidean.
+///   This and the rest of the arguments can only be passed by keyword.
+/// * `weights` - An optional arrow float64 or float32 array of per-point weights. A point counts
+///   as its weight toward min_cluster_size. Only the DBSCAN variants support weights. Rows with
+///   a null weight are labeled as noise.
+/// * `scale` - An optional (x, y) pair which stretches each point's neighborhood from a circle of
+///   radius eps into an ellipse with semi-axes eps * x and eps * y, for elongated position
+///   uncertainties. Both must be finite and positive. Not supported with the haversine metric.
+/// * `rotation` - The angle of the ellipse's x axis, in radians counterclockwise from the +x
+///   axis. Only used with scale. Defaults to 0.
+/// * `max_cluster_size` - Clusters with more points than this are labeled as noise, since
+///   implausibly large clusters are usually star fields or artifacts. Defaults to None, which
+///   keeps every cluster.
+/// * `sigmas` - An optional arrow float64 or float32 array of per-point position
+///   uncertainties, in the same units as eps. Two points are neighbors if they're within
+///   sqrt(eps^2 + n_sigma^2 * (sigma_a^2 + sigma_b^2)) of each other, so poorly measured
+///   points reach further. Only the DBSCAN variants support sigmas. Rows with a null sigma are
+///   labeled as noise.
+/// * `n_sigma` - How many sigmas to widen eps by. Defaults to 1.
+/// * `periodic_x` - If given, x wraps around with this period, so that points either side of
+///   the seam are neighbors: 360 for RA in degrees, for example. Only the DBSCAN variants
+///   support it, and it can't be combined with a rotated scale. Defaults to None.
+/// * `max_neighbors` - If given, at most this many points are queued up at once to be checked for
+///   being core points themselves, which bounds memory in very dense regions. Neighbors found
+///   while the queue is full still join the cluster, and are checked once it has room, so the
+///   clusters are the same, but each refill of the queue scans every point. Only the DBSCAN
+///   variants support it. Defaults to None, which queues every neighbor.
+///
+/// # Returns
+///
+/// The cluster label of each row of the input arrays, as an arrow int32 array. Clusters are
+/// numbered from 1 with no gaps, and noise is labeled -1. Rows with a null x or y are labeled
+/// as noise.
+#[pyfunction]
+#[pyo3(
+    name = "find_clusters",
+    signature = (xs, ys, eps, min_cluster_size, alg, **options)
+)]
+fn find_clusters_py(
+    xs: &PyAny,
+    ys: &PyAny,
+    eps: &PyFloat,
+    min_cluster_size: &PyInt,
+    alg: Py<ClusterAlgorithm>,
+    options: Option<&PyDict>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let eps = eps.extract::<f64>()?;
+    let min_cluster_size = min_cluster_size.extract::<usize>()?;
+    let alg = alg.extract::<ClusterAlgorithm>(py)?;
+    let options = FindClustersOptions::parse("find_clusters", &FindClustersOptions::ALL, options)?;
+    let labels = find_row_labels(xs, ys, eps, min_cluster_size, &alg, &options)?;
+
+    // Convert the clusters into an arrow list of int32
+    let mut builder = Int32Builder::new();
+    builder.append_slice(&labels[..]);
+    let la = builder.finish();
+    la.to_data().to_pyarrow(py)
+}
+
+/// Find clusters of points in 3D space, such as heliocentric Cartesian
+/// positions, with DBSCAN.
+///
+/// # Arguments
+///
+/// * `xs` - A arrow float64 or float32 array of x values.
+/// * `ys` - A arrow float64 or float32 array of y values.
+/// * `zs` - A arrow float64 or float32 array of z values.
+/// * `eps` - The maximum euclidean distance between two points for them to be considered as in
+///   the same cluster.
+/// * `min_cluster_size` - The minimum number of points in a cluster.
+///
+/// # Returns
+///
+/// The cluster label of each row of the input arrays, as an arrow int32 array. Noise is
+/// labeled -1. Rows with a null x, y, or z are labeled as noise.
+#[pyfunction]
+#[pyo3(name = "find_clusters_3d")]
+fn find_clusters_3d_py(
+    xs: &PyAny,
+    ys: &PyAny,
+    zs: &PyAny,
+    eps: f64,
+    min_cluster_size: usize,
+    py: Python,
+) -> PyResult<PyObject> {
+    let xs = as_float_array(xs, "xs")?;
+    let ys = as_float_array(ys, "ys")?;
+    let zs = as_float_array(zs, "zs")?;
+    if xs.len() != ys.len() || xs.len() != zs.len() {
+        return Err(PyArrowException::new_err(
+            "x, y, and z arrays must be the same length",
+        ));
+    }
+
+    let points = ArrowPoints::new(&[&xs, &ys, &zs], |i| {
+        XYZPoint::new(xs.value(i), ys.value(i), zs.value(i))
+    });
+    let cluster_labels =
+        py.allow_threads(|| dbscan::find_clusters_3d(&points.points, eps, min_cluster_size));
+
+    Int32Array::from(points.row_labels(&cluster_labels))
+        .to_data()
+        .to_pyarrow(py)
+}
+
+/// Find clusters of related x-y points, grouped by cluster.
+///
+/// Takes the same arguments as find_clusters, but of its keyword arguments, only metric, weights,
+/// and max_cluster_size.
+///
+/// # Returns
+///
+/// A list of lists of indices into the input arrays, as an arrow list of uint32 arrays. There
+/// is one list per cluster, in order of cluster label, and each list is sorted. Noise is left
+/// out.
+#[pyfunction]
+#[pyo3(
+    name = "find_clusters_grouped",
+    signature = (xs, ys, eps, min_cluster_size, alg, **options)
+)]
+fn find_clusters_grouped_py(
+    xs: &PyAny,
+    ys: &PyAny,
+    eps: &PyFloat,
+    min_cluster_size: &PyInt,
+    alg: Py<ClusterAlgorithm>,
+    options: Option<&PyDict>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let eps = eps.extract::<f64>()?;
+    let min_cluster_size = min_cluster_size.extract::<usize>()?;
+    let alg = alg.extract::<ClusterAlgorithm>(py)?;
+    let options = FindClustersOptions::parse(
+        "find_clusters_grouped",
+        &["metric", "weights", "max_cluster_size"],
+        options,
+    )?;
+    let labels = find_row_labels(xs, ys, eps, min_cluster_size, &alg, &options)?;
+
+    let mut builder = ListBuilder::new(UInt32Builder::new());
+    for group in dbscan::group_labels(&labels) {
+        for idx in group {
+            builder.values().append_value(idx as u32);
+        }
+        builder.append(true);
+    }
+    builder.finish().to_data().to_pyarrow(py)
+}
+
+/// Find clusters of related x-y points with DBSCAN, and whether each point
+/// is a core or border member of its cluster.
+///
+/// Takes the same arguments as find_clusters, up to metric. Only the DBSCAN algorithms are
+/// supported.
+///
+/// # Returns
+///
+/// An arrow struct array with a label (int32) and role (dictionary string) for each row of the
+/// input arrays. Labels are as for find_clusters. The role is "core" for points with at least
+/// min_cluster_size neighbors, "border" for the other points in a cluster, and "noise" for
+/// noise. Rows with a null x or y are noise.
+#[pyfunction]
+#[pyo3(
+    name = "find_clusters_classified",
+    signature = (xs, ys, eps, min_cluster_size, alg, metric = DistanceMetric::Euclidean)
+)]
+fn find_clusters_classified_py(
+    xs: &PyAny,
+    ys: &PyAny,
+    eps: f64,
+    min_cluster_size: usize,
+    alg: ClusterAlgorithm,
+    metric: DistanceMetric,
+    py: Python,
+) -> PyResult<PyObject> {
+    let points = points_from_arrow(ArrayData::from_pyarrow(xs)?, ArrayData::from_pyarrow(ys)?)?;
+    let classified = py
+        .allow_threads(|| {
+            find_classified_clusters(&points.points, eps, min_cluster_size, &alg, &metric)
+        })
+        .ok_or_else(|| {
+            PyValueError::new_err("find_clusters_classified only supports the DBSCAN algorithms")
+        })?;
+
+    let labels: Vec<i32> = classified.iter().map(|(label, _)| *label).collect();
+    let mut roles = vec![dbscan::PointRole::Noise; points.len];
+    for ((_, role), &row) in classified.iter().zip(points.rows.iter()) {
+        roles[row] = *role;
+    }
+    let mut role_builder = StringDictionaryBuilder::<Int8Type>::new();
+    for role in roles {
+        role_builder.append_value(role.name());
+    }
+
+    let classified = StructArray::from(vec![
+        (
+            Arc::new(Field::new("label", DataType::Int32, false)),
+            Arc::new(Int32Array::from(points.row_labels(&labels))) as ArrayRef,
+        ),
+        (
+            Arc::new(Field::new_dictionary(
+                "role",
+                DataType::Int8,
+                DataType::Utf8,
+                false,
+            )),
+            Arc::new(role_builder.finish()) as ArrayRef,
+        ),
+    ]);
+    classified.to_data().to_pyarrow(py)
+}
+
+/// Compute the k-distance graph of some x-y points, to help pick eps.
+///
+/// # Arguments
+///
+/// * `xs` - A arrow float64 or float32 array of x values.
+/// * `ys` - A arrow float64 or float32 array of y values.
+/// * `k` - Which neighbor to measure the distance to, counting each point itself. Use the
+///   min_cluster_size you plan to cluster with.
+///
+/// # Returns
+///
+/// The distance from each point to its k-th nearest neighbor, sorted, as an arrow float64 array.
+/// A good eps is near the knee of a plot of these distances. Rows with a null x or y are left
+/// out.
+#[pyfunction]
+#[pyo3(name = "estimate_eps")]
+fn estimate_eps_py(xs: &PyAny, ys: &PyAny, k: usize, py: Python) -> PyResult<PyObject> {
+    let points = points_from_arrow(ArrayData::from_pyarrow(xs)?, ArrayData::from_pyarrow(ys)?)?;
+
+    let distances = py.allow_threads(|| dbscan::estimate_eps(&points.points, k));
+    Float64Array::from(distances).to_data().to_pyarrow(py)
+}
+
+/// Compute the k-nearest-neighbor graph of some x-y points, for running other graph-based
+/// clustering on them.
+///
+/// # Arguments
+///
+/// * `xs` - A arrow float64 or float32 array of x values.
+/// * `ys` - A arrow float64 or float32 array of y values.
+/// * `k` - How many neighbors to link each point to, not counting itself.
+///
+/// # Returns
+///
+/// A RecordBatch with an edge from each point to each of its k nearest neighbors, nearest first,
+/// with the following schema:
+///     i: uint32
+///     j: uint32
+///     distance: float64
+/// i and j are row numbers in the input arrays, and distance is euclidean. The edges are
+/// directed, so a pair of points can be linked either way or both. Points have fewer than k edges
+/// if there are no more than k points. Rows with a null x or y have no edges, and are nobody's
+/// neighbor.
+#[pyfunction]
+#[pyo3(name = "knn_graph")]
+fn knn_graph_py(xs: &PyAny, ys: &PyAny, k: usize, py: Python) -> PyResult<PyObject> {
+    let ArrowPoints { points, rows, .. } =
+        points_from_arrow(ArrayData::from_pyarrow(xs)?, ArrayData::from_pyarrow(ys)?)?;
+
+    let edges = py.allow_threads(|| dbscan::knn_graph(&points, k));
+
+    let mut i_builder = UInt32Builder::new();
+    let mut j_builder = UInt32Builder::new();
+    let mut distance_builder = Float64Builder::new();
+    for (i, j, distance) in edges.into_iter() {
+        i_builder.append_value(rows[i] as u32);
+        j_builder.append_value(rows[j] as u32);
+        distance_builder.append_value(distance);
+    }
+
+    let schema = Schema::new(vec![
+        Field::new("i", DataType::UInt32, false),
+        Field::new("j", DataType::UInt32, false),
+        Field::new("distance", DataType::Float64, false),
+    ]);
+    RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(i_builder.finish()),
+            Arc::new(j_builder.finish()),
+            Arc::new(distance_builder.finish()),
+        ],
+    )
+    .map_err(to_py_err)?
+    .to_pyarrow(py)
+}
+
+/// Find the catalog points near each of some query points, for linking observations some other way
+/// than clustering.
+///
+/// # Arguments
+///
+/// * `catalog_xs` - A arrow float64 or float32 array of the catalog's x values.
+/// * `catalog_ys` - A arrow float64 or float32 array of the catalog's y values.
+/// * `query_xs` - A arrow float64 or float32 array of the query points' x values.
+/// * `query_ys` - A arrow float64 or float32 array of the query points' y values.
+/// * `eps` - The euclidean distance within which a catalog point is a neighbor.
+///
+/// # Returns
+///
+/// An arrow list<uint32> array with a list for each query row, holding the row numbers of the
+/// catalog points within eps of it, in increasing order. A query point with no neighbors has an
+/// empty list, and a query row with a null x or y has a null one. Catalog rows with a null x or y
+/// are nobody's neighbor. A catalog point exactly eps away is a neighbor.
+#[pyfunction]
+#[pyo3(name = "radius_query")]
+fn radius_query_py(
+    catalog_xs: &PyAny,
+    catalog_ys: &PyAny,
+    query_xs: &PyAny,
+    query_ys: &PyAny,
+    eps: f64,
+    py: Python,
+) -> PyResult<PyObject> {
+    let catalog = points_from_arrow(
+        ArrayData::from_pyarrow(catalog_xs)?,
+        ArrayData::from_pyarrow(catalog_ys)?,
+    )?;
+    let queries = points_from_arrow(
+        ArrayData::from_pyarrow(query_xs)?,
+        ArrayData::from_pyarrow(query_ys)?,
+    )?;
+
+    let neighbors =
+        py.allow_threads(|| dbscan::radius_query(&catalog.points, &queries.points, eps));
+
+    let mut builder = ListBuilder::new(UInt32Builder::new());
+    let mut found = queries.rows.iter().zip(neighbors.iter()).peekable();
+    for row in 0..queries.len {
+        match found.next_if(|(&query_row, _)| query_row == row) {
+            Some((_, neighbors)) => {
+                for &idx in neighbors.iter() {
+                    builder.values().append_value(catalog.rows[idx] as u32);
+                }
+                builder.append(true);
+            }
+            None => builder.append(false),
+        }
+    }
+    builder.finish().to_data().to_pyarrow(py)
+}
+
+/// Find clusters of x-y points with Hotspot2D, and describe the grid cell each one came from, for
+/// quick-look density maps.
+///
+/// # Arguments
+///
+/// * `xs` - A arrow float64 or float32 array of x values.
+/// * `ys` - A arrow float64 or float32 array of y values.
+/// * `eps` - The size of the grid cells.
+/// * `min_cluster_size` - The fewest points a cell needs to be a cluster.
+/// * `offsets` - How many shifted grids to bin the points into along each axis, to catch clusters
+///   straddling cell edges. Defaults to 2, as find_clusters uses.
+///
+/// # Returns
+///
+/// A pair of the cluster label of each row, as an arrow int32 array, labeled as for find_clusters,
+/// and a RecordBatch with a row for each cluster, in order of label, with the following schema:
+///     label: int32
+///     cell_x: int64
+///     cell_y: int64
+///     x: float64
+///     y: float64
+///     count: uint32
+/// cell_x and cell_y are the cell's position in its shifted grid, and x and y are its center.
+/// count is every point in the cell, some of which may be labeled with another cell's cluster.
+/// Rows with a null x or y are labeled as noise, and aren't counted.
+#[pyfunction]
+#[pyo3(
+    name = "hotspot2d_cells",
+    signature = (xs, ys, eps, min_cluster_size, offsets = 2)
+)]
+fn hotspot2d_cells_py(
+    xs: &PyAny,
+    ys: &PyAny,
+    eps: f64,
+    min_cluster_size: usize,
+    offsets: usize,
+    py: Python,
+) -> PyResult<PyObject> {
+    let points = points_from_arrow(ArrayData::from_pyarrow(xs)?, ArrayData::from_pyarrow(ys)?)?;
+
+    let (labels, cells) = py.allow_threads(|| {
+        hotspot2d::find_clusters_hotspot2d_cells(&points.points, eps, min_cluster_size, offsets)
+    });
 
-    let cluster_members_table = RecordBatch::try_new(
-        Arc::new(cluster_members_table_schema),
+    let mut label_builder = Int32Builder::new();
+    let mut cell_x_builder = Int64Builder::new();
+    let mut cell_y_builder = Int64Builder::new();
+    let mut x_builder = Float64Builder::new();
+    let mut y_builder = Float64Builder::new();
+    let mut count_builder = UInt32Builder::new();
+    for cell in cells.iter() {
+        label_builder.append_value(cell.label);
+        cell_x_builder.append_value(cell.cell_x);
+        cell_y_builder.append_value(cell.cell_y);
+        x_builder.append_value(cell.x);
+        y_builder.append_value(cell.y);
+        count_builder.append_value(cell.count as u32);
+    }
+    let schema = Schema::new(vec![
+        Field::new("label", DataType::Int32, false),
+        Field::new("cell_x", DataType::Int64, false),
+        Field::new("cell_y", DataType::Int64, false),
+        Field::new("x", DataType::Float64, false),
+        Field::new("y", DataType::Float64, false),
+        Field::new("count", DataType::UInt32, false),
+    ]);
+    let cells = RecordBatch::try_new(
+        Arc::new(schema),
         vec![
-            Arc::new(cluster_id_members_builder.finish()),
-            Arc::new(obs_id_members_builder.finish()),
+            Arc::new(label_builder.finish()),
+            Arc::new(cell_x_builder.finish()),
+            Arc::new(cell_y_builder.finish()),
+            Arc::new(x_builder.finish()),
+            Arc::new(y_builder.finish()),
+            Arc::new(count_builder.finish()),
         ],
     )
-    .map_err(to_py_err)?;
+    .map_err(to_py_err)?
+    .to_pyarrow(py)?;
 
-    // Convert to Python objects for output
-    let cluster_table = cluster_table.to_pyarrow(py)?;
-    let cluster_members_table = cluster_members_table.to_pyarrow(py)?;
+    let labels = Int32Array::from(points.row_labels(&labels))
+        .to_data()
+        .to_pyarrow(py)?;
+    Ok(PyTuple::new(py, vec![labels, cells]).into())
+}
 
-    // Combine into a tuple.
-    Ok(PyTuple::new(py, vec![cluster_table, cluster_members_table]).into())
+/// Score a clustering of x-y points by its mean silhouette coefficient.
+///
+/// # Arguments
+///
+/// * `xs` - A arrow float64 or float32 array of x values.
+/// * `ys` - A arrow float64 or float32 array of y values.
+/// * `labels` - An arrow int32 array of cluster labels, as returned by find_clusters. Noise
+///   (-1) and null labels are left out of the score.
+///
+/// # Returns
+///
+/// The mean silhouette coefficient of the clustered points, between -1 and 1; higher is better.
+/// A single cluster scores 0, and if every point is noise the score is NaN. Rows with a null x or
+/// y are treated as noise.
+#[pyfunction]
+#[pyo3(name = "silhouette_score")]
+fn silhouette_score_py(xs: &PyAny, ys: &PyAny, labels: &PyAny, py: Python) -> PyResult<f64> {
+    let ArrowPoints { points, rows, len } =
+        points_from_arrow(ArrayData::from_pyarrow(xs)?, ArrayData::from_pyarrow(ys)?)?;
+    let labels = make_array(ArrayData::from_pyarrow(labels)?);
+    let labels = labels
+        .as_any()
+        .downcast_ref::<Int32Array>()
+        .ok_or_else(|| PyTypeError::new_err("labels: expected an int32 array"))?;
+    if labels.len() != len {
+        return Err(PyArrowException::new_err(
+            "x, y, and labels arrays must be the same length",
+        ));
+    }
+
+    let labels = rows
+        .iter()
+        .map(|&i| {
+            if labels.is_valid(i) {
+                labels.value(i)
+            } else {
+                -1
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(py.allow_threads(|| dbscan::silhouette_score(&points, &labels)))
 }
 
-/// Find clusters of related x-y points.
+/// Compare two clusterings of the same points by their adjusted Rand index.
 ///
 /// # Arguments
 ///
-/// * `xs` - A arrow float64 array of x values.
-/// * `ys` - A arrow float64 array of y values.
-/// * `eps` - The maximum distance between two points for them to be considered as in the same cluster.
-/// * `min_cluster_size` - The minimum number of points in a cluster.
+/// * `labels_a` - An arrow int32 array of cluster labels, as returned by find_clusters.
+/// * `labels_b` - Another arrow int32 array of labels for the same points.
+/// * `exclude_noise` - If true, leave out points which are noise (-1) in either labeling.
+///   Otherwise, noise counts as a cluster of its own. Defaults to false.
 ///
 /// # Returns
 ///
-/// A list of lists of indices into the input arrays, as an arrow list of uint32 arrays.
+/// 1.0 for identical clusterings, however their clusters are numbered, and around 0 for
+/// unrelated ones. Null labels are treated as noise.
 #[pyfunction]
-#[pyo3(name = "find_clusters")]
-fn find_clusters_py(
-    xs: &PyAny,
-    ys: &PyAny,
-    eps: &PyFloat,
-    min_cluster_size: &PyInt,
-    alg: Py<ClusterAlgorithm>,
-    py: Python,
-) -> PyResult<PyObject> {
-    // Handle the Python-to-rust conversion up front
-    let xs = as_float_array(xs, "xs")?;
-    let ys = as_float_array(ys, "ys")?;
+#[pyo3(
+    name = "adjusted_rand_index",
+    signature = (labels_a, labels_b, exclude_noise = false)
+)]
+fn adjusted_rand_index_py(
+    labels_a: &PyAny,
+    labels_b: &PyAny,
+    exclude_noise: bool,
+) -> PyResult<f64> {
+    let labels = |val: &PyAny, param_name: &str| -> PyResult<Vec<i32>> {
+        let array = make_array(ArrayData::from_pyarrow(val)?);
+        let array = array.as_any().downcast_ref::<Int32Array>().ok_or_else(|| {
+            PyTypeError::new_err(format!("{}: expected an int32 array", param_name))
+        })?;
+        Ok(array.iter().map(|label| label.unwrap_or(-1)).collect())
+    };
+    let labels_a = labels(labels_a, "labels_a")?;
+    let labels_b = labels(labels_b, "labels_b")?;
+    if labels_a.len() != labels_b.len() {
+        return Err(PyArrowException::new_err(
+            "labels_a and labels_b arrays must be the same length",
+        ));
+    }
+    Ok(dbscan::adjusted_rand_index(
+        &labels_a,
+        &labels_b,
+        exclude_noise,
+    ))
+}
 
-    if xs.len() != ys.len() {
+/// Compute the arc length of each cluster from a set of labels.
+///
+/// # Arguments
+///
+/// * `labels` - An arrow int32 array of cluster labels, as returned by find_clusters.
+/// * `dts` - A arrow float64 or float32 array of the time of each point.
+///
+/// # Returns
+///
+/// An arrow struct array with a cluster_id (int32) and arc_length (float64) for each cluster,
+/// in order of cluster_id. The arc length is the difference between the cluster's latest and
+/// earliest dt. Noise, and rows with a null label or dt, are left out.
+#[pyfunction]
+#[pyo3(name = "arc_lengths")]
+fn arc_lengths_py(labels: &PyAny, dts: &PyAny, py: Python) -> PyResult<PyObject> {
+    let labels = make_array(ArrayData::from_pyarrow(labels)?);
+    let labels = labels
+        .as_any()
+        .downcast_ref::<Int32Array>()
+        .ok_or_else(|| PyTypeError::new_err("labels: expected an int32 array"))?;
+    let dts = as_float_array(dts, "dts")?;
+    if labels.len() != dts.len() {
         return Err(PyArrowException::new_err(
-            "x and y arrays must be the same length",
+            "labels and dts arrays must be the same length",
         ));
     }
 
-    let eps = eps.extract::<f64>()?;
-    let min_cluster_size = min_cluster_size.extract::<u8>()? as usize;
-    let alg = alg.extract::<ClusterAlgorithm>(py)?;
+    let (labels, dts): (Vec<i32>, Vec<f64>) = labels
+        .iter()
+        .zip(dts.iter())
+        .filter_map(|(label, dt)| Some((label?, dt?)))
+        .unzip();
+    let arcs = gridsearch::arc_lengths(&labels, &dts);
+
+    let cluster_ids = Int32Array::from(arcs.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+    let arc_lengths = Float64Array::from(arcs.iter().map(|(_, arc)| *arc).collect::<Vec<_>>());
+    let arcs = StructArray::from(vec![
+        (
+            Arc::new(Field::new("cluster_id", DataType::Int32, false)),
+            Arc::new(cluster_ids) as ArrayRef,
+        ),
+        (
+            Arc::new(Field::new("arc_length", DataType::Float64, false)),
+            Arc::new(arc_lengths) as ArrayRef,
+        ),
+    ]);
+    arcs.to_data().to_pyarrow(py)
+}
 
-    // Turn xs and ys into Vec<XYPoint> for easier processing.
-    let points = xs
+/// Group observations into connected components, linking two observations
+/// whenever they were members of the same cluster. This merges the clusters
+/// found for one object at several nearby velocities.
+///
+/// # Arguments
+///
+/// * `cluster_ids` - An arrow uint32 array of cluster IDs, like the cluster_id column of the
+///   membership table returned by grid_search.
+/// * `obs_ids` - An arrow string array, or dictionary of strings, of observation IDs, like the
+///   obs_id column of the same table.
+///
+/// # Returns
+///
+/// An arrow struct array with a component_id (uint32) and obs_id (string) for each distinct
+/// observation. Components are numbered from 1, in order of their first observation. Rows with a
+/// null cluster ID or obs ID are left out.
+#[pyfunction]
+#[pyo3(name = "connected_components")]
+fn connected_components_py(cluster_ids: &PyAny, obs_ids: &PyAny, py: Python) -> PyResult<PyObject> {
+    let cluster_ids = make_array(ArrayData::from_pyarrow(cluster_ids)?);
+    let cluster_ids = cluster_ids
+        .as_any()
+        .downcast_ref::<UInt32Array>()
+        .ok_or_else(|| PyTypeError::new_err("cluster_ids: expected a uint32 array"))?;
+    let obs_ids = make_array(ArrayData::from_pyarrow(obs_ids)?);
+    let obs_ids = string_values(&obs_ids).ok_or_else(|| {
+        PyTypeError::new_err(format!(
+            "obs_ids: unexpected array type, expected string but got {:?}",
+            obs_ids.data_type()
+        ))
+    })?;
+    if cluster_ids.len() != obs_ids.len() {
+        return Err(PyArrowException::new_err(
+            "cluster_ids and obs_ids arrays must be the same length",
+        ));
+    }
+
+    let members = cluster_ids
         .iter()
-        .zip(ys.iter())
-        .map(|(x, y)| XYPoint {
-            x: x.unwrap_or(0.0),
-            y: y.unwrap_or(0.0),
+        .zip(obs_ids)
+        .filter_map(|(cluster_id, obs_id)| {
+            Some(gridsearch::ClusterMember {
+                cluster_id: cluster_id?,
+                obs_id: obs_id?.to_string(),
+            })
         })
         .collect::<Vec<_>>();
+    let components = py.allow_threads(|| gridsearch::connected_components(&members));
+
+    let mut component_id_builder = UInt32Builder::new();
+    let mut obs_id_builder = StringBuilder::new();
+    for (i, component) in components.iter().enumerate() {
+        for obs_id in component.iter() {
+            component_id_builder.append_value(i as u32 + 1);
+            obs_id_builder.append_value(obs_id);
+        }
+    }
+    let components = StructArray::from(vec![
+        (
+            Arc::new(Field::new("component_id", DataType::UInt32, false)),
+            Arc::new(component_id_builder.finish()) as ArrayRef,
+        ),
+        (
+            Arc::new(Field::new("obs_id", DataType::Utf8, false)),
+            Arc::new(obs_id_builder.finish()) as ArrayRef,
+        ),
+    ]);
+    components.to_data().to_pyarrow(py)
+}
 
-    let cluster_labels = find_clusters(&points, eps, min_cluster_size, &alg);
+/// The values of a string array, or of a dictionary of strings with int32 or
+/// int64 keys. Returns None for any other type of array.
+fn string_values(array: &ArrayRef) -> Option<Vec<Option<&str>>> {
+    fn decode<K: ArrowDictionaryKeyType>(array: &ArrayRef) -> Option<Vec<Option<&str>>> {
+        let dictionary = array.as_any().downcast_ref::<DictionaryArray<K>>()?;
+        let values = string_values(dictionary.values())?;
+        Some(
+            (0..dictionary.len())
+                .map(|i| dictionary.key(i).and_then(|k| values[k]))
+                .collect(),
+        )
+    }
+    match array.data_type() {
+        DataType::Utf8 => array
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .map(|array| array.iter().collect()),
+        DataType::LargeUtf8 => array
+            .as_any()
+            .downcast_ref::<LargeStringArray>()
+            .map(|array| array.iter().collect()),
+        DataType::Dictionary(key, _) if key.as_ref() == &DataType::Int32 => {
+            decode::<Int32Type>(array)
+        }
+        DataType::Dictionary(key, _) if key.as_ref() == &DataType::Int64 => {
+            decode::<Int64Type>(array)
+        }
+        _ => None,
+    }
+}
 
-    // Convert the clusters into an arrow list of int32
-    let mut builder = Int32Builder::new();
-    builder.append_slice(&cluster_labels[..]);
-    let la = builder.finish();
-    la.to_data().to_pyarrow(py)
+/// A search to run with whichever SearchTree a DBSCAN algorithm uses; see
+/// with_dbscan_tree.
+trait DbscanSearch {
+    type Output;
+    fn run<T: SearchTree>(self) -> Self::Output;
 }
 
-pub fn find_clusters(
+/// Run search with the tree that alg uses to find neighbors under metric.
+/// Only the DBSCAN variants search a tree; for any other algorithm, this
+/// returns None.
+fn with_dbscan_tree<S: DbscanSearch>(
+    alg: &ClusterAlgorithm,
+    metric: &DistanceMetric,
+    search: S,
+) -> Option<S::Output> {
+    let output = match alg {
+        ClusterAlgorithm::DBSCAN
+        | ClusterAlgorithm::DbscanRStar
+        | ClusterAlgorithm::DbscanFixed16
+        | ClusterAlgorithm::DbscanBruteForce
+            if *metric == DistanceMetric::Haversine =>
+        {
+            search.run::<SphereTree>()
+        }
+        ClusterAlgorithm::DBSCAN => search.run::<float32_kdtree::PointTree>(),
+        ClusterAlgorithm::DbscanRStar => search.run::<rstar::Tree>(),
+        ClusterAlgorithm::DbscanFixed16 => search.run::<fixed16_kdtree::FixedPointTree>(),
+        ClusterAlgorithm::DbscanBruteForce => search.run::<brute_force::BruteForceTree>(),
+        _ => return None,
+    };
+    Some(output)
+}
+
+/// Like find_clusters, but with any combination of weights, sigmas, a
+/// period, and a cap on neighbors; see dbscan::find_clusters_with. Only the
+/// DBSCAN variants support these; for any other algorithm, this returns
+/// None.
+pub fn find_clusters_with(
     points: &Vec<XYPoint<f64>>,
     eps: f64,
     min_cluster_size: usize,
     alg: &ClusterAlgorithm,
-) -> Vec<i32> {
-    match alg {
-        ClusterAlgorithm::Hotspot2D => {
-            hotspot2d::find_clusters_hotspot2d(points, eps, min_cluster_size)
-        }
-        ClusterAlgorithm::DBSCAN => {
-            dbscan::find_clusters::<float32_kdtree::PointTree>(points, eps, min_cluster_size)
+    metric: &DistanceMetric,
+    options: &dbscan::DbscanOptions,
+) -> Option<Vec<i32>> {
+    struct Search<'a> {
+        points: &'a Vec<XYPoint<f64>>,
+        eps: f64,
+        min_cluster_size: usize,
+        metric: &'a DistanceMetric,
+        options: &'a dbscan::DbscanOptions<'a>,
+    }
+    impl DbscanSearch for Search<'_> {
+        type Output = Vec<i32>;
+        fn run<T: SearchTree>(self) -> Vec<i32> {
+            dbscan::find_clusters_with::<T>(
+                self.points,
+                self.eps,
+                self.min_cluster_size,
+                self.metric,
+                self.options,
+            )
         }
-        ClusterAlgorithm::DbscanRStar => {
-            dbscan::find_clusters::<rstar::Tree>(points, eps, min_cluster_size)
+    }
+    let search = Search {
+        points,
+        eps,
+        min_cluster_size,
+        metric,
+        options,
+    };
+    with_dbscan_tree(alg, metric, search)
+}
+
+/// Like find_clusters, but also returns the role each point plays in its
+/// cluster; see dbscan::find_clusters_classified. Only the DBSCAN variants
+/// classify points; for any other algorithm, this returns None.
+pub fn find_classified_clusters(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    alg: &ClusterAlgorithm,
+    metric: &DistanceMetric,
+) -> Option<Vec<(i32, dbscan::PointRole)>> {
+    struct Search<'a> {
+        points: &'a Vec<XYPoint<f64>>,
+        eps: f64,
+        min_cluster_size: usize,
+        metric: &'a DistanceMetric,
+    }
+    impl DbscanSearch for Search<'_> {
+        type Output = Vec<(i32, dbscan::PointRole)>;
+        fn run<T: SearchTree>(self) -> Self::Output {
+            dbscan::find_clusters_classified::<T>(
+                self.points,
+                self.eps,
+                self.min_cluster_size,
+                self.metric,
+            )
         }
-        ClusterAlgorithm::DbscanFixed16 => {
-            dbscan::find_clusters::<fixed16_kdtree::FixedPointTree>(points, eps, min_cluster_size)
+    }
+    let search = Search {
+        points,
+        eps,
+        min_cluster_size,
+        metric,
+    };
+    with_dbscan_tree(alg, metric, search)
+}
+
+/// Like find_clusters, but reads the points straight from columns of x and
+/// y, rather than from a Vec of points; see dbscan::find_clusters_columns.
+/// Only the DBSCAN variants support this; for any other algorithm, this
+/// returns None.
+pub fn find_clusters_columns(
+    xs: &[f64],
+    ys: &[f64],
+    eps: f64,
+    min_cluster_size: usize,
+    alg: &ClusterAlgorithm,
+    metric: &DistanceMetric,
+) -> Option<Vec<i32>> {
+    struct Search<'a> {
+        xs: &'a [f64],
+        ys: &'a [f64],
+        eps: f64,
+        min_cluster_size: usize,
+        metric: &'a DistanceMetric,
+    }
+    impl DbscanSearch for Search<'_> {
+        type Output = Vec<i32>;
+        fn run<T: SearchTree>(self) -> Vec<i32> {
+            dbscan::find_clusters_columns::<T>(
+                self.xs,
+                self.ys,
+                self.eps,
+                self.min_cluster_size,
+                self.metric,
+            )
         }
     }
+    let search = Search {
+        xs,
+        ys,
+        eps,
+        min_cluster_size,
+        metric,
+    };
+    with_dbscan_tree(alg, metric, search)
+}
+
+/// Find clusters of related x-y points, returning a cluster label for each
+/// point. Noise is labeled -1, and the clusters are numbered from 1 with no
+/// gaps, whichever algorithm finds them.
+///
+/// The metric applies to the DBSCAN variants and OPTICS; Hotspot2D bins points
+/// into a grid and ignores it. With the haversine metric, every DBSCAN variant
+/// searches a tree of points on the unit sphere. DbscanBruteForce checks every
+/// pair of points, which is slow but a useful reference. GridDbscan finds
+/// neighbors in a grid of eps-sized cells rather than a tree; it doesn't
+/// support weights, sigmas, or the other DBSCAN options. OPTICS treats eps as the
+/// largest neighborhood to consider, and separates clusters of differing
+/// density within it. HDBSCAN picks clusters across all densities, using eps
+/// only to cap the neighborhoods it searches.
+pub fn find_clusters(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    alg: &ClusterAlgorithm,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let mut labels =
+        match alg {
+            ClusterAlgorithm::Hotspot2D => {
+                hotspot2d::find_clusters_hotspot2d(points, eps, min_cluster_size, 2)
+            }
+            // Planar trees can't bound great-circle distances.
+            ClusterAlgorithm::Optics if *metric == DistanceMetric::Haversine => {
+                optics::find_clusters::<SphereTree>(points, eps, min_cluster_size, metric)
+            }
+            ClusterAlgorithm::Hdbscan if *metric == DistanceMetric::Haversine => {
+                hdbscan::find_clusters::<SphereTree>(points, eps, min_cluster_size, metric)
+            }
+            _ if *metric == DistanceMetric::Haversine => {
+                dbscan::find_clusters::<SphereTree>(points, eps, min_cluster_size, metric)
+            }
+            ClusterAlgorithm::DBSCAN => dbscan::find_clusters::<float32_kdtree::PointTree>(
+                points,
+                eps,
+                min_cluster_size,
+                metric,
+            ),
+            ClusterAlgorithm::DbscanRStar => {
+                dbscan::find_clusters::<rstar::Tree>(points, eps, min_cluster_size, metric)
+            }
+            ClusterAlgorithm::DbscanFixed16 => dbscan::find_clusters::<
+                fixed16_kdtree::FixedPointTree,
+            >(points, eps, min_cluster_size, metric),
+            ClusterAlgorithm::DbscanBruteForce => dbscan::find_clusters::<
+                brute_force::BruteForceTree,
+            >(
+                points, eps, min_cluster_size, metric
+            ),
+            ClusterAlgorithm::GridDbscan => {
+                grid::find_clusters(points, eps, min_cluster_size, metric)
+            }
+            ClusterAlgorithm::Optics => optics::find_clusters::<float32_kdtree::PointTree>(
+                points,
+                eps,
+                min_cluster_size,
+                metric,
+            ),
+            ClusterAlgorithm::Hdbscan => hdbscan::find_clusters::<float32_kdtree::PointTree>(
+                points,
+                eps,
+                min_cluster_size,
+                metric,
+            ),
+        };
+    dbscan::relabel_contiguous(&mut labels);
+    labels
 }
 
 #[pyfunction]
-#[pyo3(name = "cellsearch")]
+#[pyo3(
+    name = "cellsearch",
+    signature = (ids, xs, ys, dts, vxs, vys, eps, min_cluster_size, metric = DistanceMetric::Euclidean, n_threads = 0, max_dt_span = None, linking = "forward")
+)]
 fn cellsearch_py(
     ids: &PyAny,
     xs: &PyAny,
@@ -385,40 +2620,173 @@ fn cellsearch_py(
     vys: &PyAny,
     eps: &PyFloat,
     min_cluster_size: &PyInt,
+    metric: DistanceMetric,
+    n_threads: usize,
+    max_dt_span: Option<f64>,
+    linking: &str,
     py: Python,
 ) -> PyResult<PyObject> {
-    // Handle the Python-to-rust conversion up front
-    let _ids = as_string_array(ids, "ids")?;
-    let xs = as_float_array(xs, "xs")?;
-    let ys = as_float_array(ys, "ys")?;
-    let dts = as_float_array(dts, "dts")?;
+    let mut cell = PyThorCell::new();
+    cell.add_points(ids, xs, ys, dts)?;
+    cell.find_clusters(
+        vxs,
+        vys,
+        eps.extract::<f64>()?,
+        min_cluster_size.extract::<usize>()?,
+        metric,
+        n_threads,
+        max_dt_span,
+        linking,
+        py,
+    )
+}
 
-    if xs.len() != ys.len() || xs.len() != dts.len() {
-        return Err(PyArrowException::new_err(
-            "x y, and dts arrays must be the same length",
-        ));
+/// The cellsearch::Linking named by cellsearch's linking argument.
+fn cellsearch_linking(linking: &str) -> PyResult<cellsearch::Linking> {
+    match linking {
+        "forward" => Ok(cellsearch::Linking::Forward),
+        "dbscan" => Ok(cellsearch::Linking::Dbscan),
+        _ => Err(PyValueError::new_err(format!(
+            "linking: expected \"forward\" or \"dbscan\", but got {:?}",
+            linking
+        ))),
     }
+}
 
-    let vxs = as_float_array(vxs, "vxs")?;
-    let vys = as_float_array(vys, "vys")?;
+/// A cell of observations for cellsearch, which can be built once and then
+/// searched many times, for example to refine a velocity grid or try
+/// several eps values without rebuilding the trees each time.
+#[pyclass(name = "ThorCell")]
+pub struct PyThorCell {
+    cell: cellsearch::ThorCell,
+    // The obs_id of each point, in the order they were added to the cell.
+    obs_ids: Vec<Option<String>>,
+}
 
-    let eps = eps.extract::<f64>()?;
-    let min_cluster_size = min_cluster_size.extract::<u8>()? as usize;
+// pyo3 0.19 expands #[new] into an impl nested inside a function, which
+// rustc warns about as a non-local definition. The methods are in a module
+// of their own so that the warning can be allowed for them alone.
+#[allow(non_local_definitions)]
+mod thor_cell_methods {
+    use super::*;
+
+    #[pymethods]
+    impl PyThorCell {
+        #[new]
+        pub(super) fn new() -> Self {
+            PyThorCell {
+                cell: cellsearch::ThorCell::new(),
+                obs_ids: Vec::new(),
+            }
+        }
+
+        /// Add observations to the cell. Rows with a null x, y, or dt are
+        /// skipped.
+        pub(super) fn add_points(
+            &mut self,
+            ids: &PyAny,
+            xs: &PyAny,
+            ys: &PyAny,
+            dts: &PyAny,
+        ) -> PyResult<()> {
+            // Handle the Python-to-rust conversion up front
+            let ids = as_string_array(ids, "ids")?;
+            let points = xyt_points_from_arrow(
+                ArrayData::from_pyarrow(xs)?,
+                ArrayData::from_pyarrow(ys)?,
+                ArrayData::from_pyarrow(dts)?,
+            )?;
+            check_ids_len(&ids, points.len)?;
+
+            for (point, &i) in points.points.iter().zip(points.rows.iter()) {
+                let x = point.x as f32;
+                let y = point.y as f32;
+                let dt = point.t as f32;
+                self.cell
+                    .add_point(dt, XYPoint { x, y })
+                    .map_err(|err| PyValueError::new_err(format!("dts: {}", err)))?;
+                self.obs_ids
+                    .push(ids.is_valid(i).then(|| ids.value(i).to_string()));
+            }
+            Ok(())
+        }
+
+        /// Search the cell at every pair in the product of vxs and vys. Returns
+        /// the same table as cellsearch. eps is measured in metric, which may be
+        /// Euclidean or Manhattan. linking is "forward", which only links each
+        /// point to nearby points at the same or later dts, or "dbscan", which
+        /// finds the same clusters as grid_search with DBSCAN.
+        #[pyo3(signature = (vxs, vys, eps, min_cluster_size, metric = DistanceMetric::Euclidean, n_threads = 0, max_dt_span = None, linking = "forward"))]
+        pub(super) fn find_clusters(
+            &self,
+            vxs: &PyAny,
+            vys: &PyAny,
+            eps: f64,
+            min_cluster_size: usize,
+            metric: DistanceMetric,
+            n_threads: usize,
+            max_dt_span: Option<f64>,
+            linking: &str,
+            py: Python,
+        ) -> PyResult<PyObject> {
+            let linking = cellsearch_linking(linking)?;
+            let vxs = as_float_array(vxs, "vxs")?;
+            let vys = as_float_array(vys, "vys")?;
+            check_velocities(&vxs, "vxs")?;
+            check_velocities(&vys, "vys")?;
+
+            let vxs = vxs.iter().flatten().map(|vx| vx as f32).collect::<Vec<_>>();
+            let vys = vys.iter().flatten().map(|vy| vy as f32).collect::<Vec<_>>();
 
-    // Turn xs ys, and dts into Vec<XYPoint> for easier processing.
-    let mut cell = cellsearch::ThorCell::new();
+            // The search is pure Rust, so let other Python threads run meanwhile.
+            let results = py
+                .allow_threads(|| {
+                    self.cell.grid_search(
+                        eps as f32,
+                        min_cluster_size,
+                        &vxs,
+                        &vys,
+                        max_dt_span.unwrap_or(f64::INFINITY) as f32,
+                        &metric,
+                        linking,
+                        n_threads,
+                    )
+                })
+                .map_err(|err| PyValueError::new_err(err.to_string()))?;
+            cellsearch_table(results, min_cluster_size, &self.obs_ids, py)
+        }
+
+        fn __len__(&self) -> usize {
+            self.obs_ids.len()
+        }
 
-    for (i, x) in xs.into_iter().enumerate() {
-        let x = x.unwrap_or(0.0) as f32;
-        let y = ys.value(i) as f32;
-        let dt = dts.value(i) as f32;
-        cell.add_point(dt, XYPoint { x, y });
+        /// Describe how the cell's points are laid out, as a dict with keys
+        /// n_subtrees (one per distinct dt), n_points, and points_per_subtree
+        /// (a list of counts, in order of dt).
+        fn stats(&self, py: Python) -> PyResult<PyObject> {
+            let stats = self.cell.stats();
+            let dict = PyDict::new(py);
+            dict.set_item("n_subtrees", stats.n_subtrees)?;
+            dict.set_item("n_points", stats.n_points)?;
+            dict.set_item("points_per_subtree", stats.points_per_subtree)?;
+            Ok(dict.into())
+        }
     }
+}
 
+/// Build the cellsearch output table. obs_ids holds the obs_id of each
+/// point in the cell, indexed by the order the points were added.
+fn cellsearch_table(
+    results: Vec<cellsearch::VelocityClusters>,
+    min_cluster_size: usize,
+    obs_ids: &[Option<String>],
+    py: Python,
+) -> PyResult<PyObject> {
     let points_fields = Fields::from(vec![
         Field::new("x", DataType::Float32, false),
         Field::new("y", DataType::Float32, false),
         Field::new("dt", DataType::Float32, false),
+        Field::new("obs_id", DataType::Utf8, true),
     ]);
 
     let clusters_fields = Fields::from(vec![
@@ -437,46 +2805,48 @@ fn cellsearch_py(
             Box::new(Float32Builder::new()) as Box<dyn ArrayBuilder>,
             Box::new(Float32Builder::new()) as Box<dyn ArrayBuilder>,
             Box::new(Float32Builder::new()) as Box<dyn ArrayBuilder>,
+            Box::new(StringBuilder::new()) as Box<dyn ArrayBuilder>,
         ],
     );
     let mut vx_builder = Float32Builder::new();
     let mut vy_builder = Float32Builder::new();
     let mut cluster_list_builder = ListBuilder::new(points_builder);
 
-    for (_i, vx) in vxs.into_iter().enumerate() {
-        for (_j, vy) in vys.into_iter().enumerate() {
-            let vx = vx.unwrap_or(0.0) as f32;
-            let vy = vy.unwrap_or(0.0) as f32;
-            //	    debug!("cellsearch vx={}, vy={}", vx, vy);
-            let clusters_vxvy = cell.find_clusters2(eps as f32, min_cluster_size, vx, vy);
-            //	    debug!("found {} clusters", clusters_vxvy.len());
-            for (_k, cluster) in clusters_vxvy.into_iter().enumerate() {
-                if cluster.len() < min_cluster_size {
-                    continue;
-                }
-                for (_l, point) in cluster.into_iter().enumerate() {
-                    // Jesus, this is a mess.
-                    cluster_list_builder
-                        .values()
-                        .field_builder::<Float32Builder>(0)
-                        .unwrap()
-                        .append_value(point.x);
-                    cluster_list_builder
-                        .values()
-                        .field_builder::<Float32Builder>(1)
-                        .unwrap()
-                        .append_value(point.y);
-                    cluster_list_builder
-                        .values()
-                        .field_builder::<Float32Builder>(2)
-                        .unwrap()
-                        .append_value(point.t);
-                    cluster_list_builder.values().append(true);
-                }
-                vx_builder.append_value(vx);
-                vy_builder.append_value(vy);
-                cluster_list_builder.append(true);
+    for (vx, vy, clusters_vxvy) in results.into_iter() {
+        //	    debug!("found {} clusters", clusters_vxvy.len());
+        for cluster in clusters_vxvy.into_iter() {
+            if cluster.len() < min_cluster_size {
+                continue;
+            }
+            for cluster_point in cluster.into_iter() {
+                let point = cluster_point.point;
+                // Jesus, this is a mess.
+                cluster_list_builder
+                    .values()
+                    .field_builder::<Float32Builder>(0)
+                    .unwrap()
+                    .append_value(point.x);
+                cluster_list_builder
+                    .values()
+                    .field_builder::<Float32Builder>(1)
+                    .unwrap()
+                    .append_value(point.y);
+                cluster_list_builder
+                    .values()
+                    .field_builder::<Float32Builder>(2)
+                    .unwrap()
+                    .append_value(point.t);
+                let obs_id = obs_ids[cluster_point.idx].as_deref();
+                cluster_list_builder
+                    .values()
+                    .field_builder::<StringBuilder>(3)
+                    .unwrap()
+                    .append_option(obs_id);
+                cluster_list_builder.values().append(true);
             }
+            vx_builder.append_value(vx);
+            vy_builder.append_value(vy);
+            cluster_list_builder.append(true);
         }
     }
 
@@ -532,6 +2902,434 @@ fn cellsearch_py(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::gridsearch::{
+        cluster_members_table_schema, cluster_table_schema, obs_id_array, obs_id_column,
+        obs_id_field,
+    };
+
+    #[test]
+    fn test_haversine_near_pole() {
+        // Four points spread around the pole at Dec=89.9. On the sphere they
+        // are all within 0.2 degrees of each other, but in the RA/Dec plane
+        // they're 90 degrees or more apart.
+        let points = vec![
+            XYPoint::new(0.0, 89.9),
+            XYPoint::new(90.0, 89.9),
+            XYPoint::new(180.0, 89.9),
+            XYPoint::new(270.0, 89.9),
+            XYPoint::new(0.0, 80.0),
+        ];
+        let eps = 0.5_f64.to_radians();
+
+        for alg in [ClusterAlgorithm::DBSCAN, ClusterAlgorithm::DbscanRStar] {
+            let labels = find_clusters(&points, eps, 4, &alg, &DistanceMetric::Euclidean);
+            assert_eq!(labels, vec![-1, -1, -1, -1, -1]);
+
+            let labels = find_clusters(&points, eps, 4, &alg, &DistanceMetric::Haversine);
+            assert_eq!(labels, vec![1, 1, 1, 1, -1]);
+        }
+
+        let d = DistanceMetric::Haversine.distance(&points[0], &points[2]);
+        assert!((d - 0.2_f64.to_radians()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_grid_search_and_cellsearch_dbscan_agree_on_clusters() {
+        // At (0.1, 0): something moving at 0.14, which leaves a chain of
+        // points 0.04 apart that only hangs together through its core
+        // points, a tight group of four, and some clutter.
+        let mut points: Vec<XYTPoint<f64>> = (0..9)
+            .map(|i| XYTPoint::new(0.14 * i as f64, 0.0, i as f64))
+            .collect();
+        for (i, dy) in [0.0, 0.01, -0.01, 0.02].iter().enumerate() {
+            let t = i as f64;
+            points.push(XYTPoint::new(5.0 + 0.1 * t, 5.0 + dy, t));
+        }
+        points.push(XYTPoint::new(2.0, -3.0, 1.0));
+        points.push(XYTPoint::new(-1.0, 2.0, 4.0));
+        points.push(XYTPoint::new(5.3, 5.5, 2.0));
+
+        let results = gridsearch::cluster_grid_search(
+            &points,
+            vec![0.1],
+            vec![0.0],
+            &gridsearch::GridSearchConfig::new(0.05, 3).n_threads(1),
+            None,
+            None,
+        );
+        let mut want = dbscan::group_labels(&results[0].cluster_labels);
+        want.sort();
+        assert_eq!(want.len(), 2);
+        assert_eq!(want[0], (0..9).collect::<Vec<usize>>());
+
+        let mut cell = cellsearch::ThorCell::new();
+        for p in points.iter() {
+            cell.add_point(p.t as f32, XYPoint::new(p.x as f32, p.y as f32))
+                .unwrap();
+        }
+        let search = |linking| {
+            let clusters = cell
+                .find_clusters2(
+                    0.05,
+                    3,
+                    0.1,
+                    0.0,
+                    f32::INFINITY,
+                    &DistanceMetric::Euclidean,
+                    linking,
+                )
+                .unwrap();
+            let mut idxs: Vec<Vec<usize>> = clusters
+                .iter()
+                .map(|cluster| {
+                    let mut idxs: Vec<usize> = cluster.iter().map(|p| p.idx).collect();
+                    idxs.sort();
+                    idxs
+                })
+                .collect();
+            idxs.sort();
+            idxs
+        };
+        assert_eq!(search(cellsearch::Linking::Dbscan), want);
+        // Linking forward, the chain's points never see enough neighbors.
+        assert_eq!(search(cellsearch::Linking::Forward), want[1..]);
+    }
+
+    #[test]
+    fn test_grid_search_and_cellsearch_agree_on_velocity() {
+        // An object at x = 1 + 0.3t, y = 2 - 0.2t, with some stationary
+        // clutter.
+        let mut points: Vec<XYTPoint<f64>> = (0..5)
+            .map(|i| {
+                let t = i as f64;
+                XYTPoint::new(1.0 + 0.3 * t, 2.0 - 0.2 * t, t)
+            })
+            .collect();
+        points.push(XYTPoint::new(4.0, 4.0, 0.0));
+        points.push(XYTPoint::new(-3.0, 1.0, 2.0));
+        let vs = gridsearch::velocity_range(-0.3, 0.3, 7);
+
+        let results = gridsearch::cluster_grid_search(
+            &points,
+            vs.clone(),
+            vs.clone(),
+            &gridsearch::GridSearchConfig::new(0.05, 5).n_threads(1),
+            None,
+            None,
+        );
+        let found: Vec<(f64, f64)> = results
+            .iter()
+            .filter(|r| r.cluster_labels.iter().any(|&l| l > 0))
+            .map(|r| (r.vx, r.vy))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert!((found[0].0 - 0.3).abs() < 1e-9);
+        assert!((found[0].1 + 0.2).abs() < 1e-9);
+
+        let mut cell = cellsearch::ThorCell::new();
+        for p in points.iter() {
+            cell.add_point(p.t as f32, XYPoint::new(p.x as f32, p.y as f32))
+                .unwrap();
+        }
+        let vs: Vec<f32> = vs.iter().map(|&v| v as f32).collect();
+        let results = cell
+            .grid_search(
+                0.05,
+                5,
+                &vs,
+                &vs,
+                f32::INFINITY,
+                &DistanceMetric::Euclidean,
+                cellsearch::Linking::Forward,
+                1,
+            )
+            .unwrap();
+        let found: Vec<(f32, f32)> = results
+            .iter()
+            .filter(|(_, _, clusters)| !clusters.is_empty())
+            .map(|(vx, vy, _)| (*vx, *vy))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert!((found[0].0 - 0.3).abs() < 1e-6);
+        assert!((found[0].1 + 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_null_rows_are_dropped() {
+        let ids = StringArray::from(vec!["a", "b", "c", "d", "e"]);
+        let xs = Float64Array::from(vec![Some(0.0), Some(0.1), None, Some(0.0), Some(0.2)]);
+        let ys = Float64Array::from(vec![0.0, 0.0, 0.0, 0.0, 0.0]);
+        let dts = Float64Array::from(vec![Some(0.0), Some(1.0), Some(2.0), None, Some(4.0)]);
+
+        let rows = valid_rows(&[&xs, &ys, &dts]);
+        assert_eq!(rows, vec![0, 1, 4]);
+
+        let points = rows
+            .iter()
+            .map(|&i| XYTPoint::new(xs.value(i), ys.value(i), dts.value(i)))
+            .collect::<Vec<_>>();
+        let ids = rows
+            .iter()
+            .map(|&i| ids.value(i).to_string())
+            .collect::<Vec<_>>();
+
+        let results = gridsearch::cluster_grid_search(
+            &points,
+            vec![0.0],
+            vec![0.0],
+            &gridsearch::GridSearchConfig::new(0.5, 2).n_threads(1),
+            None,
+            None,
+        );
+        let (_, members) = gridsearch::summarize_clusters(&results, &points, &ids, 1.0);
+        let obs_ids: Vec<&str> = members.iter().map(|m| m.obs_id.as_str()).collect();
+        assert_eq!(obs_ids, vec!["a", "b", "e"]);
+    }
+
+    #[test]
+    fn test_points_from_arrow() {
+        let xs = Float64Array::from(vec![Some(0.0), None, Some(2.0), Some(3.0)]);
+        let ys = PrimitiveArray::<Float32Type>::from(vec![1.0, 1.0, 3.0, 4.0]);
+        let mut points = points_from_arrow(xs.to_data(), ys.to_data()).unwrap();
+        assert_eq!(
+            points.points,
+            vec![
+                XYPoint::new(0.0, 1.0),
+                XYPoint::new(2.0, 3.0),
+                XYPoint::new(3.0, 4.0)
+            ]
+        );
+        assert_eq!(points.rows, vec![0, 2, 3]);
+
+        points.drop_nulls(&Float64Array::from(vec![
+            Some(1.0),
+            Some(1.0),
+            None,
+            Some(1.0),
+        ]));
+        assert_eq!(points.rows, vec![0, 3]);
+        assert_eq!(points.row_labels(&[1, 2]), vec![1, -1, -1, 2]);
+
+        let dts = Float64Array::from(vec![0.0, 1.0, f64::NAN, 3.0]);
+        assert!(xyt_points_from_arrow(xs.to_data(), ys.to_data(), dts.to_data()).is_err());
+    }
+
+    #[test]
+    fn test_points_from_arrow_errors() {
+        let xs = Float64Array::from(vec![0.0, 1.0, 2.0]);
+        let short = Float64Array::from(vec![0.0, 1.0]);
+        let ints = Int32Array::from(vec![0, 1, 2]);
+
+        assert!(points_from_arrow(xs.to_data(), short.to_data()).is_err());
+        assert!(xyt_points_from_arrow(xs.to_data(), xs.to_data(), short.to_data()).is_err());
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let err = points_from_arrow(xs.to_data(), ints.to_data())
+                .err()
+                .unwrap();
+            assert!(err.is_instance_of::<PyTypeError>(py));
+            assert!(err.value(py).to_string().starts_with("ys: "));
+
+            let err = xyt_points_from_arrow(xs.to_data(), xs.to_data(), ints.to_data())
+                .err()
+                .unwrap();
+            assert!(err.value(py).to_string().starts_with("dts: "));
+        });
+    }
+
+    #[test]
+    fn test_cluster_algorithm_from_str() {
+        assert!("dbscan".parse::<ClusterAlgorithm>() == Ok(ClusterAlgorithm::DBSCAN));
+        assert!("DBSCAN_RStar".parse::<ClusterAlgorithm>() == Ok(ClusterAlgorithm::DbscanRStar));
+        assert!("hotspot2d".parse::<ClusterAlgorithm>() == Ok(ClusterAlgorithm::Hotspot2D));
+
+        let err = "kmeans".parse::<ClusterAlgorithm>().err().unwrap();
+        assert!(err.contains("\"kmeans\""));
+        assert!(err.contains("dbscan, hotspot2d, dbscan_rstar, dbscan_fixed16"));
+    }
+
+    #[test]
+    fn test_grid_search_options() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let parse = |function, accepted, options: &PyDict| {
+                GridSearchOptions::parse(function, accepted, Some(options))
+            };
+
+            let options = PyDict::new(py);
+            options.set_item("min_nights", 3).unwrap();
+            options.set_item("output", "joined").unwrap();
+            options.set_item("max_speed", 0.5).unwrap();
+            let parsed = parse("grid_search", &GridSearchOptions::ALL, options).unwrap();
+            assert_eq!(parsed.min_nights, 3);
+            assert!(parsed.joined);
+            assert_eq!(parsed.max_speed, Some(0.5));
+            // The rest keep their defaults.
+            assert!(parsed.dictionary_obs_ids);
+            assert_eq!(parsed.progress_interval, 100);
+            let config = parsed.config(0.1, 4, ClusterAlgorithm::DbscanRStar);
+            assert_eq!(config.max_speed, Some(0.5));
+            assert_eq!(config.night_length, 1.0);
+
+            // Variants only take some of the options.
+            let err = parse("grid_search_labels", &["max_speed"], options)
+                .err()
+                .unwrap();
+            assert!(err.is_instance_of::<PyTypeError>(py));
+            assert_eq!(
+                err.value(py).to_string(),
+                "grid_search_labels() got an unexpected keyword argument 'min_nights'"
+            );
+
+            let options = PyDict::new(py);
+            options.set_item("night_length", "long").unwrap();
+            let err = parse("grid_search", &GridSearchOptions::ALL, options)
+                .err()
+                .unwrap();
+            assert!(err.is_instance_of::<PyTypeError>(py));
+            assert!(err
+                .value(py)
+                .to_string()
+                .starts_with("argument 'night_length': "));
+
+            let options = PyDict::new(py);
+            options.set_item("output", "both").unwrap();
+            let err = parse("grid_search", &GridSearchOptions::ALL, options)
+                .err()
+                .unwrap();
+            assert!(err.is_instance_of::<PyValueError>(py));
+        });
+    }
+
+    #[test]
+    fn test_string_values() {
+        let want = vec![Some("a"), None, Some("b"), Some("a")];
+        let strings: ArrayRef = Arc::new(StringArray::from(want.clone()));
+        assert_eq!(string_values(&strings), Some(want.clone()));
+
+        let dict32: DictionaryArray<Int32Type> = want.iter().copied().collect();
+        let dict32: ArrayRef = Arc::new(dict32);
+        assert_eq!(string_values(&dict32), Some(want.clone()));
+
+        let dict64: DictionaryArray<Int64Type> = want.iter().copied().collect();
+        let dict64: ArrayRef = Arc::new(dict64);
+        assert_eq!(string_values(&dict64), Some(want));
+
+        let floats: ArrayRef = Arc::new(Float64Array::from(vec![1.0]));
+        assert_eq!(string_values(&floats), None);
+    }
+
+    #[test]
+    fn test_obs_id_column_plain_strings() {
+        let members: Vec<gridsearch::ClusterMember> = ["b", "a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, obs_id)| gridsearch::ClusterMember {
+                cluster_id: i as u32 / 2,
+                obs_id: obs_id.to_string(),
+            })
+            .collect();
+        let dictionary = obs_id_column(&members, true);
+        let plain = obs_id_column(&members, false);
+        assert_eq!(dictionary.data_type(), obs_id_field(true).data_type());
+        assert_eq!(plain.data_type(), obs_id_field(false).data_type());
+        assert_eq!(plain.data_type(), &DataType::Utf8);
+
+        let want = Some(vec![Some("b"), Some("a"), Some("b"), Some("c")]);
+        assert_eq!(string_values(&dictionary), want);
+        assert_eq!(string_values(&plain), want);
+    }
+
+    #[test]
+    fn test_obs_id_array_many_ids() {
+        // Every member has its own ID, so the dictionary grows with the
+        // table. Each ID appears in two clusters, but is only stored once.
+        let n = 500_000;
+        let members: Vec<gridsearch::ClusterMember> = (0..2 * n)
+            .map(|i| gridsearch::ClusterMember {
+                cluster_id: (i / 4) as u32,
+                obs_id: format!("obs{}", i % n),
+            })
+            .collect();
+        let array = obs_id_array(&members);
+        assert_eq!(array.len(), 2 * n);
+        assert_eq!(array.values().len(), n);
+        assert_eq!(array.key(n + 7), Some(7));
+
+        let values = array
+            .values()
+            .as_any()
+            .downcast_ref::<LargeStringArray>()
+            .unwrap();
+        assert_eq!(values.value(array.key(2 * n - 1).unwrap()), "obs499999");
+    }
+
+    #[test]
+    fn test_grid_search_schemas_match_docs() {
+        // These must agree with the docstring on grid_search_py.
+        let schema = cluster_table_schema();
+        let fields: Vec<(&str, &DataType)> = schema
+            .fields()
+            .iter()
+            .map(|f| (f.name().as_str(), f.data_type()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("cluster_id", &DataType::UInt32),
+                ("vx", &DataType::Float64),
+                ("vy", &DataType::Float64),
+                ("arc_length", &DataType::Float64),
+                ("n_obs", &DataType::UInt32),
+                ("mean_x", &DataType::Float64),
+                ("mean_y", &DataType::Float64),
+                ("n_nights", &DataType::UInt32),
+                ("residual_rms", &DataType::Float64),
+                ("vx_fit", &DataType::Float64),
+                ("vy_fit", &DataType::Float64),
+                ("single_exposure", &DataType::Boolean),
+                ("eps", &DataType::Float64),
+            ]
+        );
+
+        let schema = cluster_members_table_schema(true);
+        let fields: Vec<(&str, &DataType)> = schema
+            .fields()
+            .iter()
+            .map(|f| (f.name().as_str(), f.data_type()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("cluster_id", &DataType::UInt32),
+                (
+                    "obs_id",
+                    &DataType::Dictionary(Box::new(DataType::Int64), Box::new(DataType::LargeUtf8))
+                ),
+            ]
+        );
+
+        let schema = joined_table_schema(true);
+        let fields: Vec<(&str, &DataType)> = schema
+            .fields()
+            .iter()
+            .map(|f| (f.name().as_str(), f.data_type()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("cluster_id", &DataType::UInt32),
+                ("vx", &DataType::Float64),
+                ("vy", &DataType::Float64),
+                ("arc_length", &DataType::Float64),
+                (
+                    "obs_id",
+                    &DataType::Dictionary(Box::new(DataType::Int64), Box::new(DataType::LargeUtf8))
+                ),
+            ]
+        );
+    }
 
     #[test]
     fn test_find_clusters_near_miss() {
@@ -545,11 +3343,41 @@ mod tests {
             XYPoint::new(2.0, 0.0),
             XYPoint::new(2.0, 0.0),
         ];
-        let clusters = find_clusters(&points, 1.0, 4, &ClusterAlgorithm::Hotspot2D);
-        let expect = vec![-1, -1, -1, -1, 0, 0, 0, 0];
+        let clusters = find_clusters(
+            &points,
+            1.0,
+            4,
+            &ClusterAlgorithm::Hotspot2D,
+            &DistanceMetric::Euclidean,
+        );
+        let expect = vec![-1, -1, -1, -1, 1, 1, 1, 1];
         assert_eq!(clusters, expect);
     }
 
+    #[test]
+    fn test_find_clusters_numbers_hotspot2d_without_gaps() {
+        // One clump found by every pass, and one straddling a cell corner
+        // which only the pass shifted along both axes finds. Numbered across
+        // the passes, the second clump's label skips past the first clump's
+        // labels from the passes in between.
+        let mut points = vec![XYPoint::new(0.2, 0.2); 4];
+        points.extend(
+            [(5.4, 5.4), (5.6, 5.6), (5.4, 5.6), (5.6, 5.4)].map(|(x, y)| XYPoint::new(x, y)),
+        );
+        let raw = hotspot2d::find_clusters_hotspot2d(&points, 1.0, 4, 2);
+        assert_eq!(raw[..4], [1, 1, 1, 1]);
+        assert!(raw[4] > 2, "{:?}", raw);
+
+        let clusters = find_clusters(
+            &points,
+            1.0,
+            4,
+            &ClusterAlgorithm::Hotspot2D,
+            &DistanceMetric::Euclidean,
+        );
+        assert_eq!(clusters, vec![1, 1, 1, 1, 2, 2, 2, 2]);
+    }
+
     #[test]
     fn test_find_clusters_two_hits() {
         let points = vec![
@@ -562,11 +3390,98 @@ mod tests {
             XYPoint::new(2.0, 0.0),
             XYPoint::new(2.0, 0.0),
         ];
-        let clusters = find_clusters(&points, 1.0, 4, &ClusterAlgorithm::DBSCAN);
+        let clusters = find_clusters(
+            &points,
+            0.5,
+            4,
+            &ClusterAlgorithm::DBSCAN,
+            &DistanceMetric::Euclidean,
+        );
         let allowed = vec![vec![1, 1, 1, 1, 2, 2, 2, 2], vec![2, 2, 2, 2, 1, 1, 1, 1]];
         assert!(allowed.contains(&clusters));
     }
 
+    #[test]
+    fn test_find_classified_clusters_two_hits() {
+        // The two hits, with a short tail off the first. The tail's first
+        // point is dense enough to be a core point, but its last isn't.
+        let mut points = vec![XYPoint::new(1.0, 0.0); 4];
+        points.extend(vec![XYPoint::new(2.0, 0.0); 4]);
+        points.push(XYPoint::new(1.0, 0.4));
+        points.push(XYPoint::new(1.0, 0.8));
+        for alg in [ClusterAlgorithm::DBSCAN, ClusterAlgorithm::DbscanBruteForce] {
+            let classified =
+                find_classified_clusters(&points, 0.5, 4, &alg, &DistanceMetric::Euclidean)
+                    .unwrap();
+            let (labels, roles): (Vec<i32>, Vec<dbscan::PointRole>) =
+                classified.into_iter().unzip();
+            assert_eq!(labels, vec![1, 1, 1, 1, 2, 2, 2, 2, 1, 1]);
+            let mut want = vec![dbscan::PointRole::Core; 9];
+            want.push(dbscan::PointRole::Border);
+            assert_eq!(roles, want);
+        }
+
+        let noise = find_classified_clusters(
+            &vec![XYPoint::new(0.0, 0.0)],
+            0.5,
+            2,
+            &ClusterAlgorithm::DBSCAN,
+            &DistanceMetric::Euclidean,
+        )
+        .unwrap();
+        assert_eq!(noise, vec![(-1, dbscan::PointRole::Noise)]);
+
+        assert!(find_classified_clusters(
+            &points,
+            0.5,
+            4,
+            &ClusterAlgorithm::Hotspot2D,
+            &DistanceMetric::Euclidean
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_find_clusters_anisotropic() {
+        // Points along a line, each 0.12 from the next.
+        let line = [0.0, 0.12, 0.24, 0.36];
+        let metric = DistanceMetric::Euclidean;
+        for (rotation, points) in [
+            (0.0, line.map(|x| XYPoint::new(x, 0.0))),
+            (
+                std::f64::consts::FRAC_PI_2,
+                line.map(|y| XYPoint::new(0.0, y)),
+            ),
+        ] {
+            // A circular eps of 0.2 doesn't reach far enough along the line.
+            let labels =
+                find_clusters(&points.to_vec(), 0.2, 4, &ClusterAlgorithm::DBSCAN, &metric);
+            assert_eq!(labels, vec![-1; 4]);
+
+            // An ellipse stretched along the line does.
+            let anisotropy = Anisotropy::new((2.0, 1.0), rotation);
+            let stretched: Vec<XYPoint<f64>> =
+                points.iter().map(|p| anisotropy.transform(p)).collect();
+            let labels = find_clusters(&stretched, 0.2, 4, &ClusterAlgorithm::DBSCAN, &metric);
+            assert_eq!(labels, vec![1; 4]);
+        }
+    }
+
+    #[test]
+    fn test_find_clusters_empty() {
+        let metrics = [
+            DistanceMetric::Euclidean,
+            DistanceMetric::Manhattan,
+            DistanceMetric::Haversine,
+        ];
+        for (_, alg) in ClusterAlgorithm::NAMES.iter() {
+            for metric in metrics.iter() {
+                let labels = find_clusters(&vec![], 1.0, 2, alg, metric);
+                assert_eq!(labels, Vec::<i32>::new());
+            }
+        }
+    }
+
     #[test]
     fn test_find_clusters_bigger_than_min() {
         let points = vec![
@@ -579,7 +3494,13 @@ mod tests {
             XYPoint::new(2.0, 0.0),
             XYPoint::new(2.0, 0.0),
         ];
-        let clusters = find_clusters(&points, 1.0, 2, &ClusterAlgorithm::DBSCAN);
+        let clusters = find_clusters(
+            &points,
+            0.5,
+            2,
+            &ClusterAlgorithm::DBSCAN,
+            &DistanceMetric::Euclidean,
+        );
         let allowed = vec![vec![1, 1, 1, 1, 2, 2, 2, 2], vec![2, 2, 2, 2, 1, 1, 1, 1]];
         assert!(allowed.contains(&clusters));
     }
@@ -590,8 +3511,28 @@ mod tests {
 fn thor_cluster(_py: Python, m: &PyModule) -> PyResult<()> {
     pyo3_log::init();
     m.add_function(wrap_pyfunction!(find_clusters_py, m)?)?;
+    m.add_function(wrap_pyfunction!(find_clusters_grouped_py, m)?)?;
+    m.add_function(wrap_pyfunction!(find_clusters_3d_py, m)?)?;
+    m.add_function(wrap_pyfunction!(find_clusters_classified_py, m)?)?;
+    m.add_function(wrap_pyfunction!(estimate_eps_py, m)?)?;
+    m.add_function(wrap_pyfunction!(knn_graph_py, m)?)?;
+    m.add_function(wrap_pyfunction!(radius_query_py, m)?)?;
+    m.add_function(wrap_pyfunction!(hotspot2d_cells_py, m)?)?;
+    m.add_function(wrap_pyfunction!(silhouette_score_py, m)?)?;
+    m.add_function(wrap_pyfunction!(adjusted_rand_index_py, m)?)?;
+    m.add_function(wrap_pyfunction!(arc_lengths_py, m)?)?;
+    m.add_function(wrap_pyfunction!(connected_components_py, m)?)?;
     m.add_function(wrap_pyfunction!(grid_search_py, m)?)?;
+    m.add_function(wrap_pyfunction!(grid_search_struct_py, m)?)?;
+    m.add_function(wrap_pyfunction!(cluster_single_py, m)?)?;
+    m.add_function(wrap_pyfunction!(grid_search_labels_py, m)?)?;
+    m.add_function(wrap_pyfunction!(grid_search_counts_py, m)?)?;
+    m.add_function(wrap_pyfunction!(grid_search_batch_py, m)?)?;
     m.add_function(wrap_pyfunction!(cellsearch_py, m)?)?;
+    m.add_function(wrap_pyfunction!(velocity_grid_py, m)?)?;
+    m.add_function(wrap_pyfunction!(apply_velocity_py, m)?)?;
     m.add_class::<ClusterAlgorithm>()?;
+    m.add_class::<DistanceMetric>()?;
+    m.add_class::<PyThorCell>()?;
     Ok(())
 }
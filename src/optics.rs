@@ -0,0 +1,290 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use ordered_float::OrderedFloat;
+
+use crate::dbscan::{neighbors_within, SearchTree};
+use crate::points::XYPoint;
+use crate::DistanceMetric;
+
+/// Steepness used to extract clusters from the reachability plot. A drop or
+/// rise of at least 5% between consecutive points marks a cluster boundary.
+const XI: f64 = 0.05;
+
+/// Find clusters with OPTICS, using eps as the largest neighborhood
+/// considered. Unlike DBSCAN, clusters of different densities below eps are
+/// kept apart. Noise is labeled -1 and clusters are labeled from 1.
+pub fn find_clusters<T: SearchTree>(
+    points: &Vec<XYPoint<f64>>,
+    eps: f64,
+    min_cluster_size: usize,
+    metric: &DistanceMetric,
+) -> Vec<i32> {
+    let tree: T = T::from_points(points);
+
+    let (ordering, reachability) = optics(points, &tree, eps, min_cluster_size, metric);
+    let reachability_plot: Vec<f64> = ordering.iter().map(|&i| reachability[i]).collect();
+    let clusters = xi_clusters(&reachability_plot, min_cluster_size, min_cluster_size);
+    label_clusters(&ordering, &clusters)
+}
+
+/// Compute the OPTICS ordering of the points, and the reachability distance
+/// of each point (indexed by point, not by position in the ordering).
+/// Unreachable points have infinite reachability.
+fn optics(
+    points: &[XYPoint<f64>],
+    tree: &impl SearchTree,
+    eps: f64,
+    min_pts: usize,
+    metric: &DistanceMetric,
+) -> (Vec<usize>, Vec<f64>) {
+    let mut reachability = vec![f64::INFINITY; points.len()];
+    let mut processed = vec![false; points.len()];
+    let mut ordering = Vec::with_capacity(points.len());
+
+    for start in 0..points.len() {
+        if processed[start] {
+            continue;
+        }
+        let mut seeds = BinaryHeap::new();
+        seeds.push(Reverse((OrderedFloat(f64::INFINITY), start)));
+
+        while let Some(Reverse((_, idx))) = seeds.pop() {
+            if processed[idx] {
+                // A stale entry; the point was reached more cheaply already.
+                continue;
+            }
+            processed[idx] = true;
+            ordering.push(idx);
+
+            let point = &points[idx];
+            let mut neighbors: Vec<(f64, usize)> =
+                neighbors_within(points, tree, point, eps, metric)
+                    .into_iter()
+                    .map(|n| (metric.distance(point, &points[n]), n))
+                    .collect();
+            if neighbors.is_empty() || neighbors.len() < min_pts {
+                // Not a core point, so it can't reach anything.
+                continue;
+            }
+            neighbors.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let core_distance = neighbors[min_pts.max(1) - 1].0;
+
+            for (distance, neighbor) in neighbors {
+                if processed[neighbor] {
+                    continue;
+                }
+                let reach = core_distance.max(distance);
+                if reach < reachability[neighbor] {
+                    reachability[neighbor] = reach;
+                    seeds.push(Reverse((OrderedFloat(reach), neighbor)));
+                }
+            }
+        }
+    }
+    (ordering, reachability)
+}
+
+/// A steep down area in the reachability plot.
+struct SteepDownArea {
+    start: usize,
+    end: usize,
+    // Maximum reachability seen between the end of this area and the
+    // current position.
+    mib: f64,
+}
+
+/// Extract clusters from a reachability plot using the xi method of Ankerst
+/// et al. Returns (start, end) ranges into the plot, inclusive, with nested
+/// clusters listed before the clusters that contain them.
+fn xi_clusters(
+    reachability_plot: &[f64],
+    min_samples: usize,
+    min_cluster_size: usize,
+) -> Vec<(usize, usize)> {
+    let n = reachability_plot.len();
+    if n < 2 {
+        return Vec::new();
+    }
+    // A trailing infinity closes off the last cluster.
+    let mut r = reachability_plot.to_vec();
+    r.push(f64::INFINITY);
+    let xi_complement = 1.0 - XI;
+
+    // Ratios involving two infinities are NaN, and every comparison
+    // against NaN is false, so such pairs are neither steep nor sloped.
+    let ratio: Vec<f64> = (0..n).map(|i| r[i] / r[i + 1]).collect();
+    let steep_up: Vec<bool> = ratio.iter().map(|&x| x <= xi_complement).collect();
+    let steep_down: Vec<bool> = ratio.iter().map(|&x| x >= 1.0 / xi_complement).collect();
+    let down: Vec<bool> = ratio.iter().map(|&x| x > 1.0).collect();
+    let up: Vec<bool> = ratio.iter().map(|&x| x < 1.0).collect();
+
+    let mut sdas: Vec<SteepDownArea> = Vec::new();
+    let mut clusters = Vec::new();
+    let mut index = 0;
+    let mut mib: f64 = 0.0;
+
+    for steep_index in 0..n {
+        if !(steep_up[steep_index] || steep_down[steep_index]) || steep_index < index {
+            continue;
+        }
+        mib = r[index..=steep_index]
+            .iter()
+            .fold(mib, |acc, &x| acc.max(x));
+        update_steep_down_areas(&mut sdas, mib, xi_complement, &r);
+
+        if steep_down[steep_index] {
+            let end = extend_region(&steep_down, &up, steep_index, min_samples);
+            sdas.push(SteepDownArea {
+                start: steep_index,
+                end,
+                mib: 0.0,
+            });
+            index = end + 1;
+            mib = r[index];
+            continue;
+        }
+
+        let up_start = steep_index;
+        let up_end = extend_region(&steep_up, &down, up_start, min_samples);
+        index = up_end + 1;
+        mib = r[index];
+
+        let mut up_clusters = Vec::new();
+        for sda in sdas.iter() {
+            let mut c_start = sda.start;
+            let mut c_end = up_end;
+
+            if r[c_end + 1] * xi_complement < sda.mib {
+                continue;
+            }
+
+            // Trim whichever side of the cluster sits well above the other.
+            let d_max = r[sda.start];
+            if d_max * xi_complement >= r[c_end + 1] {
+                while r[c_start + 1] > r[c_end + 1] && c_start < sda.end {
+                    c_start += 1;
+                }
+            } else if r[c_end + 1] * xi_complement >= d_max {
+                while c_end > up_start && r[c_end - 1] > d_max {
+                    c_end -= 1;
+                }
+            }
+
+            if c_end - c_start + 1 < min_cluster_size {
+                continue;
+            }
+            if c_start > sda.end || c_end < up_start {
+                continue;
+            }
+            up_clusters.push((c_start, c_end));
+        }
+        // Smaller clusters first.
+        up_clusters.reverse();
+        clusters.extend(up_clusters);
+    }
+    clusters
+}
+
+/// Drop steep down areas that are too low to start a cluster ending after
+/// mib, and raise the mib of the rest.
+fn update_steep_down_areas(sdas: &mut Vec<SteepDownArea>, mib: f64, xi_complement: f64, r: &[f64]) {
+    if mib.is_infinite() {
+        sdas.clear();
+        return;
+    }
+    sdas.retain(|sda| mib <= r[sda.start] * xi_complement);
+    for sda in sdas.iter_mut() {
+        sda.mib = sda.mib.max(mib);
+    }
+}
+
+/// Extend a steep region starting at start as far as possible. It may include
+/// up to min_samples consecutive points which aren't steep, but it stops at
+/// the first point heading in the reverse direction. Returns the index of the
+/// last steep point.
+fn extend_region(steep: &[bool], reverse: &[bool], start: usize, min_samples: usize) -> usize {
+    let mut non_steep_points = 0;
+    let mut end = start;
+    for index in start..steep.len() {
+        if steep[index] {
+            non_steep_points = 0;
+            end = index;
+        } else if !reverse[index] {
+            non_steep_points += 1;
+            if non_steep_points > min_samples {
+                break;
+            }
+        } else {
+            break;
+        }
+    }
+    end
+}
+
+/// Label each point with the innermost cluster that contains it. Ranges refer
+/// to positions in the ordering.
+fn label_clusters(ordering: &[usize], clusters: &[(usize, usize)]) -> Vec<i32> {
+    let mut ordered_labels = vec![-1; ordering.len()];
+    let mut label = 0;
+    for &(start, end) in clusters.iter() {
+        if ordered_labels[start..=end].iter().all(|&l| l == -1) {
+            label += 1;
+            ordered_labels[start..=end].fill(label);
+        }
+    }
+
+    let mut labels = vec![-1; ordering.len()];
+    for (position, &idx) in ordering.iter().enumerate() {
+        labels[idx] = ordered_labels[position];
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbscan;
+    use crate::dbscan::float32_kdtree::PointTree;
+
+    fn near_miss() -> Vec<XYPoint<f64>> {
+        vec![
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(1.0, 0.0),
+            XYPoint::new(1.0, 1.0),
+            XYPoint::new(2.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+            XYPoint::new(2.0, 0.0),
+        ]
+    }
+
+    #[test]
+    fn test_optics_separates_densities() {
+        let points = near_miss();
+        let metric = DistanceMetric::Euclidean;
+
+        // At this eps, DBSCAN lumps everything together.
+        let labels = dbscan::find_clusters::<PointTree>(&points, 1.5, 3, &metric);
+        assert!(labels.iter().all(|&l| l == labels[0] && l != -1));
+
+        // OPTICS keeps the two dense groups apart, and leaves the sparse
+        // point between them as noise.
+        let labels = find_clusters::<PointTree>(&points, 1.5, 3, &metric);
+        assert_eq!(labels, vec![1, 1, 1, -1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_optics_all_noise() {
+        let points = vec![XYPoint::new(0.0, 0.0), XYPoint::new(5.0, 5.0)];
+        let labels = find_clusters::<PointTree>(&points, 1.0, 2, &DistanceMetric::Euclidean);
+        assert_eq!(labels, vec![-1, -1]);
+    }
+
+    #[test]
+    fn test_optics_empty() {
+        let labels = find_clusters::<PointTree>(&vec![], 1.0, 2, &DistanceMetric::Euclidean);
+        assert_eq!(labels, Vec::<i32>::new());
+    }
+}
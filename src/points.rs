@@ -1,5 +1,8 @@
+use std::ops::{Add, Mul, Sub};
+
 /// A point in 2D space.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct XYPoint<T> {
     pub x: T,
     pub y: T,
@@ -11,7 +14,49 @@ impl<T> XYPoint<T> {
     }
 }
 
+impl<T: Add<Output = T>> Add for XYPoint<T> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl<T: Sub<Output = T>> Sub for XYPoint<T> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+macro_rules! impl_distances {
+    ($t:ty) => {
+        impl XYPoint<$t> {
+            /// The square of the euclidean distance to other. Cheaper than
+            /// euclidean_distance when only comparing distances.
+            pub fn squared_distance(&self, other: &Self) -> $t {
+                let d = *self - *other;
+                d.x * d.x + d.y * d.y
+            }
+
+            pub fn euclidean_distance(&self, other: &Self) -> $t {
+                self.squared_distance(other).sqrt()
+            }
+
+            pub fn manhattan_distance(&self, other: &Self) -> $t {
+                let d = *self - *other;
+                d.x.abs() + d.y.abs()
+            }
+        }
+    };
+}
+
+impl_distances!(f32);
+impl_distances!(f64);
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct XYTPoint<T> {
     pub x: T,
     pub y: T,
@@ -23,3 +68,131 @@ impl<T> XYTPoint<T> {
         Self { x, y, t }
     }
 }
+
+impl<T: Copy + Mul<Output = T> + Sub<Output = T>> XYTPoint<T> {
+    /// Where the point would have been at t = 0, if it moved at (vx, vy):
+    /// (x - vx*t, y - vy*t). Observations of an object moving at (vx, vy)
+    /// all shift to the same place.
+    ///
+    /// Everything in this crate uses this convention. To go the other way,
+    /// predicting where a shifted point is at time t, add (vx*t, vy*t).
+    pub fn shift_by_velocity(&self, vx: T, vy: T) -> XYPoint<T> {
+        XYPoint::new(self.x - vx * self.t, self.y - vy * self.t)
+    }
+}
+
+/// A point in 3D space.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XYZPoint<T> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
+}
+
+impl<T> XYZPoint<T> {
+    pub fn new(x: T, y: T, z: T) -> Self {
+        Self { x, y, z }
+    }
+}
+
+/// Stretches the neighborhood used for clustering from a circle of radius
+/// eps into an ellipse, with semi-axes eps * scale.0 and eps * scale.1,
+/// rotated counterclockwise by rotation radians. This is a diagonal
+/// Mahalanobis distance, for positions whose uncertainties are elongated.
+///
+/// Rather than teaching each search tree about ellipses, points are mapped
+/// into a space where the ellipse is a circle of radius eps, and clustered
+/// there as usual.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Anisotropy {
+    pub scale: (f64, f64),
+    pub rotation: f64,
+}
+
+impl Anisotropy {
+    /// Both of scale's components must be finite and positive.
+    pub fn new(scale: (f64, f64), rotation: f64) -> Self {
+        Self { scale, rotation }
+    }
+
+    /// Rotate point into the ellipse's frame and scale it, so that points
+    /// within the ellipse of each other end up within a circle.
+    pub fn transform(&self, point: &XYPoint<f64>) -> XYPoint<f64> {
+        let (sin, cos) = self.rotation.sin_cos();
+        XYPoint::new(
+            (point.x * cos + point.y * sin) / self.scale.0,
+            (point.y * cos - point.x * sin) / self.scale.1,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_sub() {
+        let a = XYPoint::new(1.0, 2.0);
+        let b = XYPoint::new(0.5, -1.0);
+        assert_eq!(a + b, XYPoint::new(1.5, 1.0));
+        assert_eq!(a - b, XYPoint::new(0.5, 3.0));
+    }
+
+    #[test]
+    fn test_shift_by_velocity() {
+        let point = XYTPoint::new(1.0, 2.0, 4.0);
+        assert_eq!(point.shift_by_velocity(0.0, 0.0), XYPoint::new(1.0, 2.0));
+        assert_eq!(point.shift_by_velocity(0.25, -0.5), XYPoint::new(0.0, 4.0));
+
+        // Two observations of something moving at (0.25, -0.5) land on the
+        // same spot.
+        let later = XYTPoint::new(1.5, 1.0, 6.0);
+        assert_eq!(
+            later.shift_by_velocity(0.25, -0.5),
+            point.shift_by_velocity(0.25, -0.5)
+        );
+
+        let point = XYTPoint::new(1.0_f32, 2.0, 4.0);
+        assert_eq!(point.shift_by_velocity(0.25, -0.5), XYPoint::new(0.0, 4.0));
+    }
+
+    #[test]
+    fn test_distances() {
+        let a = XYPoint::new(1.0_f64, 1.0);
+
+        // Zero distance.
+        assert_eq!(a.squared_distance(&a), 0.0);
+        assert_eq!(a.euclidean_distance(&a), 0.0);
+        assert_eq!(a.manhattan_distance(&a), 0.0);
+
+        // Along an axis, the metrics agree.
+        let b = XYPoint::new(1.0, -2.0);
+        assert_eq!(a.euclidean_distance(&b), 3.0);
+        assert_eq!(a.manhattan_distance(&b), 3.0);
+        assert_eq!(a.squared_distance(&b), 9.0);
+
+        // Off the axes, they don't.
+        let c = XYPoint::new(4.0, 5.0);
+        assert_eq!(a.euclidean_distance(&c), 5.0);
+        assert_eq!(c.euclidean_distance(&a), 5.0);
+        assert_eq!(a.manhattan_distance(&c), 7.0);
+
+        let a = XYPoint::new(1.0_f32, 1.0);
+        let c = XYPoint::new(4.0_f32, 5.0);
+        assert_eq!(a.euclidean_distance(&c), 5.0);
+        assert_eq!(a.manhattan_distance(&c), 7.0);
+    }
+
+    #[test]
+    fn test_anisotropy_transform() {
+        let a = Anisotropy::new((2.0, 0.5), 0.0);
+        assert_eq!(a.transform(&XYPoint::new(1.0, 1.0)), XYPoint::new(0.5, 2.0));
+
+        // Rotated a quarter turn, the long axis runs along y.
+        let a = Anisotropy::new((2.0, 1.0), std::f64::consts::FRAC_PI_2);
+        let p = a.transform(&XYPoint::new(0.0, 1.0));
+        assert!((p.x - 0.5).abs() < 1e-12);
+        assert!(p.y.abs() < 1e-12);
+    }
+}
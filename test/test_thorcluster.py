@@ -2,15 +2,47 @@ import thor_cluster
 import pyarrow as pa
 import pytest
 import csv
+import math
+import re
+import sys
+import threading
 
 def test_thorcluster():
     x = pa.array([1.0, 2.0, 3.0, 1.0, 1.0, 1.0], type=pa.float64())
     y = pa.array([4.0, 5.0, 6.0, 4.1, 3.9, 3.8], type=pa.float64())
     have = thor_cluster.find_clusters(x, y, 1.0, 4, thor_cluster.ClusterAlgorithm.DBSCAN)
-    want = pa.array([0, -1, -1, 0, 0, 0], type=pa.int32())
+    want = pa.array([1, -1, -1, 1, 1, 1], type=pa.int32())
     assert have == (want)
 
 
+def test_large_min_cluster_size():
+    n = 1200
+    x = pa.array([1.0 + i * 1e-5 for i in range(n)], type=pa.float64())
+    y = pa.array([1.0] * n, type=pa.float64())
+    have = thor_cluster.find_clusters(x, y, 0.1, 1000, thor_cluster.ClusterAlgorithm.DBSCAN)
+    assert have == pa.array([1] * n, type=pa.int32())
+
+    have = thor_cluster.find_clusters(x[0:999], y[0:999], 0.1, 1000, thor_cluster.ClusterAlgorithm.DBSCAN)
+    assert have == pa.array([-1] * 999, type=pa.int32())
+
+
+def test_cellsearch_obs_ids():
+    ids = pa.array(["a", "b", "c", "d", "e", "f"], type=pa.string())
+    xs = pa.array([0.0, 5.0, 0.1, 0.2, 0.3, 9.0], type=pa.float64())
+    ys = pa.array([0.0, 5.0, 0.0, 0.0, 0.0, 9.0], type=pa.float64())
+    dts = pa.array([0.0, 0.0, 1.0, 2.0, 3.0, 3.0], type=pa.float64())
+    velocities = pa.array([0.0], type=pa.float64())
+
+    table = thor_cluster.cellsearch(ids, xs, ys, dts, velocities, velocities, 0.5, 4)
+    clusters = table.column("points").to_pylist()
+    assert len(clusters) == 1
+    assert sorted(p["obs_id"] for p in clusters[0]) == ["a", "c", "d", "e"]
+    for p in clusters[0]:
+        row = ids.to_pylist().index(p["obs_id"])
+        assert p["x"] == pytest.approx(xs[row].as_py())
+        assert p["dt"] == pytest.approx(dts[row].as_py())
+
+
 @pytest.mark.parametrize("n", [100, 1000, 10000, 30000, 50000, 70000])
 @pytest.mark.benchmark(group="thorcluster")
 def test_thorcluster_benchmark(benchmark, benchmark_data, n):
@@ -21,6 +53,900 @@ def test_thorcluster_benchmark(benchmark, benchmark_data, n):
     benchmark(thor_cluster.find_clusters, xs=x_array, ys=y_array, eps=0.02, min_cluster_size=4, alg=thor_cluster.ClusterAlgorithm.DBSCAN)
 
 
+def test_grid_search_releases_gil(benchmark_data):
+    n = 2000
+    velocities = pa.array([(i - 10) / 10.0 for i in range(21)], type=pa.float64())
+    kwargs = dict(
+        ids=benchmark_data["ids"][0:n],
+        xs=benchmark_data["x"][0:n],
+        ys=benchmark_data["y"][0:n],
+        dts=benchmark_data["dt"][0:n],
+        vxs=velocities,
+        vys=velocities,
+        eps=0.02,
+        min_cluster_size=4,
+        n_threads=1,
+        alg=thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+
+    # Another Python thread waits for the search to start, and then notes
+    # that it ran. With the switch interval this long, Python never takes
+    # the GIL from a thread which holds it, so the other thread can only run
+    # before grid_search returns if grid_search lets go of the GIL.
+    started = threading.Event()
+    ran = threading.Event()
+
+    def note_progress():
+        started.wait()
+        ran.set()
+
+    other = threading.Thread(target=note_progress)
+    other.start()
+    switch_interval = sys.getswitchinterval()
+    sys.setswitchinterval(100.0)
+    try:
+        started.set()
+        thor_cluster.grid_search(**kwargs)
+        ran_during_search = ran.is_set()
+    finally:
+        sys.setswitchinterval(switch_interval)
+    other.join()
+    assert ran_during_search
+
+
+def test_grid_search_progress(benchmark_data):
+    n = 100
+    velocities = pa.array([(i - 5) / 10.0 for i in range(10)], type=pa.float64())
+    calls = []
+    thor_cluster.grid_search(
+        ids=benchmark_data["ids"][0:n],
+        xs=benchmark_data["x"][0:n],
+        ys=benchmark_data["y"][0:n],
+        dts=benchmark_data["dt"][0:n],
+        vxs=velocities,
+        vys=velocities,
+        eps=0.02,
+        min_cluster_size=4,
+        n_threads=2,
+        alg=thor_cluster.ClusterAlgorithm.DBSCAN,
+        progress=lambda completed, total: calls.append((completed, total)),
+        progress_interval=30,
+    )
+    assert calls == [(30, 100), (60, 100), (90, 100), (100, 100)]
+
+
+def test_grid_search_deterministic(benchmark_data):
+    n = 2000
+    velocities = pa.array([(i - 5) / 10.0 for i in range(11)], type=pa.float64())
+    kwargs = dict(
+        ids=benchmark_data["ids"][0:n],
+        xs=benchmark_data["x"][0:n],
+        ys=benchmark_data["y"][0:n],
+        dts=benchmark_data["dt"][0:n],
+        vxs=velocities,
+        vys=velocities,
+        eps=0.02,
+        min_cluster_size=4,
+        n_threads=4,
+        alg=thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    first_clusters, first_members = thor_cluster.grid_search(**kwargs)
+    second_clusters, second_members = thor_cluster.grid_search(**kwargs)
+    assert first_clusters.equals(second_clusters)
+    assert first_members.equals(second_members)
+
+
+def test_float32_inputs(benchmark_data):
+    n = 1000
+    xs32 = benchmark_data["x"][0:n].cast(pa.float32())
+    ys32 = benchmark_data["y"][0:n].cast(pa.float32())
+    dts32 = benchmark_data["dt"][0:n].cast(pa.float32())
+    xs64 = xs32.cast(pa.float64())
+    ys64 = ys32.cast(pa.float64())
+    dts64 = dts32.cast(pa.float64())
+
+    have = thor_cluster.find_clusters(xs32, ys32, 0.02, 4, thor_cluster.ClusterAlgorithm.DBSCAN)
+    want = thor_cluster.find_clusters(xs64, ys64, 0.02, 4, thor_cluster.ClusterAlgorithm.DBSCAN)
+    assert have == want
+
+    velocities32 = pa.array([-0.1, 0.0, 0.1], type=pa.float32())
+    velocities64 = velocities32.cast(pa.float64())
+    kwargs = dict(
+        ids=benchmark_data["ids"][0:n],
+        eps=0.02,
+        min_cluster_size=4,
+        n_threads=1,
+        alg=thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    have = thor_cluster.grid_search(
+        xs=xs32, ys=ys32, dts=dts32, vxs=velocities32, vys=velocities32, **kwargs
+    )
+    want = thor_cluster.grid_search(
+        xs=xs64, ys=ys64, dts=dts64, vxs=velocities64, vys=velocities64, **kwargs
+    )
+    assert have[0].equals(want[0])
+    assert have[1].equals(want[1])
+
+
+def test_find_clusters_weights():
+    xs = pa.array([0.0, 0.1, 0.0, 5.0, 5.1, 5.0], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.1, 5.0, 5.0, 5.1], type=pa.float64())
+    weights = pa.array([2.0, 2.0, 2.0, 1.0, 1.0, 1.0], type=pa.float64())
+    have = thor_cluster.find_clusters(
+        xs, ys, 0.5, 5, thor_cluster.ClusterAlgorithm.DBSCAN, weights=weights
+    )
+    assert have == pa.array([1, 1, 1, -1, -1, -1], type=pa.int32())
+
+    with pytest.raises(ValueError):
+        thor_cluster.find_clusters(
+            xs, ys, 0.5, 5, thor_cluster.ClusterAlgorithm.Optics, weights=weights
+        )
+
+
+def test_cellsearch_threads(benchmark_data):
+    n = 500
+    velocities = pa.array([(i - 5) / 10.0 for i in range(11)], type=pa.float64())
+    kwargs = dict(
+        ids=benchmark_data["ids"][0:n],
+        xs=benchmark_data["x"][0:n],
+        ys=benchmark_data["y"][0:n],
+        dts=benchmark_data["dt"][0:n],
+        vxs=velocities,
+        vys=velocities,
+        eps=0.02,
+        min_cluster_size=4,
+    )
+    serial = thor_cluster.cellsearch(n_threads=1, **kwargs)
+    parallel = thor_cluster.cellsearch(n_threads=4, **kwargs)
+    assert parallel.equals(serial)
+
+
+def test_find_clusters_grouped():
+    xs = pa.array([5.0, 0.0, 9.0, 0.1, 5.1, 0.0, 5.0], type=pa.float64())
+    ys = pa.array([5.0, 0.0, 9.0, 0.0, 5.0, 0.1, 5.1], type=pa.float64())
+    have = thor_cluster.find_clusters_grouped(xs, ys, 0.5, 3, thor_cluster.ClusterAlgorithm.DBSCAN)
+    assert have.to_pylist() == [[0, 4, 6], [1, 3, 5]]
+
+
+@pytest.mark.parametrize(
+    "field,values,message",
+    [
+        ("vxs", [], "vxs: at least one velocity is required"),
+        ("vys", [None], "vys: at least one velocity is required"),
+        ("vxs", [0.0, float("nan")], "vxs: values must be finite, but got NaN at index 1"),
+        ("vys", [float("inf")], "vys: values must be finite, but got inf at index 0"),
+        ("xs", [0.0, float("nan"), 0.0], "xs: values must be finite, but got NaN at index 1"),
+        ("ys", [0.0, 0.0, float("-inf")], "ys: values must be finite, but got -inf at index 2"),
+        ("dts", [float("nan"), 0.0, 0.0], "dts: values must be finite, but got NaN at index 0"),
+    ],
+)
+@pytest.mark.parametrize("search", ["grid_search", "cellsearch"])
+def test_search_input_validation(search, field, values, message):
+    kwargs = dict(
+        ids=pa.array(["a", "b", "c"], type=pa.string()),
+        xs=pa.array([0.0, 0.1, 0.2], type=pa.float64()),
+        ys=pa.array([0.0, 0.0, 0.0], type=pa.float64()),
+        dts=pa.array([0.0, 1.0, 2.0], type=pa.float64()),
+        vxs=pa.array([0.0, 0.1], type=pa.float64()),
+        vys=pa.array([0.0, 0.1], type=pa.float64()),
+        eps=0.5,
+        min_cluster_size=3,
+    )
+    kwargs[field] = pa.array(values, type=pa.float64())
+    if search == "grid_search":
+        kwargs["n_threads"] = 1
+        kwargs["alg"] = thor_cluster.ClusterAlgorithm.DBSCAN
+    with pytest.raises(ValueError, match=re.escape(message)):
+        getattr(thor_cluster, search)(**kwargs)
+
+
+def test_estimate_eps():
+    xs = pa.array([0.0, 0.01, 0.02, 0.03, 10.0, 11.0, 12.0], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.0, 0.0, 5.0, 5.0, 5.0], type=pa.float64())
+    have = thor_cluster.estimate_eps(xs, ys, 2).to_pylist()
+    assert have == sorted(have)
+    assert have[:4] == pytest.approx([0.01] * 4, abs=1e-4)
+    assert have[4:] == pytest.approx([1.0] * 3, abs=1e-4)
+
+
+def test_silhouette_score():
+    xs = pa.array([0.0, 0.1, 0.0, 10.0, 10.1, 10.0], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.1, 10.0, 10.0, 10.1], type=pa.float64())
+    labels = thor_cluster.find_clusters(xs, ys, 0.5, 3, thor_cluster.ClusterAlgorithm.DBSCAN)
+    assert thor_cluster.silhouette_score(xs, ys, labels) == pytest.approx(1.0, abs=0.02)
+
+    noise = pa.array([-1] * 6, type=pa.int32())
+    assert math.isnan(thor_cluster.silhouette_score(xs, ys, noise))
+
+
+def test_arc_lengths():
+    labels = pa.array([2, 1, -1, 2, 1, 2, None], type=pa.int32())
+    dts = pa.array([0.5, 1.0, 9.0, 3.0, 1.25, 2.0, 100.0], type=pa.float64())
+    have = thor_cluster.arc_lengths(labels, dts)
+    assert have.to_pylist() == [
+        {"cluster_id": 1, "arc_length": 0.25},
+        {"cluster_id": 2, "arc_length": 2.5},
+    ]
+
+
+def test_find_clusters_3d():
+    xs = pa.array([1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0, None], type=pa.float64())
+    ys = pa.array([0.0] * 9, type=pa.float64())
+    zs = pa.array([0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0], type=pa.float64())
+    have = thor_cluster.find_clusters_3d(xs, ys, zs, 0.5, 4)
+    assert have == pa.array([-1, -1, -1, -1, 1, 1, 1, 1, -1], type=pa.int32())
+
+
+def test_thor_cell_reuse():
+    ids = pa.array(["a", "b", "c", "d", "e", "f"], type=pa.string())
+    xs = pa.array([0.0, 5.0, 0.1, 0.3, 0.6, 9.0], type=pa.float64())
+    ys = pa.array([0.0, 5.0, 0.0, 0.0, 0.0, 9.0], type=pa.float64())
+    dts = pa.array([0.0, 0.0, 1.0, 2.0, 3.0, 3.0], type=pa.float64())
+    velocities = pa.array([0.0], type=pa.float64())
+
+    cell = thor_cluster.ThorCell()
+    cell.add_points(ids, xs, ys, dts)
+    assert len(cell) == 6
+
+    # The same cell can be searched again with different parameters.
+    tight = cell.find_clusters(velocities, velocities, 0.15, 4)
+    assert tight.num_rows == 0
+    loose = cell.find_clusters(velocities, velocities, 1.0, 4)
+    clusters = loose.column("points").to_pylist()
+    assert len(clusters) == 1
+    assert sorted(p["obs_id"] for p in clusters[0]) == ["a", "c", "d", "e"]
+
+    # It gives the same answer as cellsearch.
+    assert loose.equals(thor_cluster.cellsearch(ids, xs, ys, dts, velocities, velocities, 1.0, 4))
+
+    with pytest.raises(ValueError, match="linking"):
+        cell.find_clusters(velocities, velocities, 1.0, 4, linking="optics")
+
+
+def test_thor_cell_dbscan_linking_matches_grid_search():
+    # At vx = 0.1, a chain of points 0.04 apart which only hangs together
+    # through its core points, a tight group of four, and some clutter.
+    rows = [(0.14 * i, 0.0, float(i)) for i in range(9)]
+    rows += [(5.0 + 0.1 * t, 5.0 + dy, float(t)) for t, dy in enumerate([0.0, 0.01, -0.01, 0.02])]
+    rows += [(2.0, -3.0, 1.0), (-1.0, 2.0, 4.0), (5.3, 5.5, 2.0)]
+    ids = pa.array([f"obs{i}" for i in range(len(rows))], type=pa.string())
+    xs, ys, dts = (pa.array(column, type=pa.float64()) for column in zip(*rows))
+    vxs = pa.array([0.1], type=pa.float64())
+    vys = pa.array([0.0], type=pa.float64())
+
+    _, members = thor_cluster.grid_search(
+        ids, xs, ys, dts, vxs, vys, 0.05, 3, 1, thor_cluster.ClusterAlgorithm.DBSCAN
+    )
+    want = {}
+    for cluster_id, obs_id in zip(members["cluster_id"].to_pylist(), members["obs_id"].to_pylist()):
+        want.setdefault(cluster_id, set()).add(obs_id)
+    want = sorted(sorted(obs_ids) for obs_ids in want.values())
+    assert len(want) == 2
+
+    cell = thor_cluster.ThorCell()
+    cell.add_points(ids, xs, ys, dts)
+
+    def search(linking):
+        table = cell.find_clusters(vxs, vys, 0.05, 3, linking=linking)
+        return sorted(sorted(p["obs_id"] for p in points) for points in table.column("points").to_pylist())
+
+    assert search("dbscan") == want
+    # Linking forward, the chain's points never see enough neighbors.
+    assert search("forward") == [obs_ids for obs_ids in want if "obs0" not in obs_ids]
+    assert thor_cluster.cellsearch(ids, xs, ys, dts, vxs, vys, 0.05, 3, linking="dbscan").equals(
+        cell.find_clusters(vxs, vys, 0.05, 3, linking="dbscan")
+    )
+
+
+def test_grid_search_joined_output(benchmark_data):
+    n = 1000
+    velocities = pa.array([-0.1, 0.0, 0.1], type=pa.float64())
+    kwargs = dict(
+        ids=benchmark_data["ids"][0:n],
+        xs=benchmark_data["x"][0:n],
+        ys=benchmark_data["y"][0:n],
+        dts=benchmark_data["dt"][0:n],
+        vxs=velocities,
+        vys=velocities,
+        eps=0.02,
+        min_cluster_size=4,
+        n_threads=1,
+        alg=thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    clusters, members = thor_cluster.grid_search(**kwargs)
+    joined = thor_cluster.grid_search(output="joined", **kwargs)
+
+    # One row per clustered observation, with its cluster's velocity.
+    assert joined.num_rows == members.num_rows
+    assert joined.column("cluster_id").equals(members.column("cluster_id"))
+    assert joined.column("obs_id").equals(members.column("obs_id"))
+    by_id = {c["cluster_id"]: c for c in clusters.to_pylist()}
+    for row in joined.to_pylist():
+        cluster = by_id[row["cluster_id"]]
+        assert row["vx"] == cluster["vx"]
+        assert row["vy"] == cluster["vy"]
+        assert row["arc_length"] == cluster["arc_length"]
+
+    with pytest.raises(ValueError):
+        thor_cluster.grid_search(output="both", **kwargs)
+
+
+def test_cluster_algorithm_from_str():
+    alg = thor_cluster.ClusterAlgorithm.from_str("DBSCAN_rstar")
+    assert alg == thor_cluster.ClusterAlgorithm.DbscanRStar
+
+    with pytest.raises(ValueError, match="expected one of: dbscan, hotspot2d"):
+        thor_cluster.ClusterAlgorithm.from_str("kmeans")
+
+
+def test_empty_inputs():
+    ids = pa.array([], type=pa.string())
+    empty = pa.array([], type=pa.float64())
+    velocities = pa.array([-0.1, 0.0, 0.1], type=pa.float64())
+
+    for alg in [thor_cluster.ClusterAlgorithm.DBSCAN, thor_cluster.ClusterAlgorithm.Hotspot2D]:
+        labels = thor_cluster.find_clusters(empty, empty, 0.1, 4, alg)
+        assert labels == pa.array([], type=pa.int32())
+
+    clusters, members = thor_cluster.grid_search(
+        ids, empty, empty, empty, velocities, velocities, 0.1, 4, 2,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    assert clusters.num_rows == 0
+    assert "residual_rms" in clusters.schema.names
+    assert members.num_rows == 0
+    assert members.schema.field("obs_id").type == pa.dictionary(pa.int64(), pa.large_string())
+
+    joined = thor_cluster.grid_search(
+        ids, empty, empty, empty, velocities, velocities, 0.1, 4, 2,
+        thor_cluster.ClusterAlgorithm.DBSCAN, output="joined",
+    )
+    assert joined.num_rows == 0
+    assert joined.schema.names == ["cluster_id", "vx", "vy", "arc_length", "obs_id"]
+
+    table = thor_cluster.cellsearch(ids, empty, empty, empty, velocities, velocities, 0.1, 4)
+    assert table.num_rows == 0
+    assert table.schema.names == ["vx", "vy", "points"]
+
+
+def test_connected_components():
+    # Clusters 1 and 2 share "c", so they're one object.
+    cluster_ids = pa.array([1, 1, 1, 2, 2, 3, 3], type=pa.uint32())
+    obs_ids = pa.array(["a", "b", "c", "c", "d", "x", "y"], type=pa.string())
+    have = thor_cluster.connected_components(cluster_ids, obs_ids)
+    assert have.field("component_id") == pa.array([1, 1, 1, 1, 2, 2], type=pa.uint32())
+    assert have.field("obs_id").to_pylist() == ["a", "b", "c", "d", "x", "y"]
+
+    # The membership table from grid_search can be passed straight in.
+    again = thor_cluster.connected_components(cluster_ids, obs_ids.dictionary_encode())
+    assert again.equals(have)
+
+
+def test_grid_search_max_speed():
+    ids = pa.array(["a", "b", "c", "d"], type=pa.string())
+    xs = pa.array([0.0, 1.0, 2.0, 3.0], type=pa.float64())
+    ys = pa.array([0.0, 1.0, 2.0, 3.0], type=pa.float64())
+    dts = pa.array([0.0, 1.0, 2.0, 3.0], type=pa.float64())
+    velocities = pa.array([-1.0, 0.0, 1.0], type=pa.float64())
+    calls = []
+    clusters, _ = thor_cluster.grid_search(
+        ids, xs, ys, dts, velocities, velocities, 0.1, 4, 1,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+        max_speed=1.0,
+        progress=lambda completed, total: calls.append((completed, total)),
+        progress_interval=1,
+    )
+    # The object moves at (1, 1), a corner of the grid, which is skipped.
+    assert clusters.num_rows == 0
+    assert calls[-1] == (5, 5)
+
+
+def test_grid_search_plain_obs_ids(benchmark_data):
+    n = 1000
+    velocities = pa.array([-0.1, 0.0, 0.1], type=pa.float64())
+    kwargs = dict(
+        ids=benchmark_data["ids"][0:n],
+        xs=benchmark_data["x"][0:n],
+        ys=benchmark_data["y"][0:n],
+        dts=benchmark_data["dt"][0:n],
+        vxs=velocities,
+        vys=velocities,
+        eps=0.02,
+        min_cluster_size=4,
+        n_threads=1,
+        alg=thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    _, dictionary = thor_cluster.grid_search(**kwargs)
+    _, plain = thor_cluster.grid_search(dictionary_obs_ids=False, **kwargs)
+    assert pa.types.is_dictionary(dictionary.schema.field("obs_id").type)
+    assert plain.schema.field("obs_id").type == pa.string()
+    assert plain.column("obs_id").to_pylist() == dictionary.column("obs_id").to_pylist()
+
+
+def test_grid_search_labels(benchmark_data):
+    n = 500
+    velocities = pa.array([-0.1, 0.0, 0.1], type=pa.float64())
+    xs = benchmark_data["x"][0:n]
+    # A null row is kept, and labeled as noise.
+    xs = pa.concat_arrays([xs, pa.array([None], type=xs.type)])
+    ys = pa.concat_arrays([benchmark_data["y"][0:n], pa.array([0.0])])
+    dts = pa.concat_arrays([benchmark_data["dt"][0:n], pa.array([0.0])])
+    table = thor_cluster.grid_search_labels(
+        xs, ys, dts, velocities, velocities, 0.02, 4, 1,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    assert table.num_rows == 9
+    assert table.column("vx").to_pylist()[0:3] == [-0.1, -0.1, -0.1]
+    for labels in table.column("labels").to_pylist():
+        assert len(labels) == n + 1
+        assert labels[n] == -1
+
+
+def test_find_clusters_scale():
+    xs = pa.array([0.0, 0.12, 0.24, 0.36], type=pa.float64())
+    ys = pa.array([0.0] * 4, type=pa.float64())
+    alg = thor_cluster.ClusterAlgorithm.DBSCAN
+    have = thor_cluster.find_clusters(xs, ys, 0.2, 4, alg)
+    assert have == pa.array([-1] * 4, type=pa.int32())
+    have = thor_cluster.find_clusters(xs, ys, 0.2, 4, alg, scale=(2.0, 1.0))
+    assert have == pa.array([1] * 4, type=pa.int32())
+    # Stretched across the line instead, the ellipse doesn't help.
+    have = thor_cluster.find_clusters(xs, ys, 0.2, 4, alg, scale=(2.0, 1.0), rotation=math.pi / 2)
+    assert have == pa.array([-1] * 4, type=pa.int32())
+
+    with pytest.raises(ValueError):
+        thor_cluster.find_clusters(
+            xs, ys, 0.2, 4, alg, metric=thor_cluster.DistanceMetric.Haversine, scale=(2.0, 1.0)
+        )
+    for scale in [(0.0, 1.0), (1.0, -2.0), (math.inf, 1.0), (1.0, math.nan)]:
+        with pytest.raises(ValueError, match="scale must be finite and positive"):
+            thor_cluster.find_clusters(xs, ys, 0.2, 4, alg, scale=scale)
+
+
+def test_grid_search_struct(benchmark_data):
+    n = 1000
+    velocities = pa.array([-0.1, 0.0, 0.1], type=pa.float64())
+    xs = benchmark_data["x"][0:n]
+    ys = benchmark_data["y"][0:n]
+    dts = benchmark_data["dt"][0:n]
+    kwargs = dict(
+        ids=benchmark_data["ids"][0:n],
+        vxs=velocities,
+        vys=velocities,
+        eps=0.02,
+        min_cluster_size=4,
+        n_threads=1,
+        alg=thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    want = thor_cluster.grid_search(xs=xs, ys=ys, dts=dts, **kwargs)
+    points = pa.StructArray.from_arrays([xs, ys, dts], names=["x", "y", "dt"])
+    have = thor_cluster.grid_search_struct(points=points, **kwargs)
+    assert have[0] == want[0]
+    assert have[1] == want[1]
+
+    missing = pa.StructArray.from_arrays([xs, ys], names=["x", "y"])
+    with pytest.raises(ValueError, match="missing field"):
+        thor_cluster.grid_search_struct(points=missing, **kwargs)
+    wrong_type = pa.StructArray.from_arrays(
+        [xs, ys, pa.array(range(n), type=pa.int64())], names=["x", "y", "dt"]
+    )
+    with pytest.raises(TypeError, match="points.dt"):
+        thor_cluster.grid_search_struct(points=wrong_type, **kwargs)
+    with pytest.raises(TypeError, match="expected Struct"):
+        thor_cluster.grid_search_struct(points=xs, **kwargs)
+
+
+def test_find_clusters_max_cluster_size():
+    # A dense 100-point blob, and a 4-point cluster well away from it.
+    xs = [(i // 10) * 0.01 for i in range(100)] + [5.0] * 4
+    ys = [(i % 10) * 0.01 for i in range(100)] + [5.0] * 4
+    xs = pa.array(xs, type=pa.float64())
+    ys = pa.array(ys, type=pa.float64())
+    alg = thor_cluster.ClusterAlgorithm.DBSCAN
+    have = thor_cluster.find_clusters(xs, ys, 0.5, 4, alg, max_cluster_size=50).to_pylist()
+    assert have[:100] == [-1] * 100
+    assert len(set(have[100:])) == 1
+    assert have[100] != -1
+
+    groups = thor_cluster.find_clusters_grouped(xs, ys, 0.5, 4, alg, max_cluster_size=50)
+    assert groups.to_pylist() == [[100, 101, 102, 103]]
+
+
+def test_cluster_single(benchmark_data):
+    # All of the data, so that there are some stationary sources to find.
+    kwargs = dict(
+        ids=benchmark_data["ids"],
+        xs=benchmark_data["x"],
+        ys=benchmark_data["y"],
+        dts=benchmark_data["dt"],
+        eps=0.02,
+        min_cluster_size=4,
+        alg=thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    zero = pa.array([0.0], type=pa.float64())
+    want = thor_cluster.grid_search(vxs=zero, vys=zero, n_threads=1, **kwargs)
+    have = thor_cluster.cluster_single(**kwargs)
+    assert have[0] == want[0]
+    assert have[1] == want[1]
+    assert have[0].num_rows > 0
+
+
+def test_grid_search_noise():
+    # An object moving at (1, 1), and a point far away from it.
+    ids = pa.array(["a", "b", "c", "d", "far"], type=pa.string())
+    xs = pa.array([0.0, 1.0, 2.0, 3.0, 50.0], type=pa.float64())
+    ys = pa.array([0.0, 1.0, 2.0, 3.0, 50.0], type=pa.float64())
+    dts = pa.array([0.0, 1.0, 2.0, 3.0, 1.0], type=pa.float64())
+    velocities = pa.array([0.0, 1.0], type=pa.float64())
+    args = (ids, xs, ys, dts, velocities, velocities, 0.1, 4, 1, thor_cluster.ClusterAlgorithm.DBSCAN)
+
+    clusters, members, noise = thor_cluster.grid_search(*args, noise=True)
+    assert clusters.num_rows == 1
+    assert noise.column("obs_id").to_pylist() == ["far"]
+    assert "far" not in members.column("obs_id").to_pylist()
+
+    joined, noise = thor_cluster.grid_search(*args, output="joined", noise=True)
+    assert noise.column("obs_id").to_pylist() == ["far"]
+    assert "far" not in joined.column("obs_id").to_pylist()
+
+    # Without noise=True, the output is unchanged.
+    assert len(thor_cluster.grid_search(*args)) == 2
+
+
+def test_find_clusters_sigmas():
+    # Two pairs of points 0.6 apart, just outside eps. Only the poorly
+    # measured pair is close enough to cluster.
+    xs = pa.array([0.0, 0.6, 5.0, 5.6], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 5.0, 5.0], type=pa.float64())
+    sigmas = pa.array([0.3, 0.3, 0.01, 0.01], type=pa.float64())
+    alg = thor_cluster.ClusterAlgorithm.DBSCAN
+    have = thor_cluster.find_clusters(xs, ys, 0.5, 2, alg, sigmas=sigmas).to_pylist()
+    assert have == [1, 1, -1, -1]
+
+    have = thor_cluster.find_clusters(xs, ys, 0.5, 2, alg, sigmas=sigmas, n_sigma=0.0).to_pylist()
+    assert have == [-1, -1, -1, -1]
+
+    with pytest.raises(ValueError):
+        thor_cluster.find_clusters(xs, ys, 0.5, 2, alg, sigmas=sigmas, weights=sigmas)
+
+
+def test_find_clusters_classified():
+    # Four points together with a short tail, and one far off. The end of
+    # the tail has too few neighbors to be a core point.
+    xs = pa.array([0.0, 0.0, 0.0, 0.0, 0.4, 0.8, 5.0], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 5.0], type=pa.float64())
+    alg = thor_cluster.ClusterAlgorithm.DBSCAN
+    have = thor_cluster.find_clusters_classified(xs, ys, 0.5, 5, alg)
+    assert have.field("label").to_pylist() == [1] * 6 + [-1]
+    assert have.field("role").to_pylist() == ["core"] * 5 + ["border", "noise"]
+
+    with pytest.raises(ValueError):
+        thor_cluster.find_clusters_classified(xs, ys, 0.5, 5, thor_cluster.ClusterAlgorithm.Optics)
+
+
+def test_grid_search_velocity_pairs():
+    ids = pa.array(["a", "b", "c", "d"], type=pa.string())
+    xs = pa.array([0.0, 1.0, 2.0, 3.0], type=pa.float64())
+    ys = pa.array([0.0, 1.0, 2.0, 3.0], type=pa.float64())
+    dts = pa.array([0.0, 1.0, 2.0, 3.0], type=pa.float64())
+    vxs = pa.array([-1.0, 0.0, 1.0], type=pa.float64())
+    vys = pa.array([0.0, 0.0, 1.0], type=pa.float64())
+    calls = []
+    clusters, _ = thor_cluster.grid_search(
+        ids, xs, ys, dts, vxs, vys, 0.1, 4, 1,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+        velocity_pairs=True,
+        progress=lambda completed, total: calls.append((completed, total)),
+        progress_interval=1,
+    )
+    # Three searches, not nine, and the last finds the object.
+    assert calls[-1] == (3, 3)
+    assert clusters.num_rows == 1
+    assert clusters["vx"].to_pylist() == [1.0]
+    assert clusters["vy"].to_pylist() == [1.0]
+
+    with pytest.raises(ValueError):
+        thor_cluster.grid_search(
+            ids, xs, ys, dts, vxs, vys[:2], 0.1, 4, 1,
+            thor_cluster.ClusterAlgorithm.DBSCAN,
+            velocity_pairs=True,
+        )
+
+
+def test_mismatched_ids_length():
+    ids = pa.array(["a", "b"], type=pa.string())
+    xs = pa.array([0.0, 0.0, 0.0], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.0], type=pa.float64())
+    dts = pa.array([0.0, 1.0, 2.0], type=pa.float64())
+    velocities = pa.array([0.0], type=pa.float64())
+    message = "ids must be the same length as x, y, and dts, but got 2 ids for 3 rows"
+    with pytest.raises(pa.ArrowException, match=re.escape(message)):
+        thor_cluster.grid_search(
+            ids, xs, ys, dts, velocities, velocities, 0.1, 3, 1,
+            thor_cluster.ClusterAlgorithm.DBSCAN,
+        )
+    with pytest.raises(pa.ArrowException, match=re.escape(message)):
+        thor_cluster.cellsearch(ids, xs, ys, dts, velocities, velocities, 0.1, 3)
+
+
+def test_adjusted_rand_index():
+    labels = pa.array([1, 1, 1, 2, 2, 2, -1], type=pa.int32())
+    renumbered = pa.array([2, 2, 2, 1, 1, 1, -1], type=pa.int32())
+    assert thor_cluster.adjusted_rand_index(labels, renumbered) == 1.0
+
+    shuffled = pa.array([1, 2, 1, 2, 1, 2, -1], type=pa.int32())
+    assert thor_cluster.adjusted_rand_index(labels, shuffled) < 0.5
+
+    with pytest.raises(pa.ArrowException):
+        thor_cluster.adjusted_rand_index(labels, renumbered[:3])
+
+
+def test_thor_cell_stats():
+    cell = thor_cluster.ThorCell()
+    assert cell.stats() == {"n_subtrees": 0, "n_points": 0, "points_per_subtree": []}
+
+    ids = pa.array(["a", "b", "c", "d", "e"], type=pa.string())
+    xs = pa.array([0.0, 0.0, 0.0, 0.0, 0.0], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.0, 0.0, 0.0], type=pa.float64())
+    dts = pa.array([1.0, 0.0, 1.0, 2.0, 1.0], type=pa.float64())
+    cell.add_points(ids, xs, ys, dts)
+    assert cell.stats() == {"n_subtrees": 3, "n_points": 5, "points_per_subtree": [1, 3, 1]}
+
+
+def test_grid_search_sort_members():
+    # One stationary object, observed out of order in dt.
+    ids = pa.array(["c", "a", "d", "b"], type=pa.string())
+    xs = pa.array([0.0, 0.0, 0.0, 0.0], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.0, 0.0], type=pa.float64())
+    dts = pa.array([2.0, 0.0, 3.0, 1.0], type=pa.float64())
+    velocities = pa.array([0.0], type=pa.float64())
+    args = (
+        ids, xs, ys, dts, velocities, velocities, 0.1, 4, 1,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    _, members = thor_cluster.grid_search(*args)
+    assert members.column("obs_id").to_pylist() == ["c", "a", "d", "b"]
+
+    _, members = thor_cluster.grid_search(*args, sort_members=True)
+    assert members.column("obs_id").to_pylist() == ["a", "b", "c", "d"]
+
+
+def test_apply_velocity():
+    xs = pa.array([1.0, 1.0, None], type=pa.float64())
+    ys = pa.array([2.0, 2.0, 0.0], type=pa.float64())
+    dts = pa.array([0.0, 4.0, 1.0], type=pa.float64())
+    shifted_xs, shifted_ys = thor_cluster.apply_velocity(xs, ys, dts, 0.25, -0.5)
+    # At dt=0 nothing moves; later points move back along the velocity.
+    assert shifted_xs.to_pylist() == [1.0, 0.0, None]
+    assert shifted_ys.to_pylist() == [2.0, 4.0, None]
+
+
+def test_grid_search_require_motion():
+    # A blob of detections from a single exposure, and an object seen in
+    # three.
+    ids = pa.array(["blob1", "blob2", "blob3", "obj1", "obj2", "obj3"], type=pa.string())
+    xs = pa.array([0.0, 0.01, 0.0, 5.0, 5.0, 5.0], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.01, 5.0, 5.0, 5.0], type=pa.float64())
+    dts = pa.array([1.0, 1.0, 1.0, 0.0, 1.0, 2.0], type=pa.float64())
+    velocities = pa.array([0.0], type=pa.float64())
+    args = (
+        ids, xs, ys, dts, velocities, velocities, 0.1, 3, 1,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    clusters, _ = thor_cluster.grid_search(*args)
+    assert clusters.num_rows == 2
+    assert clusters["arc_length"].to_pylist() == [0.0, 2.0]
+    assert clusters["single_exposure"].to_pylist() == [True, False]
+
+    clusters, members = thor_cluster.grid_search(*args, require_motion=True)
+    assert clusters["n_obs"].to_pylist() == [3]
+    assert members.column("obs_id").to_pylist() == ["obj1", "obj2", "obj3"]
+
+
+def test_grid_search_eps_values():
+    # An object moving at 0.5, searched at velocity 0, where its points are
+    # spread over 1.5: only the larger eps takes them all in.
+    ids = pa.array(["a", "b", "c", "d"], type=pa.string())
+    xs = pa.array([0.0, 0.5, 1.0, 1.5], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.0, 0.0], type=pa.float64())
+    dts = pa.array([0.0, 1.0, 2.0, 3.0], type=pa.float64())
+    velocities = pa.array([0.0], type=pa.float64())
+    progress_calls = []
+    clusters, members = thor_cluster.grid_search(
+        ids, xs, ys, dts, velocities, velocities,
+        pa.array([0.1, 1.6], type=pa.float64()), 4, 1,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+        progress=lambda completed, total: progress_calls.append((completed, total)),
+    )
+    assert clusters["eps"].to_pylist() == [1.6]
+    assert members.num_rows == 4
+    assert progress_calls[-1] == (2, 2)
+
+    clusters, _ = thor_cluster.grid_search(
+        ids, xs, ys, dts, velocities, velocities, 0.1, 4, 1,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    assert clusters.num_rows == 0
+    assert clusters.schema.field("eps").type == pa.float64()
+
+    with pytest.raises(ValueError):
+        thor_cluster.grid_search(
+            ids, xs, ys, dts, velocities, velocities,
+            pa.array([], type=pa.float64()), 4, 1,
+            thor_cluster.ClusterAlgorithm.DBSCAN,
+        )
+
+
+def test_find_clusters_dense_and_nullable_inputs_agree():
+    # Dense float64 columns are clustered in place; a null row sends the
+    # same points through the copying path instead.
+    xs = [0.0, 0.25, 0.5, 5.0, 5.25, 5.5, 9.0]
+    ys = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 9.0]
+    alg = thor_cluster.ClusterAlgorithm.DBSCAN
+    dense = thor_cluster.find_clusters(
+        pa.array(xs, type=pa.float64()), pa.array(ys, type=pa.float64()), 0.3, 3, alg,
+    ).to_pylist()
+    assert dense == [1, 1, 1, 2, 2, 2, -1]
+
+    nullable = thor_cluster.find_clusters(
+        pa.array(xs + [None], type=pa.float64()), pa.array(ys + [0.0], type=pa.float64()), 0.3, 3, alg,
+    ).to_pylist()
+    assert nullable == dense + [-1]
+
+
+def test_find_clusters_periodic_x():
+    # A cluster straddling RA 0/360.
+    xs = pa.array([359.8, 359.9, 0.05, 0.15, 90.0], type=pa.float64())
+    ys = pa.array([10.0, 10.0, 10.0, 10.0, 10.0], type=pa.float64())
+    alg = thor_cluster.ClusterAlgorithm.DBSCAN
+    have = thor_cluster.find_clusters(xs, ys, 0.2, 3, alg, periodic_x=360.0).to_pylist()
+    assert have == [1, 1, 1, 1, -1]
+
+    # Without periodic_x, the seam splits it into two halves, each too
+    # small to be a cluster.
+    have = thor_cluster.find_clusters(xs, ys, 0.2, 3, alg).to_pylist()
+    assert have == [-1, -1, -1, -1, -1]
+
+    with pytest.raises(ValueError):
+        thor_cluster.find_clusters(xs, ys, 0.2, 3, thor_cluster.ClusterAlgorithm.Hotspot2D, periodic_x=360.0)
+    with pytest.raises(ValueError):
+        thor_cluster.find_clusters(xs, ys, 0.2, 3, alg, periodic_x=0.0)
+
+    # It combines with the other options. Weighting the two points nearest
+    # the seam makes them core points at a min_cluster_size of 5.
+    weights = pa.array([1.0, 2.0, 2.0, 1.0, 1.0], type=pa.float64())
+    have = thor_cluster.find_clusters(
+        xs, ys, 0.2, 5, alg, weights=weights, periodic_x=360.0, max_neighbors=10
+    ).to_pylist()
+    assert have == [1, 1, 1, 1, -1]
+
+
+def test_find_clusters_max_neighbors():
+    # A tight stack of points, which are all each other's neighbors.
+    xs = pa.array([0.0] * 50 + [5.0], type=pa.float64())
+    ys = pa.array([0.0] * 50 + [5.0], type=pa.float64())
+    alg = thor_cluster.ClusterAlgorithm.DBSCAN
+    have = thor_cluster.find_clusters(xs, ys, 0.1, 4, alg, max_neighbors=3).to_pylist()
+    assert have == [1] * 50 + [-1]
+
+    with pytest.raises(ValueError):
+        thor_cluster.find_clusters(xs, ys, 0.1, 4, thor_cluster.ClusterAlgorithm.Hotspot2D, max_neighbors=3)
+
+
+def test_grid_search_counts(benchmark_data):
+    n = 500
+    velocities = pa.array([-0.1, 0.0, 0.1], type=pa.float64())
+    args = (
+        benchmark_data["x"][0:n], benchmark_data["y"][0:n], benchmark_data["dt"][0:n],
+        velocities, velocities, 0.02, 4, 2,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    counts = thor_cluster.grid_search_counts(*args)
+    labels = thor_cluster.grid_search_labels(*args)
+    assert counts.column("vx") == labels.column("vx")
+    assert counts.column("vy") == labels.column("vy")
+    want = [
+        len(set(row) - {-1}) for row in labels.column("labels").to_pylist()
+    ]
+    assert counts.column("n_clusters").to_pylist() == want
+    assert max(want) > 0
+
+
+def test_grid_search_batch():
+    # The same stationary object in two cells, right on top of each other,
+    # and a stray point in a third cell and one with no cell.
+    cell_ids = pa.array([5, 5, 5, 5, 2, 2, 2, 2, 9, None], type=pa.int64())
+    ids = pa.array([f"obs{i}" for i in range(10)], type=pa.string())
+    xs = pa.array([0.0, 0.0, 0.0, 0.0, 0.01, 0.01, 0.01, 0.01, 0.0, 0.0], type=pa.float64())
+    ys = pa.array([0.0] * 10, type=pa.float64())
+    dts = pa.array([0.0, 1.0, 2.0, 3.0] * 2 + [1.0, 2.0], type=pa.float64())
+    velocities = pa.array([0.0], type=pa.float64())
+    clusters, members = thor_cluster.grid_search_batch(
+        cell_ids, ids, xs, ys, dts, velocities, velocities, 0.1, 4, 2,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    assert clusters.schema.names[0] == "cell_id"
+    assert clusters["cell_id"].to_pylist() == [2, 5]
+    assert clusters["cluster_id"].to_pylist() == [1, 2]
+    assert clusters["n_obs"].to_pylist() == [4, 4]
+    by_cluster = {}
+    for cluster_id, obs_id in zip(members["cluster_id"].to_pylist(), members["obs_id"].to_pylist()):
+        by_cluster.setdefault(cluster_id, []).append(obs_id)
+    assert by_cluster == {
+        1: ["obs4", "obs5", "obs6", "obs7"],
+        2: ["obs0", "obs1", "obs2", "obs3"],
+    }
+
+    # Searched together, the points all make one cluster.
+    clusters, _ = thor_cluster.grid_search(
+        ids, xs, ys, dts, velocities, velocities, 0.1, 4, 1,
+        thor_cluster.ClusterAlgorithm.DBSCAN,
+    )
+    assert clusters["n_obs"].to_pylist() == [10]
+
+    # It takes the same options as grid_search.
+    args = (cell_ids, ids, xs, ys, dts, velocities, velocities)
+    alg = thor_cluster.ClusterAlgorithm.DBSCAN
+    eps = pa.array([0.1, 0.2], type=pa.float64())
+    clusters, _ = thor_cluster.grid_search_batch(*args, eps, 4, 2, alg)
+    assert clusters["cell_id"].to_pylist() == [2, 2, 5, 5]
+    assert clusters["eps"].to_pylist() == [0.1, 0.2, 0.1, 0.2]
+    joined = thor_cluster.grid_search_batch(*args, 0.1, 4, 2, alg, output="joined", sort_members=True)
+    assert joined.schema.names[0] == "cell_id"
+    assert joined["cell_id"].to_pylist() == [2] * 4 + [5] * 4
+    clusters, members = thor_cluster.grid_search_batch(*args, 0.1, 4, 2, alg, max_cluster_size=3)
+    assert clusters.num_rows == 0 and members.num_rows == 0
+
+
+def test_knn_graph():
+    xs = pa.array([0.0, 1.0, None, 0.0, 0.75], type=pa.float64())
+    ys = pa.array([0.0, 0.0, 0.0, 1.0, 0.75], type=pa.float64())
+    table = thor_cluster.knn_graph(xs, ys, 2)
+    assert table.num_rows == 8
+    edges = list(zip(*(table.column(name).to_pylist() for name in ["i", "j", "distance"])))
+    # The null row is skipped, and the rest keep their row numbers.
+    assert all(2 not in (i, j) for i, j, _ in edges)
+    assert [(i, j) for i, j, _ in edges if i == 1] == [(1, 4), (1, 0)]
+    for i, j, distance in edges:
+        want = math.hypot(xs[i].as_py() - xs[j].as_py(), ys[i].as_py() - ys[j].as_py())
+        assert distance == pytest.approx(want)
+
+    assert thor_cluster.knn_graph(xs, ys, 100).num_rows == 12
+
+
+def test_radius_query():
+    catalog_xs = pa.array([0.0, 1.0, None, 0.0, 0.5], type=pa.float64())
+    catalog_ys = pa.array([0.0, 0.0, 0.0, 1.0, 0.5], type=pa.float64())
+    query_xs = pa.array([0.0, 0.9, 10.0, None], type=pa.float64())
+    query_ys = pa.array([0.0, 0.1, 10.0, 0.0], type=pa.float64())
+    neighbors = thor_cluster.radius_query(catalog_xs, catalog_ys, query_xs, query_ys, 0.75)
+    assert neighbors.type == pa.list_(pa.uint32())
+    # The null catalog row is nobody's neighbor, the isolated query has
+    # none, and the null query row is null.
+    assert neighbors.to_pylist() == [[0, 4], [1, 4], [], None]
+
+
+def test_grid_dbscan_matches_dbscan(benchmark_data):
+    xs = benchmark_data["x"][0:5000]
+    ys = benchmark_data["y"][0:5000]
+    want = thor_cluster.find_clusters(xs, ys, 0.02, 4, thor_cluster.ClusterAlgorithm.DBSCAN)
+    have = thor_cluster.find_clusters(xs, ys, 0.02, 4, thor_cluster.ClusterAlgorithm.GridDbscan)
+    assert have == want
+    assert max(want.to_pylist()) > 0
+
+
+def test_hotspot2d_cells():
+    xs = [2.0 + 0.01 * i for i in range(6)] + [10.0, None]
+    ys = [-1.0 - 0.01 * i for i in range(6)] + [10.0, 0.0]
+    xs = pa.array(xs, type=pa.float64())
+    ys = pa.array(ys, type=pa.float64())
+    labels, cells = thor_cluster.hotspot2d_cells(xs, ys, 0.5, 4)
+    assert labels.to_pylist() == [1] * 6 + [-1, -1]
+    assert cells.to_pylist() == [
+        {"label": 1, "cell_x": 4, "cell_y": -2, "x": 2.0, "y": -1.0, "count": 6},
+    ]
+
+
 @pytest.fixture(scope="session")
 def benchmark_data():
     datafile = "./testdata/cluster_input.csv"
@@ -30,18 +956,22 @@ def benchmark_data():
         x = []
         y = []
         dt = []
+        ids = []
         for row in reader:
-            x.append(float(row[0]))
-            y.append(float(row[1]))
-            dt.append(float(row[2]))
+            x.append(float(row[1]))
+            y.append(float(row[2]))
+            dt.append(float(row[3]))
+            ids.append(row[4])
 
     x_array = pa.array(x, type=pa.float64())
     y_array = pa.array(y, type=pa.float64())
     dt_array = pa.array(dt, type=pa.float64())
+    ids_array = pa.array(ids, type=pa.string())
     return {
         "x": x_array,
         "y": y_array,
         "dt": dt_array,
+        "ids": ids_array,
     }
 
 